@@ -0,0 +1,194 @@
+use std::{
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+use axum::{
+    body::{Body, Bytes, HttpBody},
+    extract::Request,
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use http_body::{Frame, SizeHint};
+use http_body_util::BodyExt;
+use sha2::{Digest, Sha256};
+
+/// Log the SHA-256 and length of the request's body as the handler reads it,
+/// for when that's all there is to know and buffering it would be a waste.
+pub async fn hash_request_body(request: Request, next: Next) -> Response {
+    let request = request.map(|body| {
+        Body::new(HashBody {
+            inner: body,
+            hasher: Sha256::new(),
+            len: 0,
+            done: false,
+        })
+    });
+    next.run(request).await
+}
+
+/// Read the whole body, keeping none of it.
+pub async fn upload(mut body: Body) -> Result<StatusCode, Response> {
+    while let Some(frame) = body.frame().await {
+        frame
+            .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response())?;
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Passes every frame of `inner` through unchanged, hashing the data on the
+/// way.
+///
+/// Bodies dropped before their end, like when the handler turns the request
+/// away without reading it all, are logged as partial instead.
+struct HashBody {
+    inner: Body,
+    hasher: Sha256,
+    len: u64,
+    done: bool,
+}
+
+impl HashBody {
+    fn finish(&mut self) {
+        if self.done {
+            return;
+        }
+        self.done = true;
+        let digest = hex::encode(self.hasher.finalize_reset());
+        tracing::debug!(sha256 = %digest, len = self.len, "hashed the request body");
+    }
+}
+
+impl HttpBody for HashBody {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.get_mut();
+        let frame = ready!(Pin::new(&mut this.inner).poll_frame(cx));
+        match &frame {
+            Some(Ok(frame)) => {
+                if let Some(data) = frame.data_ref() {
+                    this.hasher.update(data);
+                    this.len += data.len() as u64;
+                }
+                if this.inner.is_end_stream() {
+                    this.finish();
+                }
+            }
+            // Left to `drop` to log as partial.
+            Some(Err(_)) => {}
+            None => this.finish(),
+        }
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl Drop for HashBody {
+    fn drop(&mut self) {
+        if !self.done {
+            tracing::debug!(
+                len = self.len,
+                partial = true,
+                "request body dropped before its end"
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use axum::{http::header, middleware, routing::post, Router};
+    use futures::stream;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{app, test_support::Logs, AppState};
+
+    /// Ten chunks of 100 bytes, each a different letter.
+    fn chunks() -> Vec<Bytes> {
+        (b'a'..b'a' + 10)
+            .map(|c| Bytes::from(vec![c; 100]))
+            .collect()
+    }
+
+    fn streamed() -> Body {
+        Body::from_stream(stream::iter(chunks().into_iter().map(Ok::<_, Infallible>)))
+    }
+
+    async fn send(app: Router, body: Body) -> (StatusCode, Bytes) {
+        let request = Request::post("/upload")
+            .header(header::CONTENT_TYPE, "application/octet-stream")
+            .body(body)
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        let status = response.status();
+        (
+            status,
+            response.into_body().collect().await.unwrap().to_bytes(),
+        )
+    }
+
+    #[tokio::test]
+    async fn uploads_are_hashed_as_they_stream() {
+        let (logs, _guard) = Logs::capture();
+        let (status, _) = send(app(AppState::default()), streamed()).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+
+        let digest = hex::encode(Sha256::digest(chunks().concat()));
+        let logs = logs.contents();
+        assert!(
+            logs.contains(&format!("sha256={digest} len=1000")),
+            "{logs}"
+        );
+        assert!(!logs.contains("partial"), "{logs}");
+    }
+
+    #[tokio::test]
+    async fn bodies_left_unread_are_partial() {
+        let (logs, _guard) = Logs::capture();
+        let app = Router::new()
+            .route(
+                "/upload",
+                post(|mut body: Body| async move {
+                    body.frame().await;
+                    StatusCode::FORBIDDEN
+                }),
+            )
+            .layer(middleware::from_fn(hash_request_body));
+        let (status, _) = send(app, streamed()).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let logs = logs.contents();
+        assert!(logs.contains("len=100 partial=true"), "{logs}");
+        assert!(!logs.contains("sha256"), "{logs}");
+    }
+
+    #[tokio::test]
+    async fn size_hints_are_kept() {
+        let app = Router::new()
+            .route(
+                "/upload",
+                post(|body: Body| async move { format!("{:?}", body.size_hint().exact()) }),
+            )
+            .layer(middleware::from_fn(hash_request_body));
+        let (_, response) = send(app.clone(), Body::from(vec![b'x'; 1000])).await;
+        assert_eq!(response, "Some(1000)");
+        let (_, response) = send(app, streamed()).await;
+        assert_eq!(response, "None");
+    }
+}
@@ -1,14 +1,32 @@
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
 use axum::body::{Body, Bytes};
-use axum::extract::{FromRequest, Request};
-use axum::http::StatusCode;
+use axum::extract::{DefaultBodyLimit, FromRef, FromRequest, Request, State};
+use axum::http::{header, HeaderMap, StatusCode};
 use axum::middleware::Next;
 use axum::response::{IntoResponse, Response};
 use axum::routing::post;
-use axum::{async_trait, middleware, Router};
+use axum::{async_trait, middleware, Json, Router};
+use bytes::BytesMut;
 use http_body_util::BodyExt;
+use serde_json::json;
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::util::SubscriberInitExt;
 
+use crate::redact::Redactor;
+use crate::signature::VerifiedBody;
+use crate::tee::TeeConfig;
+
+mod hash;
+mod redact;
+mod response_body;
+mod signature;
+mod tee;
+#[cfg(test)]
+mod test_support;
+
 #[tokio::main]
 async fn main() {
     tracing_subscriber::registry()
@@ -19,43 +37,246 @@ async fn main() {
         .with(tracing_subscriber::fmt::layer())
         .init();
 
-    let app = Router::new()
-        .route("/", post(handler))
-        .layer(middleware::from_fn(print_request_body));
-
     let listener = tokio::net::TcpListener::bind("127.0.0.1:3000")
         .await
         .unwrap();
     tracing::debug!("listening on {}", listener.local_addr().unwrap());
-    axum::serve(listener, app).await.unwrap();
+    let state = AppState {
+        webhook_secret: std::env::var("WEBHOOK_SECRET").ok().map(String::into_bytes),
+        log_response_bodies: std::env::var("LOG_RESPONSE_BODIES").is_ok_and(|flag| flag == "true"),
+        tee: std::env::var_os("TEE_DIR").map(|dir| TeeConfig {
+            dir: dir.into(),
+            max_bytes: DEFAULT_TEE_MAX_BYTES,
+        }),
+        ..AppState::default()
+    };
+    axum::serve(listener, app(state)).await.unwrap();
+}
+
+/// Request bodies longer than this are rejected, unless set otherwise.
+const DEFAULT_BODY_LIMIT: usize = 1024 * 1024;
+
+/// Response bodies longer than this aren't logged, unless set otherwise.
+const DEFAULT_LOGGED_RESPONSE_LIMIT: usize = 64 * 1024;
+
+/// Teed request bodies are cut short after this many bytes.
+const DEFAULT_TEE_MAX_BYTES: u64 = 16 * 1024 * 1024;
+
+#[derive(Debug, Clone)]
+struct AppState {
+    /// The most bytes of a request body that are buffered.
+    body_limit: usize,
+    /// Only bodies of these content types are buffered, whatever their
+    /// parameters.
+    buffered_types: Vec<String>,
+    /// Only bodies sent to paths starting with one of these are buffered, or
+    /// to any path if `None`.
+    buffered_paths: Option<Vec<String>>,
+    /// What `/webhook` requests are signed with. They're all turned away
+    /// without one.
+    webhook_secret: Option<Vec<u8>>,
+    /// How far a webhook's `X-Timestamp` can be from the clock before it's
+    /// taken for a replay.
+    signature_tolerance: Duration,
+    /// Whether `print_response_body` logs anything.
+    log_response_bodies: bool,
+    /// Response bodies longer than this are passed through without being
+    /// logged.
+    logged_response_limit: usize,
+    /// What's done with every buffered request body.
+    body_observer: BodyObserver,
+    /// Where every request body is copied to as it's read, if anywhere.
+    tee: Option<TeeConfig>,
+}
+
+impl Default for AppState {
+    fn default() -> Self {
+        Self {
+            body_limit: DEFAULT_BODY_LIMIT,
+            buffered_types: vec![
+                "application/json".to_owned(),
+                "application/x-www-form-urlencoded".to_owned(),
+            ],
+            buffered_paths: None,
+            webhook_secret: None,
+            signature_tolerance: Duration::from_secs(5 * 60),
+            log_response_bodies: false,
+            logged_response_limit: DEFAULT_LOGGED_RESPONSE_LIMIT,
+            body_observer: BodyObserver::default(),
+            tee: None,
+        }
+    }
+}
+
+impl AppState {
+    fn buffers(&self, request: &Request) -> bool {
+        let content_type = media_type(request.headers());
+        let buffered_type = self
+            .buffered_types
+            .iter()
+            .any(|buffered| buffered.eq_ignore_ascii_case(content_type));
+
+        let path = request.uri().path();
+        let buffered_path = self.buffered_paths.as_ref().is_none_or(|prefixes| {
+            prefixes
+                .iter()
+                .any(|prefix| path.starts_with(prefix.as_str()))
+        });
+
+        buffered_type && buffered_path
+    }
+}
+
+/// The `Content-Type` without its parameters, or `""`.
+fn media_type(headers: &HeaderMap) -> &str {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .map(str::trim)
+        .unwrap_or_default()
+}
+
+/// Called with every request body once it's buffered, by the middleware or the
+/// extractor, whichever gets to it first.
+#[derive(Clone)]
+struct BodyObserver(Arc<ObserveBody>);
+
+type ObserveBody = dyn Fn(&HeaderMap, Bytes) + Send + Sync;
+
+impl Default for BodyObserver {
+    fn default() -> Self {
+        Self::logging(Redactor::default())
+    }
+}
+
+impl BodyObserver {
+    /// Log bodies with `redactor`'s keys left out.
+    fn logging(redactor: Redactor) -> Self {
+        Self(Arc::new(move |headers, bytes| {
+            do_thing_with_request_body(&redactor, headers, bytes)
+        }))
+    }
 }
 
-async fn print_request_body(request: Request, next: Next) -> Result<impl IntoResponse, Response> {
-    let request = buffer_request_body(request).await?;
+impl fmt::Debug for BodyObserver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("BodyObserver").finish_non_exhaustive()
+    }
+}
+
+/// The request body, in the extensions once `print_request_body` has buffered
+/// it.
+#[derive(Debug, Clone)]
+struct BufferedBody(Bytes);
+
+fn app(state: AppState) -> Router {
+    Router::new()
+        .route("/", post(handler))
+        .route(
+            "/webhook",
+            post(webhook).route_layer(middleware::from_fn_with_state(
+                state.clone(),
+                signature::verify_signature,
+            )),
+        )
+        // Hashed as it streams, unless `print_request_body` buffers it first,
+        // as it does for JSON and form bodies by default, see `AppState::buffers`.
+        .route(
+            "/upload",
+            post(hash::upload).route_layer(middleware::from_fn(hash::hash_request_body)),
+        )
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            print_request_body,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            response_body::print_response_body,
+        ))
+        // For `BufferRequestBody`, which goes through `Bytes`.
+        .layer(DefaultBodyLimit::max(state.body_limit))
+        .with_state(state)
+}
+
+async fn print_request_body(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Result<impl IntoResponse, Response> {
+    // Teed or not, bodies are buffered as they would be otherwise.
+    let request = match &state.tee {
+        Some(tee) => tee::tee_request_body(request, tee),
+        None => request,
+    };
+
+    if !state.buffers(&request) {
+        return Ok(next.run(request).await);
+    }
+
+    // Rather than reading a body that's said to be too long.
+    let content_length = request
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok()?.parse::<usize>().ok());
+    if content_length.is_some_and(|len| len > state.body_limit) {
+        return Err(too_large(state.body_limit));
+    }
+
+    let request = buffer_request_body(request, &state).await?;
 
     Ok(next.run(request).await)
 }
 
-async fn buffer_request_body(request: Request) -> Result<Request, Response> {
-    let (parts, body) = request.into_parts();
+/// Collect the body a frame at a time, giving up as soon as it's longer than
+/// the limit, without reading the rest.
+async fn buffer_request_body(request: Request, state: &AppState) -> Result<Request, Response> {
+    let (mut parts, body) = request.into_parts();
 
-    let bytes = body
-        .collect()
-        .await
-        .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response())?
-        .to_bytes();
+    let bytes = collect_limited(body, state.body_limit).await?;
 
-    do_thing_with_request_body(bytes.clone());
+    tracing::debug!(
+        len = bytes.len(),
+        "buffered the request body in the middleware"
+    );
+    (state.body_observer.0)(&parts.headers, bytes.clone());
 
+    parts.extensions.insert(BufferedBody(bytes.clone()));
     Ok(Request::from_parts(parts, Body::from(bytes)))
 }
 
-fn do_thing_with_request_body(bytes: Bytes) {
-    tracing::debug!(body=?bytes);
+async fn collect_limited(mut body: Body, limit: usize) -> Result<Bytes, Response> {
+    let mut bytes = BytesMut::new();
+    while let Some(frame) = body.frame().await {
+        let frame = frame
+            .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response())?;
+        if let Ok(data) = frame.into_data() {
+            if bytes.len() + data.len() > limit {
+                return Err(too_large(limit));
+            }
+            bytes.extend_from_slice(&data);
+        }
+    }
+    Ok(bytes.freeze())
+}
+
+fn too_large(limit: usize) -> Response {
+    let body = json!({
+        "error": format!("request body is larger than the limit of {limit} bytes"),
+    });
+    (StatusCode::PAYLOAD_TOO_LARGE, Json(body)).into_response()
+}
+
+fn do_thing_with_request_body(redactor: &Redactor, headers: &HeaderMap, bytes: Bytes) {
+    tracing::debug!(body = %redactor.loggable(headers, &bytes));
 }
 
 async fn handler(BufferRequestBody(body): BufferRequestBody) {
-    tracing::debug!(?body, "handler received body");
+    tracing::debug!(len = body.len(), "handler received body");
+}
+
+async fn webhook(BufferRequestBody(body): BufferRequestBody) -> String {
+    format!("{} bytes verified", body.len())
 }
 
 struct BufferRequestBody(Bytes);
@@ -64,16 +285,297 @@ struct BufferRequestBody(Bytes);
 impl<S> FromRequest<S> for BufferRequestBody
 where
     S: Send + Sync,
+    AppState: FromRef<S>,
 {
     type Rejection = Response;
 
     async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
-        let body = Bytes::from_request(req, state)
-            .await
-            .map_err(|err| err.into_response())?;
+        if let Some(VerifiedBody(body)) = req.extensions().get::<VerifiedBody>() {
+            return Ok(Self(body.clone()));
+        }
+        if let Some(BufferedBody(body)) = req.extensions().get::<BufferedBody>() {
+            tracing::debug!(len = body.len(), "reusing the body the middleware buffered");
+            return Ok(Self(body.clone()));
+        }
+
+        let headers = req.headers().clone();
+        let body = Bytes::from_request(req, state).await.map_err(|err| {
+            // Over the `DefaultBodyLimit`.
+            if err.status() == StatusCode::PAYLOAD_TOO_LARGE {
+                too_large(AppState::from_ref(state).body_limit)
+            } else {
+                err.into_response()
+            }
+        })?;
 
-        do_thing_with_request_body(body.clone());
+        tracing::debug!(
+            len = body.len(),
+            "buffered the request body in the extractor"
+        );
+        (AppState::from_ref(state).body_observer.0)(&headers, body.clone());
 
         Ok(Self(body))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use axum::body::HttpBody;
+    use futures::stream::{self, StreamExt};
+    use serde_json::Value;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::test_support::Logs;
+
+    const LIMIT: usize = 1024;
+
+    /// `len` bytes in chunks of 100, without a `Content-Length`.
+    fn chunked(len: usize) -> Body {
+        let chunks = vec![b'x'; len]
+            .chunks(100)
+            .map(|chunk| Ok::<_, Infallible>(Bytes::copy_from_slice(chunk)))
+            .collect::<Vec<_>>();
+        Body::from_stream(stream::iter(chunks))
+    }
+
+    async fn send(app: Router, body: Body) -> (StatusCode, Bytes) {
+        let request = Request::post("/")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(body)
+            .unwrap();
+        send_request(app, request).await
+    }
+
+    async fn send_request(app: Router, request: Request) -> (StatusCode, Bytes) {
+        let response = tokio::time::timeout(Duration::from_secs(5), app.oneshot(request))
+            .await
+            .expect("no response within 5 seconds")
+            .unwrap();
+        let status = response.status();
+        (
+            status,
+            response.into_body().collect().await.unwrap().to_bytes(),
+        )
+    }
+
+    fn limited() -> AppState {
+        AppState {
+            body_limit: LIMIT,
+            ..AppState::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn bodies_under_the_limit_are_buffered() {
+        let (status, _) = send(app(limited()), chunked(LIMIT)).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn bodies_over_the_limit_are_rejected_early() {
+        // Never ends, so only giving up early gets a response.
+        let endless = stream::iter([Ok::<_, Infallible>(Bytes::from(vec![b'x'; LIMIT + 1]))])
+            .chain(stream::pending());
+        let (status, body) = send(app(limited()), Body::from_stream(endless)).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            body["error"],
+            "request body is larger than the limit of 1024 bytes"
+        );
+
+        let (status, _) = send(app(limited()), chunked(LIMIT + 1)).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn the_extractor_is_limited_too() {
+        let state = limited();
+        let app = Router::new()
+            .route("/", post(handler))
+            .layer(DefaultBodyLimit::max(state.body_limit))
+            .with_state(state);
+
+        let (status, _) = send(app.clone(), chunked(LIMIT)).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, body) = send(app, chunked(LIMIT + 1)).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            body["error"],
+            "request body is larger than the limit of 1024 bytes"
+        );
+    }
+
+    /// Answers with whether the body it got has a known length, which
+    /// buffered bodies do and streamed ones don't.
+    fn inspecting(state: AppState) -> Router {
+        async fn inspect(request: Request) -> String {
+            let buffered = request.body().size_hint().exact().is_some();
+            let body = request.into_body().collect().await.unwrap().to_bytes();
+            format!("buffered={buffered} len={}", body.len())
+        }
+
+        Router::new()
+            .route("/*path", post(inspect))
+            .layer(middleware::from_fn_with_state(
+                state.clone(),
+                print_request_body,
+            ))
+            .with_state(state)
+    }
+
+    async fn inspect(app: Router, uri: &str, content_type: &str) -> String {
+        let request = Request::post(uri)
+            .header(header::CONTENT_TYPE, content_type)
+            .body(chunked(300))
+            .unwrap();
+        let (status, body) = send_request(app, request).await;
+        assert_eq!(status, StatusCode::OK);
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn only_some_content_types_are_buffered() {
+        let app = inspecting(limited());
+        for content_type in [
+            "application/json",
+            "application/json; charset=utf-8",
+            "Application/JSON",
+            "application/x-www-form-urlencoded",
+        ] {
+            let response = inspect(app.clone(), "/items", content_type).await;
+            assert_eq!(response, "buffered=true len=300", "{content_type}");
+        }
+        for content_type in [
+            "application/octet-stream",
+            "multipart/form-data; boundary=x",
+        ] {
+            let response = inspect(app.clone(), "/items", content_type).await;
+            assert_eq!(response, "buffered=false len=300", "{content_type}");
+        }
+    }
+
+    #[tokio::test]
+    async fn only_some_paths_are_buffered() {
+        let app = inspecting(AppState {
+            buffered_paths: Some(vec!["/api/".to_owned()]),
+            ..limited()
+        });
+        let response = inspect(app.clone(), "/api/items", "application/json").await;
+        assert_eq!(response, "buffered=true len=300");
+        let response = inspect(app, "/uploads/items", "application/json").await;
+        assert_eq!(response, "buffered=false len=300");
+    }
+
+    #[tokio::test]
+    async fn long_content_lengths_are_rejected_unread() {
+        let request = Request::post("/items")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(header::CONTENT_LENGTH, LIMIT + 1)
+            .body(Body::from_stream(stream::pending::<
+                Result<Bytes, Infallible>,
+            >()))
+            .unwrap();
+        let (status, _) = send_request(inspecting(limited()), request).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+
+        // Not buffered, so not limited here.
+        let request = Request::post("/items")
+            .header(header::CONTENT_TYPE, "application/octet-stream")
+            .header(header::CONTENT_LENGTH, 2 * LIMIT)
+            .body(Body::from(vec![b'x'; 2 * LIMIT]))
+            .unwrap();
+        let (status, _) = send_request(inspecting(limited()), request).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    /// Counts the bodies it's called with.
+    fn counting() -> (AppState, Arc<AtomicUsize>) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let state = AppState {
+            body_observer: BodyObserver(Arc::new(move |_, _| {
+                counter.fetch_add(1, Ordering::SeqCst);
+            })),
+            ..limited()
+        };
+        (state, calls)
+    }
+
+    async fn echo(BufferRequestBody(body): BufferRequestBody) -> Bytes {
+        body
+    }
+
+    #[tokio::test]
+    async fn the_middleware_and_the_extractor_buffer_once() {
+        let (state, calls) = counting();
+        let app = Router::new()
+            .route("/", post(echo))
+            .layer(middleware::from_fn_with_state(
+                state.clone(),
+                print_request_body,
+            ))
+            .with_state(state);
+
+        let (status, body) = send(app, chunked(300)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, vec![b'x'; 300]);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn the_extractor_buffers_on_its_own() {
+        let (state, calls) = counting();
+        let app = Router::new().route("/", post(echo)).with_state(state);
+
+        let (status, body) = send(app, chunked(300)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, vec![b'x'; 300]);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn the_middleware_buffers_on_its_own() {
+        let (state, calls) = counting();
+        let app = Router::new()
+            .route("/", post(|body: Bytes| async move { body }))
+            .layer(middleware::from_fn_with_state(
+                state.clone(),
+                print_request_body,
+            ))
+            .with_state(state);
+
+        let (status, body) = send(app, chunked(300)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, vec![b'x'; 300]);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn passwords_are_kept_out_of_the_logs() {
+        let (logs, _guard) = Logs::capture();
+        let state = limited();
+        let app = Router::new()
+            .route("/", post(echo))
+            .layer(middleware::from_fn_with_state(
+                state.clone(),
+                print_request_body,
+            ))
+            .with_state(state);
+
+        let sent = r#"{"user":{"name":"ferris","Password":"hunter2"}}"#;
+        let (status, body) = send(app, Body::from(sent)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, sent);
+
+        let logs = logs.contents();
+        assert!(logs.contains(r#""Password":"[REDACTED]""#), "{logs}");
+        assert!(!logs.contains("hunter2"), "{logs}");
+    }
+}
@@ -0,0 +1,191 @@
+use axum::{body::Bytes, http::HeaderMap};
+use serde_json::Value;
+
+use crate::media_type;
+
+/// What the values of redacted keys are replaced with.
+const REDACTED: &str = "[REDACTED]";
+
+/// Which keys of JSON request bodies have their values kept out of the logs.
+#[derive(Debug, Clone)]
+pub struct Redactor {
+    /// Compared without regard to case. `*_key` matches every key ending in
+    /// `_key`, and `api_*` every key starting with `api_`.
+    patterns: Vec<String>,
+}
+
+impl Default for Redactor {
+    fn default() -> Self {
+        Self::new(["password", "token", "secret", "authorization", "*_key"])
+    }
+}
+
+impl Redactor {
+    pub fn new<I>(patterns: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        let patterns = patterns
+            .into_iter()
+            .map(|pattern| pattern.into().to_ascii_lowercase())
+            .collect();
+        Self { patterns }
+    }
+
+    fn matches(&self, key: &str) -> bool {
+        let key = key.to_ascii_lowercase();
+        self.patterns.iter().any(|pattern| {
+            if let Some(suffix) = pattern.strip_prefix('*') {
+                key.ends_with(suffix)
+            } else if let Some(prefix) = pattern.strip_suffix('*') {
+                key.starts_with(prefix)
+            } else {
+                key == *pattern
+            }
+        })
+    }
+
+    /// Replace the values of matching keys, at any depth, with `"[REDACTED]"`.
+    pub fn redact(&self, value: &mut Value) {
+        match value {
+            Value::Object(object) => {
+                for (key, value) in object {
+                    if self.matches(key) {
+                        *value = Value::from(REDACTED);
+                    } else {
+                        self.redact(value);
+                    }
+                }
+            }
+            Value::Array(values) => {
+                for value in values {
+                    self.redact(value);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// The body as it can be logged: redacted if it's JSON, and only its
+    /// length otherwise, since there's no telling what's in it.
+    pub fn loggable(&self, headers: &HeaderMap, body: &Bytes) -> String {
+        let media_type = media_type(headers).to_ascii_lowercase();
+        let json = media_type == "application/json" || media_type.ends_with("+json");
+        if json {
+            if let Ok(mut value) = serde_json::from_slice::<Value>(body) {
+                self.redact(&mut value);
+                return value.to_string();
+            }
+        }
+        format!("<{} bytes, not shown>", body.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::header;
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn redacts_nested_objects() {
+        let mut value = json!({
+            "user": {
+                "name": "ferris",
+                "password": "hunter2",
+                "session": { "token": "abc" },
+            },
+            "secret": { "nested": "whole objects go too" },
+        });
+        Redactor::default().redact(&mut value);
+        assert_eq!(
+            value,
+            json!({
+                "user": {
+                    "name": "ferris",
+                    "password": "[REDACTED]",
+                    "session": { "token": "[REDACTED]" },
+                },
+                "secret": "[REDACTED]",
+            })
+        );
+    }
+
+    #[test]
+    fn redacts_arrays_of_objects() {
+        let mut value = json!([
+            { "id": 1, "api_key": "k1" },
+            { "id": 2, "keys": ["not", "redacted"] },
+            [{ "authorization": "Bearer abc" }],
+        ]);
+        Redactor::default().redact(&mut value);
+        assert_eq!(
+            value,
+            json!([
+                { "id": 1, "api_key": "[REDACTED]" },
+                { "id": 2, "keys": ["not", "redacted"] },
+                [{ "authorization": "[REDACTED]" }],
+            ])
+        );
+    }
+
+    #[test]
+    fn keys_match_whatever_their_case() {
+        let mut value = json!({
+            "Password": "a",
+            "TOKEN": "b",
+            "Private_Key": "c",
+            "passwords_match": true,
+            "key": "d",
+        });
+        Redactor::default().redact(&mut value);
+        assert_eq!(
+            value,
+            json!({
+                "Password": "[REDACTED]",
+                "TOKEN": "[REDACTED]",
+                "Private_Key": "[REDACTED]",
+                "passwords_match": true,
+                "key": "d",
+            })
+        );
+    }
+
+    #[test]
+    fn patterns_are_configurable() {
+        let mut value = json!({ "password": "a", "x_api_thing": "b", "ssn": "c" });
+        Redactor::new(["SSN", "x_api_*"]).redact(&mut value);
+        assert_eq!(
+            value,
+            json!({ "password": "a", "x_api_thing": "[REDACTED]", "ssn": "[REDACTED]" })
+        );
+    }
+
+    #[test]
+    fn only_json_is_shown() {
+        let headers = |content_type: &'static str| {
+            HeaderMap::from_iter([(header::CONTENT_TYPE, content_type.parse().unwrap())])
+        };
+        let redactor = Redactor::default();
+
+        let body = Bytes::from(r#"{"password":"hunter2"}"#);
+        assert_eq!(
+            redactor.loggable(&headers("application/json; charset=utf-8"), &body),
+            r#"{"password":"[REDACTED]"}"#
+        );
+        assert_eq!(
+            redactor.loggable(&headers("text/plain"), &body),
+            "<22 bytes, not shown>"
+        );
+        assert_eq!(
+            redactor.loggable(&headers("application/json"), &Bytes::from("{nope")),
+            "<5 bytes, not shown>"
+        );
+        assert_eq!(
+            redactor.loggable(&HeaderMap::new(), &Bytes::from("password=hunter2")),
+            "<16 bytes, not shown>"
+        );
+    }
+}
@@ -0,0 +1,200 @@
+use axum::{
+    body::{Body, HttpBody},
+    extract::{Request, State},
+    http::{header, HeaderMap, HeaderName, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use http_body_util::BodyExt;
+
+use crate::{media_type, AppState};
+
+/// Headers whose values are never logged.
+const SENSITIVE_HEADERS: [HeaderName; 4] = [
+    header::SET_COOKIE,
+    header::COOKIE,
+    header::AUTHORIZATION,
+    header::PROXY_AUTHORIZATION,
+];
+
+/// In the extensions of responses `print_response_body` didn't touch.
+#[derive(Debug, Clone, Copy)]
+pub struct PassedThrough;
+
+/// Log the response's body along with its status and headers, when that's
+/// turned on, for text bodies of a known, short enough length.
+///
+/// Everything else, like server-sent events and large downloads, is passed
+/// through as it is.
+pub async fn print_response_body(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let mut response = next.run(request).await;
+    if !state.log_response_bodies || !loggable(&response, state.logged_response_limit) {
+        response.extensions_mut().insert(PassedThrough);
+        return response;
+    }
+
+    let (parts, body) = response.into_parts();
+    let bytes = match body.collect().await {
+        Ok(collected) => collected.to_bytes(),
+        Err(err) => {
+            tracing::warn!(%err, "failed to read the response body");
+            return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response();
+        }
+    };
+    tracing::debug!(
+        status = %parts.status,
+        content_type = media_type(&parts.headers),
+        headers = ?loggable_headers(&parts.headers),
+        body = ?bytes,
+        "response body",
+    );
+
+    Response::from_parts(parts, Body::from(bytes))
+}
+
+/// Text, and no longer than `limit`, which a streaming body, whose length
+/// isn't known, can't be sure to be.
+fn loggable(response: &Response, limit: usize) -> bool {
+    let media_type = media_type(response.headers()).to_ascii_lowercase();
+    let text = match media_type.split_once('/') {
+        Some(("text", "event-stream")) => false,
+        Some(("text", _)) => true,
+        Some(("application", subtype)) => {
+            matches!(subtype, "json" | "xml" | "x-www-form-urlencoded")
+                || subtype.ends_with("+json")
+                || subtype.ends_with("+xml")
+        }
+        _ => false,
+    };
+    let short = response
+        .body()
+        .size_hint()
+        .upper()
+        .is_some_and(|len| len <= limit as u64);
+    text && short
+}
+
+fn loggable_headers(headers: &HeaderMap) -> Vec<(&HeaderName, &str)> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let value = if SENSITIVE_HEADERS.contains(name) {
+                "[redacted]"
+            } else {
+                value.to_str().unwrap_or("[not text]")
+            };
+            (name, value)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use axum::{
+        body::Bytes,
+        middleware,
+        response::sse::{Event, Sse},
+        routing::get,
+        Json, Router,
+    };
+    use futures::stream;
+    use serde_json::json;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::test_support::Logs;
+
+    fn app(log_response_bodies: bool) -> Router {
+        let state = AppState {
+            log_response_bodies,
+            logged_response_limit: 1024,
+            ..AppState::default()
+        };
+        Router::new()
+            .route(
+                "/json",
+                get(|| async {
+                    let cookie = [(header::SET_COOKIE, "session=hunter2")];
+                    (cookie, Json(json!({ "id": 1, "name": "ferris" })))
+                }),
+            )
+            .route("/large", get(|| async { "x".repeat(1025) }))
+            .route(
+                "/binary",
+                get(|| async { ([(header::CONTENT_TYPE, "image/png")], vec![0_u8; 16]) }),
+            )
+            .route(
+                "/events",
+                get(|| async {
+                    let events = stream::iter([Ok::<_, Infallible>(Event::default().data("hi"))]);
+                    Sse::new(events)
+                }),
+            )
+            .layer(middleware::from_fn_with_state(
+                state.clone(),
+                print_response_body,
+            ))
+            .with_state(state)
+    }
+
+    /// The response's body, and whether it was passed through.
+    async fn get_body(app: Router, uri: &str) -> (Response<()>, Bytes, bool) {
+        let request = Request::get(uri).body(Body::empty()).unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        let passed_through = response.extensions().get::<PassedThrough>().is_some();
+        let (parts, body) = response.into_parts();
+        let body = body.collect().await.unwrap().to_bytes();
+        (Response::from_parts(parts, ()), body, passed_through)
+    }
+
+    #[tokio::test]
+    async fn json_responses_are_logged_unchanged() {
+        let (logs, _guard) = Logs::capture();
+        let (response, body, passed_through) = get_body(app(true), "/json").await;
+        assert!(!passed_through);
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::SET_COOKIE], "session=hunter2");
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+        assert_eq!(body, r#"{"id":1,"name":"ferris"}"#);
+
+        let logs = logs.contents();
+        assert!(logs.contains("response body"), "{logs}");
+        assert!(logs.contains(r#"{\"id\":1,\"name\":\"ferris\"}"#), "{logs}");
+        assert!(logs.contains("content_type=\"application/json\""), "{logs}");
+        assert!(!logs.contains("hunter2"), "{logs}");
+    }
+
+    #[tokio::test]
+    async fn other_responses_pass_through() {
+        let (logs, _guard) = Logs::capture();
+        let (_, body, passed_through) = get_body(app(true), "/large").await;
+        assert!(passed_through);
+        assert_eq!(body, "x".repeat(1025));
+
+        let (_, body, passed_through) = get_body(app(true), "/binary").await;
+        assert!(passed_through);
+        assert_eq!(body, vec![0_u8; 16]);
+
+        let (_, body, passed_through) = get_body(app(true), "/events").await;
+        assert!(passed_through);
+        assert_eq!(body, "data: hi\n\n");
+
+        let logs = logs.contents();
+        assert!(!logs.contains("response body"), "{logs}");
+    }
+
+    #[tokio::test]
+    async fn logging_can_be_turned_off() {
+        let (logs, _guard) = Logs::capture();
+        let (_, body, passed_through) = get_body(app(false), "/json").await;
+        assert!(passed_through);
+        assert_eq!(body, r#"{"id":1,"name":"ferris"}"#);
+        assert!(!logs.contents().contains("response body"));
+    }
+}
@@ -0,0 +1,189 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use axum::{
+    body::{Body, Bytes},
+    extract::{Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use hmac::{Hmac, Mac};
+use serde_json::json;
+use sha2::Sha256;
+
+use crate::{collect_limited, AppState};
+
+/// `sha256=<hex>`, the HMAC-SHA256 of `<timestamp>.<body>` by the shared
+/// secret.
+pub const X_SIGNATURE: &str = "x-signature";
+/// When the request was signed, in seconds since the Unix epoch.
+pub const X_TIMESTAMP: &str = "x-timestamp";
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// The body of a request whose signature checked out, for `BufferRequestBody`
+/// to use rather than collecting it again.
+#[derive(Debug, Clone)]
+pub struct VerifiedBody(pub Bytes);
+
+/// Turn away requests that aren't signed by the webhook secret, or were signed
+/// too long ago, before they reach the handler.
+pub async fn verify_signature(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Result<Response, Response> {
+    let Some(secret) = state.webhook_secret.as_deref() else {
+        return Err(unauthorized("webhooks aren't configured"));
+    };
+
+    let headers = request.headers();
+    let signature = headers
+        .get(X_SIGNATURE)
+        .and_then(|value| value.to_str().ok()?.strip_prefix("sha256="))
+        .and_then(|signature| hex::decode(signature).ok())
+        .ok_or_else(|| unauthorized("missing or malformed X-Signature"))?;
+    let timestamp: u64 = headers
+        .get(X_TIMESTAMP)
+        .and_then(|value| value.to_str().ok()?.parse().ok())
+        .ok_or_else(|| unauthorized("missing or malformed X-Timestamp"))?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    if now.abs_diff(timestamp) > state.signature_tolerance.as_secs() {
+        return Err(unauthorized("stale X-Timestamp"));
+    }
+
+    let (mut parts, body) = request.into_parts();
+    let body = collect_limited(body, state.body_limit).await?;
+    // `verify_slice` compares in constant time.
+    mac(secret, timestamp, &body)
+        .verify_slice(&signature)
+        .map_err(|_| unauthorized("signature mismatch"))?;
+
+    parts.extensions.insert(VerifiedBody(body.clone()));
+    Ok(next.run(Request::from_parts(parts, Body::from(body))).await)
+}
+
+/// Signing the timestamp too, so that it can't be changed to replay a request.
+fn mac(secret: &[u8], timestamp: u64, body: &[u8]) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC takes keys of any length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    mac
+}
+
+fn unauthorized(reason: &str) -> Response {
+    (StatusCode::UNAUTHORIZED, Json(json!({ "error": reason }))).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::header;
+    use http_body_util::BodyExt;
+    use serde_json::Value;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::app;
+
+    const SECRET: &[u8] = b"it's a secret";
+    const BODY: &str = r#"{"event": "push"}"#;
+
+    /// The `X-Signature` a sender would send.
+    fn sign(secret: &[u8], timestamp: u64, body: &[u8]) -> String {
+        let signature = mac(secret, timestamp, body).finalize().into_bytes();
+        format!("sha256={}", hex::encode(signature))
+    }
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    async fn deliver(
+        body: &'static str,
+        signature: Option<String>,
+        timestamp: u64,
+    ) -> (StatusCode, String) {
+        let state = AppState {
+            webhook_secret: Some(SECRET.to_vec()),
+            ..AppState::default()
+        };
+        let mut request = Request::post("/webhook")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(X_TIMESTAMP, timestamp);
+        if let Some(signature) = signature {
+            request = request.header(X_SIGNATURE, signature);
+        }
+        let request = request.body(Body::from(body)).unwrap();
+
+        let response = app(state).oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    fn error(body: &str) -> Value {
+        serde_json::from_str::<Value>(body).unwrap()["error"].clone()
+    }
+
+    #[tokio::test]
+    async fn signed_bodies_reach_the_handler() {
+        let timestamp = now();
+        let signature = sign(SECRET, timestamp, BODY.as_bytes());
+        let (status, body) = deliver(BODY, Some(signature), timestamp).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, format!("{} bytes verified", BODY.len()));
+    }
+
+    #[tokio::test]
+    async fn tampered_bodies_are_rejected() {
+        let timestamp = now();
+        let signature = sign(SECRET, timestamp, BODY.as_bytes());
+        let (status, body) = deliver(r#"{"event": "delete"}"#, Some(signature), timestamp).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(error(&body), "signature mismatch");
+    }
+
+    #[tokio::test]
+    async fn other_secrets_are_rejected() {
+        let timestamp = now();
+        let signature = sign(b"another secret", timestamp, BODY.as_bytes());
+        let (status, body) = deliver(BODY, Some(signature), timestamp).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(error(&body), "signature mismatch");
+    }
+
+    #[tokio::test]
+    async fn unsigned_bodies_are_rejected() {
+        let (status, body) = deliver(BODY, None, now()).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(error(&body), "missing or malformed X-Signature");
+
+        let garbage = Some("sha256=not hex".to_owned());
+        let (status, _) = deliver(BODY, garbage, now()).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn stale_timestamps_are_rejected() {
+        // Correctly signed, but a replay from ten minutes ago.
+        let timestamp = now() - 10 * 60;
+        let signature = sign(SECRET, timestamp, BODY.as_bytes());
+        let (status, body) = deliver(BODY, Some(signature), timestamp).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(error(&body), "stale X-Timestamp");
+
+        // Or with the timestamp moved forward, which breaks the signature.
+        let signature = sign(SECRET, timestamp, BODY.as_bytes());
+        let (status, body) = deliver(BODY, Some(signature), now()).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(error(&body), "signature mismatch");
+    }
+}
@@ -0,0 +1,441 @@
+use std::{
+    path::PathBuf,
+    pin::Pin,
+    sync::atomic::{AtomicU64, Ordering},
+    task::{ready, Context, Poll},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    body::{Body, Bytes, HttpBody},
+    extract::Request,
+    http::HeaderMap,
+};
+use http_body::{Frame, SizeHint};
+use tokio::{fs::File, io::AsyncWriteExt, sync::mpsc};
+
+/// Where request bodies are copied to, for debugging ones too large to log.
+#[derive(Debug, Clone)]
+pub struct TeeConfig {
+    pub dir: PathBuf,
+    /// Bodies longer than this are cut short in their files, but not on their
+    /// way to the handler.
+    pub max_bytes: u64,
+}
+
+/// Named after this when there's no `X-Request-Id`.
+static NEXT_REQUEST: AtomicU64 = AtomicU64::new(1);
+
+/// Copy the request's body to a new file under `config.dir` as the handler
+/// reads it.
+///
+/// The file is only created once there's data to write, so empty bodies and
+/// ones turned away unread, like with a 413, leave nothing behind.
+pub fn tee_request_body(request: Request, config: &TeeConfig) -> Request {
+    let path = config.dir.join(file_name(request.headers()));
+    request.map(|body| {
+        Body::new(TeeBody {
+            inner: body,
+            path,
+            max_bytes: config.max_bytes,
+            writer: None,
+            len: 0,
+            truncated: false,
+            done: false,
+        })
+    })
+}
+
+/// `<request id>-<milliseconds since the Unix epoch>.body`, with only the
+/// request id's letters, digits, `-` and `_`.
+fn file_name(headers: &HeaderMap) -> String {
+    let request_id = headers
+        .get("x-request-id")
+        .and_then(|value| value.to_str().ok())
+        .map(|id| {
+            id.chars()
+                .filter(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_'))
+                .collect::<String>()
+        })
+        .filter(|id| !id.is_empty())
+        .unwrap_or_else(|| NEXT_REQUEST.fetch_add(1, Ordering::Relaxed).to_string());
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    format!("{request_id}-{millis}.body")
+}
+
+/// What a `TeeBody` sends the task writing its file.
+enum TeeMessage {
+    Data(Bytes),
+    /// There's no more data coming. `partial` if that's because the body was
+    /// dropped before its end.
+    End {
+        len: u64,
+        truncated: bool,
+        partial: bool,
+    },
+}
+
+/// Write the data from `messages` to a new file at `path`, giving up on the
+/// first error.
+async fn write_tee_file(path: PathBuf, mut messages: mpsc::UnboundedReceiver<TeeMessage>) {
+    let mut file = match File::create(&path).await {
+        Ok(file) => file,
+        Err(err) => {
+            tracing::warn!(%err, path = %path.display(), "failed to create the tee file");
+            return;
+        }
+    };
+    loop {
+        let result = match messages.recv().await {
+            Some(TeeMessage::Data(data)) => file.write_all(&data).await,
+            Some(TeeMessage::End {
+                len,
+                truncated,
+                partial,
+            }) => {
+                if let Err(err) = file.flush().await {
+                    tracing::warn!(%err, path = %path.display(), "failed to write the tee file");
+                    return;
+                }
+                tracing::debug!(
+                    path = %path.display(),
+                    len,
+                    truncated,
+                    partial,
+                    "teed the request body",
+                );
+                return;
+            }
+            // The body always says when it's done, so only if it panicked.
+            None => return,
+        };
+        if let Err(err) = result {
+            tracing::warn!(%err, path = %path.display(), "failed to write the tee file");
+            return;
+        }
+    }
+}
+
+/// Passes every frame of `inner` through unchanged, sending the data up to
+/// `max_bytes` to a task that writes it to the file, so a slow disk doesn't
+/// hold up the runtime.
+///
+/// Bodies dropped before their end, like when the handler turns the request
+/// away without reading it all, are logged as partial.
+struct TeeBody {
+    inner: Body,
+    path: PathBuf,
+    max_bytes: u64,
+    /// To the task writing the file, started by the first data. The channel
+    /// is unbounded, but never carries more than `max_bytes`.
+    writer: Option<mpsc::UnboundedSender<TeeMessage>>,
+    /// All of the body's data so far, written or not.
+    len: u64,
+    truncated: bool,
+    done: bool,
+}
+
+impl TeeBody {
+    fn write(&mut self, data: &Bytes) {
+        let room = self.max_bytes.saturating_sub(self.len);
+        self.len += data.len() as u64;
+        if (data.len() as u64) > room {
+            self.truncated = true;
+        }
+        let data = data.slice(..data.len().min(room as usize));
+        if data.is_empty() {
+            return;
+        }
+
+        let writer = self.writer.get_or_insert_with(|| {
+            let (writer, messages) = mpsc::unbounded_channel();
+            tokio::spawn(write_tee_file(self.path.clone(), messages));
+            writer
+        });
+        // Only fails once the task has given up, which it's logged.
+        let _ = writer.send(TeeMessage::Data(data));
+    }
+
+    fn finish(&mut self, partial: bool) {
+        if self.done {
+            return;
+        }
+        self.done = true;
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.send(TeeMessage::End {
+                len: self.len,
+                truncated: self.truncated,
+                partial,
+            });
+        }
+    }
+}
+
+impl HttpBody for TeeBody {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.get_mut();
+        let frame = ready!(Pin::new(&mut this.inner).poll_frame(cx));
+        match &frame {
+            Some(Ok(frame)) => {
+                if let Some(data) = frame.data_ref() {
+                    this.write(data);
+                }
+                if this.inner.is_end_stream() {
+                    this.finish(false);
+                }
+            }
+            // Left to `drop` to finish as partial.
+            Some(Err(err)) => {
+                tracing::warn!(%err, path = %this.path.display(), "request body failed while teeing");
+            }
+            None => this.finish(false),
+        }
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl Drop for TeeBody {
+    fn drop(&mut self) {
+        self.finish(true);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{convert::Infallible, fs, io, path::Path};
+
+    use axum::{
+        http::{header, StatusCode},
+        middleware,
+        routing::post,
+        Router,
+    };
+    use futures::stream;
+    use http_body_util::{BodyExt, StreamBody};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{print_request_body, test_support::Logs, AppState, BufferRequestBody};
+
+    /// Answers `/echo` with the body it got, teed to `dir`.
+    fn teeing(dir: &Path, max_bytes: u64) -> Router {
+        let state = AppState {
+            tee: Some(TeeConfig {
+                dir: dir.to_owned(),
+                max_bytes,
+            }),
+            ..AppState::default()
+        };
+        Router::new()
+            .route(
+                "/echo",
+                post(|BufferRequestBody(body): BufferRequestBody| async move { body }),
+            )
+            .layer(middleware::from_fn_with_state(
+                state.clone(),
+                print_request_body,
+            ))
+            .with_state(state)
+    }
+
+    /// Ten chunks of 100 bytes, each a different letter.
+    fn chunks() -> Vec<Bytes> {
+        (b'a'..b'a' + 10)
+            .map(|c| Bytes::from(vec![c; 100]))
+            .collect()
+    }
+
+    /// What the handler got back from `/echo`.
+    async fn echo(app: Router, content_type: &str, request_id: &str) -> Bytes {
+        let chunks = chunks().into_iter().map(Ok::<_, Infallible>);
+        let request = Request::post("/echo")
+            .header(header::CONTENT_TYPE, content_type)
+            .header("x-request-id", request_id)
+            .body(Body::from_stream(stream::iter(chunks)))
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        response.into_body().collect().await.unwrap().to_bytes()
+    }
+
+    fn teed(dir: &tempfile::TempDir) -> (String, Vec<u8>) {
+        let mut files = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect::<Vec<_>>();
+        assert_eq!(files.len(), 1, "{files:?}");
+        let path = files.pop().unwrap();
+        let name = path.file_name().unwrap().to_str().unwrap().to_owned();
+        (name, fs::read(path).unwrap())
+    }
+
+    #[tokio::test]
+    async fn streamed_bodies_are_teed() {
+        let (logs, _guard) = Logs::capture();
+        let dir = tempfile::tempdir().unwrap();
+        let received = echo(
+            teeing(dir.path(), 1 << 20),
+            "application/octet-stream",
+            "abc/123",
+        )
+        .await;
+        assert_eq!(received, chunks().concat());
+
+        let logs = logs.wait_for("teed the request body").await;
+        let (name, contents) = teed(&dir);
+        assert!(name.starts_with("abc123-"), "{name}");
+        assert!(name.ends_with(".body"), "{name}");
+        assert_eq!(contents, received);
+
+        assert!(logs.contains(&name), "{logs}");
+        assert!(
+            logs.contains("len=1000 truncated=false partial=false"),
+            "{logs}"
+        );
+    }
+
+    #[tokio::test]
+    async fn buffered_bodies_are_teed() {
+        let (logs, _guard) = Logs::capture();
+        let dir = tempfile::tempdir().unwrap();
+        let received = echo(teeing(dir.path(), 1 << 20), "application/json", "abc").await;
+        assert_eq!(received, chunks().concat());
+        logs.wait_for("teed the request body").await;
+        assert_eq!(teed(&dir).1, received);
+    }
+
+    #[tokio::test]
+    async fn long_bodies_are_cut_short_in_the_file_only() {
+        let (logs, _guard) = Logs::capture();
+        let dir = tempfile::tempdir().unwrap();
+        let received = echo(teeing(dir.path(), 250), "application/octet-stream", "abc").await;
+        assert_eq!(received, chunks().concat());
+
+        let logs = logs.wait_for("teed the request body").await;
+        assert_eq!(teed(&dir).1, received[..250]);
+        assert!(
+            logs.contains("len=1000 truncated=true partial=false"),
+            "{logs}"
+        );
+    }
+
+    #[tokio::test]
+    async fn nothing_is_teed_without_data() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = teeing(dir.path(), 1 << 20);
+
+        let empty = Request::post("/echo")
+            .header(header::CONTENT_TYPE, "application/octet-stream")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(empty).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let too_large = Request::post("/echo")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(header::CONTENT_LENGTH, 2 << 20)
+            .body(Body::from_stream(stream::iter(
+                chunks().into_iter().map(Ok::<_, Infallible>),
+            )))
+            .unwrap();
+        let response = app.oneshot(too_large).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    fn tee(body: Body, dir: &tempfile::TempDir) -> Body {
+        let config = TeeConfig {
+            dir: dir.path().to_owned(),
+            max_bytes: 1 << 20,
+        };
+        tee_request_body(Request::new(body), &config).into_body()
+    }
+
+    #[tokio::test]
+    async fn trailers_pass_through() {
+        let (logs, _guard) = Logs::capture();
+        let dir = tempfile::tempdir().unwrap();
+        let mut trailers = HeaderMap::new();
+        trailers.insert("x-checksum", "abc".parse().unwrap());
+        let frames = [
+            Ok::<_, Infallible>(Frame::data(Bytes::from("hello"))),
+            Ok(Frame::trailers(trailers.clone())),
+        ];
+        let body = tee(Body::new(StreamBody::new(stream::iter(frames))), &dir);
+
+        let collected = body.collect().await.unwrap();
+        assert_eq!(collected.trailers(), Some(&trailers));
+        assert_eq!(collected.to_bytes(), "hello");
+        logs.wait_for("teed the request body").await;
+        assert_eq!(teed(&dir).1, b"hello");
+    }
+
+    #[tokio::test]
+    async fn errors_pass_through() {
+        let (logs, _guard) = Logs::capture();
+        let dir = tempfile::tempdir().unwrap();
+        let chunks = [
+            Ok(Bytes::from("hello")),
+            Err(io::Error::new(io::ErrorKind::ConnectionReset, "reset")),
+        ];
+        let mut body = tee(Body::from_stream(stream::iter(chunks)), &dir);
+
+        let frame = body.frame().await.unwrap().unwrap();
+        assert_eq!(frame.into_data().unwrap(), "hello");
+        let err = body.frame().await.unwrap().unwrap_err();
+        assert!(err.to_string().contains("reset"), "{err}");
+        drop(body);
+
+        let logs = logs.wait_for("teed the request body").await;
+        assert!(
+            logs.contains("len=5 truncated=false partial=true"),
+            "{logs}"
+        );
+        assert_eq!(teed(&dir).1, b"hello");
+    }
+
+    #[tokio::test]
+    async fn bodies_left_unread_are_partial() {
+        let (logs, _guard) = Logs::capture();
+        let dir = tempfile::tempdir().unwrap();
+        let frames = chunks().into_iter().map(Ok::<_, Infallible>);
+        let mut body = tee(Body::from_stream(stream::iter(frames)), &dir);
+
+        body.frame().await.unwrap().unwrap();
+        drop(body);
+
+        let logs = logs.wait_for("teed the request body").await;
+        assert!(
+            logs.contains("len=100 truncated=false partial=true"),
+            "{logs}"
+        );
+        assert_eq!(teed(&dir).1, chunks()[0]);
+    }
+
+    #[tokio::test]
+    async fn bodies_are_left_be_without_a_directory() {
+        let (logs, _guard) = Logs::capture();
+        let app = teeing(Path::new("/nonexistent/tee"), 1 << 20);
+        let received = echo(app, "application/octet-stream", "abc").await;
+        assert_eq!(received, chunks().concat());
+        logs.wait_for("failed to create the tee file").await;
+    }
+}
@@ -0,0 +1,187 @@
+use std::{fmt, sync::Arc};
+
+use axum::{
+    async_trait,
+    extract::{FromRef, FromRequestParts},
+    http::{request::Parts, StatusCode},
+    response::{IntoResponse, Response},
+};
+use diesel_async::{pooled_connection::AsyncDieselConnectionManager, AsyncPgConnection};
+use tokio::time::Instant;
+
+use crate::metrics::Metrics;
+
+pub type Pool<M = AsyncDieselConnectionManager<AsyncPgConnection>> = bb8::Pool<M>;
+
+/// Extractor that checks a connection out of the [`Pool`] in the router state.
+///
+/// The connection manager defaults to the diesel-async Postgres one but can be
+/// swapped out, which is what the tests below do.
+pub struct DatabaseConnection<M = AsyncDieselConnectionManager<AsyncPgConnection>>(
+    pub bb8::PooledConnection<'static, M>,
+)
+where
+    M: bb8::ManageConnection;
+
+#[async_trait]
+impl<S, M> FromRequestParts<S> for DatabaseConnection<M>
+where
+    S: Send + Sync,
+    M: bb8::ManageConnection,
+    M::Error: fmt::Display,
+    Pool<M>: FromRef<S>,
+    Arc<Metrics>: FromRef<S>,
+{
+    type Rejection = DbRejection;
+
+    async fn from_request_parts(_parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let pool = Pool::<M>::from_ref(state);
+        let metrics = Arc::<Metrics>::from_ref(state);
+
+        let start = Instant::now();
+        let res = pool.get_owned().await;
+        metrics.record_checkout(start.elapsed(), &res);
+
+        let conn = res.map_err(DbRejection::from)?;
+
+        Ok(Self(conn))
+    }
+}
+
+/// Rejection used for [`DatabaseConnection`].
+#[derive(Debug)]
+pub enum DbRejection {
+    /// No connection became available within the pool's `connection_timeout`.
+    PoolTimeout,
+    /// The pool failed to build a new connection.
+    Build(String),
+}
+
+impl<E> From<bb8::RunError<E>> for DbRejection
+where
+    E: fmt::Display,
+{
+    fn from(err: bb8::RunError<E>) -> Self {
+        match err {
+            bb8::RunError::TimedOut => Self::PoolTimeout,
+            bb8::RunError::User(err) => Self::Build(err.to_string()),
+        }
+    }
+}
+
+impl IntoResponse for DbRejection {
+    fn into_response(self) -> Response {
+        match self {
+            Self::PoolTimeout => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "timed out waiting for a database connection",
+            )
+                .into_response(),
+            Self::Build(err) => (StatusCode::INTERNAL_SERVER_ERROR, err).into_response(),
+        }
+    }
+}
+
+pub fn internal_error<E>(err: E) -> (StatusCode, String)
+where
+    E: std::error::Error,
+{
+    (StatusCode::INTERNAL_SERVER_ERROR, err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{convert::Infallible, time::Duration};
+
+    use axum::{body::Body, http::Request, routing::get, Router};
+    use tower::ServiceExt;
+
+    use super::*;
+
+    /// Hands out unit "connections" so the pool can be exercised without a
+    /// database.
+    struct StubManager;
+
+    #[async_trait]
+    impl bb8::ManageConnection for StubManager {
+        type Connection = ();
+        type Error = Infallible;
+
+        async fn connect(&self) -> Result<Self::Connection, Self::Error> {
+            Ok(())
+        }
+
+        async fn is_valid(&self, _conn: &mut Self::Connection) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn has_broken(&self, _conn: &mut Self::Connection) -> bool {
+            false
+        }
+    }
+
+    #[derive(Clone)]
+    struct TestState {
+        pool: Pool<StubManager>,
+        metrics: Arc<Metrics>,
+    }
+
+    impl FromRef<TestState> for Pool<StubManager> {
+        fn from_ref(state: &TestState) -> Self {
+            state.pool.clone()
+        }
+    }
+
+    impl FromRef<TestState> for Arc<Metrics> {
+        fn from_ref(state: &TestState) -> Self {
+            state.metrics.clone()
+        }
+    }
+
+    const HOLD: Duration = Duration::from_millis(200);
+
+    async fn hold_connection(_conn: DatabaseConnection<StubManager>) {
+        tokio::time::sleep(HOLD).await;
+    }
+
+    fn app(connection_timeout: Duration) -> Router {
+        let pool = bb8::Pool::builder()
+            .max_size(1)
+            .connection_timeout(connection_timeout)
+            .build_unchecked(StubManager);
+
+        Router::new()
+            .route("/", get(hold_connection))
+            .with_state(TestState {
+                pool,
+                metrics: Arc::default(),
+            })
+    }
+
+    async fn send_two_concurrent_requests(app: Router) -> (StatusCode, StatusCode) {
+        let request = || Request::get("/").body(Body::empty()).unwrap();
+        let (first, second) = tokio::join!(
+            app.clone().oneshot(request()),
+            app.clone().oneshot(request()),
+        );
+        (first.unwrap().status(), second.unwrap().status())
+    }
+
+    #[tokio::test]
+    async fn second_request_waits_for_the_connection() {
+        let start = Instant::now();
+        let statuses = send_two_concurrent_requests(app(Duration::from_secs(5))).await;
+
+        assert_eq!(statuses, (StatusCode::OK, StatusCode::OK));
+        assert!(start.elapsed() >= HOLD * 2);
+    }
+
+    #[tokio::test]
+    async fn second_request_times_out_waiting_for_the_connection() {
+        let (first, second) = send_two_concurrent_requests(app(Duration::from_millis(50))).await;
+
+        let mut statuses = [first, second];
+        statuses.sort();
+        assert_eq!(statuses, [StatusCode::OK, StatusCode::SERVICE_UNAVAILABLE]);
+    }
+}
@@ -1,19 +1,28 @@
 use axum::{
-    async_trait,
-    extract::{FromRef, FromRequestParts, State},
-    http::{request::Parts, StatusCode},
+    extract::{FromRef, State},
+    http::StatusCode,
     response::Json,
     routing::{get, post},
     Router,
 };
 use diesel::prelude::*;
-use diesel_async::{
-    pooled_connection::AsyncDieselConnectionManager, AsyncPgConnection, RunQueryDsl,
-};
+use diesel_async::RunQueryDsl;
+use rustls::ClientConfig;
 use serde::{Deserialize, Serialize};
-use std::net::SocketAddr;
+use std::{fmt, net::SocketAddr, sync::Arc, time::Duration};
+use tokio::time::Instant;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
+use crate::{
+    db::{internal_error, DatabaseConnection, Pool},
+    metrics::{CheckoutStats, Metrics},
+    tls::{connection_manager, tls_config_from_env},
+};
+
+mod db;
+mod metrics;
+mod tls;
+
 table! {
     users (id) {
         id -> Integer,
@@ -36,32 +45,211 @@ struct NewUser {
     hair_color: Option<String>,
 }
 
-type Pool = bb8::Pool<AsyncDieselConnectionManager<AsyncPgConnection>>;
+/// How long to keep retrying the initial database connection when
+/// `DB_CONNECT_TIMEOUT_SECS` isn't set.
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(60);
 
 #[tokio::main]
 async fn main() {
     tracing_subscriber::registry()
         .with(
             tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| "example_diesel_async_postgres=debug".into()),
+                .unwrap_or_else(|_| format!("{}=debug", env!("CARGO_CRATE_NAME")).into()),
         )
         .with(tracing_subscriber::fmt::layer())
         .init();
 
     let db_url = std::env::var("DATABASE_URL").unwrap();
 
-    let config = AsyncDieselConnectionManager::<AsyncPgConnection>::new(db_url);
-    let pool = bb8::Pool::builder().build(config).await.unwrap();
+    let tls = match tls_config_from_env() {
+        Ok(tls) => tls,
+        Err(err) => {
+            tracing::error!("{err}");
+            std::process::exit(1);
+        }
+    };
 
-    let app = Router::new()
-        .route("/user/list", get(list_users))
-        .route("/user/create", post(create_user))
-        .with_state(pool);
+    let deadline = std::env::var("DB_CONNECT_TIMEOUT_SECS")
+        .ok()
+        .and_then(|secs| secs.parse().ok())
+        .map(Duration::from_secs)
+        .unwrap_or(DEFAULT_CONNECT_TIMEOUT);
+
+    let pool = match connect_with_retry(&db_url, tls.as_ref(), deadline).await {
+        Ok(pool) => pool,
+        Err(err) => {
+            tracing::error!("{err}");
+            std::process::exit(1);
+        }
+    };
 
     let addr = SocketAddr::from(([127, 0, 0, 1], 3000));
     tracing::debug!("listening on {addr}");
     let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+    axum::serve(listener, app(pool)).await.unwrap();
+}
+
+fn app(pool: Pool) -> Router {
+    let state = AppState {
+        pool,
+        metrics: Arc::default(),
+    };
+
+    Router::new()
+        .route("/user/list", get(list_users))
+        .route("/user/create", post(create_user))
+        .route("/debug/pool", get(pool_stats))
+        .with_state(state)
+}
+
+#[derive(Clone)]
+struct AppState {
+    pool: Pool,
+    metrics: Arc<Metrics>,
+}
+
+impl FromRef<AppState> for Pool {
+    fn from_ref(state: &AppState) -> Self {
+        state.pool.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<Metrics> {
+    fn from_ref(state: &AppState) -> Self {
+        state.metrics.clone()
+    }
+}
+
+#[derive(Serialize)]
+struct PoolStats {
+    connections: u32,
+    idle_connections: u32,
+    #[serde(flatten)]
+    checkouts: CheckoutStats,
+}
+
+async fn pool_stats(
+    State(pool): State<Pool>,
+    State(metrics): State<Arc<Metrics>>,
+) -> Json<PoolStats> {
+    let state = pool.state();
+
+    Json(PoolStats {
+        connections: state.connections,
+        idle_connections: state.idle_connections,
+        checkouts: metrics.snapshot(),
+    })
+}
+
+/// Error returned by [`connect_with_retry`] once the deadline has passed.
+#[derive(Debug)]
+struct ConnectError {
+    attempts: u32,
+    elapsed: Duration,
+    last_error: String,
+}
+
+impl fmt::Display for ConnectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "could not connect to the database after {} attempts in {:?}: {}",
+            self.attempts, self.elapsed, self.last_error
+        )
+    }
+}
+
+impl std::error::Error for ConnectError {}
+
+/// Delays between connection attempts, doubling from `initial` up to `max`.
+#[derive(Debug, Clone, Copy)]
+struct Backoff {
+    initial: Duration,
+    max: Duration,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_secs(1),
+            max: Duration::from_secs(30),
+        }
+    }
+}
+
+impl Backoff {
+    fn delay(&self, attempt: u32) -> Duration {
+        self.initial
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max)
+    }
+}
+
+/// Build the pool and check that the database answers, retrying with
+/// exponential backoff until `deadline` has elapsed.
+///
+/// Useful when the database is started alongside the app (e.g. with
+/// docker-compose) and may not be accepting connections yet.
+async fn connect_with_retry(
+    db_url: &str,
+    tls: Option<&ClientConfig>,
+    deadline: Duration,
+) -> Result<Pool, ConnectError> {
+    connect_with_backoff(db_url, tls, deadline, Backoff::default()).await
+}
+
+async fn connect_with_backoff(
+    db_url: &str,
+    tls: Option<&ClientConfig>,
+    deadline: Duration,
+    backoff: Backoff,
+) -> Result<Pool, ConnectError> {
+    let start = Instant::now();
+    let mut attempts = 0;
+
+    loop {
+        attempts += 1;
+
+        let last_error = match try_connect(db_url, tls).await {
+            Ok(pool) => return Ok(pool),
+            Err(err) => err,
+        };
+
+        let elapsed = start.elapsed();
+        let Some(remaining) = deadline.checked_sub(elapsed).filter(|d| !d.is_zero()) else {
+            return Err(ConnectError {
+                attempts,
+                elapsed,
+                last_error,
+            });
+        };
+
+        let delay = backoff.delay(attempts).min(remaining);
+        tracing::warn!(attempts, ?delay, "database not ready: {last_error}");
+        tokio::time::sleep(delay).await;
+    }
+}
+
+async fn try_connect(db_url: &str, tls: Option<&ClientConfig>) -> Result<Pool, String> {
+    let manager = connection_manager(db_url, tls.cloned());
+    let pool = bb8::Pool::builder()
+        .build(manager)
+        .await
+        .map_err(|err| err.to_string())?;
+
+    // `build` doesn't open any connections by default, so open one through the
+    // pool's manager and make sure the server actually answers queries. Unlike
+    // `Pool::get` this surfaces the underlying error instead of a timeout.
+    let mut conn = pool
+        .dedicated_connection()
+        .await
+        .map_err(|err| err.to_string())?;
+    diesel::sql_query("SELECT 1")
+        .execute(&mut conn)
+        .await
+        .map_err(|err| err.to_string())?;
+
+    Ok(pool)
 }
 
 async fn create_user(
@@ -80,27 +268,6 @@ async fn create_user(
     Ok(Json(res))
 }
 
-struct DatabaseConnection(
-    bb8::PooledConnection<'static, AsyncDieselConnectionManager<AsyncPgConnection>>,
-);
-
-#[async_trait]
-impl<S> FromRequestParts<S> for DatabaseConnection
-where
-    S: Send + Sync,
-    Pool: FromRef<S>,
-{
-    type Rejection = (StatusCode, String);
-
-    async fn from_request_parts(_parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
-        let pool = Pool::from_ref(state);
-
-        let conn = pool.get_owned().await.map_err(internal_error)?;
-
-        Ok(Self(conn))
-    }
-}
-
 async fn list_users(
     DatabaseConnection(mut conn): DatabaseConnection,
 ) -> Result<Json<Vec<User>>, (StatusCode, String)> {
@@ -112,9 +279,93 @@ async fn list_users(
     Ok(Json(res))
 }
 
-fn internal_error<E>(err: E) -> (StatusCode, String)
-where
-    E: std::error::Error,
-{
-    (StatusCode::INTERNAL_SERVER_ERROR, err.to_string())
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use axum::{body::Body, http::Request};
+    use http_body_util::BodyExt;
+    use serde_json::Value;
+    use tokio::net::TcpListener;
+    use tower::ServiceExt;
+
+    use super::*;
+
+    /// A database URL pointing at a port nothing is listening on.
+    async fn closed_port_url() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+        format!("postgres://postgres:postgres@{addr}/postgres")
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        let backoff = Backoff::default();
+        let delays: Vec<_> = (1..=7)
+            .map(|attempt| backoff.delay(attempt).as_secs())
+            .collect();
+        assert_eq!(delays, [1, 2, 4, 8, 16, 30, 30]);
+    }
+
+    #[tokio::test]
+    async fn retries_until_deadline() {
+        let db_url = closed_port_url().await;
+        let backoff = Backoff {
+            initial: Duration::from_millis(10),
+            max: Duration::from_millis(40),
+        };
+
+        let err = connect_with_backoff(&db_url, None, Duration::from_millis(300), backoff)
+            .await
+            .unwrap_err();
+
+        assert!(err.attempts > 1, "only {} attempts", err.attempts);
+        assert!(err.elapsed >= Duration::from_millis(300));
+        assert!(err.to_string().contains("could not connect"));
+    }
+
+    #[tokio::test]
+    async fn gives_up_quickly_with_tiny_deadline() {
+        let db_url = closed_port_url().await;
+
+        let start = std::time::Instant::now();
+        let err = connect_with_retry(&db_url, None, Duration::from_millis(50))
+            .await
+            .unwrap_err();
+
+        assert!(err.attempts >= 1);
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn pool_stats_track_checkout_timeouts() {
+        let manager = connection_manager(&closed_port_url().await, None);
+        let pool = bb8::Pool::builder()
+            .connection_timeout(Duration::from_millis(50))
+            .build_unchecked(manager);
+        let app = app(pool);
+
+        for _ in 0..3 {
+            let response = app
+                .clone()
+                .oneshot(Request::get("/user/list").body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        }
+
+        let response = app
+            .oneshot(Request::get("/debug/pool").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let stats: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(stats["connections"], 0);
+        assert_eq!(stats["checkouts"], 0);
+        assert_eq!(stats["checkout_timeouts"], 3);
+        assert!(stats["max_wait_us"].as_u64().unwrap() >= 50_000);
+    }
 }
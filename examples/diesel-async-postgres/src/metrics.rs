@@ -0,0 +1,65 @@
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use serde::Serialize;
+
+/// Counters for connections checked out through `DatabaseConnection`.
+#[derive(Default)]
+pub struct Metrics {
+    checkouts: AtomicU64,
+    checkout_timeouts: AtomicU64,
+    max_wait_us: AtomicU64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CheckoutStats {
+    pub checkouts: u64,
+    pub checkout_timeouts: u64,
+    pub max_wait_us: u64,
+}
+
+impl Metrics {
+    /// Record one attempt to check a connection out of the pool.
+    pub fn record_checkout<T, E>(&self, wait: Duration, result: &Result<T, bb8::RunError<E>>) {
+        let wait = u64::try_from(wait.as_micros()).unwrap_or(u64::MAX);
+        self.max_wait_us.fetch_max(wait, Ordering::Relaxed);
+
+        match result {
+            Ok(_) => {
+                self.checkouts.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(bb8::RunError::TimedOut) => {
+                self.checkout_timeouts.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(bb8::RunError::User(_)) => {}
+        }
+    }
+
+    pub fn snapshot(&self) -> CheckoutStats {
+        CheckoutStats {
+            checkouts: self.checkouts.load(Ordering::Relaxed),
+            checkout_timeouts: self.checkout_timeouts.load(Ordering::Relaxed),
+            max_wait_us: self.max_wait_us.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_checkouts_and_keeps_the_longest_wait() {
+        let metrics = Metrics::default();
+        metrics.record_checkout::<_, ()>(Duration::from_millis(5), &Ok(()));
+        metrics.record_checkout::<(), ()>(Duration::from_millis(20), &Err(bb8::RunError::TimedOut));
+        metrics.record_checkout::<_, ()>(Duration::from_millis(10), &Ok(()));
+
+        let stats = metrics.snapshot();
+        assert_eq!(stats.checkouts, 2);
+        assert_eq!(stats.checkout_timeouts, 1);
+        assert_eq!(stats.max_wait_us, 20_000);
+    }
+}
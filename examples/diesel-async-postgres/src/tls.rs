@@ -0,0 +1,204 @@
+use std::{
+    fmt, fs,
+    io::{self, BufReader},
+    path::{Path, PathBuf},
+};
+
+use diesel::{ConnectionError, ConnectionResult};
+use diesel_async::{
+    pooled_connection::{AsyncDieselConnectionManager, ManagerConfig},
+    AsyncPgConnection,
+};
+use futures_util::FutureExt;
+use rustls::{Certificate, ClientConfig, RootCertStore};
+
+/// Read the TLS settings for the database connection from the environment.
+///
+/// TLS is only used when `DATABASE_SSL=require`. The server certificate is
+/// verified against the PEM encoded CA certificate in `DATABASE_CA_CERT`, or
+/// the system roots if that isn't set.
+pub fn tls_config_from_env() -> Result<Option<ClientConfig>, TlsError> {
+    match std::env::var("DATABASE_SSL").as_deref() {
+        Ok("require") => {}
+        Ok("disable") | Err(_) => return Ok(None),
+        Ok(mode) => return Err(TlsError::UnsupportedSslMode(mode.to_owned())),
+    }
+
+    let ca_cert = std::env::var_os("DATABASE_CA_CERT").map(PathBuf::from);
+    client_config(ca_cert.as_deref()).map(Some)
+}
+
+pub fn client_config(ca_cert: Option<&Path>) -> Result<ClientConfig, TlsError> {
+    let roots = match ca_cert {
+        Some(path) => load_ca_cert(path)?,
+        None => load_system_roots()?,
+    };
+
+    Ok(ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+        .with_no_client_auth())
+}
+
+fn load_ca_cert(path: &Path) -> Result<RootCertStore, TlsError> {
+    let read_error = |source| TlsError::ReadCaCert {
+        path: path.to_owned(),
+        source,
+    };
+
+    let file = fs::File::open(path).map_err(read_error)?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file)).map_err(read_error)?;
+    if certs.is_empty() {
+        return Err(TlsError::NoCertificates(path.to_owned()));
+    }
+
+    let mut roots = RootCertStore::empty();
+    for cert in certs {
+        roots
+            .add(&Certificate(cert))
+            .map_err(|err| TlsError::InvalidCaCert {
+                path: path.to_owned(),
+                source: err,
+            })?;
+    }
+    Ok(roots)
+}
+
+fn load_system_roots() -> Result<RootCertStore, TlsError> {
+    let certs = rustls_native_certs::load_native_certs().map_err(TlsError::SystemRoots)?;
+
+    let mut roots = RootCertStore::empty();
+    let certs: Vec<_> = certs.into_iter().map(|cert| cert.0).collect();
+    roots.add_parsable_certificates(&certs);
+    Ok(roots)
+}
+
+/// Build a connection manager that connects over TLS when `tls` is set and in
+/// plaintext otherwise.
+pub fn connection_manager(
+    db_url: &str,
+    tls: Option<ClientConfig>,
+) -> AsyncDieselConnectionManager<AsyncPgConnection> {
+    let Some(tls) = tls else {
+        return AsyncDieselConnectionManager::new(db_url);
+    };
+
+    let mut config = ManagerConfig::default();
+    config.custom_setup = Box::new(move |url| establish_tls_connection(url, tls.clone()).boxed());
+    AsyncDieselConnectionManager::new_with_config(db_url, config)
+}
+
+async fn establish_tls_connection(
+    url: &str,
+    tls: ClientConfig,
+) -> ConnectionResult<AsyncPgConnection> {
+    let tls = tokio_postgres_rustls::MakeRustlsConnect::new(tls);
+    let (client, conn) = tokio_postgres::connect(url, tls)
+        .await
+        .map_err(|err| ConnectionError::BadConnection(err.to_string()))?;
+
+    tokio::spawn(async move {
+        if let Err(err) = conn.await {
+            tracing::error!("database connection error: {err}");
+        }
+    });
+
+    AsyncPgConnection::try_from(client).await
+}
+
+#[derive(Debug)]
+pub enum TlsError {
+    UnsupportedSslMode(String),
+    ReadCaCert {
+        path: PathBuf,
+        source: io::Error,
+    },
+    NoCertificates(PathBuf),
+    InvalidCaCert {
+        path: PathBuf,
+        source: rustls::Error,
+    },
+    SystemRoots(io::Error),
+}
+
+impl fmt::Display for TlsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnsupportedSslMode(mode) => write!(
+                f,
+                "unsupported DATABASE_SSL value `{mode}`, expected `require` or `disable`"
+            ),
+            Self::ReadCaCert { path, source } => {
+                write!(
+                    f,
+                    "failed to read CA certificate {}: {source}",
+                    path.display()
+                )
+            }
+            Self::NoCertificates(path) => {
+                write!(f, "no PEM certificates found in {}", path.display())
+            }
+            Self::InvalidCaCert { path, source } => {
+                write!(f, "invalid CA certificate in {}: {source}", path.display())
+            }
+            Self::SystemRoots(err) => write!(f, "failed to load system root certificates: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for TlsError {}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn temp_file(name: &str, contents: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "diesel-async-postgres-{}-{name}",
+            std::process::id()
+        ));
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn loads_pem_ca_cert() {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("../low-level-rustls/self_signed_certs/cert.pem");
+
+        assert!(client_config(Some(&path)).is_ok());
+    }
+
+    #[test]
+    fn missing_ca_cert_is_an_error() {
+        let err = client_config(Some(Path::new("/does/not/exist.pem"))).unwrap_err();
+
+        assert!(matches!(err, TlsError::ReadCaCert { .. }), "{err:?}");
+        assert!(err.to_string().contains("/does/not/exist.pem"));
+    }
+
+    #[test]
+    fn file_without_certificates_is_an_error() {
+        let path = temp_file("empty.pem", "not a certificate\n");
+
+        let err = client_config(Some(&path)).unwrap_err();
+        fs::remove_file(&path).unwrap();
+
+        assert!(matches!(err, TlsError::NoCertificates(_)), "{err:?}");
+    }
+
+    #[test]
+    fn garbage_certificate_is_an_error() {
+        let path = temp_file(
+            "garbage.pem",
+            "-----BEGIN CERTIFICATE-----\nZ2FyYmFnZQ==\n-----END CERTIFICATE-----\n",
+        );
+
+        let err = client_config(Some(&path)).unwrap_err();
+        fs::remove_file(&path).unwrap();
+
+        assert!(matches!(err, TlsError::InvalidCaCert { .. }), "{err:?}");
+    }
+}
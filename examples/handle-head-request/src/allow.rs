@@ -0,0 +1,76 @@
+//! `Allow` headers derived from the methods a route actually registers.
+//!
+//! [`AllowedRoute`] records each method as it is added to the route, so
+//! the `Allow` list sent with OPTIONS and 405 responses cannot drift from
+//! what the router serves.
+
+use axum::handler::Handler;
+use axum::http::header::ALLOW;
+use axum::http::{HeaderValue, Method, StatusCode};
+use axum::response::IntoResponse;
+use axum::routing::{self, MethodRouter};
+use axum::Extension;
+
+/// Methods registered for one path, in registration order.
+#[derive(Clone, Debug, Default)]
+pub struct MethodSet(Vec<Method>);
+
+impl MethodSet {
+    fn insert(&mut self, method: Method) {
+        if !self.0.contains(&method) {
+            self.0.push(method);
+        }
+    }
+
+    /// The set as an `Allow` header value, e.g. `GET, HEAD, OPTIONS`.
+    pub fn allow(&self) -> HeaderValue {
+        let methods: Vec<_> = self.0.iter().map(Method::as_str).collect();
+        HeaderValue::from_str(&methods.join(", ")).unwrap()
+    }
+}
+
+/// A [`MethodRouter`] that keeps track of its methods in a [`MethodSet`].
+pub struct AllowedRoute {
+    router: MethodRouter,
+    methods: MethodSet,
+}
+
+impl AllowedRoute {
+    /// Routes GET, and therefore HEAD, to `handler`.
+    pub fn get<H, T>(handler: H) -> Self
+    where
+        H: Handler<T, ()>,
+        T: 'static,
+    {
+        let mut methods = MethodSet::default();
+        methods.insert(Method::GET);
+        methods.insert(Method::HEAD);
+        Self {
+            router: routing::get(handler),
+            methods,
+        }
+    }
+
+    /// Answers OPTIONS with `204 No Content` and the `Allow` header.
+    pub fn options(mut self) -> Self {
+        self.methods.insert(Method::OPTIONS);
+        self.router = self.router.options(options);
+        self
+    }
+
+    /// Finishes the route: other methods get `405` with the same `Allow`
+    /// header, and handlers can read the set through an [`Extension`].
+    pub fn into_method_router(self) -> MethodRouter {
+        self.router
+            .fallback(method_not_allowed)
+            .layer(Extension(self.methods))
+    }
+}
+
+async fn options(Extension(methods): Extension<MethodSet>) -> impl IntoResponse {
+    (StatusCode::NO_CONTENT, [(ALLOW, methods.allow())])
+}
+
+async fn method_not_allowed(Extension(methods): Extension<MethodSet>) -> impl IntoResponse {
+    (StatusCode::METHOD_NOT_ALLOWED, [(ALLOW, methods.allow())])
+}
@@ -0,0 +1,114 @@
+//! Answer HEAD requests for routes that only know about GET.
+//!
+//! [`HeadAutoLayer`] runs HEAD requests as GET and drops the body of the
+//! response, so handlers never have to special-case HEAD. Routes wrapped
+//! with [`handles_head`] opt out and see the original HEAD request.
+
+use std::convert::Infallible;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use axum::body::{Body, Bytes, HttpBody};
+use axum::extract::Request;
+use axum::http::{header, Method};
+use axum::middleware::{self, Next};
+use axum::response::Response;
+use axum::routing::{any_service, MethodRouter};
+use futures::stream;
+use tower::{Layer, Service};
+
+/// Rewrites HEAD requests to GET and strips the body from the response.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct HeadAutoLayer;
+
+impl<S> Layer<S> for HeadAutoLayer {
+    type Service = HeadAuto<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        HeadAuto { inner }
+    }
+}
+
+/// Service created by [`HeadAutoLayer`].
+#[derive(Clone, Debug)]
+pub struct HeadAuto<S> {
+    inner: S,
+}
+
+/// Request marker: this GET request was a HEAD request before the rewrite.
+#[derive(Clone, Copy, Debug)]
+struct AutoHead;
+
+/// Response marker: the route answered HEAD itself.
+#[derive(Clone, Copy, Debug)]
+struct HandledHead;
+
+impl<S> Service<Request> for HeadAuto<S>
+where
+    S: Service<Request, Response = Response>,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Response, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request) -> Self::Future {
+        if request.method() != Method::HEAD {
+            return Box::pin(self.inner.call(request));
+        }
+
+        *request.method_mut() = Method::GET;
+        request.extensions_mut().insert(AutoHead);
+        let response = self.inner.call(request);
+
+        Box::pin(async move { Ok(strip_body(response.await?)) })
+    }
+}
+
+/// Drops the body, keeping the length it would have had if it is known
+/// up front. Streaming bodies are never polled, so nothing is buffered.
+fn strip_body(response: Response) -> Response {
+    let (mut parts, body) = response.into_parts();
+
+    let handled = parts.extensions.remove::<HandledHead>().is_some();
+    let len = body.size_hint().exact();
+    if !handled && !parts.headers.contains_key(header::CONTENT_LENGTH) {
+        if let Some(len) = len {
+            parts.headers.insert(header::CONTENT_LENGTH, len.into());
+        }
+    }
+
+    // The router sets `Content-Length` from the size of whatever body we
+    // return, so a stream of unknown length stays one, just an empty one.
+    let body = match len {
+        Some(_) => Body::empty(),
+        None => Body::from_stream(stream::empty::<Result<Bytes, Infallible>>()),
+    };
+
+    Response::from_parts(parts, body)
+}
+
+/// Marks routes that answer HEAD themselves, either through an explicit
+/// `head()` handler or by checking the method, so [`HeadAutoLayer`] hands
+/// them the original HEAD request and leaves their headers alone.
+pub fn handles_head(routes: MethodRouter) -> MethodRouter {
+    any_service(middleware::from_fn(restore_head).layer(routes))
+}
+
+async fn restore_head(mut request: Request, next: Next) -> Response {
+    let auto_head = request.extensions_mut().remove::<AutoHead>().is_some();
+    if auto_head {
+        *request.method_mut() = Method::HEAD;
+    }
+
+    let mut response = next.run(request).await;
+    if auto_head {
+        response.extensions_mut().insert(HandledHead);
+    }
+    response
+}
@@ -1,9 +1,52 @@
+mod allow;
+mod head_auto;
+
+use std::convert::Infallible;
+
+use axum::body::Body;
+use axum::http::header::{CONTENT_LENGTH, CONTENT_TYPE};
+use axum::http::{HeaderMap, HeaderValue};
 use axum::response::{IntoResponse, Response};
 use axum::routing::get;
-use axum::{http, Router};
+use axum::{http, Extension, Router};
+use futures::stream;
+
+use crate::allow::AllowedRoute;
+use crate::head_auto::{handles_head, HeadAutoLayer};
+
+const GET_BODY: &str = "body from GET";
+
+/// Injected into `get_head_handler` so tests can prove HEAD never reaches
+/// the expensive part of the GET response.
+#[derive(Clone, Copy, Debug, Default)]
+struct ComputeGuard {
+    panic_under_head: bool,
+}
 
 fn app() -> Router {
-    Router::new().route("/get-head", get(get_head_handler))
+    app_with(ComputeGuard::default())
+}
+
+fn app_with(guard: ComputeGuard) -> Router {
+    Router::new()
+        .route(
+            "/get-head",
+            AllowedRoute::get(get_head_handler)
+                .options()
+                .into_method_router(),
+        )
+        .layer(Extension(guard))
+}
+
+/// The same routes, but HEAD is answered by `HeadAutoLayer` for every
+/// route that does not handle it itself.
+fn app_with_head_auto() -> Router {
+    Router::new()
+        .route("/plain", get(plain_handler))
+        .route("/stream", get(stream_handler))
+        .route("/get-head", handles_head(get(get_head_handler)))
+        .layer(HeadAutoLayer)
+        .layer(Extension(ComputeGuard::default()))
 }
 
 #[tokio::main]
@@ -12,20 +55,50 @@ async fn main() {
         .await
         .unwrap();
     println!("listening on {}", listener.local_addr().unwrap());
-    axum::serve(listener, app()).await.unwrap();
+    axum::serve(listener, app().nest("/auto", app_with_head_auto()))
+        .await
+        .unwrap();
 }
 
-async fn get_head_handler(method: http::Method) -> Response {
+async fn get_head_handler(
+    method: http::Method,
+    Extension(guard): Extension<ComputeGuard>,
+) -> Response {
+    let (mut headers, len) = response_meta();
+
     if method == http::Method::HEAD {
-        return ([("x-some-header", "header from HEAD")]).into_response();
+        headers.insert(CONTENT_LENGTH, len.into());
+        return (headers, [("x-some-header", "header from HEAD")]).into_response();
     }
 
-    do_some_computing_task();
+    do_some_computing_task(&method, guard);
+
+    (headers, [("x-some-header", "header from GET")], GET_BODY).into_response()
+}
+
+/// Headers and body length of the GET response, cheap enough for HEAD.
+fn response_meta() -> (HeaderMap, usize) {
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static("text/plain; charset=utf-8"),
+    );
+    (headers, GET_BODY.len())
+}
 
-    ([("x-some-header", "header from GET")], "body from GET").into_response()
+async fn plain_handler() -> &'static str {
+    "body from GET"
 }
 
-fn do_some_computing_task() {
+async fn stream_handler() -> Body {
+    let chunks = ["streamed ", "body ", "from GET"].map(Ok::<_, Infallible>);
+    Body::from_stream(stream::iter(chunks))
+}
+
+fn do_some_computing_task(method: &http::Method, guard: ComputeGuard) {
+    if guard.panic_under_head && method == http::Method::HEAD {
+        panic!("computing task called for a HEAD request");
+    }
     println!("doing some computing task");
 }
 
@@ -33,10 +106,17 @@ fn do_some_computing_task() {
 mod tests {
     use axum::body::Body;
     use http_body_util::BodyExt;
+    use hyper::header::{ALLOW, CONTENT_LENGTH, CONTENT_TYPE};
     use hyper::{Request, StatusCode};
     use tower::ServiceExt;
 
-    use crate::app;
+    use crate::{app_with, app_with_head_auto, ComputeGuard};
+
+    fn app() -> axum::Router {
+        app_with(ComputeGuard {
+            panic_under_head: true,
+        })
+    }
 
     #[tokio::test]
     async fn test_get() {
@@ -65,8 +145,111 @@ mod tests {
 
         assert_eq!(response.status(), StatusCode::OK);
         assert_eq!(response.headers()["x-some-header"], "header from HEAD");
+        assert_eq!(response.headers()[CONTENT_LENGTH], "13");
 
         let body = response.collect().await.unwrap().to_bytes();
         assert!(body.is_empty());
     }
+
+    #[tokio::test]
+    async fn test_head_matches_get_meta() {
+        let get = app()
+            .oneshot(Request::get("/get-head").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let head = app()
+            .oneshot(Request::head("/get-head").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(get.headers()[CONTENT_TYPE], head.headers()[CONTENT_TYPE]);
+        let len = get.headers()[CONTENT_LENGTH].clone();
+        assert_eq!(len, head.headers()[CONTENT_LENGTH]);
+
+        let body = get.collect().await.unwrap().to_bytes();
+        assert_eq!(len, body.len().to_string().as_str());
+    }
+
+    #[tokio::test]
+    async fn test_options() {
+        let response = app()
+            .oneshot(Request::options("/get-head").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(response.headers()[ALLOW], "GET, HEAD, OPTIONS");
+
+        let body = response.collect().await.unwrap().to_bytes();
+        assert!(body.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_post_not_allowed() {
+        let response = app()
+            .oneshot(Request::post("/get-head").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(response.headers()[ALLOW], "GET, HEAD, OPTIONS");
+    }
+
+    #[tokio::test]
+    async fn test_auto_head_plain_route() {
+        let response = app_with_head_auto()
+            .oneshot(Request::head("/plain").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[CONTENT_LENGTH], "13");
+        assert_eq!(
+            response.headers()["content-type"],
+            "text/plain; charset=utf-8"
+        );
+
+        let body = response.collect().await.unwrap().to_bytes();
+        assert!(body.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_auto_head_leaves_get_head_alone() {
+        let response = app_with_head_auto()
+            .oneshot(Request::head("/get-head").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["x-some-header"], "header from HEAD");
+        assert_eq!(response.headers()[CONTENT_LENGTH], "13");
+
+        let body = response.collect().await.unwrap().to_bytes();
+        assert!(body.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_auto_head_streaming_route() {
+        let response = app_with_head_auto()
+            .oneshot(Request::head("/stream").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(!response.headers().contains_key(CONTENT_LENGTH));
+
+        let body = response.collect().await.unwrap().to_bytes();
+        assert!(body.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_auto_head_keeps_get() {
+        let response = app_with_head_auto()
+            .oneshot(Request::get("/stream").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        let body = response.collect().await.unwrap().to_bytes();
+        assert_eq!(&body[..], b"streamed body from GET");
+    }
 }
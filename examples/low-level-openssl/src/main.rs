@@ -1,90 +1,1394 @@
-use axum::{http::Request, routing::get, Router};
+use arc_swap::ArcSwap;
+use axum::{
+    extract::{Extension, State},
+    http::{header, uri::Authority, HeaderMap, Request, StatusCode, Uri},
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
 use futures_util::pin_mut;
 use hyper::body::Incoming;
 use hyper_util::rt::{TokioExecutor, TokioIo};
-use openssl::ssl::{Ssl, SslAcceptor, SslFiletype, SslMethod};
-use std::{path::PathBuf, pin::Pin};
-use tokio::net::TcpListener;
+use openssl::{
+    error::ErrorStack,
+    nid::Nid,
+    ssl::{
+        self, AlpnError, NameType, SniError, Ssl, SslAcceptor, SslAcceptorBuilder, SslContext,
+        SslFiletype, SslMethod, SslRef, SslVerifyMode,
+    },
+    x509::X509Ref,
+};
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    fmt,
+    future::Future,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    pin::Pin,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpListener, TcpStream},
+    sync::Semaphore,
+};
 use tokio_openssl::SslStream;
+use tokio_rustls::TlsAcceptor;
 use tower::Service;
 use tracing::{error, info, warn};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
+use crate::{
+    sni::SniCerts,
+    tls_session::{TicketKey, TlsStats},
+};
+
+mod rustls_backend;
+mod sni;
+mod tls_session;
+
 #[tokio::main]
 async fn main() {
     tracing_subscriber::registry()
         .with(
             tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| "example_low_level_openssl=debug".into()),
+                .unwrap_or_else(|_| format!("{}=debug", env!("CARGO_CRATE_NAME")).into()),
         )
         .with(tracing_subscriber::fmt::layer())
         .init();
 
-    let mut tls_builder = SslAcceptor::mozilla_modern_v5(SslMethod::tls()).unwrap();
+    let backend = Backend::from_args(std::env::args().skip(1)).unwrap_or_else(|err| {
+        error!("{err}");
+        std::process::exit(1);
+    });
 
-    tls_builder
-        .set_certificate_file(
-            PathBuf::from(env!("CARGO_MANIFEST_DIR"))
-                .join("self_signed_certs")
-                .join("cert.pem"),
-            SslFiletype::PEM,
-        )
-        .unwrap();
+    let ticket_key = TicketKey::from_env().unwrap_or_else(|err| {
+        error!("{err}");
+        std::process::exit(1);
+    });
 
-    tls_builder
-        .set_private_key_file(
-            PathBuf::from(env!("CARGO_MANIFEST_DIR"))
-                .join("self_signed_certs")
-                .join("key.pem"),
-            SslFiletype::PEM,
-        )
+    let sni_certs = sni::sni_certs_from_env().unwrap_or_else(|err| {
+        error!("{err}");
+        std::process::exit(1);
+    });
+
+    let certs = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("self_signed_certs");
+    let tls_files = TlsFiles {
+        cert: certs.join("cert.pem"),
+        key: certs.join("key.pem"),
+        client_ca: std::env::var_os("CLIENT_CA_PATH").map(PathBuf::from),
+        ticket_key,
+        sni_certs,
+    };
+
+    let http_port = std::env::var("HTTP_PORT")
+        .ok()
+        .and_then(|port| port.parse().ok())
+        .unwrap_or(DEFAULT_HTTP_PORT);
+
+    let handshake_timeout = std::env::var("TLS_HANDSHAKE_TIMEOUT_MS")
+        .ok()
+        .and_then(|ms| ms.parse().ok())
+        .map(Duration::from_millis)
+        .unwrap_or(DEFAULT_HANDSHAKE_TIMEOUT);
+    let limits = HandshakeLimits::new(handshake_timeout, MAX_PENDING_HANDSHAKES);
+    let stats = Arc::new(TlsStats::default());
+
+    let bind = format!("[::1]:{HTTPS_PORT}");
+    let tcp_listener = TcpListener::bind(&bind).await.unwrap();
+    info!(
+        "HTTPS server listening on {bind} using {backend}. \
+         To contact curl -k https://localhost:{HTTPS_PORT}"
+    );
+
+    let redirect = redirect_http_to_https(http_port, stats.clone());
+    let https = async move {
+        match backend {
+            Backend::Openssl => {
+                serve_tls::<SslAcceptor>(tls_files, tcp_listener, limits, stats).await
+            }
+            Backend::Rustls => {
+                serve_tls::<TlsAcceptor>(tls_files, tcp_listener, limits, stats).await
+            }
+        }
+    };
+    tokio::join!(https, redirect);
+}
+
+/// Which TLS implementation serves HTTPS, picked with `--tls-backend`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Backend {
+    Openssl,
+    Rustls,
+}
+
+impl Backend {
+    /// Parse `--tls-backend <name>` or `--tls-backend=<name>`, defaulting to
+    /// OpenSSL.
+    fn from_args(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut backend = Self::Openssl;
+        while let Some(arg) = args.next() {
+            let name = if arg == "--tls-backend" {
+                args.next()
+                    .ok_or_else(|| "missing value for --tls-backend".to_owned())?
+            } else if let Some(name) = arg.strip_prefix("--tls-backend=") {
+                name.to_owned()
+            } else {
+                return Err(format!("unexpected argument `{arg}`"));
+            };
+            backend = name.parse()?;
+        }
+        Ok(backend)
+    }
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "openssl" => Ok(Self::Openssl),
+            "rustls" => Ok(Self::Rustls),
+            _ => Err(format!(
+                "unknown TLS backend `{name}`, expected `openssl` or `rustls`"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Openssl => "openssl",
+            Self::Rustls => "rustls",
+        })
+    }
+}
+
+/// A TLS implementation the server can run on. Everything after the
+/// handshake, and the accept loop around it, is shared.
+trait TlsBackend: Send + Sync + Sized + 'static {
+    type Stream: AsyncRead + AsyncWrite + Unpin + Send + 'static;
+    type BuildError: fmt::Display;
+    type HandshakeError: fmt::Display + Send;
+
+    fn build(tls_files: &TlsFiles) -> Result<Self, Self::BuildError>;
+
+    fn accept(
+        &self,
+        cnx: TcpStream,
+    ) -> impl Future<Output = Result<Self::Stream, Self::HandshakeError>> + Send;
+
+    /// Whether the handshake failed because the client didn't present an
+    /// acceptable certificate.
+    fn is_client_cert_error(err: &Self::HandshakeError) -> bool;
+
+    fn negotiated(stream: &Self::Stream, peer_addr: SocketAddr) -> Negotiated;
+}
+
+/// What a completed handshake established.
+struct Negotiated {
+    info: TlsConnectionInfo,
+    peer_cert: Option<PeerCertInfo>,
+    resumed: bool,
+}
+
+impl TlsBackend for SslAcceptor {
+    type Stream = SslStream<TcpStream>;
+    type BuildError = AcceptorError;
+    type HandshakeError = ssl::Error;
+
+    fn build(tls_files: &TlsFiles) -> Result<Self, Self::BuildError> {
+        tls_files.build_acceptor()
+    }
+
+    async fn accept(&self, cnx: TcpStream) -> Result<Self::Stream, Self::HandshakeError> {
+        let ssl = Ssl::new(self.context()).unwrap();
+        let mut tls_stream = SslStream::new(ssl, cnx).unwrap();
+        SslStream::accept(Pin::new(&mut tls_stream)).await?;
+        Ok(tls_stream)
+    }
+
+    fn is_client_cert_error(err: &Self::HandshakeError) -> bool {
+        err.ssl_error().is_some_and(|stack| {
+            stack.errors().iter().any(|err| {
+                matches!(
+                    err.reason(),
+                    Some("peer did not return a certificate" | "certificate verify failed")
+                )
+            })
+        })
+    }
+
+    fn negotiated(stream: &Self::Stream, peer_addr: SocketAddr) -> Negotiated {
+        let ssl = stream.ssl();
+        Negotiated {
+            info: TlsConnectionInfo::new(peer_addr, ssl),
+            peer_cert: ssl
+                .peer_certificate()
+                .map(|cert| PeerCertInfo::from_cert(&cert)),
+            resumed: ssl.session_reused(),
+        }
+    }
+}
+
+/// Build the acceptor for backend `B` and serve HTTPS with it, reloading it
+/// when the certificate files change.
+async fn serve_tls<B: TlsBackend>(
+    tls_files: TlsFiles,
+    tcp_listener: TcpListener,
+    limits: HandshakeLimits,
+    stats: Arc<TlsStats>,
+) {
+    let tls_acceptor = match B::build(&tls_files) {
+        Ok(tls_acceptor) => Arc::new(ArcSwap::from_pointee(tls_acceptor)),
+        Err(err) => {
+            error!("failed to load TLS certificate: {err}");
+            std::process::exit(1);
+        }
+    };
+    tokio::spawn(watch_certificates(
+        tls_files,
+        tls_acceptor.clone(),
+        CERT_CHECK_INTERVAL,
+    ));
+
+    serve(tcp_listener, tls_acceptor, limits, stats, app()).await;
+}
+
+const HTTPS_PORT: u16 = 3000;
+
+/// How long a client gets to complete the TLS handshake when
+/// `TLS_HANDSHAKE_TIMEOUT_MS` isn't set.
+const DEFAULT_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How many connections may be in the middle of a handshake at once.
+const MAX_PENDING_HANDSHAKES: usize = 1024;
+
+/// Limits on connections that haven't completed the TLS handshake yet, so
+/// clients that connect and then stall can't pile up tasks.
+#[derive(Debug, Clone)]
+struct HandshakeLimits {
+    timeout: Duration,
+    pending: Arc<Semaphore>,
+}
+
+impl HandshakeLimits {
+    fn new(timeout: Duration, max_pending: usize) -> Self {
+        Self {
+            timeout,
+            pending: Arc::new(Semaphore::new(max_pending)),
+        }
+    }
+}
+
+/// Plain HTTP port redirecting to HTTPS when `HTTP_PORT` isn't set.
+const DEFAULT_HTTP_PORT: u16 = 3001;
+
+async fn redirect_http_to_https(http_port: u16, stats: Arc<TlsStats>) {
+    let bind = format!("[::1]:{http_port}");
+    let listener = TcpListener::bind(&bind).await.unwrap();
+    info!("HTTP redirect listening on {bind}");
+    axum::serve(listener, redirect_app(HTTPS_PORT, stats))
+        .await
         .unwrap();
+}
 
-    tls_builder.check_private_key().unwrap();
+/// Everything on the plain HTTP port redirects to HTTPS, except for the TLS
+/// stats which are meant for monitoring.
+fn redirect_app(https_port: u16, stats: Arc<TlsStats>) -> Router {
+    Router::new()
+        .route("/tls-stats", get(tls_stats))
+        .fallback(move |headers: HeaderMap, uri: Uri| async move {
+            redirect_to_https(&headers, &uri, https_port)
+        })
+        .with_state(stats)
+}
 
-    let tls_acceptor = tls_builder.build();
+async fn tls_stats(State(stats): State<Arc<TlsStats>>) -> impl IntoResponse {
+    Json(stats.snapshot())
+}
 
-    let bind = "[::1]:3000";
-    let tcp_listener = TcpListener::bind(bind).await.unwrap();
-    info!("HTTPS server listening on {bind}. To contact curl -k https://localhost:3000");
-    let app = Router::new().route("/", get(handler));
+/// Redirect to the same path and query on the HTTPS port of the `Host` the
+/// client asked for. Unlike axum's `Host` extractor this ignores
+/// `X-Forwarded-Host`, which anyone can set, so the redirect can't be pointed
+/// at another site. Requests without a valid `Host` header are rejected.
+fn redirect_to_https(headers: &HeaderMap, uri: &Uri, https_port: u16) -> Response {
+    let Some(authority) = headers
+        .get(header::HOST)
+        .and_then(|host| Authority::try_from(host.as_bytes()).ok())
+        // `Host` never carries userinfo.
+        .filter(|authority| !authority.as_str().contains('@'))
+    else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+    // `host()` drops the port but keeps the brackets around IPv6 addresses.
+    let host = authority.host();
+    let path_and_query = uri.path_and_query().map_or("/", |pq| pq.as_str());
 
+    let location = format!("https://{host}:{https_port}{path_and_query}");
+    (
+        StatusCode::MOVED_PERMANENTLY,
+        [(header::LOCATION, location)],
+    )
+        .into_response()
+}
+
+/// Configure an acceptor from PEM files. When `client_ca` is set, clients
+/// must present a certificate signed by that CA.
+fn acceptor_builder(
+    cert: &Path,
+    key: &Path,
+    client_ca: Option<&Path>,
+    ticket_key: &TicketKey,
+) -> Result<SslAcceptorBuilder, ErrorStack> {
+    let mut tls_builder = SslAcceptor::mozilla_modern_v5(SslMethod::tls())?;
+
+    tls_builder.set_certificate_file(cert, SslFiletype::PEM)?;
+    tls_builder.set_private_key_file(key, SslFiletype::PEM)?;
+    tls_builder.check_private_key()?;
+
+    if let Some(client_ca) = client_ca {
+        tls_builder.set_ca_file(client_ca)?;
+        tls_builder.set_verify(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT);
+    }
+
+    // Clients offering only protocols we don't speak are rejected, as rustls
+    // does.
+    tls_builder.set_alpn_select_callback(|_, client| {
+        ssl::select_next_proto(ALPN_PROTOCOLS, client).ok_or(AlpnError::ALERT_FATAL)
+    });
+
+    tls_session::configure_resumption(&mut tls_builder, ticket_key)?;
+
+    Ok(tls_builder)
+}
+
+/// Switch to the context for the requested server name, if there is one.
+/// Otherwise the default certificate is served.
+fn select_context(
+    ssl: &mut SslRef,
+    contexts: &HashMap<String, SslContext>,
+) -> Result<(), SniError> {
+    let Some(context) = ssl
+        .servername(NameType::HOST_NAME)
+        .and_then(|name| contexts.get(&name.to_ascii_lowercase()))
+    else {
+        return Ok(());
+    };
+
+    ssl.set_ssl_context(context)
+        .map_err(|_| SniError::ALERT_FATAL)
+}
+
+/// Building the acceptor failed, either for the default certificate or the
+/// one for a server name in `TLS_CERTS`.
+#[derive(Debug)]
+enum AcceptorError {
+    Default(ErrorStack),
+    ServerName { name: String, source: ErrorStack },
+}
+
+impl fmt::Display for AcceptorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Default(err) => write!(f, "{err}"),
+            Self::ServerName { name, source } => {
+                write!(f, "invalid certificate for `{name}`: {source}")
+            }
+        }
+    }
+}
+
+/// HTTP/2 and HTTP/1.1 in the ALPN wire format, in order of preference.
+const ALPN_PROTOCOLS: &[u8] = b"\x02h2\x08http/1.1";
+
+/// How often to check whether the certificate files changed.
+const CERT_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// The files the acceptor is built from.
+///
+/// `cert` and `key` are served to clients whose server name isn't in
+/// `sni_certs`, or that don't send one.
+///
+/// The ticket key is kept across reloads so sessions can still be resumed
+/// after the certificate is rotated. Only OpenSSL uses it, rustls resumes
+/// sessions from its in-memory cache.
+#[derive(Debug, Clone)]
+struct TlsFiles {
+    cert: PathBuf,
+    key: PathBuf,
+    client_ca: Option<PathBuf>,
+    ticket_key: TicketKey,
+    sni_certs: SniCerts,
+}
+
+impl TlsFiles {
+    fn acceptor_builder(&self, cert: &Path, key: &Path) -> Result<SslAcceptorBuilder, ErrorStack> {
+        acceptor_builder(cert, key, self.client_ca.as_deref(), &self.ticket_key)
+    }
+
+    /// Build the acceptor, with a context for each server name in
+    /// `sni_certs` that's switched to during the handshake.
+    fn build_acceptor(&self) -> Result<SslAcceptor, AcceptorError> {
+        let contexts = self
+            .sni_certs
+            .iter()
+            .map(|(name, (cert, key))| {
+                self.acceptor_builder(cert, key)
+                    .map(|builder| (name.clone(), builder.build().into_context()))
+                    .map_err(|source| AcceptorError::ServerName {
+                        name: name.clone(),
+                        source,
+                    })
+            })
+            .collect::<Result<HashMap<_, _>, _>>()?;
+
+        let mut tls_builder = self
+            .acceptor_builder(&self.cert, &self.key)
+            .map_err(AcceptorError::Default)?;
+        if !contexts.is_empty() {
+            tls_builder.set_servername_callback(move |ssl, _| select_context(ssl, &contexts));
+        }
+
+        Ok(tls_builder.build())
+    }
+
+    /// The most recent modification time of any of the files.
+    fn modified(&self) -> Option<SystemTime> {
+        let sni_files = self.sni_certs.values().flat_map(|(cert, key)| [cert, key]);
+
+        [Some(&self.cert), Some(&self.key), self.client_ca.as_ref()]
+            .into_iter()
+            .flatten()
+            .chain(sni_files)
+            .filter_map(|path| {
+                std::fs::metadata(path)
+                    .and_then(|meta| meta.modified())
+                    .ok()
+            })
+            .max()
+    }
+}
+
+/// Rebuild the acceptor and swap it in for new connections. Connections that
+/// are already established keep using the old one.
+///
+/// If the files are broken, e.g. halfway through being replaced, the current
+/// acceptor is kept.
+fn reload<B: TlsBackend>(tls_files: &TlsFiles, tls_acceptor: &ArcSwap<B>) -> bool {
+    match B::build(tls_files) {
+        Ok(new_acceptor) => {
+            tls_acceptor.store(Arc::new(new_acceptor));
+            info!("reloaded TLS certificate");
+            true
+        }
+        Err(err) => {
+            error!("failed to reload TLS certificate, keeping the current one: {err}");
+            false
+        }
+    }
+}
+
+/// Reload the acceptor whenever the certificate files change, checking every
+/// `interval`. On unix `SIGHUP` forces a reload.
+async fn watch_certificates<B: TlsBackend>(
+    tls_files: TlsFiles,
+    tls_acceptor: Arc<ArcSwap<B>>,
+    interval: Duration,
+) {
+    let mut last_modified = tls_files.modified();
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    #[cfg(unix)]
+    let mut hangup =
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()).unwrap();
+
+    loop {
+        #[cfg(unix)]
+        let forced = tokio::select! {
+            _ = ticker.tick() => false,
+            _ = hangup.recv() => true,
+        };
+        #[cfg(not(unix))]
+        let forced = {
+            ticker.tick().await;
+            false
+        };
+
+        let modified = tls_files.modified();
+        if forced || modified != last_modified {
+            last_modified = modified;
+            reload(&tls_files, &tls_acceptor);
+        }
+    }
+}
+
+fn app() -> Router {
+    Router::new()
+        .route("/", get(handler))
+        .route("/whoami", get(whoami))
+        .route("/conn-info", get(conn_info))
+}
+
+async fn serve<B: TlsBackend>(
+    tcp_listener: TcpListener,
+    tls_acceptor: Arc<ArcSwap<B>>,
+    limits: HandshakeLimits,
+    stats: Arc<TlsStats>,
+    app: Router,
+) {
     pin_mut!(tcp_listener);
 
     loop {
         let tower_service = app.clone();
-        let tls_acceptor = tls_acceptor.clone();
 
         let (cnx, addr) = tcp_listener.accept().await.unwrap();
+        // Loaded once the connection is in, so it gets certificates reloaded
+        // while we were waiting for it.
+        let tls_acceptor = tls_acceptor.load_full();
+
+        let Ok(permit) = limits.pending.clone().try_acquire_owned() else {
+            warn!("too many pending TLS handshakes, dropping connection from {addr}");
+            continue;
+        };
+        let handshake_timeout = limits.timeout;
+        let stats = stats.clone();
 
         tokio::spawn(async move {
-            let ssl = Ssl::new(tls_acceptor.context()).unwrap();
-            let mut tls_stream = SslStream::new(ssl, cnx).unwrap();
-            if let Err(err) = SslStream::accept(Pin::new(&mut tls_stream)).await {
-                error!(
-                    "error during tls handshake connection from {}: {}",
-                    addr, err
-                );
-                return;
-            }
+            let handshake = tls_acceptor.accept(cnx);
+            let tls_stream = match tokio::time::timeout(handshake_timeout, handshake).await {
+                Ok(Ok(tls_stream)) => tls_stream,
+                Ok(Err(err)) => {
+                    // Clients without an acceptable certificate are turned away
+                    // as intended, that's not a server error.
+                    if B::is_client_cert_error(&err) {
+                        info!("rejected client certificate from {}: {}", addr, err);
+                    } else {
+                        error!(
+                            "error during tls handshake connection from {}: {}",
+                            addr, err
+                        );
+                    }
+                    return;
+                }
+                Err(_) => {
+                    warn!(
+                        "tls handshake with {} timed out after {:?}",
+                        addr, handshake_timeout
+                    );
+                    return;
+                }
+            };
+            drop(permit);
 
-            let stream = TokioIo::new(tls_stream);
+            let negotiated = B::negotiated(&tls_stream, addr);
+            stats.record(negotiated.resumed);
 
-            let hyper_service = hyper::service::service_fn(move |request: Request<Incoming>| {
-                tower_service.clone().call(request)
-            });
+            serve_connection(tls_stream, addr, negotiated, tower_service).await;
+        });
+    }
+}
 
-            let ret = hyper_util::server::conn::auto::Builder::new(TokioExecutor::new())
-                .serve_connection_with_upgrades(stream, hyper_service)
-                .await;
+/// Serve HTTP on a connection whose TLS handshake is done, making what was
+/// negotiated available to handlers as request extensions.
+async fn serve_connection<S>(stream: S, addr: SocketAddr, negotiated: Negotiated, app: Router)
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let Negotiated {
+        info, peer_cert, ..
+    } = negotiated;
+    let conn_info = Arc::new(info);
 
-            if let Err(err) = ret {
-                warn!("error serving connection from {}: {}", addr, err);
-            }
-        });
+    let stream = TokioIo::new(stream);
+
+    let hyper_service = hyper::service::service_fn(move |mut request: Request<Incoming>| {
+        if let Some(peer_cert) = &peer_cert {
+            request.extensions_mut().insert(peer_cert.clone());
+        }
+        request.extensions_mut().insert(conn_info.clone());
+        app.clone().call(request)
+    });
+
+    let ret = hyper_util::server::conn::auto::Builder::new(TokioExecutor::new())
+        .serve_connection_with_upgrades(stream, hyper_service)
+        .await;
+
+    if let Err(err) = ret {
+        warn!("error serving connection from {}: {}", addr, err);
+    }
+}
+
+/// Details about the connection a request came in on.
+#[derive(Debug, Serialize)]
+struct TlsConnectionInfo {
+    peer_addr: SocketAddr,
+    tls_version: String,
+    cipher: String,
+    sni: Option<String>,
+    alpn: Option<String>,
+}
+
+impl TlsConnectionInfo {
+    fn new(peer_addr: SocketAddr, ssl: &SslRef) -> Self {
+        Self {
+            peer_addr,
+            tls_version: ssl.version_str().to_owned(),
+            cipher: ssl
+                .current_cipher()
+                .map(|cipher| cipher.name().to_owned())
+                .unwrap_or_default(),
+            sni: ssl.servername(NameType::HOST_NAME).map(ToOwned::to_owned),
+            alpn: ssl
+                .selected_alpn_protocol()
+                .map(|alpn| String::from_utf8_lossy(alpn).into_owned()),
+        }
+    }
+}
+
+/// The client certificate presented during the handshake.
+#[derive(Debug, Clone)]
+struct PeerCertInfo {
+    subject: String,
+    serial: String,
+    not_after: String,
+}
+
+impl PeerCertInfo {
+    fn from_cert(cert: &X509Ref) -> Self {
+        let subject = cert
+            .subject_name()
+            .entries_by_nid(Nid::COMMONNAME)
+            .next()
+            .and_then(|cn| cn.data().to_string().ok())
+            .unwrap_or_default();
+        let serial = cert
+            .serial_number()
+            .to_bn()
+            .and_then(|serial| serial.to_hex_str().map(|hex| hex.to_string()))
+            .unwrap_or_default();
+
+        Self {
+            subject,
+            serial,
+            not_after: cert.not_after().to_string(),
+        }
     }
 }
 
 async fn handler() -> &'static str {
     "Hello, World!"
 }
+
+async fn conn_info(
+    Extension(info): Extension<Arc<TlsConnectionInfo>>,
+) -> Json<Arc<TlsConnectionInfo>> {
+    Json(info)
+}
+
+async fn whoami(peer_cert: Option<Extension<PeerCertInfo>>) -> String {
+    match peer_cert {
+        Some(Extension(cert)) => format!(
+            "{} (serial {}, expires {})",
+            cert.subject, cert.serial, cert.not_after
+        ),
+        None => "anonymous".to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use openssl::{
+        asn1::Asn1Time,
+        bn::BigNum,
+        hash::MessageDigest,
+        pkey::{PKey, Private},
+        rsa::Rsa,
+        x509::{extension::BasicConstraints, X509Name, X509},
+    };
+
+    use super::*;
+
+    struct KeyPair {
+        cert: X509,
+        key: PKey<Private>,
+    }
+
+    /// Issue a certificate for `cn`, signed by `issuer` or self-signed.
+    fn issue(cn: &str, serial: u32, issuer: Option<&KeyPair>) -> KeyPair {
+        let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+
+        let mut name = X509Name::builder().unwrap();
+        name.append_entry_by_nid(Nid::COMMONNAME, cn).unwrap();
+        let name = name.build();
+
+        let mut cert = X509::builder().unwrap();
+        cert.set_version(2).unwrap();
+        cert.set_serial_number(&BigNum::from_u32(serial).unwrap().to_asn1_integer().unwrap())
+            .unwrap();
+        cert.set_subject_name(&name).unwrap();
+        cert.set_pubkey(&key).unwrap();
+        cert.set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        cert.set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        match issuer {
+            Some(issuer) => {
+                cert.set_issuer_name(issuer.cert.subject_name()).unwrap();
+                cert.sign(&issuer.key, MessageDigest::sha256()).unwrap();
+            }
+            None => {
+                let ca = BasicConstraints::new().critical().ca().build().unwrap();
+                cert.append_extension(ca).unwrap();
+                cert.set_issuer_name(&name).unwrap();
+                cert.sign(&key, MessageDigest::sha256()).unwrap();
+            }
+        }
+
+        KeyPair {
+            cert: cert.build(),
+            key,
+        }
+    }
+
+    fn identity(pair: &KeyPair) -> reqwest::Identity {
+        reqwest::Identity::from_pkcs8_pem(
+            &pair.cert.to_pem().unwrap(),
+            &pair.key.private_key_to_pem_pkcs8().unwrap(),
+        )
+        .unwrap()
+    }
+
+    /// Every test that talks to a running server runs against both.
+    const BACKENDS: [Backend; 2] = [Backend::Openssl, Backend::Rustls];
+
+    /// The checked in certificate, optionally requiring client certificates.
+    fn server_files(client_ca: Option<&Path>) -> TlsFiles {
+        let certs = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("self_signed_certs");
+        TlsFiles {
+            cert: certs.join("cert.pem"),
+            key: certs.join("key.pem"),
+            client_ca: client_ca.map(ToOwned::to_owned),
+            ticket_key: TicketKey::random(),
+            sni_certs: SniCerts::new(),
+        }
+    }
+
+    fn spawn_backend<B>(
+        tls_files: &TlsFiles,
+        listener: TcpListener,
+        limits: HandshakeLimits,
+        stats: Arc<TlsStats>,
+    ) where
+        B: TlsBackend,
+        B::BuildError: fmt::Debug,
+    {
+        let acceptor = Arc::new(ArcSwap::from_pointee(B::build(tls_files).unwrap()));
+        tokio::spawn(serve(listener, acceptor, limits, stats, app()));
+    }
+
+    /// Start the server on `backend`, returning the address it listens on.
+    async fn start(
+        backend: Backend,
+        tls_files: &TlsFiles,
+        limits: HandshakeLimits,
+        stats: Arc<TlsStats>,
+    ) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        match backend {
+            Backend::Openssl => spawn_backend::<SslAcceptor>(tls_files, listener, limits, stats),
+            Backend::Rustls => spawn_backend::<TlsAcceptor>(tls_files, listener, limits, stats),
+        }
+        addr
+    }
+
+    /// Start the server requiring client certificates signed by `client_ca`.
+    async fn spawn_server(
+        backend: Backend,
+        client_ca: &KeyPair,
+    ) -> (SocketAddr, tempfile::NamedTempFile) {
+        let ca_file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(ca_file.path(), client_ca.cert.to_pem().unwrap()).unwrap();
+
+        let tls_files = server_files(Some(ca_file.path()));
+        let limits = HandshakeLimits::new(Duration::from_secs(10), 16);
+        let addr = start(backend, &tls_files, limits, Arc::default()).await;
+        (addr, ca_file)
+    }
+
+    async fn whoami_with(
+        addr: SocketAddr,
+        identity: Option<reqwest::Identity>,
+    ) -> reqwest::Result<String> {
+        // The checked in server certificate is self-signed.
+        let mut client = reqwest::Client::builder().danger_accept_invalid_certs(true);
+        if let Some(identity) = identity {
+            client = client.identity(identity);
+        }
+
+        client
+            .build()?
+            .get(format!("https://{addr}/whoami"))
+            .send()
+            .await?
+            .text()
+            .await
+    }
+
+    #[tokio::test]
+    async fn accepts_client_signed_by_ca() {
+        let ca = issue("Test CA", 1, None);
+        let client = issue("test-client", 0x2a, Some(&ca));
+
+        for backend in BACKENDS {
+            let (addr, _ca_file) = spawn_server(backend, &ca).await;
+
+            let body = whoami_with(addr, Some(identity(&client))).await.unwrap();
+
+            assert!(
+                body.starts_with("test-client (serial 2A, expires "),
+                "{backend}: {body}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_missing_client_cert() {
+        let ca = issue("Test CA", 1, None);
+
+        for backend in BACKENDS {
+            let (addr, _ca_file) = spawn_server(backend, &ca).await;
+
+            assert!(whoami_with(addr, None).await.is_err(), "{backend}");
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_client_signed_by_other_ca() {
+        let ca = issue("Test CA", 1, None);
+        let other_ca = issue("Other CA", 2, None);
+        let client = issue("intruder", 3, Some(&other_ca));
+
+        for backend in BACKENDS {
+            let (addr, _ca_file) = spawn_server(backend, &ca).await;
+
+            assert!(
+                whoami_with(addr, Some(identity(&client))).await.is_err(),
+                "{backend}"
+            );
+        }
+    }
+
+    /// Write `pair` to the temporary cert and key files.
+    fn write_pair(files: &TlsFiles, pair: &KeyPair) {
+        std::fs::write(&files.cert, pair.cert.to_pem().unwrap()).unwrap();
+        std::fs::write(&files.key, pair.key.private_key_to_pem_pkcs8().unwrap()).unwrap();
+    }
+
+    fn temp_tls_files(dir: &tempfile::TempDir) -> TlsFiles {
+        TlsFiles {
+            cert: dir.path().join("cert.pem"),
+            key: dir.path().join("key.pem"),
+            client_ca: None,
+            ticket_key: TicketKey::random(),
+            sni_certs: SniCerts::new(),
+        }
+    }
+
+    fn served_cn(tls_acceptor: &ArcSwap<SslAcceptor>) -> String {
+        let tls_acceptor = tls_acceptor.load();
+        let cert = tls_acceptor.context().certificate().unwrap();
+        PeerCertInfo::from_cert(cert).subject
+    }
+
+    #[test]
+    fn build_acceptor_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let files = temp_tls_files(&dir);
+
+        // Missing files.
+        assert!(files.build_acceptor().is_err());
+
+        // Garbage key.
+        write_pair(&files, &issue("localhost", 1, None));
+        std::fs::write(&files.key, "not a key").unwrap();
+        assert!(files.build_acceptor().is_err());
+
+        // Key belonging to a different certificate.
+        let other = issue("localhost", 2, None);
+        std::fs::write(&files.key, other.key.private_key_to_pem_pkcs8().unwrap()).unwrap();
+        assert!(files.build_acceptor().is_err());
+
+        write_pair(&files, &other);
+        assert!(files.build_acceptor().is_ok());
+    }
+
+    #[test]
+    fn reload_keeps_old_acceptor_on_failure() {
+        let dir = tempfile::tempdir().unwrap();
+        let files = temp_tls_files(&dir);
+        write_pair(&files, &issue("first", 1, None));
+        let tls_acceptor = ArcSwap::from_pointee(files.build_acceptor().unwrap());
+
+        write_pair(&files, &issue("second", 2, None));
+        assert!(reload(&files, &tls_acceptor));
+        assert_eq!(served_cn(&tls_acceptor), "second");
+
+        let mismatched = issue("third", 3, None);
+        std::fs::write(&files.cert, mismatched.cert.to_pem().unwrap()).unwrap();
+        assert!(!reload(&files, &tls_acceptor));
+        assert_eq!(served_cn(&tls_acceptor), "second");
+    }
+
+    #[tokio::test]
+    async fn watcher_picks_up_new_certificate() {
+        let dir = tempfile::tempdir().unwrap();
+        let files = temp_tls_files(&dir);
+        write_pair(&files, &issue("before", 1, None));
+        let tls_acceptor = Arc::new(ArcSwap::from_pointee(files.build_acceptor().unwrap()));
+
+        tokio::spawn(watch_certificates(
+            files.clone(),
+            tls_acceptor.clone(),
+            Duration::from_millis(10),
+        ));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(served_cn(&tls_acceptor), "before");
+
+        write_pair(&files, &issue("after", 2, None));
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while served_cn(&tls_acceptor) != "after" {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("certificate wasn't reloaded");
+    }
+
+    async fn redirect(uri: &str, host: Option<&str>) -> Response {
+        use tower::ServiceExt;
+
+        let mut request = Request::get(uri);
+        if let Some(host) = host {
+            request = request.header(header::HOST, host);
+        }
+        redirect_app(3000, Arc::default())
+            .oneshot(request.body(axum::body::Body::empty()).unwrap())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn redirect_preserves_path_and_query() {
+        let response = redirect("/a/b?x=1&y=two", Some("example.com:3001")).await;
+
+        assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(
+            response.headers()[header::LOCATION],
+            "https://example.com:3000/a/b?x=1&y=two"
+        );
+    }
+
+    #[tokio::test]
+    async fn redirect_rewrites_host_port() {
+        for (host, location) in [
+            ("example.com", "https://example.com:3000/"),
+            ("localhost:8080", "https://localhost:3000/"),
+            ("[::1]:3001", "https://[::1]:3000/"),
+            ("[::1]", "https://[::1]:3000/"),
+        ] {
+            let response = redirect("/", Some(host)).await;
+
+            assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY, "{host}");
+            assert_eq!(response.headers()[header::LOCATION], location, "{host}");
+        }
+    }
+
+    #[tokio::test]
+    async fn redirect_without_host_is_bad_request() {
+        for host in [None, Some("example.com/evil"), Some("user@example.com")] {
+            let response = redirect("/a?b=c", host).await;
+
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{host:?}");
+            assert!(response.headers().get(header::LOCATION).is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn redirect_ignores_forwarded_host() {
+        use tower::ServiceExt;
+
+        let response = redirect_app(3000, Arc::default())
+            .oneshot(
+                Request::get("/")
+                    .header(header::HOST, "example.com")
+                    .header("x-forwarded-host", "evil.example")
+                    .body(axum::body::Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(
+            response.headers()[header::LOCATION],
+            "https://example.com:3000/"
+        );
+    }
+
+    /// Whether the server closes `stream` within `timeout`.
+    async fn closed_within(stream: &mut tokio::net::TcpStream, timeout: Duration) -> bool {
+        use tokio::io::AsyncReadExt;
+
+        let mut buf = [0; 1];
+        let read = tokio::time::timeout(timeout, stream.read(&mut buf)).await;
+        matches!(read, Ok(Ok(0) | Err(_)))
+    }
+
+    #[tokio::test]
+    async fn stalled_handshakes_are_reaped() {
+        for backend in BACKENDS {
+            let limits = HandshakeLimits::new(Duration::from_millis(200), 2);
+            let addr = start(backend, &server_files(None), limits.clone(), Arc::default()).await;
+
+            // Connect without ever sending a ClientHello.
+            let mut stalled = [
+                TcpStream::connect(addr).await.unwrap(),
+                TcpStream::connect(addr).await.unwrap(),
+            ];
+            let wait_for_permits = |expected| {
+                let pending = limits.pending.clone();
+                tokio::time::timeout(Duration::from_secs(5), async move {
+                    while pending.available_permits() != expected {
+                        tokio::time::sleep(Duration::from_millis(5)).await;
+                    }
+                })
+            };
+            wait_for_permits(0).await.expect("handshakes never started");
+
+            // Saturated, so this one is dropped right away.
+            let mut rejected = TcpStream::connect(addr).await.unwrap();
+            assert!(
+                closed_within(&mut rejected, Duration::from_millis(100)).await,
+                "{backend}"
+            );
+
+            // The stalled ones are closed once the timeout passes.
+            for stream in &mut stalled {
+                assert!(
+                    closed_within(stream, Duration::from_secs(2)).await,
+                    "{backend}"
+                );
+            }
+            wait_for_permits(2).await.expect("permits weren't released");
+        }
+    }
+
+    #[tokio::test]
+    async fn conn_info_reports_tls_details() {
+        let ca = issue("Test CA", 1, None);
+        let client = issue("test-client", 4, Some(&ca));
+
+        for backend in BACKENDS {
+            let (addr, _ca_file) = spawn_server(backend, &ca).await;
+
+            // Connect by name so the client sends it as SNI.
+            let info: serde_json::Value = reqwest::Client::builder()
+                .danger_accept_invalid_certs(true)
+                .identity(identity(&client))
+                .resolve("localhost", addr)
+                .build()
+                .unwrap()
+                .get(format!("https://localhost:{}/conn-info", addr.port()))
+                .send()
+                .await
+                .unwrap()
+                .json()
+                .await
+                .unwrap();
+
+            assert_eq!(info["sni"], "localhost", "{backend}");
+            assert_eq!(info["tls_version"], "TLSv1.3", "{backend}");
+            assert!(
+                !info["cipher"].as_str().unwrap().is_empty(),
+                "{backend}: {info}"
+            );
+            let peer_addr: SocketAddr = info["peer_addr"].as_str().unwrap().parse().unwrap();
+            assert_eq!(peer_addr.ip(), addr.ip(), "{backend}");
+        }
+    }
+
+    /// A client that doesn't verify the checked in, self-signed, server
+    /// certificate.
+    fn insecure_connector() -> openssl::ssl::SslConnectorBuilder {
+        let mut connector = openssl::ssl::SslConnector::builder(SslMethod::tls()).unwrap();
+        connector.set_verify(SslVerifyMode::NONE);
+        connector
+    }
+
+    /// Complete a TLS handshake with the server, offering `session` for
+    /// resumption if there is one.
+    async fn connect(
+        addr: SocketAddr,
+        connector: &openssl::ssl::SslConnector,
+        session: Option<&openssl::ssl::SslSession>,
+    ) -> Result<SslStream<TcpStream>, ssl::Error> {
+        let mut ssl = connector
+            .configure()
+            .unwrap()
+            .into_ssl("localhost")
+            .unwrap();
+        if let Some(session) = session {
+            // SAFETY: the session comes from a connection using the same
+            // connector.
+            unsafe { ssl.set_session(session).unwrap() };
+        }
+
+        let tcp = TcpStream::connect(addr).await.unwrap();
+        let mut stream = SslStream::new(ssl, tcp).unwrap();
+        Pin::new(&mut stream).connect().await?;
+        Ok(stream)
+    }
+
+    /// Make an HTTPS request over a fresh connection, offering the session in
+    /// `cache` if there is one, and storing the new session the server issues.
+    async fn get_with_session_cache(
+        addr: SocketAddr,
+        connector: &openssl::ssl::SslConnector,
+        cache: &std::sync::Mutex<Option<openssl::ssl::SslSession>>,
+    ) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let session = cache.lock().unwrap().clone();
+        let mut stream = connect(addr, connector, session.as_ref()).await.unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        // Read until the server closes, TLS 1.3 tickets arrive after the
+        // handshake.
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await.unwrap();
+        assert!(response.starts_with(b"HTTP/1.1 200 OK"));
+        // OpenSSL won't resume a session whose connection wasn't shut down
+        // cleanly.
+        stream.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn resumed_sessions_are_counted() {
+        use openssl::ssl::SslSessionCacheMode;
+        use tower::ServiceExt;
+
+        for backend in BACKENDS {
+            let limits = HandshakeLimits::new(Duration::from_secs(10), 16);
+            let stats = Arc::new(TlsStats::default());
+            let addr = start(backend, &server_files(None), limits, stats.clone()).await;
+
+            let cache = Arc::new(std::sync::Mutex::new(None));
+            let mut connector = insecure_connector();
+            connector.set_session_cache_mode(SslSessionCacheMode::CLIENT);
+            connector.set_new_session_callback({
+                let cache = cache.clone();
+                move |_, session| *cache.lock().unwrap() = Some(session)
+            });
+            let connector = connector.build();
+
+            get_with_session_cache(addr, &connector, &cache).await;
+            assert!(cache.lock().unwrap().is_some(), "{backend}: no session");
+            get_with_session_cache(addr, &connector, &cache).await;
+
+            let response = redirect_app(3000, stats)
+                .oneshot(
+                    Request::get("/tls-stats")
+                        .body(axum::body::Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(
+                body,
+                serde_json::json!({ "full_handshakes": 1, "resumed_sessions": 1 }),
+                "{backend}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn negotiates_alpn() {
+        for backend in BACKENDS {
+            let limits = HandshakeLimits::new(Duration::from_secs(10), 16);
+            let addr = start(backend, &server_files(None), limits, Arc::default()).await;
+
+            let negotiate = |offered: &'static [u8]| async move {
+                let mut connector = insecure_connector();
+                connector.set_alpn_protos(offered).unwrap();
+                let stream = connect(addr, &connector.build(), None).await?;
+                Ok::<_, ssl::Error>(stream.ssl().selected_alpn_protocol().map(ToOwned::to_owned))
+            };
+
+            assert_eq!(
+                negotiate(b"\x08http/1.1\x02h2").await.unwrap().as_deref(),
+                Some(&b"h2"[..]),
+                "{backend}"
+            );
+            assert_eq!(
+                negotiate(b"\x08http/1.1").await.unwrap().as_deref(),
+                Some(&b"http/1.1"[..]),
+                "{backend}"
+            );
+            assert!(negotiate(b"\x06spdy/1").await.is_err(), "{backend}");
+            // Not offering ALPN at all is fine.
+            let stream = connect(addr, &insecure_connector().build(), None)
+                .await
+                .unwrap();
+            assert_eq!(stream.ssl().selected_alpn_protocol(), None, "{backend}");
+        }
+    }
+
+    #[test]
+    fn tls_backend_args() {
+        let parse = |args: &[&str]| Backend::from_args(args.iter().map(|arg| arg.to_string()));
+
+        assert_eq!(parse(&[]), Ok(Backend::Openssl));
+        assert_eq!(parse(&["--tls-backend", "rustls"]), Ok(Backend::Rustls));
+        assert_eq!(parse(&["--tls-backend=openssl"]), Ok(Backend::Openssl));
+        assert!(parse(&["--tls-backend"]).is_err());
+        assert!(parse(&["--tls-backend", "gnutls"])
+            .unwrap_err()
+            .contains("gnutls"));
+        assert!(parse(&["--verbose"]).is_err());
+    }
+
+    #[test]
+    fn ticket_key_file_must_be_80_bytes() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+
+        std::fs::write(file.path(), [0; 79]).unwrap();
+        let err = TicketKey::from_file(file.path()).unwrap_err();
+        assert!(
+            err.to_string().contains("exactly 80 bytes, found 79"),
+            "{err}"
+        );
+
+        std::fs::write(file.path(), [0; 80]).unwrap();
+        assert!(TicketKey::from_file(file.path()).is_ok());
+
+        let err = TicketKey::from_file(Path::new("/does/not/exist")).unwrap_err();
+        assert!(err.to_string().contains("/does/not/exist"), "{err}");
+    }
+
+    /// Which certificate the server presents for `server_name`.
+    async fn served_subject(addr: SocketAddr, server_name: Option<&str>) -> String {
+        let connector = insecure_connector().build();
+        let mut config = connector.configure().unwrap();
+        config.set_use_server_name_indication(server_name.is_some());
+        let ssl = config.into_ssl(server_name.unwrap_or("ignored")).unwrap();
+
+        let tcp = TcpStream::connect(addr).await.unwrap();
+        let mut stream = SslStream::new(ssl, tcp).unwrap();
+        Pin::new(&mut stream).connect().await.unwrap();
+
+        let cert = stream.ssl().peer_certificate().unwrap();
+        PeerCertInfo::from_cert(&cert).subject
+    }
+
+    /// Add a certificate for `name` to `tls_files`, stored in `dir`.
+    fn add_sni_cert(tls_files: &mut TlsFiles, dir: &tempfile::TempDir, name: &str, pair: &KeyPair) {
+        let cert = dir.path().join(format!("{name}.pem"));
+        let key = dir.path().join(format!("{name}.key"));
+        std::fs::write(&cert, pair.cert.to_pem().unwrap()).unwrap();
+        std::fs::write(&key, pair.key.private_key_to_pem_pkcs8().unwrap()).unwrap();
+        tls_files.sni_certs.insert(name.to_owned(), (cert, key));
+    }
+
+    #[tokio::test]
+    async fn serves_certificate_for_server_name() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut tls_files = server_files(None);
+        add_sni_cert(&mut tls_files, &dir, "a.test", &issue("a.test", 1, None));
+        add_sni_cert(&mut tls_files, &dir, "b.test", &issue("b.test", 2, None));
+
+        for backend in BACKENDS {
+            let limits = HandshakeLimits::new(Duration::from_secs(10), 16);
+            let addr = start(backend, &tls_files, limits, Arc::default()).await;
+
+            assert_eq!(
+                served_subject(addr, Some("a.test")).await,
+                "a.test",
+                "{backend}"
+            );
+            assert_eq!(
+                served_subject(addr, Some("B.Test")).await,
+                "b.test",
+                "{backend}"
+            );
+            // The checked in default certificate.
+            assert_eq!(
+                served_subject(addr, Some("c.test")).await,
+                "localhost",
+                "{backend}"
+            );
+            assert_eq!(served_subject(addr, None).await, "localhost", "{backend}");
+        }
+    }
+
+    #[test]
+    fn invalid_sni_cert_names_the_server_name() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut tls_files = server_files(None);
+        let mismatched = KeyPair {
+            cert: issue("a.test", 1, None).cert,
+            key: issue("a.test", 2, None).key,
+        };
+        add_sni_cert(&mut tls_files, &dir, "a.test", &mismatched);
+
+        let Err(err) = SslAcceptor::build(&tls_files) else {
+            panic!("mismatched key was accepted");
+        };
+        assert!(err.to_string().contains("`a.test`"), "{err}");
+        let Err(err) = TlsAcceptor::build(&tls_files) else {
+            panic!("mismatched key was accepted");
+        };
+        assert!(err.to_string().contains("`a.test`"), "{err}");
+    }
+
+    #[test]
+    fn parse_tls_certs() {
+        let certs =
+            sni::parse_sni_certs("Example.com=certs/a.pem:certs/a.key, other.test=b.pem:b.key,")
+                .unwrap();
+        assert_eq!(certs.len(), 2);
+        assert_eq!(
+            certs["example.com"],
+            (PathBuf::from("certs/a.pem"), PathBuf::from("certs/a.key"))
+        );
+        assert_eq!(
+            certs["other.test"],
+            (PathBuf::from("b.pem"), PathBuf::from("b.key"))
+        );
+
+        assert!(sni::parse_sni_certs("").unwrap().is_empty());
+        for invalid in [
+            "example.com",
+            "example.com=a.pem",
+            "=a.pem:a.key",
+            "a=b:c,A=d:e",
+        ] {
+            assert!(sni::parse_sni_certs(invalid).is_err(), "{invalid}");
+        }
+    }
+}
@@ -0,0 +1,238 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    fs::File,
+    io::{self, BufReader},
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use openssl::x509::X509;
+use tokio::net::TcpStream;
+use tokio_rustls::{
+    rustls::{
+        self,
+        crypto::CryptoProvider,
+        pki_types::{CertificateDer, PrivateKeyDer},
+        server::{
+            danger::ClientCertVerifier, ClientHello, ResolvesServerCert, VerifierBuilderError,
+            WebPkiClientVerifier,
+        },
+        sign::CertifiedKey,
+        HandshakeKind, ProtocolVersion, RootCertStore, ServerConfig,
+    },
+    server::TlsStream,
+    TlsAcceptor,
+};
+
+use crate::{Negotiated, PeerCertInfo, TlsBackend, TlsConnectionInfo, TlsFiles};
+
+impl TlsBackend for TlsAcceptor {
+    type Stream = TlsStream<TcpStream>;
+    type BuildError = RustlsError;
+    type HandshakeError = io::Error;
+
+    fn build(tls_files: &TlsFiles) -> Result<Self, Self::BuildError> {
+        server_config(tls_files).map(|config| TlsAcceptor::from(Arc::new(config)))
+    }
+
+    async fn accept(&self, cnx: TcpStream) -> Result<Self::Stream, Self::HandshakeError> {
+        TlsAcceptor::accept(self, cnx).await
+    }
+
+    fn is_client_cert_error(err: &Self::HandshakeError) -> bool {
+        matches!(
+            err.get_ref()
+                .and_then(|err| err.downcast_ref::<rustls::Error>()),
+            Some(rustls::Error::NoCertificatesPresented | rustls::Error::InvalidCertificate(_))
+        )
+    }
+
+    fn negotiated(stream: &Self::Stream, peer_addr: SocketAddr) -> Negotiated {
+        let (_, conn) = stream.get_ref();
+
+        // Use the same names as OpenSSL.
+        let tls_version = match conn.protocol_version() {
+            Some(ProtocolVersion::TLSv1_3) => "TLSv1.3".to_owned(),
+            Some(ProtocolVersion::TLSv1_2) => "TLSv1.2".to_owned(),
+            Some(version) => format!("{version:?}"),
+            None => String::new(),
+        };
+        let info = TlsConnectionInfo {
+            peer_addr,
+            tls_version,
+            cipher: conn
+                .negotiated_cipher_suite()
+                .and_then(|suite| suite.suite().as_str())
+                .unwrap_or_default()
+                .to_owned(),
+            sni: conn.server_name().map(ToOwned::to_owned),
+            alpn: conn
+                .alpn_protocol()
+                .map(|alpn| String::from_utf8_lossy(alpn).into_owned()),
+        };
+
+        let peer_cert = conn
+            .peer_certificates()
+            .and_then(|certs| certs.first())
+            .and_then(|cert| X509::from_der(cert).ok())
+            .map(|cert| PeerCertInfo::from_cert(&cert));
+
+        Negotiated {
+            info,
+            peer_cert,
+            resumed: conn.handshake_kind() == Some(HandshakeKind::Resumed),
+        }
+    }
+}
+
+/// The rustls equivalent of `TlsFiles::build_acceptor`: TLS 1.3 only, HTTP/2
+/// and HTTP/1.1 over ALPN, certificates picked by server name, and client
+/// certificates required when `client_ca` is set.
+fn server_config(tls_files: &TlsFiles) -> Result<ServerConfig, RustlsError> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+
+    let builder = ServerConfig::builder_with_provider(provider.clone())
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .map_err(RustlsError::Rustls)?;
+    let builder = match &tls_files.client_ca {
+        Some(client_ca) => {
+            builder.with_client_cert_verifier(client_verifier(client_ca, provider.clone())?)
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    let by_name = tls_files
+        .sni_certs
+        .iter()
+        .map(|(name, (cert, key))| {
+            certified_key(cert, key, &provider)
+                .map(|certified| (name.clone(), certified))
+                .map_err(|source| RustlsError::ServerName {
+                    name: name.clone(),
+                    source: Box::new(source),
+                })
+        })
+        .collect::<Result<_, _>>()?;
+    let resolver = SniResolver {
+        default: certified_key(&tls_files.cert, &tls_files.key, &provider)?,
+        by_name,
+    };
+
+    let mut config = builder.with_cert_resolver(Arc::new(resolver));
+
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+    Ok(config)
+}
+
+/// Serves the certificate for the client's server name, like
+/// `select_context` does for OpenSSL.
+#[derive(Debug)]
+struct SniResolver {
+    default: Arc<CertifiedKey>,
+    by_name: HashMap<String, Arc<CertifiedKey>>,
+}
+
+impl ResolvesServerCert for SniResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        let certified = client_hello
+            .server_name()
+            .and_then(|name| self.by_name.get(&name.to_ascii_lowercase()))
+            .unwrap_or(&self.default);
+        Some(certified.clone())
+    }
+}
+
+/// Load a certificate chain and its key, checking that they belong together.
+fn certified_key(
+    cert: &Path,
+    key: &Path,
+    provider: &CryptoProvider,
+) -> Result<Arc<CertifiedKey>, RustlsError> {
+    let certified = CertifiedKey::from_der(load_certs(cert)?, load_key(key)?, provider)
+        .map_err(RustlsError::Rustls)?;
+    Ok(Arc::new(certified))
+}
+
+fn client_verifier(
+    client_ca: &Path,
+    provider: Arc<CryptoProvider>,
+) -> Result<Arc<dyn ClientCertVerifier>, RustlsError> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(client_ca)? {
+        roots.add(cert).map_err(RustlsError::Rustls)?;
+    }
+
+    WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+        .build()
+        .map_err(RustlsError::ClientVerifier)
+}
+
+fn load_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>, RustlsError> {
+    let read_error = |source| RustlsError::Read {
+        path: path.to_owned(),
+        source,
+    };
+
+    let file = File::open(path).map_err(read_error)?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(read_error)?;
+    if certs.is_empty() {
+        return Err(RustlsError::NoCertificates(path.to_owned()));
+    }
+    Ok(certs)
+}
+
+fn load_key(path: &Path) -> Result<PrivateKeyDer<'static>, RustlsError> {
+    let read_error = |source| RustlsError::Read {
+        path: path.to_owned(),
+        source,
+    };
+
+    let file = File::open(path).map_err(read_error)?;
+    rustls_pemfile::private_key(&mut BufReader::new(file))
+        .map_err(read_error)?
+        .ok_or_else(|| RustlsError::NoPrivateKey(path.to_owned()))
+}
+
+#[derive(Debug)]
+pub enum RustlsError {
+    Read {
+        path: PathBuf,
+        source: io::Error,
+    },
+    NoCertificates(PathBuf),
+    NoPrivateKey(PathBuf),
+    ClientVerifier(VerifierBuilderError),
+    Rustls(rustls::Error),
+    ServerName {
+        name: String,
+        source: Box<RustlsError>,
+    },
+}
+
+impl fmt::Display for RustlsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Read { path, source } => {
+                write!(f, "failed to read {}: {source}", path.display())
+            }
+            Self::NoCertificates(path) => {
+                write!(f, "no PEM certificates found in {}", path.display())
+            }
+            Self::NoPrivateKey(path) => {
+                write!(f, "no PEM private key found in {}", path.display())
+            }
+            Self::ClientVerifier(err) => write!(f, "invalid client CA: {err}"),
+            Self::Rustls(err) => write!(f, "{err}"),
+            Self::ServerName { name, source } => {
+                write!(f, "invalid certificate for `{name}`: {source}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for RustlsError {}
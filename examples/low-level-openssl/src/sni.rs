@@ -0,0 +1,38 @@
+use std::{collections::HashMap, path::PathBuf};
+
+/// Certificate and key files to serve for each server name, keyed by the
+/// lowercased name.
+pub type SniCerts = HashMap<String, (PathBuf, PathBuf)>;
+
+/// Read the per name certificates from `TLS_CERTS`, none if it isn't set.
+pub fn sni_certs_from_env() -> Result<SniCerts, String> {
+    match std::env::var("TLS_CERTS") {
+        Ok(spec) => parse_sni_certs(&spec),
+        Err(_) => Ok(SniCerts::new()),
+    }
+}
+
+/// Parse a list like `example.com=certs/a.pem:certs/a.key,other.test=...`.
+pub fn parse_sni_certs(spec: &str) -> Result<SniCerts, String> {
+    let mut certs = SniCerts::new();
+    for entry in spec
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+    {
+        let invalid = || format!("invalid TLS_CERTS entry `{entry}`, expected `name=cert:key`");
+
+        let (name, files) = entry.split_once('=').ok_or_else(invalid)?;
+        let (cert, key) = files.split_once(':').ok_or_else(invalid)?;
+        if name.is_empty() || cert.is_empty() || key.is_empty() {
+            return Err(invalid());
+        }
+
+        let name = name.to_ascii_lowercase();
+        if certs.contains_key(&name) {
+            return Err(format!("duplicate TLS_CERTS entry for `{name}`"));
+        }
+        certs.insert(name, (PathBuf::from(cert), PathBuf::from(key)));
+    }
+    Ok(certs)
+}
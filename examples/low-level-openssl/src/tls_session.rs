@@ -0,0 +1,157 @@
+use std::{
+    fmt, io,
+    os::raw::{c_long, c_void},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use openssl::{
+    error::ErrorStack,
+    ssl::{SslContextBuilder, SslSessionCacheMode},
+};
+use serde::Serialize;
+
+/// How long a session can be resumed for.
+const SESSION_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+
+/// 16 bytes of key name followed by 32 bytes each of HMAC and AES key.
+const TICKET_KEY_LEN: usize = 80;
+
+/// From OpenSSL's `tls1.h`, `openssl-sys` doesn't expose it.
+const SSL_CTRL_SET_TLSEXT_TICKET_KEYS: i32 = 59;
+
+extern "C" {
+    // Not bound by `openssl-sys` either, but part of libssl.
+    fn SSL_CTX_set_timeout(ctx: *mut openssl_sys::SSL_CTX, timeout: c_long) -> c_long;
+}
+
+/// The key session tickets are encrypted with.
+///
+/// OpenSSL generates a new one for every context, which would invalidate all
+/// tickets whenever the acceptor is rebuilt, and differ between instances
+/// behind a load balancer.
+#[derive(Clone)]
+pub struct TicketKey([u8; TICKET_KEY_LEN]);
+
+impl TicketKey {
+    /// Load the key from the file in `TLS_TICKET_KEY_FILE`, or generate one
+    /// for this process if it isn't set.
+    pub fn from_env() -> Result<Self, TicketKeyError> {
+        match std::env::var_os("TLS_TICKET_KEY_FILE") {
+            Some(path) => Self::from_file(Path::new(&path)),
+            None => Ok(Self::random()),
+        }
+    }
+
+    /// Read a key of exactly 80 bytes, e.g. made with `openssl rand 80`.
+    pub fn from_file(path: &Path) -> Result<Self, TicketKeyError> {
+        let bytes = std::fs::read(path).map_err(|source| TicketKeyError::Read {
+            path: path.to_owned(),
+            source,
+        })?;
+        let key = bytes
+            .try_into()
+            .map_err(|bytes: Vec<u8>| TicketKeyError::Length {
+                path: path.to_owned(),
+                len: bytes.len(),
+            })?;
+        Ok(Self(key))
+    }
+
+    pub fn random() -> Self {
+        let mut key = [0; TICKET_KEY_LEN];
+        openssl::rand::rand_bytes(&mut key).unwrap();
+        Self(key)
+    }
+}
+
+impl fmt::Debug for TicketKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("TicketKey(..)")
+    }
+}
+
+#[derive(Debug)]
+pub enum TicketKeyError {
+    Read { path: PathBuf, source: io::Error },
+    Length { path: PathBuf, len: usize },
+}
+
+impl fmt::Display for TicketKeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Read { path, source } => write!(
+                f,
+                "failed to read TLS_TICKET_KEY_FILE {}: {source}",
+                path.display()
+            ),
+            Self::Length { path, len } => write!(
+                f,
+                "TLS_TICKET_KEY_FILE {} must contain exactly {TICKET_KEY_LEN} bytes, found {len}",
+                path.display()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TicketKeyError {}
+
+/// Let clients resume sessions, both from the server side cache and with
+/// tickets encrypted with `ticket_key`.
+pub fn configure_resumption(
+    builder: &mut SslContextBuilder,
+    ticket_key: &TicketKey,
+) -> Result<(), ErrorStack> {
+    // Required for resumption when client certificates are verified.
+    builder.set_session_id_context(b"low-level-openssl")?;
+    builder.set_session_cache_mode(SslSessionCacheMode::SERVER);
+
+    // SAFETY: the context pointer is valid for the lifetime of `builder`, and
+    // OpenSSL copies the 80 byte key rather than keeping the pointer.
+    let ret = unsafe {
+        SSL_CTX_set_timeout(builder.as_ptr(), SESSION_TIMEOUT.as_secs() as c_long);
+        openssl_sys::SSL_CTX_ctrl(
+            builder.as_ptr(),
+            SSL_CTRL_SET_TLSEXT_TICKET_KEYS,
+            TICKET_KEY_LEN as c_long,
+            ticket_key.0.as_ptr() as *mut c_void,
+        )
+    };
+    if ret != 1 {
+        return Err(ErrorStack::get());
+    }
+
+    Ok(())
+}
+
+/// Counts of completed handshakes, by whether a session was resumed.
+#[derive(Debug, Default)]
+pub struct TlsStats {
+    full_handshakes: AtomicU64,
+    resumed_sessions: AtomicU64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TlsStatsSnapshot {
+    pub full_handshakes: u64,
+    pub resumed_sessions: u64,
+}
+
+impl TlsStats {
+    pub fn record(&self, resumed: bool) {
+        let counter = if resumed {
+            &self.resumed_sessions
+        } else {
+            &self.full_handshakes
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> TlsStatsSnapshot {
+        TlsStatsSnapshot {
+            full_handshakes: self.full_handshakes.load(Ordering::Relaxed),
+            resumed_sessions: self.resumed_sessions.load(Ordering::Relaxed),
+        }
+    }
+}
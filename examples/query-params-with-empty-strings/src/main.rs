@@ -1,11 +1,20 @@
 use std::fmt;
+use std::ops::Deref;
 use std::str::FromStr;
 
-use axum::extract::Query;
+use axum::async_trait;
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
 use axum::routing::get;
-use axum::Router;
-use serde::de::Error;
-use serde::{de, Deserialize, Deserializer};
+use axum::{Json, Router};
+use serde::de::{DeserializeOwned, Error};
+use serde::{Deserialize, Deserializer};
+use serde_json::json;
+use time::format_description::well_known::Rfc3339;
+use time::macros::format_description;
+use time::{Date, OffsetDateTime, UtcOffset};
 
 #[tokio::main]
 async fn main() {
@@ -17,19 +26,105 @@ async fn main() {
 }
 
 fn app() -> Router {
-    Router::new().route("/", get(handler))
+    Router::new()
+        .route("/", get(handler))
+        .route("/multi", get(multi_handler))
 }
 
-async fn handler(Query(params): Query<Params>) -> String {
+async fn handler(AppQuery(params): AppQuery<Params>) -> String {
     format!("{params:?}")
 }
 
+async fn multi_handler(params: MultiParams) -> String {
+    format!("{params:?}")
+}
+
+/// Like `Query` but rejects with a JSON body naming the bad parameter, if it
+/// can be identified.
+struct AppQuery<T>(T);
+
+#[async_trait]
+impl<T, S> FromRequestParts<S> for AppQuery<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = QueryError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let query = parts.uri.query().unwrap_or_default();
+        // `form_urlencoded::parse` would quietly replace invalid UTF-8 with
+        // U+FFFD, so reject the query string as a whole instead.
+        if percent_encoding::percent_decode_str(query)
+            .decode_utf8()
+            .is_err()
+        {
+            return Err(QueryError {
+                parameter: None,
+                detail: "query string is not valid UTF-8".to_owned(),
+            });
+        }
+        let de = serde_urlencoded::Deserializer::new(form_urlencoded::parse(query.as_bytes()));
+        serde_path_to_error::deserialize(de)
+            .map(AppQuery)
+            .map_err(QueryError::from)
+    }
+}
+
+#[derive(Debug)]
+struct QueryError {
+    parameter: Option<String>,
+    detail: String,
+}
+
+impl From<serde_path_to_error::Error<serde_urlencoded::de::Error>> for QueryError {
+    fn from(err: serde_path_to_error::Error<serde_urlencoded::de::Error>) -> Self {
+        let detail = err.inner().to_string();
+        // Errors raised for the struct as a whole, like duplicate fields, have
+        // an empty path but usually mention the field in the message.
+        let parameter = match err.path().to_string() {
+            path if path != "." => Some(path),
+            _ => field_from_message(&detail),
+        };
+        Self { parameter, detail }
+    }
+}
+
+/// Pull the field out of serde messages like "duplicate field `foo`".
+fn field_from_message(message: &str) -> Option<String> {
+    let (_, rest) = message.split_once("field `")?;
+    let (field, _) = rest.split_once('`')?;
+    Some(field.to_owned())
+}
+
+impl IntoResponse for QueryError {
+    fn into_response(self) -> Response {
+        let body = json!({
+            "error": "invalid query parameter",
+            "parameter": self.parameter,
+            "detail": self.detail,
+        });
+        (StatusCode::BAD_REQUEST, Json(body)).into_response()
+    }
+}
+
 #[derive(Debug, Deserialize)]
 #[allow(dead_code)]
 struct Params {
     #[serde(default, deserialize_with = "empty_string_as_none")]
     foo: Option<i32>,
     bar: Option<String>,
+    #[serde(default, deserialize_with = "comma_separated")]
+    tags: Vec<String>,
+    #[serde(default, deserialize_with = "comma_separated")]
+    nums: Vec<i32>,
+    #[serde(default, deserialize_with = "lenient_bool")]
+    active: Option<bool>,
+    #[serde(default, deserialize_with = "since")]
+    since: Option<Date>,
+    // The same as `foo` without needing the attribute.
+    page: NoneIfEmpty<u32>,
+    q: NoneIfBlank<String>,
 }
 
 fn empty_string_as_none<'de, D, T>(de: D) -> Result<Option<T>, D::Error>
@@ -45,70 +140,456 @@ where
     }
 }
 
+/// An optional value where a missing parameter and an empty string are both
+/// `None`, like `empty_string_as_none` but usable as a field type.
+///
+/// With `BLANK` set, whitespace-only strings are `None` too, see
+/// [`NoneIfBlank`].
+struct NoneIfEmpty<T, const BLANK: bool = false>(pub Option<T>);
+
+/// Like [`NoneIfEmpty`] but strings made up of only whitespace are also `None`.
+type NoneIfBlank<T> = NoneIfEmpty<T, true>;
+
+impl<'de, T, const BLANK: bool> Deserialize<'de> for NoneIfEmpty<T, BLANK>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    fn deserialize<D>(de: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        // Missing fields are deserialized as `None` too, so this doesn't need
+        // `#[serde(default)]`.
+        let Some(s) = Option::<String>::deserialize(de)? else {
+            return Ok(Self(None));
+        };
+        if s.is_empty() || (BLANK && s.trim().is_empty()) {
+            return Ok(Self(None));
+        }
+        T::from_str(&s)
+            .map(|value| Self(Some(value)))
+            .map_err(|err| Error::custom(format!("invalid value `{s}`: {err}")))
+    }
+}
+
+impl<T: fmt::Debug, const BLANK: bool> fmt::Debug for NoneIfEmpty<T, BLANK> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl<T, const BLANK: bool> Deref for NoneIfEmpty<T, BLANK> {
+    type Target = Option<T>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<T, const BLANK: bool> From<NoneIfEmpty<T, BLANK>> for Option<T> {
+    fn from(value: NoneIfEmpty<T, BLANK>) -> Self {
+        value.0
+    }
+}
+
+impl<T, const BLANK: bool> From<Option<T>> for NoneIfEmpty<T, BLANK> {
+    fn from(value: Option<T>) -> Self {
+        Self(value)
+    }
+}
+
+/// Deserialize `a,b,c` into a `Vec`, ignoring empty elements so that an empty
+/// string and trailing commas are fine.
+fn comma_separated<'de, D, T>(de: D) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    let s = String::deserialize(de)?;
+    s.split(',')
+        .filter(|element| !element.is_empty())
+        .map(|element| {
+            T::from_str(element)
+                .map_err(|err| Error::custom(format!("invalid element `{element}`: {err}")))
+        })
+        .collect()
+}
+
+/// Deserialize the many ways clients spell booleans. An empty value counts as
+/// `true` since the key being present at all usually means "enabled", like
+/// with `<input type="checkbox">`.
+fn lenient_bool<'de, D>(de: D) -> Result<Option<bool>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s = String::deserialize(de)?;
+    match s.to_ascii_lowercase().as_str() {
+        "" | "true" | "1" | "on" | "yes" => Ok(Some(true)),
+        "false" | "0" | "off" | "no" => Ok(Some(false)),
+        _ => Err(Error::custom(format!(
+            "invalid boolean `{s}`, expected true/1/on/yes or false/0/off/no"
+        ))),
+    }
+}
+
+fn since<'de, D>(de: D) -> Result<Option<Date>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s = String::deserialize(de)?;
+    if s.is_empty() {
+        return Ok(None);
+    }
+    parse_flexible_date(&s)
+        .map(Some)
+        .map_err(|err| Error::custom(format!("invalid `since` parameter: {err}")))
+}
+
+/// Parse a date given as `2024-01-31`, an RFC 3339 timestamp like
+/// `2024-01-31T10:00:00Z` or a unix timestamp like `1706659200`. Timestamps
+/// are converted to UTC before taking the date.
+fn parse_flexible_date(s: &str) -> Result<Date, DateParseError> {
+    if let Ok(date) = Date::parse(s, format_description!("[year]-[month]-[day]")) {
+        return Ok(date);
+    }
+
+    let datetime = OffsetDateTime::parse(s, &Rfc3339).ok().or_else(|| {
+        s.parse()
+            .ok()
+            .and_then(|secs| OffsetDateTime::from_unix_timestamp(secs).ok())
+    });
+    match datetime {
+        Some(datetime) => Ok(datetime.to_offset(UtcOffset::UTC).date()),
+        None => Err(DateParseError(s.to_owned())),
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct DateParseError(String);
+
+impl fmt::Display for DateParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "`{}` is not a date, expected YYYY-MM-DD, an RFC 3339 timestamp or a unix timestamp",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for DateParseError {}
+
+/// Query parameters where `id` may be repeated, as in `?id=1&id=2`.
+///
+/// `Query` can't do this since serde_urlencoded only keeps a single value per
+/// key, so this parses the query string by hand. Empty values are skipped.
+#[derive(Debug)]
+#[allow(dead_code)]
+struct MultiParams {
+    id: Vec<i32>,
+    name: Option<String>,
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for MultiParams
+where
+    S: Send + Sync,
+{
+    type Rejection = (StatusCode, String);
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let query = parts.uri.query().unwrap_or_default();
+
+        let mut params = MultiParams {
+            id: Vec::new(),
+            name: None,
+        };
+        for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+            match &*key {
+                "id" if value.is_empty() => {}
+                "id" => {
+                    let id = value.parse().map_err(|err| {
+                        (
+                            StatusCode::BAD_REQUEST,
+                            format!("invalid id `{value}`: {err}"),
+                        )
+                    })?;
+                    params.id.push(id);
+                }
+                "name" => params.name = Some(value.into_owned()),
+                _ => {}
+            }
+        }
+        Ok(params)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use axum::body::Body;
     use axum::extract::Request;
+    use axum::http::{header, StatusCode};
     use http_body_util::BodyExt;
     use tower::ServiceExt;
 
-    use crate::app;
+    use time::macros::date;
+
+    use serde_json::{json, Value};
+
+    use crate::{app, parse_flexible_date, DateParseError};
+
+    #[test]
+    fn flexible_dates() {
+        for s in ["2024-01-31", "2024-01-31T10:00:00Z", "1706659200"] {
+            assert_eq!(parse_flexible_date(s), Ok(date!(2024 - 01 - 31)), "{s}");
+        }
+
+        // Timestamps with an offset are converted to UTC first.
+        assert_eq!(
+            parse_flexible_date("2024-01-31T23:30:00-01:00"),
+            Ok(date!(2024 - 02 - 01))
+        );
+
+        for s in ["", "yesterday", "2024-13-01", "2024-01-31T10:00:00"] {
+            assert_eq!(parse_flexible_date(s), Err(DateParseError(s.to_owned())));
+        }
+    }
 
     #[tokio::test]
     async fn test_something() {
         assert_eq!(
-            send_request_get_body("foo=1&bar=bar").await,
-            r#"Params { foo: Some(1), bar: Some("bar") }"#,
+            send_request_get_body("/", "foo=1&bar=bar").await,
+            r#"Params { foo: Some(1), bar: Some("bar"), tags: [], nums: [], active: None, since: None, page: None, q: None }"#,
+        );
+
+        assert_eq!(
+            send_request_get_body("/", "foo=&bar=bar").await,
+            r#"Params { foo: None, bar: Some("bar"), tags: [], nums: [], active: None, since: None, page: None, q: None }"#,
+        );
+
+        assert_eq!(
+            send_request_get_body("/", "foo=&bar=").await,
+            r#"Params { foo: None, bar: Some(""), tags: [], nums: [], active: None, since: None, page: None, q: None }"#,
+        );
+
+        assert_eq!(
+            send_request_get_body("/", "foo=1").await,
+            r#"Params { foo: Some(1), bar: None, tags: [], nums: [], active: None, since: None, page: None, q: None }"#,
+        );
+
+        assert_eq!(
+            send_request_get_body("/", "bar=bar").await,
+            r#"Params { foo: None, bar: Some("bar"), tags: [], nums: [], active: None, since: None, page: None, q: None }"#,
+        );
+
+        assert_eq!(
+            send_request_get_body("/", "foo=").await,
+            r#"Params { foo: None, bar: None, tags: [], nums: [], active: None, since: None, page: None, q: None }"#,
+        );
+
+        assert_eq!(
+            send_request_get_body("/", "bar=").await,
+            r#"Params { foo: None, bar: Some(""), tags: [], nums: [], active: None, since: None, page: None, q: None }"#,
+        );
+
+        assert_eq!(
+            send_request_get_body("/", "").await,
+            r#"Params { foo: None, bar: None, tags: [], nums: [], active: None, since: None, page: None, q: None }"#,
+        );
+
+        assert_eq!(
+            send_request_get_body("/", "tags=").await,
+            r#"Params { foo: None, bar: None, tags: [], nums: [], active: None, since: None, page: None, q: None }"#,
+        );
+
+        assert_eq!(
+            send_request_get_body("/", "tags=a").await,
+            r#"Params { foo: None, bar: None, tags: ["a"], nums: [], active: None, since: None, page: None, q: None }"#,
+        );
+
+        assert_eq!(
+            send_request_get_body("/", "tags=a,b,").await,
+            r#"Params { foo: None, bar: None, tags: ["a", "b"], nums: [], active: None, since: None, page: None, q: None }"#,
+        );
+
+        assert_eq!(
+            send_request_get_body("/", "nums=1,2").await,
+            r#"Params { foo: None, bar: None, tags: [], nums: [1, 2], active: None, since: None, page: None, q: None }"#,
+        );
+
+        assert_eq!(
+            send_request_get_body("/", "nums=1,x").await,
+            query_error(
+                Some("nums"),
+                "invalid element `x`: invalid digit found in string"
+            ),
+        );
+
+        assert_eq!(
+            send_request_get_body("/", "active=").await,
+            r#"Params { foo: None, bar: None, tags: [], nums: [], active: Some(true), since: None, page: None, q: None }"#,
+        );
+
+        assert_eq!(
+            send_request_get_body("/", "active=ON").await,
+            r#"Params { foo: None, bar: None, tags: [], nums: [], active: Some(true), since: None, page: None, q: None }"#,
+        );
+
+        assert_eq!(
+            send_request_get_body("/", "active=0").await,
+            r#"Params { foo: None, bar: None, tags: [], nums: [], active: Some(false), since: None, page: None, q: None }"#,
+        );
+
+        assert_eq!(
+            send_request_get_body("/", "active=maybe").await,
+            query_error(
+                Some("active"),
+                "invalid boolean `maybe`, expected true/1/on/yes or false/0/off/no"
+            ),
+        );
+
+        for since in ["2024-01-31", "2024-01-31T10:00:00Z", "1706659200"] {
+            assert_eq!(
+                send_request_get_body("/", &format!("since={since}")).await,
+                r#"Params { foo: None, bar: None, tags: [], nums: [], active: None, since: Some(2024-01-31), page: None, q: None }"#,
+                "{since}",
+            );
+        }
+
+        assert_eq!(
+            send_request_get_body("/", "since=").await,
+            r#"Params { foo: None, bar: None, tags: [], nums: [], active: None, since: None, page: None, q: None }"#,
+        );
+
+        assert_eq!(
+            send_request_get_body("/", "since=soon").await,
+            query_error(Some("since"), "invalid `since` parameter: `soon` is not a date, expected YYYY-MM-DD, an RFC 3339 timestamp or a unix timestamp"),
+        );
+
+        assert_eq!(
+            send_request_get_body("/", "page=2&q=rust").await,
+            r#"Params { foo: None, bar: None, tags: [], nums: [], active: None, since: None, page: Some(2), q: Some("rust") }"#,
         );
 
         assert_eq!(
-            send_request_get_body("foo=&bar=bar").await,
-            r#"Params { foo: None, bar: Some("bar") }"#,
+            send_request_get_body("/", "page=&q=").await,
+            r#"Params { foo: None, bar: None, tags: [], nums: [], active: None, since: None, page: None, q: None }"#,
         );
 
         assert_eq!(
-            send_request_get_body("foo=&bar=").await,
-            r#"Params { foo: None, bar: Some("") }"#,
+            send_request_get_body("/", "q=%20%20").await,
+            r#"Params { foo: None, bar: None, tags: [], nums: [], active: None, since: None, page: None, q: None }"#,
         );
 
         assert_eq!(
-            send_request_get_body("foo=1").await,
-            r#"Params { foo: Some(1), bar: None }"#,
+            send_request_get_body("/", "page=%20").await,
+            query_error(
+                Some("page"),
+                "invalid value ` `: invalid digit found in string"
+            ),
         );
 
         assert_eq!(
-            send_request_get_body("bar=bar").await,
-            r#"Params { foo: None, bar: Some("bar") }"#,
+            send_request_get_body("/", "page=two").await,
+            query_error(
+                Some("page"),
+                "invalid value `two`: invalid digit found in string"
+            ),
+        );
+    }
+
+    #[tokio::test]
+    async fn repeated_keys() {
+        assert_eq!(
+            send_request_get_body("/multi", "id=1&id=2&id=3").await,
+            r#"MultiParams { id: [1, 2, 3], name: None }"#,
         );
 
         assert_eq!(
-            send_request_get_body("foo=").await,
-            r#"Params { foo: None, bar: None }"#,
+            send_request_get_body("/multi", "id=5&name=ferris").await,
+            r#"MultiParams { id: [5], name: Some("ferris") }"#,
         );
 
         assert_eq!(
-            send_request_get_body("bar=").await,
-            r#"Params { foo: None, bar: Some("") }"#,
+            send_request_get_body("/multi", "id=&id=2").await,
+            r#"MultiParams { id: [2], name: None }"#,
         );
 
         assert_eq!(
-            send_request_get_body("").await,
-            r#"Params { foo: None, bar: None }"#,
+            send_request("/multi", "id=1&id=abc").await,
+            (
+                StatusCode::BAD_REQUEST,
+                "invalid id `abc`: invalid digit found in string".to_owned(),
+            ),
         );
+
+        // The `/` route rejects the repeated key instead.
+        assert_eq!(
+            send_request("/", "foo=1&foo=2").await,
+            (
+                StatusCode::BAD_REQUEST,
+                query_error(Some("foo"), "duplicate field `foo`"),
+            ),
+        );
+    }
+
+    #[tokio::test]
+    async fn json_query_errors() {
+        let response = app()
+            .oneshot(Request::get("/?foo=abc").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            body,
+            json!({
+                "error": "invalid query parameter",
+                "parameter": "foo",
+                "detail": "invalid digit found in string",
+            })
+        );
+
+        // There's no field to blame when the query string can't be decoded at
+        // all.
+        assert_eq!(
+            send_request("/", "%FF%FE").await,
+            (
+                StatusCode::BAD_REQUEST,
+                query_error(None, "query string is not valid UTF-8"),
+            ),
+        );
+    }
+
+    fn query_error(parameter: Option<&str>, detail: &str) -> String {
+        json!({
+            "error": "invalid query parameter",
+            "parameter": parameter,
+            "detail": detail,
+        })
+        .to_string()
+    }
+
+    async fn send_request_get_body(path: &str, query: &str) -> String {
+        send_request(path, query).await.1
     }
 
-    async fn send_request_get_body(query: &str) -> String {
-        let body = app()
+    async fn send_request(path: &str, query: &str) -> (StatusCode, String) {
+        let response = app()
             .oneshot(
                 Request::builder()
-                    .uri(format!("/?{query}"))
+                    .uri(format!("{path}?{query}"))
                     .body(Body::empty())
                     .unwrap(),
             )
             .await
-            .unwrap()
-            .into_body();
-        let bytes = body.collect().await.unwrap().to_bytes();
-        String::from_utf8(bytes.to_vec()).unwrap()
+            .unwrap();
+        let status = response.status();
+        let bytes = response.into_body().collect().await.unwrap().to_bytes();
+        (status, String::from_utf8(bytes.to_vec()).unwrap())
     }
 }
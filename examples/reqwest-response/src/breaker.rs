@@ -0,0 +1,229 @@
+use std::{collections::HashMap, sync::Mutex, time::Duration};
+
+use tokio::time::Instant;
+
+use crate::timeouts::env_millis;
+
+/// Stops sending requests to an upstream that keeps failing, giving it time
+/// to recover, and then lets a single request through to see if it has.
+///
+/// Upstreams are told apart by their host and port. Uses tokio's clock, so
+/// the tests can move time along.
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    /// Consecutive failures that open the circuit.
+    threshold: u32,
+    /// The failures have to happen within this long of the first one.
+    window: Duration,
+    /// How long the circuit stays open before letting a probe through.
+    cooldown: Duration,
+    circuits: Mutex<HashMap<String, Circuit>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Circuit {
+    Closed {
+        failures: u32,
+        since: Instant,
+    },
+    Open {
+        until: Instant,
+    },
+    /// A probe went out at `probing`. If it never comes back, another one is
+    /// let through after the cooldown.
+    HalfOpen {
+        probing: Instant,
+    },
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        Self::new(5, Duration::from_secs(10), Duration::from_secs(30))
+    }
+}
+
+impl CircuitBreaker {
+    pub fn new(threshold: u32, window: Duration, cooldown: Duration) -> Self {
+        Self {
+            threshold,
+            window,
+            cooldown,
+            circuits: Mutex::default(),
+        }
+    }
+
+    /// From `PROXY_BREAKER_FAILURES`, `PROXY_BREAKER_WINDOW_MS` and
+    /// `PROXY_BREAKER_COOLDOWN_MS`, with the defaults for those that aren't
+    /// set.
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self::new(
+            std::env::var("PROXY_BREAKER_FAILURES")
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(defaults.threshold),
+            env_millis("PROXY_BREAKER_WINDOW_MS").unwrap_or(defaults.window),
+            env_millis("PROXY_BREAKER_COOLDOWN_MS").unwrap_or(defaults.cooldown),
+        )
+    }
+
+    /// Whether a request can go to `upstream`, or how long until it's worth
+    /// trying again if not.
+    pub fn check(&self, upstream: &str) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut circuits = self.circuits.lock().unwrap();
+        let Some(circuit) = circuits.get_mut(upstream) else {
+            return Ok(());
+        };
+        match *circuit {
+            Circuit::Closed { .. } => Ok(()),
+            Circuit::Open { until } if now < until => Err(until - now),
+            Circuit::Open { .. } => {
+                tracing::info!(upstream, "circuit half-open, probing");
+                *circuit = Circuit::HalfOpen { probing: now };
+                Ok(())
+            }
+            Circuit::HalfOpen { probing } if now < probing + self.cooldown => {
+                Err(probing + self.cooldown - now)
+            }
+            Circuit::HalfOpen { .. } => {
+                tracing::info!(upstream, "probe never finished, probing again");
+                *circuit = Circuit::HalfOpen { probing: now };
+                Ok(())
+            }
+        }
+    }
+
+    pub fn record_success(&self, upstream: &str) {
+        let mut circuits = self.circuits.lock().unwrap();
+        if let Some(Circuit::HalfOpen { .. }) = circuits.remove(upstream) {
+            tracing::info!(upstream, "circuit closed");
+        }
+    }
+
+    pub fn record_failure(&self, upstream: &str) {
+        let now = Instant::now();
+        let mut circuits = self.circuits.lock().unwrap();
+        let circuit = circuits
+            .entry(upstream.to_owned())
+            .or_insert(Circuit::Closed {
+                failures: 0,
+                since: now,
+            });
+        match *circuit {
+            Circuit::Closed { failures, since } => {
+                let (failures, since) = if now - since > self.window {
+                    (1, now)
+                } else {
+                    (failures + 1, since)
+                };
+                if failures >= self.threshold {
+                    tracing::info!(upstream, failures, "circuit open");
+                    *circuit = Circuit::Open {
+                        until: now + self.cooldown,
+                    };
+                } else {
+                    *circuit = Circuit::Closed { failures, since };
+                }
+            }
+            // A request let through before the circuit opened.
+            Circuit::Open { .. } => {}
+            Circuit::HalfOpen { .. } => {
+                tracing::info!(upstream, "probe failed, circuit open");
+                *circuit = Circuit::Open {
+                    until: now + self.cooldown,
+                };
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UPSTREAM: &str = "127.0.0.1:4001";
+
+    fn breaker() -> CircuitBreaker {
+        CircuitBreaker::new(3, Duration::from_secs(10), Duration::from_secs(30))
+    }
+
+    fn state(breaker: &CircuitBreaker) -> Option<Circuit> {
+        breaker.circuits.lock().unwrap().get(UPSTREAM).copied()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn opens_probes_and_closes() {
+        let breaker = breaker();
+
+        for _ in 0..2 {
+            breaker.record_failure(UPSTREAM);
+            assert_eq!(breaker.check(UPSTREAM), Ok(()));
+        }
+        breaker.record_failure(UPSTREAM);
+        assert!(matches!(state(&breaker), Some(Circuit::Open { .. })));
+        assert_eq!(breaker.check(UPSTREAM), Err(Duration::from_secs(30)));
+        // Other upstreams aren't affected.
+        assert_eq!(breaker.check("127.0.0.1:4002"), Ok(()));
+
+        tokio::time::advance(Duration::from_secs(20)).await;
+        assert_eq!(breaker.check(UPSTREAM), Err(Duration::from_secs(10)));
+
+        tokio::time::advance(Duration::from_secs(10)).await;
+        assert_eq!(breaker.check(UPSTREAM), Ok(()));
+        assert!(matches!(state(&breaker), Some(Circuit::HalfOpen { .. })));
+        // Only the one probe.
+        assert!(breaker.check(UPSTREAM).is_err());
+
+        breaker.record_success(UPSTREAM);
+        assert_eq!(state(&breaker), None);
+        assert_eq!(breaker.check(UPSTREAM), Ok(()));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn failed_probes_reopen() {
+        let breaker = breaker();
+        for _ in 0..3 {
+            breaker.record_failure(UPSTREAM);
+        }
+
+        tokio::time::advance(Duration::from_secs(30)).await;
+        assert_eq!(breaker.check(UPSTREAM), Ok(()));
+        breaker.record_failure(UPSTREAM);
+        assert_eq!(breaker.check(UPSTREAM), Err(Duration::from_secs(30)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn lost_probes_are_replaced() {
+        let breaker = breaker();
+        for _ in 0..3 {
+            breaker.record_failure(UPSTREAM);
+        }
+        tokio::time::advance(Duration::from_secs(30)).await;
+        assert_eq!(breaker.check(UPSTREAM), Ok(()));
+
+        tokio::time::advance(Duration::from_secs(30)).await;
+        assert_eq!(breaker.check(UPSTREAM), Ok(()));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn failures_outside_the_window_start_over() {
+        let breaker = breaker();
+        breaker.record_failure(UPSTREAM);
+        breaker.record_failure(UPSTREAM);
+
+        tokio::time::advance(Duration::from_secs(11)).await;
+        breaker.record_failure(UPSTREAM);
+        assert!(matches!(
+            state(&breaker),
+            Some(Circuit::Closed { failures: 1, .. })
+        ));
+
+        // Successes reset the count too.
+        breaker.record_failure(UPSTREAM);
+        breaker.record_success(UPSTREAM);
+        breaker.record_failure(UPSTREAM);
+        breaker.record_failure(UPSTREAM);
+        assert_eq!(breaker.check(UPSTREAM), Ok(()));
+    }
+}
@@ -0,0 +1,299 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use axum::{
+    body::{Body, Bytes},
+    http::{header, HeaderMap, HeaderName, HeaderValue, Method, StatusCode},
+    response::Response,
+};
+use reqwest::Url;
+use tokio::time::Instant;
+
+/// Whether a response came from the cache.
+pub const X_CACHE: &str = "x-cache";
+
+/// Responses with a longer body are never stored, they're streamed instead.
+pub const MAX_CACHED_BODY: u64 = 1024 * 1024;
+
+/// The request headers responses can vary on and still be cached.
+const VARY: [HeaderName; 1] = [header::ACCEPT_ENCODING];
+
+/// An in-memory cache of upstream responses to `GET` requests, following
+/// what the upstream says in `Cache-Control`.
+///
+/// Entries are only dropped when they go stale, by `spawn_sweeper`.
+#[derive(Debug, Default)]
+pub struct ResponseCache {
+    entries: RwLock<HashMap<CacheKey, CachedResponse>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    method: Method,
+    url: String,
+    /// The values of the `VARY` headers, in order.
+    vary: Vec<Option<HeaderValue>>,
+}
+
+impl CacheKey {
+    /// `None` for requests that can't be answered from the cache: anything
+    /// but a `GET`, and anything with credentials, since the response could
+    /// be meant for that user only.
+    pub fn for_request(method: &Method, url: &Url, headers: &HeaderMap) -> Option<Self> {
+        if method != Method::GET || headers.contains_key(header::AUTHORIZATION) {
+            return None;
+        }
+        Some(Self {
+            method: method.clone(),
+            url: url.to_string(),
+            vary: VARY.iter().map(|name| headers.get(name).cloned()).collect(),
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CachedResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+    freshness: Freshness,
+    stored: Instant,
+}
+
+impl CachedResponse {
+    pub fn new(status: StatusCode, headers: HeaderMap, body: Bytes, freshness: Freshness) -> Self {
+        Self {
+            status,
+            headers,
+            body,
+            freshness,
+            stored: Instant::now(),
+        }
+    }
+
+    /// How old the response is, counting the time it spent in caches before
+    /// reaching the proxy.
+    fn age(&self, now: Instant) -> Duration {
+        self.freshness.age + now.saturating_duration_since(self.stored)
+    }
+
+    fn is_fresh(&self, now: Instant) -> bool {
+        self.age(now) < self.freshness.max_age
+    }
+}
+
+/// How long a response can be served from the cache, and how old it already
+/// was when it arrived, from its `Age` header.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Freshness {
+    pub max_age: Duration,
+    pub age: Duration,
+}
+
+impl Freshness {
+    /// `None` for responses that mustn't be stored: without a `max-age`, or
+    /// with `no-store`, `no-cache` or `private`, or varying on headers that
+    /// aren't part of the key, or setting cookies.
+    pub fn of(headers: &HeaderMap) -> Option<Self> {
+        let mut max_age = None;
+        let mut s_maxage = None;
+        for directive in comma_separated(headers, header::CACHE_CONTROL) {
+            let directive = directive.to_ascii_lowercase();
+            match directive.split_once('=') {
+                Some(("max-age", seconds)) => max_age = seconds.parse().ok(),
+                Some(("s-maxage", seconds)) => s_maxage = seconds.parse().ok(),
+                None if matches!(&*directive, "no-store" | "no-cache" | "private") => return None,
+                _ => {}
+            }
+        }
+        // The proxy is a shared cache, which `s-maxage` is meant for.
+        let max_age = Duration::from_secs(s_maxage.or(max_age).filter(|&age| age > 0)?);
+
+        let varies_on_others = comma_separated(headers, header::VARY).any(|name| {
+            !VARY
+                .iter()
+                .any(|vary| name.eq_ignore_ascii_case(vary.as_str()))
+        });
+        if varies_on_others || headers.contains_key(header::SET_COOKIE) {
+            return None;
+        }
+
+        let age = headers
+            .get(header::AGE)
+            .and_then(|age| age.to_str().ok()?.parse().ok())
+            .map_or(Duration::ZERO, Duration::from_secs);
+        Some(Self { max_age, age })
+    }
+}
+
+fn comma_separated(headers: &HeaderMap, name: HeaderName) -> impl Iterator<Item = &str> {
+    headers
+        .get_all(name)
+        .into_iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+}
+
+impl ResponseCache {
+    /// The cached response for `key`, if there's one that's still fresh, with
+    /// its `Age` brought up to date.
+    pub fn get(&self, key: &CacheKey) -> Option<Response> {
+        let now = Instant::now();
+        let entries = self.entries.read().unwrap();
+        let cached = entries.get(key).filter(|cached| cached.is_fresh(now))?;
+
+        let mut response = Response::new(Body::from(cached.body.clone()));
+        *response.status_mut() = cached.status;
+        *response.headers_mut() = cached.headers.clone();
+        let age = cached.age(now).as_secs();
+        response.headers_mut().insert(header::AGE, age.into());
+        response
+            .headers_mut()
+            .insert(X_CACHE, HeaderValue::from_static("HIT"));
+        Some(response)
+    }
+
+    pub fn insert(&self, key: CacheKey, response: CachedResponse) {
+        self.entries.write().unwrap().insert(key, response);
+    }
+
+    /// Drop the stale entries.
+    pub fn sweep(&self) {
+        let now = Instant::now();
+        let mut entries = self.entries.write().unwrap();
+        let before = entries.len();
+        entries.retain(|_, cached| cached.is_fresh(now));
+        if entries.len() < before {
+            tracing::debug!("evicted {} stale responses", before - entries.len());
+        }
+    }
+
+    /// Sweep every `period`, for as long as the cache is in use.
+    pub fn spawn_sweeper(self: &Arc<Self>, period: Duration) {
+        let cache = Arc::downgrade(self);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                let Some(cache) = cache.upgrade() else {
+                    break;
+                };
+                cache.sweep();
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(HeaderName, &'static str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.clone(), HeaderValue::from_static(value)))
+            .collect()
+    }
+
+    #[test]
+    fn reads_cache_control() {
+        let cases = [
+            (vec![(header::CACHE_CONTROL, "max-age=60")], Some(60)),
+            (
+                vec![(header::CACHE_CONTROL, "public, Max-Age=60")],
+                Some(60),
+            ),
+            (
+                vec![(header::CACHE_CONTROL, "max-age=60, s-maxage=10")],
+                Some(10),
+            ),
+            (vec![(header::CACHE_CONTROL, "max-age=0")], None),
+            (vec![(header::CACHE_CONTROL, "max-age=60, no-store")], None),
+            (vec![(header::CACHE_CONTROL, "private, max-age=60")], None),
+            (vec![(header::CACHE_CONTROL, "no-cache")], None),
+            (vec![], None),
+            (
+                vec![
+                    (header::CACHE_CONTROL, "max-age=60"),
+                    (header::VARY, "Accept-Encoding"),
+                ],
+                Some(60),
+            ),
+            (
+                vec![
+                    (header::CACHE_CONTROL, "max-age=60"),
+                    (header::VARY, "accept-encoding, cookie"),
+                ],
+                None,
+            ),
+            (
+                vec![
+                    (header::CACHE_CONTROL, "max-age=60"),
+                    (header::SET_COOKIE, "session=1"),
+                ],
+                None,
+            ),
+        ];
+        for (pairs, max_age) in cases {
+            let freshness = Freshness::of(&headers(&pairs));
+            assert_eq!(
+                freshness.map(|freshness| freshness.max_age),
+                max_age.map(Duration::from_secs),
+                "{pairs:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn keys_vary_on_accept_encoding() {
+        let url: Url = "http://127.0.0.1:4001/items".parse().unwrap();
+        let plain = CacheKey::for_request(&Method::GET, &url, &HeaderMap::new()).unwrap();
+        let gzip = headers(&[(header::ACCEPT_ENCODING, "gzip")]);
+        let gzip = CacheKey::for_request(&Method::GET, &url, &gzip).unwrap();
+        assert_ne!(plain, gzip);
+
+        let other = headers(&[(header::ACCEPT, "text/html")]);
+        let other = CacheKey::for_request(&Method::GET, &url, &other).unwrap();
+        assert_eq!(plain, other);
+
+        assert!(CacheKey::for_request(&Method::POST, &url, &HeaderMap::new()).is_none());
+        let credentials = headers(&[(header::AUTHORIZATION, "Bearer secret")]);
+        assert!(CacheKey::for_request(&Method::GET, &url, &credentials).is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn entries_age_and_go_stale() {
+        let cache = Arc::new(ResponseCache::default());
+        let url: Url = "http://127.0.0.1:4001/".parse().unwrap();
+        let key = CacheKey::for_request(&Method::GET, &url, &HeaderMap::new()).unwrap();
+        let freshness = Freshness {
+            max_age: Duration::from_secs(60),
+            age: Duration::from_secs(5),
+        };
+        let cached = CachedResponse::new(
+            StatusCode::OK,
+            HeaderMap::new(),
+            Bytes::from("cached"),
+            freshness,
+        );
+        cache.insert(key.clone(), cached);
+        cache.spawn_sweeper(Duration::from_secs(10));
+
+        tokio::time::advance(Duration::from_secs(10)).await;
+        let response = cache.get(&key).unwrap();
+        assert_eq!(response.headers()[header::AGE], "15");
+        assert_eq!(response.headers()[X_CACHE], "HIT");
+
+        tokio::time::advance(Duration::from_secs(45)).await;
+        assert!(cache.get(&key).is_none());
+        // Let the sweeper run.
+        tokio::time::sleep(Duration::from_secs(10)).await;
+        assert!(cache.entries.read().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,158 @@
+use std::net::SocketAddr;
+
+use axum::http::{header, HeaderMap, HeaderName, HeaderValue};
+
+/// Headers that only apply to a single connection (RFC 7230, section 6.1),
+/// so a proxy mustn't pass them on.
+const HOP_BY_HOP: [HeaderName; 8] = [
+    header::CONNECTION,
+    HeaderName::from_static("keep-alive"),
+    header::PROXY_AUTHENTICATE,
+    header::PROXY_AUTHORIZATION,
+    header::TE,
+    header::TRAILER,
+    header::TRANSFER_ENCODING,
+    header::UPGRADE,
+];
+
+const X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+const X_FORWARDED_PROTO: HeaderName = HeaderName::from_static("x-forwarded-proto");
+const X_FORWARDED_HOST: HeaderName = HeaderName::from_static("x-forwarded-host");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// From the client, to the upstream.
+    Request,
+    /// From the upstream, back to the client.
+    Response,
+}
+
+/// Remove the hop-by-hop headers, including any the `Connection` header
+/// names, and `Content-Length`, since the body is streamed anew.
+///
+/// `Host` is removed from requests too, it's set from the upstream's URL.
+pub fn sanitize_headers(direction: Direction, headers: &mut HeaderMap) {
+    // `Connection: keep-alive, x-custom` makes `X-Custom` hop-by-hop, and
+    // there can be several `Connection` headers.
+    let named: Vec<HeaderName> = headers
+        .get_all(header::CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|name| HeaderName::from_bytes(name.trim().as_bytes()).ok())
+        .collect();
+
+    // `remove` takes out every value for the name.
+    for name in HOP_BY_HOP.iter().chain(&named) {
+        headers.remove(name);
+    }
+    headers.remove(header::CONTENT_LENGTH);
+    if direction == Direction::Request {
+        headers.remove(header::HOST);
+    }
+}
+
+/// Tell the upstream who the request is really from. `headers` are the
+/// incoming ones, before `sanitize_headers` removes `Host`.
+///
+/// The client is appended to any `X-Forwarded-For` from proxies in front of
+/// this one, while `X-Forwarded-Proto` and `X-Forwarded-Host` describe the
+/// request this proxy received.
+pub fn add_forwarded(headers: &mut HeaderMap, client: Option<SocketAddr>, proto: &'static str) {
+    if let Some(client) = client {
+        let client = HeaderValue::from_str(&client.ip().to_string()).unwrap();
+        headers.append(X_FORWARDED_FOR, client);
+    }
+    headers.insert(X_FORWARDED_PROTO, HeaderValue::from_static(proto));
+    match headers.get(header::HOST).cloned() {
+        Some(host) => headers.insert(X_FORWARDED_HOST, host),
+        None => headers.remove(X_FORWARDED_HOST),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(*name, HeaderValue::from_static(value));
+        }
+        headers
+    }
+
+    #[test]
+    fn removes_hop_by_hop_headers() {
+        let mut request = headers(&[
+            ("host", "proxy.example"),
+            ("connection", "keep-alive, X-Custom-Hop"),
+            ("connection", "x-other-hop"),
+            ("keep-alive", "timeout=5"),
+            ("transfer-encoding", "chunked"),
+            ("te", "trailers"),
+            ("upgrade", "h2c"),
+            ("content-length", "12"),
+            ("x-custom-hop", "a"),
+            ("x-other-hop", "b"),
+            ("accept", "text/html"),
+            ("accept", "application/json"),
+        ]);
+        let mut response = request.clone();
+
+        sanitize_headers(Direction::Request, &mut request);
+        assert_eq!(
+            request,
+            headers(&[("accept", "text/html"), ("accept", "application/json")])
+        );
+
+        sanitize_headers(Direction::Response, &mut response);
+        assert_eq!(
+            response,
+            headers(&[
+                ("host", "proxy.example"),
+                ("accept", "text/html"),
+                ("accept", "application/json"),
+            ])
+        );
+    }
+
+    #[test]
+    fn ignores_unparsable_connection_names() {
+        let mut request = headers(&[("connection", "close, not a header"), ("x-kept", "1")]);
+
+        sanitize_headers(Direction::Request, &mut request);
+
+        assert_eq!(request, headers(&[("x-kept", "1")]));
+    }
+
+    #[test]
+    fn appends_to_forwarded_for() {
+        let mut request = headers(&[
+            ("host", "proxy.example"),
+            ("x-forwarded-for", "203.0.113.7"),
+            ("x-forwarded-proto", "https"),
+        ]);
+
+        add_forwarded(
+            &mut request,
+            Some("198.51.100.1:50000".parse().unwrap()),
+            "http",
+        );
+
+        let forwarded_for: Vec<_> = request.get_all("x-forwarded-for").iter().collect();
+        assert_eq!(forwarded_for, ["203.0.113.7", "198.51.100.1"]);
+        assert_eq!(request["x-forwarded-proto"], "http");
+        assert_eq!(request["x-forwarded-host"], "proxy.example");
+    }
+
+    #[test]
+    fn forwarded_host_needs_a_host() {
+        let mut request = headers(&[("x-forwarded-host", "spoofed.example")]);
+
+        add_forwarded(&mut request, None, "http");
+
+        assert!(request.get("x-forwarded-for").is_none());
+        assert!(request.get("x-forwarded-host").is_none());
+    }
+}
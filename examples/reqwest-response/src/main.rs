@@ -1,72 +1,137 @@
 use std::convert::Infallible;
 use std::time::Duration;
 
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
 use axum::body::{Body, Bytes};
-use axum::extract::State;
-use axum::handler::Handler;
-use axum::http::{HeaderMap, HeaderName, HeaderValue};
-use axum::response::{IntoResponse, Response};
-use axum::routing::get;
+use axum::extract::Request;
+use axum::middleware;
+use axum::routing::{any, get};
 use axum::Router;
-use reqwest::{Client, StatusCode};
+use reqwest::Url;
 use tokio_stream::StreamExt;
 use tower_http::trace::TraceLayer;
 use tracing::Span;
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::util::SubscriberInitExt;
 
+use crate::breaker::CircuitBreaker;
+use crate::cache::ResponseCache;
+use crate::proxy::Proxy;
+use crate::retry::RetryPolicy;
+use crate::timeouts::Timeouts;
+
+mod breaker;
+mod cache;
+mod headers;
+mod metrics;
+mod proxy;
+mod retry;
+#[cfg(test)]
+mod test_support;
+mod timeouts;
+mod trace_context;
+mod upgrade;
+
 #[tokio::main]
 async fn main() {
     tracing_subscriber::registry()
         .with(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| "example_reqwest_response=debug,tower_http=debug".into()),
+            tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| {
+                format!("{}=debug,tower_http=debug", env!("CARGO_CRATE_NAME")).into()
+            }),
         )
         .with(tracing_subscriber::fmt::layer())
         .init();
 
-    let client = Client::new();
+    // Without an upstream to proxy to, serve one that streams some data at
+    // `/stream`, so `curl localhost:3000/stream` shows the proxy at work.
+    let upstream = match std::env::var("UPSTREAM_URL") {
+        Ok(url) => url.parse().expect("UPSTREAM_URL is not a valid URL"),
+        Err(_) => demo_upstream().await,
+    };
+    tracing::debug!("proxying to {upstream}");
+    // e.g. `users=http://127.0.0.1:4001,billing=http://127.0.0.1:4002`, for
+    // `/svc/users/...` and `/svc/billing/...`.
+    let services = match std::env::var("UPSTREAMS") {
+        Ok(value) => proxy::parse_services(&value).expect("invalid UPSTREAMS"),
+        Err(_) => HashMap::new(),
+    };
 
-    let app = Router::new()
-        .route("/", get(proxy_via_reqwest))
-        .route("/stream", get(stream_some_data))
-        .layer(TraceLayer::new_for_http().on_body_chunk(
-            |chunk: &Bytes, _latency: Duration, _span: &Span| {
-                tracing::debug!("streaming {} bytes", chunk.len());
-            },
-        ))
-        .with_state(client);
+    let timeouts = Timeouts::from_env();
+    tracing::debug!("upstream timeouts: {timeouts:?}");
+
+    let retry = RetryPolicy::from_env();
+    tracing::debug!("retrying with {retry:?}");
+
+    let cache = Arc::new(ResponseCache::default());
+    cache.spawn_sweeper(Duration::from_secs(30));
+
+    let proxy = Proxy::new(timeouts.client(), upstream, services)
+        .with_deadline(timeouts.deadline)
+        .with_retry(retry)
+        .with_breaker(CircuitBreaker::from_env())
+        .with_cache(cache);
+    let proxy = match std::env::var("PROXY_MAX_IN_FLIGHT") {
+        Ok(max) => proxy.with_max_in_flight(max.parse().expect("invalid PROXY_MAX_IN_FLIGHT")),
+        Err(_) => proxy,
+    };
+    let app = app(proxy);
 
     let listener = tokio::net::TcpListener::bind("127.0.0.1:3000")
         .await
         .unwrap();
     tracing::debug!("listening on {}", listener.local_addr().unwrap());
-    axum::serve(listener, app).await.unwrap();
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+    .unwrap();
 }
 
-async fn proxy_via_reqwest(State(client): State<Client>) -> Response {
-    let reqwest_response = match client.get("http://127.0.0.1:3000/stream").send().await {
-        Ok(res) => res,
-        Err(err) => {
-            tracing::error!(%err,"request failed");
-            return (StatusCode::BAD_REQUEST, Body::empty()).into_response();
-        }
-    };
-
-    let response_builder = Response::builder().status(reqwest_response.status().as_u16());
-
-    let mut headers = HeaderMap::with_capacity(reqwest_response.headers().len());
-    headers.extend(reqwest_response.headers().into_iter().map(|(name, value)| {
-        let name = HeaderName::from_bytes(name.as_ref()).unwrap();
-        let value = HeaderValue::from_bytes(value.as_ref()).unwrap();
-        (name, value)
-    }));
-
-    tracing::debug!("headers: {:?}", headers);
+fn app(proxy: Proxy) -> Router {
+    Router::new()
+        .route("/metrics.json", get(metrics::report))
+        .route("/", any(proxy::proxy))
+        .route("/*path", any(proxy::proxy))
+        .route("/svc/", any(proxy::service))
+        .route("/svc/:name", any(proxy::service))
+        .route("/svc/:name/", any(proxy::service))
+        .route("/svc/:name/*rest", any(proxy::service))
+        // Inside the trace layer, to record the ids on its span.
+        .layer(middleware::from_fn(trace_context::propagate))
+        .layer(
+            TraceLayer::new_for_http()
+                // Like the default span, with the upstream the handler picks and
+                // the ids `trace_context::propagate` sends it.
+                .make_span_with(|request: &Request| {
+                    tracing::debug_span!(
+                        "request",
+                        method = %request.method(),
+                        uri = %request.uri(),
+                        version = ?request.version(),
+                        upstream = tracing::field::Empty,
+                        request_id = tracing::field::Empty,
+                        trace_id = tracing::field::Empty,
+                    )
+                })
+                .on_body_chunk(|chunk: &Bytes, _latency: Duration, _span: &Span| {
+                    tracing::debug!("streaming {} bytes", chunk.len());
+                }),
+        )
+        .with_state(proxy)
+}
 
-    response_builder
-        .body(Body::from_stream(reqwest_response.bytes_stream()))
-        .unwrap()
+async fn demo_upstream() -> Url {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:3001")
+        .await
+        .unwrap();
+    let app = Router::new().route("/stream", get(stream_some_data));
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    "http://127.0.0.1:3001".parse().unwrap()
 }
 
 async fn stream_some_data() -> Body {
@@ -0,0 +1,254 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, RwLock,
+    },
+    time::Duration,
+};
+
+use axum::{extract::State, Json};
+use serde_json::{json, Map, Value};
+
+use crate::proxy::Proxy;
+
+/// The upper bounds of the latency histogram's buckets, in milliseconds. The
+/// last bucket has no bound.
+const BUCKETS_MS: [u64; 10] = [5, 10, 25, 50, 100, 250, 500, 1000, 2500, 5000];
+
+/// Counters for every upstream requests went to, by host and port.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    upstreams: RwLock<HashMap<String, Arc<UpstreamMetrics>>>,
+}
+
+#[derive(Debug, Default)]
+struct UpstreamMetrics {
+    requests: AtomicU64,
+    failures: AtomicU64,
+    /// Turned away because too many requests were in flight.
+    rejected: AtomicU64,
+    /// How long until the response headers arrived, by `BUCKETS_MS`.
+    latency: [AtomicU64; BUCKETS_MS.len() + 1],
+}
+
+impl Metrics {
+    fn upstream(&self, upstream: &str) -> Arc<UpstreamMetrics> {
+        if let Some(metrics) = self.upstreams.read().unwrap().get(upstream) {
+            return metrics.clone();
+        }
+        let mut upstreams = self.upstreams.write().unwrap();
+        upstreams.entry(upstream.to_owned()).or_default().clone()
+    }
+
+    /// A request that got an answer, or failed to, after `latency`.
+    pub fn record(&self, upstream: &str, latency: Duration, failed: bool) {
+        let metrics = self.upstream(upstream);
+        metrics.requests.fetch_add(1, Ordering::Relaxed);
+        if failed {
+            metrics.failures.fetch_add(1, Ordering::Relaxed);
+        }
+        let millis = u64::try_from(latency.as_millis()).unwrap_or(u64::MAX);
+        let bucket = BUCKETS_MS
+            .iter()
+            .position(|&bound| millis <= bound)
+            .unwrap_or(BUCKETS_MS.len());
+        metrics.latency[bucket].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_rejected(&self, upstream: &str) {
+        let metrics = self.upstream(upstream);
+        metrics.rejected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn to_json(&self) -> Value {
+        let upstreams: Map<_, _> = self
+            .upstreams
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(upstream, metrics)| (upstream.clone(), metrics.to_json()))
+            .collect();
+        Value::Object(upstreams)
+    }
+}
+
+impl UpstreamMetrics {
+    fn to_json(&self) -> Value {
+        let bounds = BUCKETS_MS
+            .iter()
+            .map(ToString::to_string)
+            .chain(["+Inf".to_owned()]);
+        let latency: Map<_, _> = bounds
+            .zip(&self.latency)
+            .map(|(bound, count)| (bound, count.load(Ordering::Relaxed).into()))
+            .collect();
+        json!({
+            "requests": self.requests.load(Ordering::Relaxed),
+            "failures": self.failures.load(Ordering::Relaxed),
+            "rejected": self.rejected.load(Ordering::Relaxed),
+            "latency_ms": latency,
+        })
+    }
+}
+
+/// `GET /metrics.json`, the counters for every upstream and how many requests
+/// are in flight.
+pub async fn report(State(proxy): State<Proxy>) -> Json<Value> {
+    let (in_flight, max_in_flight) = proxy.in_flight();
+    Json(json!({
+        "in_flight": in_flight,
+        "max_in_flight": max_in_flight,
+        "upstreams": proxy.metrics().to_json(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::{Body, Bytes},
+        http::{header, Request, StatusCode},
+        routing::get,
+        Router,
+    };
+    use http_body_util::BodyExt;
+    use reqwest::{Client, Url};
+    use tokio::sync::Notify;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{
+        app,
+        test_support::{send, spawn_upstream},
+    };
+
+    /// `/slow` answers after 300ms, `/stream` sends a first chunk right away
+    /// and the last one once `release` is notified.
+    async fn slow_upstream(release: Arc<Notify>) -> Url {
+        let router = Router::new()
+            .route(
+                "/slow",
+                get(|| async {
+                    tokio::time::sleep(Duration::from_millis(300)).await;
+                    "slow"
+                }),
+            )
+            .route(
+                "/stream",
+                get(move || async move {
+                    let last = async move {
+                        release.notified().await;
+                        Ok::<_, std::convert::Infallible>(Bytes::from("last"))
+                    };
+                    let first = tokio_stream::once(Ok(Bytes::from("first")));
+                    Body::from_stream(tokio_stream::StreamExt::chain(
+                        first,
+                        futures::stream::once(last),
+                    ))
+                }),
+            )
+            .route("/fast", get(|| async { "fast" }));
+        spawn_upstream(router).await
+    }
+
+    async fn wait_for_in_flight(proxy: &Proxy, expected: usize) {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while proxy.in_flight().0 != expected {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("in-flight requests never settled");
+    }
+
+    async fn assert_saturated(app: &Router) {
+        let request = Request::get("/fast").body(Body::empty()).unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "1");
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["error"], "saturated");
+    }
+
+    #[tokio::test]
+    async fn saturation_is_a_service_unavailable() {
+        let upstream = slow_upstream(Arc::default()).await;
+        let key = format!("127.0.0.1:{}", upstream.port().unwrap());
+        let proxy = Proxy::new(Client::new(), upstream, HashMap::new()).with_max_in_flight(1);
+        let app = app(proxy.clone());
+
+        let slow = tokio::spawn(send_owned(app.clone(), "/slow"));
+        wait_for_in_flight(&proxy, 1).await;
+        assert_saturated(&app).await;
+        assert_eq!(slow.await.unwrap(), (StatusCode::OK, Bytes::from("slow")));
+
+        let (status, body) = send(
+            &app,
+            Request::get("/metrics.json").body(Body::empty()).unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let report: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(report["in_flight"], 0);
+        assert_eq!(report["max_in_flight"], 1);
+        let upstream = &report["upstreams"][&key];
+        assert_eq!(upstream["requests"], 1);
+        assert_eq!(upstream["failures"], 0);
+        assert_eq!(upstream["rejected"], 1);
+        assert_eq!(upstream["latency_ms"]["500"], 1, "{upstream}");
+    }
+
+    #[tokio::test]
+    async fn permits_are_held_while_the_body_streams() {
+        let release = Arc::new(Notify::new());
+        let proxy = Proxy::new(
+            Client::new(),
+            slow_upstream(release.clone()).await,
+            HashMap::new(),
+        )
+        .with_max_in_flight(1);
+        let app = app(proxy.clone());
+
+        let request = Request::get("/stream").body(Body::empty()).unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        // The headers are in, but not the body.
+        assert_eq!(proxy.in_flight().0, 1);
+        assert_saturated(&app).await;
+
+        release.notify_one();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "firstlast");
+        wait_for_in_flight(&proxy, 0).await;
+
+        let (status, _) = send(&app, Request::get("/fast").body(Body::empty()).unwrap()).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    async fn send_owned(app: Router, uri: &'static str) -> (StatusCode, Bytes) {
+        send(&app, Request::get(uri).body(Body::empty()).unwrap()).await
+    }
+
+    #[test]
+    fn buckets_latencies() {
+        let metrics = Metrics::default();
+        for millis in [0, 5, 6, 300, 60_000] {
+            metrics.record("a:80", Duration::from_millis(millis), millis > 1000);
+        }
+        metrics.record_rejected("a:80");
+        metrics.record("b:80", Duration::from_millis(1), false);
+
+        let json = metrics.to_json();
+        let a = &json["a:80"];
+        assert_eq!(a["requests"], 5);
+        assert_eq!(a["failures"], 1);
+        assert_eq!(a["rejected"], 1);
+        assert_eq!(a["latency_ms"]["5"], 2);
+        assert_eq!(a["latency_ms"]["10"], 1);
+        assert_eq!(a["latency_ms"]["500"], 1);
+        assert_eq!(a["latency_ms"]["+Inf"], 1);
+        assert_eq!(a["latency_ms"]["1000"], 0);
+        assert_eq!(json["b:80"]["requests"], 1);
+    }
+}
@@ -0,0 +1,995 @@
+use std::{collections::HashMap, fmt, net::SocketAddr, sync::Arc, time::Duration};
+
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, FromRequestParts, Request, State},
+    http::{header, HeaderMap, HeaderName, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use hyper::upgrade::OnUpgrade;
+use reqwest::{Client, Url};
+use serde_json::json;
+use tokio::{
+    sync::{OwnedSemaphorePermit, Semaphore},
+    time::Instant,
+};
+use tokio_stream::StreamExt;
+use tracing::Span;
+
+use crate::{
+    breaker::CircuitBreaker,
+    cache::{CacheKey, CachedResponse, Freshness, ResponseCache, MAX_CACHED_BODY, X_CACHE},
+    headers::{add_forwarded, sanitize_headers, Direction},
+    metrics::Metrics,
+    retry::{is_transient, Outgoing, RetryPolicy},
+    timeouts::Timeouts,
+    upgrade::{splice, websocket_headers, Upgrade},
+};
+
+/// How many requests it took to get the response, retries included.
+const ATTEMPTS: &str = "x-proxy-attempts";
+
+/// Requests to upstreams in flight at once, unless configured otherwise.
+const MAX_IN_FLIGHT: usize = 256;
+
+#[derive(Clone)]
+pub struct Proxy {
+    client: Client,
+    /// Requests go to this URL with their path and query appended.
+    upstream: Url,
+    /// Upstreams by service name, for requests to `/svc/<name>/...`.
+    services: Arc<HashMap<String, Url>>,
+    /// For the upstream's response headers, see `Timeouts::deadline`.
+    deadline: Duration,
+    retry: RetryPolicy,
+    breaker: Arc<CircuitBreaker>,
+    /// `None` sends every request to the upstream.
+    cache: Option<Arc<ResponseCache>>,
+    /// A permit for every request to an upstream that's in flight, until its
+    /// response body has been streamed.
+    in_flight: Arc<Semaphore>,
+    max_in_flight: usize,
+    metrics: Arc<Metrics>,
+}
+
+impl Proxy {
+    pub fn new(client: Client, upstream: Url, services: HashMap<String, Url>) -> Self {
+        Self {
+            client,
+            upstream,
+            services: Arc::new(services),
+            deadline: Timeouts::default().deadline,
+            retry: RetryPolicy::default(),
+            breaker: Arc::default(),
+            cache: None,
+            in_flight: Arc::new(Semaphore::new(MAX_IN_FLIGHT)),
+            max_in_flight: MAX_IN_FLIGHT,
+            metrics: Arc::default(),
+        }
+    }
+
+    pub fn with_deadline(mut self, deadline: Duration) -> Self {
+        self.deadline = deadline;
+        self
+    }
+
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn with_breaker(mut self, breaker: CircuitBreaker) -> Self {
+        self.breaker = Arc::new(breaker);
+        self
+    }
+
+    pub fn with_cache(mut self, cache: Arc<ResponseCache>) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Requests beyond `max` get a 503 right away.
+    pub fn with_max_in_flight(mut self, max: usize) -> Self {
+        self.in_flight = Arc::new(Semaphore::new(max));
+        self.max_in_flight = max;
+        self
+    }
+
+    /// How many requests to upstreams are in flight, and how many can be.
+    pub fn in_flight(&self) -> (usize, usize) {
+        let available = self.in_flight.available_permits();
+        (self.max_in_flight - available, self.max_in_flight)
+    }
+
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+}
+
+/// Parse `name=url` pairs separated by commas, as in `UPSTREAMS`.
+pub fn parse_services(value: &str) -> Result<HashMap<String, Url>, ServicesError> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let Some((name, url)) = entry.split_once('=') else {
+                return Err(ServicesError::MissingName(entry.to_owned()));
+            };
+            let url = url.parse().map_err(|err| ServicesError::InvalidUrl {
+                name: name.to_owned(),
+                err,
+            })?;
+            Ok((name.to_owned(), url))
+        })
+        .collect()
+}
+
+#[derive(Debug)]
+pub enum ServicesError {
+    MissingName(String),
+    InvalidUrl { name: String, err: url::ParseError },
+}
+
+impl fmt::Display for ServicesError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingName(entry) => write!(f, "expected `name=url`, got `{entry}`"),
+            Self::InvalidUrl { name, err } => write!(f, "invalid URL for `{name}`: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for ServicesError {}
+
+/// Forward any request to the upstream, and its response back.
+pub async fn proxy(State(proxy): State<Proxy>, request: Request) -> Response {
+    let path_and_query = request
+        .uri()
+        .path_and_query()
+        .map_or("/", |path_and_query| path_and_query.as_str())
+        .to_owned();
+    forward(&proxy, &proxy.upstream, &path_and_query, request).await
+}
+
+/// Forward `/svc/<name>/rest` to `/rest` on the upstream called `name`.
+pub async fn service(State(proxy): State<Proxy>, request: Request) -> Response {
+    let Some((name, rest)) = split_service(request.uri().path()) else {
+        return unknown_service("");
+    };
+    let Some(upstream) = proxy.services.get(name) else {
+        return unknown_service(name);
+    };
+
+    let mut path_and_query = if rest.is_empty() {
+        "/".to_owned()
+    } else {
+        rest.to_owned()
+    };
+    if let Some(query) = request.uri().query() {
+        path_and_query.push('?');
+        path_and_query.push_str(query);
+    }
+    forward(&proxy, upstream, &path_and_query, request).await
+}
+
+/// Split `/svc/<name>/rest` into the name and `/rest`, which is empty when
+/// there's nothing after the name.
+fn split_service(path: &str) -> Option<(&str, &str)> {
+    let path = path.strip_prefix("/svc/")?;
+    let (name, rest) = match path.find('/') {
+        Some(slash) => path.split_at(slash),
+        None => (path, ""),
+    };
+    Some((name, rest))
+}
+
+/// Upstreams share a circuit when they share a host and port.
+fn circuit_key(base: &Url) -> String {
+    let host = base.host_str().unwrap_or_default();
+    match base.port_or_known_default() {
+        Some(port) => format!("{host}:{port}"),
+        None => host.to_owned(),
+    }
+}
+
+/// Statuses that count against the upstream's circuit.
+fn is_unavailable(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+fn upstream_url(base: &Url, path_and_query: &str) -> Result<Url, url::ParseError> {
+    let base = base.as_str().trim_end_matches('/');
+    Url::parse(&format!("{base}{path_and_query}"))
+}
+
+/// Parsing the upstream URL resolves `.` and `..` segments, which would let
+/// requests reach paths outside the upstream's base path. `url` also counts
+/// `%2e` as a dot and `\` as a slash when finding them.
+fn has_dot_segment(path_and_query: &str) -> bool {
+    let path = path_and_query.split(['?', '#']).next().unwrap_or_default();
+    path.split(['/', '\\']).any(|segment| {
+        let segment = segment.to_ascii_lowercase().replace("%2e", ".");
+        segment == "." || segment == ".."
+    })
+}
+
+async fn forward(proxy: &Proxy, base: &Url, path_and_query: &str, request: Request) -> Response {
+    Span::current().record("upstream", base.as_str());
+    if has_dot_segment(path_and_query) {
+        return bad_request("the path can't contain `.` or `..` segments".to_owned());
+    }
+    let url = match upstream_url(base, path_and_query) {
+        Ok(url) => url,
+        Err(err) => return bad_gateway(format!("invalid upstream url: {err}")),
+    };
+    // Whatever else `url` normalizes, the request stays under the base path.
+    if !url.path().starts_with(base.path().trim_end_matches('/')) {
+        return bad_request("the path leaves the upstream's base path".to_owned());
+    }
+
+    let upgrade = Upgrade::requested(request.headers());
+    match &upgrade {
+        Some(Upgrade::Other(protocol)) => {
+            return not_implemented(format!("can't upgrade to `{protocol}`"));
+        }
+        // Not over HTTP/1.1, or not served by hyper.
+        Some(Upgrade::WebSocket) if request.extensions().get::<OnUpgrade>().is_none() => {
+            return not_implemented("this connection can't be upgraded".to_owned());
+        }
+        _ => {}
+    }
+
+    let cache_key = proxy
+        .cache
+        .as_ref()
+        .filter(|_| upgrade.is_none())
+        .and_then(|_| CacheKey::for_request(request.method(), &url, request.headers()));
+    if let (Some(cache), Some(key)) = (&proxy.cache, &cache_key) {
+        if let Some(hit) = cache.get(key) {
+            tracing::debug!("served from the cache");
+            return hit;
+        }
+    }
+
+    let key = circuit_key(base);
+    if let Err(retry_after) = proxy.breaker.check(&key) {
+        return circuit_open(&key, retry_after);
+    }
+    let Ok(permit) = proxy.in_flight.clone().try_acquire_owned() else {
+        tracing::warn!("{} requests in flight, rejecting", proxy.max_in_flight);
+        proxy.metrics.record_rejected(&key);
+        return saturated();
+    };
+
+    let (mut parts, body) = request.into_parts();
+    // Missing only when not served with `into_make_service_with_connect_info`.
+    let client = ConnectInfo::<SocketAddr>::from_request_parts(&mut parts, &())
+        .await
+        .ok()
+        .map(|ConnectInfo(addr)| addr);
+    let mut headers = forwarded_headers(&parts.headers, client);
+    let mut body = match Outgoing::new(body, &proxy.retry).await {
+        Ok(body) => body,
+        Err(err) => return bad_request(format!("failed to read the request body: {err}")),
+    };
+    // Streamed bodies are sent chunked unless their length is set, which
+    // some upstreams insist on.
+    if let Some(len) = body.streamed_length() {
+        headers.insert(header::CONTENT_LENGTH, HeaderValue::from(len));
+    }
+    let on_upgrade = parts.extensions.remove::<OnUpgrade>();
+    if upgrade.is_some() {
+        websocket_headers(&mut headers);
+    }
+    let retryable = body.retryable(&parts.method, &proxy.retry);
+
+    let mut attempts = 0;
+    let send = async {
+        loop {
+            attempts += 1;
+            let mut upstream_request = proxy
+                .client
+                .request(parts.method.clone(), url.clone())
+                .headers(headers.clone());
+            // A stream would be sent chunked even when there's nothing in it.
+            if let Some(body) = body.for_attempt() {
+                upstream_request = upstream_request.body(body);
+            }
+            let result = upstream_request.send().await;
+
+            if !retryable || attempts > proxy.retry.retries || !is_transient(&result) {
+                return result;
+            }
+            let backoff = proxy.retry.backoff(attempts);
+            match &result {
+                Ok(res) => {
+                    tracing::warn!(attempt = attempts, status = %res.status(), ?backoff, "retrying")
+                }
+                Err(err) => tracing::warn!(attempt = attempts, %err, ?backoff, "retrying"),
+            }
+            tokio::time::sleep(backoff).await;
+        }
+    };
+
+    let started = Instant::now();
+    let result = tokio::time::timeout(proxy.deadline, send).await;
+    let failed = !matches!(&result, Ok(Ok(res)) if !is_unavailable(res.status()));
+    proxy.metrics.record(&key, started.elapsed(), failed);
+    if failed {
+        proxy.breaker.record_failure(&key);
+    } else {
+        proxy.breaker.record_success(&key);
+    }
+
+    let mut response = match result {
+        // WebSockets let go of their permit, they'd hold it for as long as
+        // they're open.
+        Ok(Ok(res)) if res.status() == StatusCode::SWITCHING_PROTOCOLS => {
+            match on_upgrade.filter(|_| upgrade.is_some()) {
+                Some(on_upgrade) => {
+                    let headers = upstream_headers(&res);
+                    splice(on_upgrade, res, headers)
+                }
+                None => bad_gateway("the upstream switched protocols unasked".to_owned()),
+            }
+        }
+        Ok(Ok(res)) => match (&proxy.cache, cache_key) {
+            (Some(cache), Some(key)) => through_cache(cache, key, res, permit).await,
+            _ => from_upstream(res, permit),
+        },
+        Ok(Err(err)) if err.is_timeout() => {
+            tracing::error!(%err, "request timed out");
+            let phase = if err.is_connect() { "connect" } else { "read" };
+            gateway_timeout(phase, err.to_string())
+        }
+        Ok(Err(err)) => {
+            tracing::error!(%err, "request failed");
+            bad_gateway(err.to_string())
+        }
+        Err(_) => {
+            tracing::error!("no response within {:?}", proxy.deadline);
+            let detail = format!("no response within {:?}", proxy.deadline);
+            gateway_timeout("deadline", detail)
+        }
+    };
+    response
+        .headers_mut()
+        .insert(ATTEMPTS, HeaderValue::from(attempts));
+    response
+}
+
+/// The upstream's response, streamed back, holding on to `permit` until the
+/// body is done or the client goes away.
+fn from_upstream(reqwest_response: reqwest::Response, permit: OwnedSemaphorePermit) -> Response {
+    let mut response_builder = Response::builder().status(reqwest_response.status().as_u16());
+    *response_builder.headers_mut().unwrap() = upstream_headers(&reqwest_response);
+
+    // The status has been sent by the time the body stalls, so all that's
+    // left to do is abort it, which the client sees as a broken transfer.
+    let body = reqwest_response.bytes_stream().map(move |chunk| {
+        let _permit = &permit;
+        chunk.inspect_err(|err| tracing::error!(%err, "streaming the response failed"))
+    });
+    response_builder.body(Body::from_stream(body)).unwrap()
+}
+
+/// The upstream's response, stored in `cache` when it says it can be and is
+/// small enough to buffer, otherwise streamed back.
+async fn through_cache(
+    cache: &ResponseCache,
+    key: CacheKey,
+    reqwest_response: reqwest::Response,
+    permit: OwnedSemaphorePermit,
+) -> Response {
+    let small = reqwest_response
+        .content_length()
+        .is_some_and(|len| len <= MAX_CACHED_BODY);
+    let freshness = Freshness::of(reqwest_response.headers()).filter(|_| small);
+
+    let mut response = match freshness {
+        None => from_upstream(reqwest_response, permit),
+        Some(freshness) => {
+            let status = reqwest_response.status();
+            let headers = upstream_headers(&reqwest_response);
+            let body = match reqwest_response.bytes().await {
+                Ok(body) => body,
+                Err(err) if err.is_timeout() => {
+                    tracing::error!(%err, "reading the response timed out");
+                    return gateway_timeout("read", err.to_string());
+                }
+                Err(err) => {
+                    tracing::error!(%err, "reading the response failed");
+                    return bad_gateway(err.to_string());
+                }
+            };
+            let cached = CachedResponse::new(status, headers.clone(), body.clone(), freshness);
+            cache.insert(key, cached);
+
+            let mut response = Response::new(Body::from(body));
+            *response.status_mut() = status;
+            *response.headers_mut() = headers;
+            response
+        }
+    };
+    response
+        .headers_mut()
+        .insert(X_CACHE, HeaderValue::from_static("MISS"));
+    response
+}
+
+/// The upstream's response headers, without any that can't be sent on.
+///
+/// Every value of a repeated header is kept, as separate `Set-Cookie`
+/// headers must be.
+fn upstream_headers(reqwest_response: &reqwest::Response) -> HeaderMap {
+    let mut headers = HeaderMap::with_capacity(reqwest_response.headers().len());
+    let converted = reqwest_response
+        .headers()
+        .iter()
+        .filter_map(|(name, value)| {
+            let converted = HeaderName::from_bytes(name.as_ref())
+                .ok()
+                .zip(HeaderValue::from_bytes(value.as_bytes()).ok());
+            if converted.is_none() {
+                tracing::warn!(header = %name, ?value, "dropping an unconvertible upstream header");
+            }
+            converted
+        });
+    for (name, value) in converted {
+        headers.append(name, value);
+    }
+    sanitize_headers(Direction::Response, &mut headers);
+
+    tracing::debug!("headers: {:?}", headers);
+    headers
+}
+
+fn forwarded_headers(incoming: &HeaderMap, client: Option<SocketAddr>) -> HeaderMap {
+    let mut headers = incoming.clone();
+    // This example only serves plain HTTP.
+    add_forwarded(&mut headers, client, "http");
+    sanitize_headers(Direction::Request, &mut headers);
+    headers
+}
+
+fn unknown_service(name: &str) -> Response {
+    let body =
+        json!({ "error": "unknown_service", "detail": format!("no upstream called `{name}`") });
+    (StatusCode::NOT_FOUND, Json(body)).into_response()
+}
+
+/// `phase` is `connect`, `read` or `deadline`, after the `Timeouts` field
+/// that ran out.
+fn gateway_timeout(phase: &str, detail: String) -> Response {
+    let body = json!({ "error": "gateway_timeout", "phase": phase, "detail": detail });
+    (StatusCode::GATEWAY_TIMEOUT, Json(body)).into_response()
+}
+
+fn circuit_open(upstream: &str, retry_after: Duration) -> Response {
+    // Whole seconds, rounded up so clients don't come back too early.
+    let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+    let body = json!({
+        "error": "circuit_open",
+        "detail": format!("`{upstream}` is failing, not sending requests to it"),
+    });
+    let headers = [(header::RETRY_AFTER, seconds.to_string())];
+    (StatusCode::SERVICE_UNAVAILABLE, headers, Json(body)).into_response()
+}
+
+fn saturated() -> Response {
+    let body = json!({ "error": "saturated", "detail": "too many requests in flight" });
+    let headers = [(header::RETRY_AFTER, "1")];
+    (StatusCode::SERVICE_UNAVAILABLE, headers, Json(body)).into_response()
+}
+
+fn not_implemented(detail: String) -> Response {
+    let body = json!({ "error": "not_implemented", "detail": detail });
+    (StatusCode::NOT_IMPLEMENTED, Json(body)).into_response()
+}
+
+fn bad_request(detail: String) -> Response {
+    let body = json!({ "error": "bad_request", "detail": detail });
+    (StatusCode::BAD_REQUEST, Json(body)).into_response()
+}
+
+fn bad_gateway(detail: String) -> Response {
+    let body = json!({ "error": "bad_gateway", "detail": detail });
+    (StatusCode::BAD_GATEWAY, Json(body)).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        convert::Infallible,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    use axum::{
+        body::Bytes,
+        extract::connect_info::MockConnectInfo,
+        http::{Method, Request},
+        Router,
+    };
+    use http_body_util::BodyExt;
+    use serde_json::Value;
+    use sha2::{Digest, Sha256};
+    use tokio_stream::Stream;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{
+        app,
+        test_support::{closed_url, echo_upstream, send, spawn_upstream},
+    };
+
+    const CHUNK: usize = 64 * 1024;
+
+    /// Bytes taken from the client's body and bytes the upstream got, and the
+    /// most there's been of the former ahead of the latter.
+    #[derive(Default)]
+    struct Flow {
+        pulled: AtomicUsize,
+        received: AtomicUsize,
+        peak: AtomicUsize,
+    }
+
+    /// `chunks` chunks of `CHUNK` bytes, counted into `flow` as they're taken.
+    fn counting(chunks: usize, flow: Arc<Flow>) -> impl Stream<Item = Result<Bytes, Infallible>> {
+        tokio_stream::iter(0..chunks).map(move |n| {
+            let chunk = Bytes::from(vec![n as u8; CHUNK]);
+            let pulled = flow.pulled.fetch_add(CHUNK, Ordering::SeqCst) + CHUNK;
+            let ahead = pulled - flow.received.load(Ordering::SeqCst);
+            flow.peak.fetch_max(ahead, Ordering::SeqCst);
+            Ok(chunk)
+        })
+    }
+
+    /// Answers with the SHA-256 of the body, counting what it receives into
+    /// `flow`.
+    async fn hashing_upstream(flow: Arc<Flow>) -> Url {
+        let router = Router::new().fallback(move |request: Request<Body>| {
+            let flow = flow.clone();
+            async move {
+                let mut body = request.into_body().into_data_stream();
+                let mut hasher = Sha256::new();
+                while let Some(chunk) = body.next().await {
+                    let chunk = chunk.unwrap();
+                    flow.received.fetch_add(chunk.len(), Ordering::SeqCst);
+                    hasher.update(&chunk);
+                }
+                format!("{:x}", hasher.finalize())
+            }
+        });
+        spawn_upstream(router).await
+    }
+
+    #[tokio::test]
+    async fn forwards_method_path_headers_and_body() {
+        let app = app(Proxy::new(
+            Client::new(),
+            echo_upstream().await,
+            HashMap::new(),
+        ));
+
+        let cases = [
+            (Method::GET, "/items?page=2", ""),
+            (Method::POST, "/items", "{\"name\":\"widget\"}"),
+            (Method::DELETE, "/items/7", ""),
+        ];
+        for (method, uri, body) in cases {
+            let request = Request::builder()
+                .method(method.clone())
+                .uri(uri)
+                .header("x-custom", "kept")
+                .header(header::CONNECTION, "close")
+                .header(header::HOST, "proxy.example")
+                .body(Body::from(body))
+                .unwrap();
+
+            let (status, echoed) = send(&app, request).await;
+            let echoed: Value = serde_json::from_slice(&echoed).unwrap();
+
+            assert_eq!(status, StatusCode::OK, "{method} {uri}");
+            assert_eq!(echoed["method"], method.as_str());
+            assert_eq!(echoed["uri"], uri);
+            assert_eq!(echoed["headers"]["x-custom"], "kept");
+            assert_ne!(echoed["headers"]["host"], "proxy.example");
+            assert_eq!(echoed["body"], body);
+        }
+    }
+
+    #[tokio::test]
+    async fn upstreams_see_forwarding_headers() {
+        let proxy = Proxy::new(Client::new(), echo_upstream().await, HashMap::new());
+        let client: SocketAddr = "198.51.100.1:50000".parse().unwrap();
+        let app = app(proxy).layer(MockConnectInfo(client));
+
+        let request = Request::get("/")
+            .header(header::HOST, "proxy.example")
+            .header(header::CONNECTION, "keep-alive, x-hop")
+            .header("x-hop", "dropped")
+            .header("x-forwarded-for", "203.0.113.7")
+            .body(Body::empty())
+            .unwrap();
+        let (_, echoed) = send(&app, request).await;
+        let headers = &serde_json::from_slice::<Value>(&echoed).unwrap()["headers"];
+
+        // The echo joins repeated headers with commas.
+        assert_eq!(headers["x-forwarded-for"], "203.0.113.7, 198.51.100.1");
+        assert_eq!(headers["x-forwarded-proto"], "http");
+        assert_eq!(headers["x-forwarded-host"], "proxy.example");
+        assert!(headers.get("connection").is_none(), "{headers}");
+        assert!(headers.get("x-hop").is_none(), "{headers}");
+    }
+
+    #[tokio::test]
+    async fn upstream_path_prefixes_are_kept() {
+        let upstream = echo_upstream().await.join("/api/").unwrap();
+        let app = app(Proxy::new(Client::new(), upstream, HashMap::new()));
+
+        let request = Request::get("/items").body(Body::empty()).unwrap();
+        let (_, echoed) = send(&app, request).await;
+        let echoed: Value = serde_json::from_slice(&echoed).unwrap();
+
+        assert_eq!(echoed["uri"], "/api/items");
+    }
+
+    #[tokio::test]
+    async fn dot_segments_are_rejected() {
+        let upstream = echo_upstream().await.join("/api/").unwrap();
+        let mut services = HashMap::new();
+        services.insert("echo".to_owned(), upstream.clone());
+        let app = app(Proxy::new(Client::new(), upstream, services));
+
+        for uri in [
+            "/../x",
+            "/%2e%2e/x",
+            "/items/%2E./x",
+            "/./x",
+            "/svc/echo/../x",
+            "/svc/echo/%2e%2e/x",
+        ] {
+            let request = Request::get(uri).body(Body::empty()).unwrap();
+            let (status, body) = send(&app, request).await;
+            let body: Value = serde_json::from_slice(&body).unwrap();
+
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{uri}");
+            assert_eq!(body["error"], "bad_request", "{uri}");
+        }
+
+        // Dots inside a segment are just part of the name.
+        let request = Request::get("/v1..2/.well-known")
+            .body(Body::empty())
+            .unwrap();
+        let (status, echoed) = send(&app, request).await;
+        let echoed: Value = serde_json::from_slice(&echoed).unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(echoed["uri"], "/api/v1..2/.well-known");
+    }
+
+    #[tokio::test]
+    async fn unreachable_upstream_is_a_bad_gateway() {
+        let app = app(Proxy::new(Client::new(), closed_url(), HashMap::new()));
+
+        let request = Request::get("/").body(Body::empty()).unwrap();
+        let (status, body) = send(&app, request).await;
+        let body: Value = serde_json::from_slice(&body).unwrap();
+
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+        assert_eq!(body["error"], "bad_gateway");
+    }
+
+    #[tokio::test]
+    async fn large_bodies_are_streamed() {
+        // 50 MiB.
+        let chunks = 800;
+        let flow = Arc::new(Flow::default());
+        let app = app(Proxy::new(
+            Client::new(),
+            hashing_upstream(flow.clone()).await,
+            HashMap::new(),
+        ));
+
+        let body = Body::from_stream(counting(chunks, flow.clone()));
+        let (status, hash) = send(&app, Request::post("/").body(body).unwrap()).await;
+
+        let mut expected = Sha256::new();
+        for n in 0..chunks {
+            expected.update(vec![n as u8; CHUNK]);
+        }
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(hash, format!("{:x}", expected.finalize()));
+        assert_eq!(flow.received.load(Ordering::SeqCst), chunks * CHUNK);
+        // What's in flight is in the socket buffers on both ends, nowhere near
+        // the whole body.
+        let peak = flow.peak.load(Ordering::SeqCst);
+        assert!(peak < 16 * 1024 * 1024, "{peak} bytes buffered");
+    }
+
+    #[tokio::test]
+    async fn content_length_is_kept_when_known() {
+        let app = app(Proxy::new(
+            Client::new(),
+            echo_upstream().await,
+            HashMap::new(),
+        ));
+
+        let request = Request::post("/").body(Body::from("hello")).unwrap();
+        let (_, echoed) = send(&app, request).await;
+        let headers = &serde_json::from_slice::<Value>(&echoed).unwrap()["headers"];
+        assert_eq!(headers["content-length"], "5");
+        assert!(headers.get("transfer-encoding").is_none(), "{headers}");
+
+        let body = Body::from_stream(tokio_stream::once(Ok::<_, Infallible>("hello")));
+        let (_, echoed) = send(&app, Request::post("/").body(body).unwrap()).await;
+        let echoed: Value = serde_json::from_slice(&echoed).unwrap();
+        assert_eq!(echoed["headers"]["transfer-encoding"], "chunked");
+        assert!(echoed["headers"].get("content-length").is_none());
+        assert_eq!(echoed["body"], "hello");
+    }
+
+    #[tokio::test]
+    async fn early_rejections_stop_the_upload() {
+        // 1 GiB, if it were all read.
+        let chunks = 16 * 1024;
+        let flow = Arc::new(Flow::default());
+        let upstream =
+            spawn_upstream(Router::new().fallback(|| async { StatusCode::PAYLOAD_TOO_LARGE }))
+                .await;
+        let app = app(Proxy::new(Client::new(), upstream, HashMap::new()));
+
+        let body = Body::from_stream(counting(chunks, flow.clone()));
+        let (status, _) = send(&app, Request::put("/").body(body).unwrap()).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        let pulled = flow.pulled.load(Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(flow.pulled.load(Ordering::SeqCst), pulled, "still reading");
+        assert!(pulled < chunks * CHUNK / 10, "{pulled} bytes read");
+    }
+
+    #[tokio::test]
+    async fn open_circuits_short_circuit() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counted = calls.clone();
+        let upstream = spawn_upstream(Router::new().fallback(move || {
+            counted.fetch_add(1, Ordering::SeqCst);
+            async { StatusCode::SERVICE_UNAVAILABLE }
+        }))
+        .await;
+        let proxy = Proxy::new(Client::new(), upstream, HashMap::new())
+            .with_retry(RetryPolicy {
+                retries: 0,
+                ..RetryPolicy::default()
+            })
+            .with_breaker(CircuitBreaker::new(
+                2,
+                Duration::from_secs(10),
+                Duration::from_secs(30),
+            ));
+        let app = app(proxy);
+
+        for _ in 0..2 {
+            let request = Request::get("/").body(Body::empty()).unwrap();
+            let response = app.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+            assert!(response.headers().get(header::RETRY_AFTER).is_none());
+        }
+        for _ in 0..3 {
+            let request = Request::get("/").body(Body::empty()).unwrap();
+            let response = app.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+            assert_eq!(response.headers()[header::RETRY_AFTER], "30");
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            let body: Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(body["error"], "circuit_open");
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    /// Counts the requests it gets. `/cached` can be cached for a minute,
+    /// `/no-store` can't be, and `/large` is too large to be.
+    async fn cacheable_upstream(calls: Arc<AtomicUsize>) -> Url {
+        let router = Router::new().fallback(move |request: Request<Body>| {
+            calls.fetch_add(1, Ordering::SeqCst);
+            let (cache_control, body) = match request.uri().path() {
+                "/cached" => ("max-age=60", "cached".to_owned()),
+                "/no-store" => ("max-age=60, no-store", "not cached".to_owned()),
+                _ => ("max-age=60", "x".repeat(2 * 1024 * 1024)),
+            };
+            async move { ([(header::CACHE_CONTROL, cache_control)], body) }
+        });
+        spawn_upstream(router).await
+    }
+
+    #[tokio::test]
+    async fn cacheable_responses_are_served_from_the_cache() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let proxy = Proxy::new(
+            Client::new(),
+            cacheable_upstream(calls.clone()).await,
+            HashMap::new(),
+        );
+        let app = app(proxy.with_cache(Arc::default()));
+
+        let mut seen = Vec::new();
+        for _ in 0..2 {
+            let request = Request::get("/cached").body(Body::empty()).unwrap();
+            let response = app.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            seen.push(response.headers()[X_CACHE].clone());
+            assert_eq!(response.headers()[header::CACHE_CONTROL], "max-age=60");
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            assert_eq!(body, "cached");
+        }
+        assert_eq!(seen, ["MISS", "HIT"]);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // Another encoding is another entry.
+        let request = Request::get("/cached")
+            .header(header::ACCEPT_ENCODING, "gzip")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.headers()[X_CACHE], "MISS");
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn uncacheable_responses_always_reach_the_upstream() {
+        for uri in ["/no-store", "/large"] {
+            let calls = Arc::new(AtomicUsize::new(0));
+            let proxy = Proxy::new(
+                Client::new(),
+                cacheable_upstream(calls.clone()).await,
+                HashMap::new(),
+            );
+            let app = app(proxy.with_cache(Arc::default()));
+
+            for _ in 0..2 {
+                let request = Request::get(uri).body(Body::empty()).unwrap();
+                let response = app.clone().oneshot(request).await.unwrap();
+                assert_eq!(response.status(), StatusCode::OK, "{uri}");
+                assert_eq!(response.headers()[X_CACHE], "MISS", "{uri}");
+            }
+            assert_eq!(calls.load(Ordering::SeqCst), 2, "{uri}");
+        }
+    }
+
+    /// Plain hyper, to send headers axum's responses wouldn't: two
+    /// `Set-Cookie`s and a value with a byte outside of ASCII.
+    async fn raw_upstream() -> Url {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let service = hyper::service::service_fn(|_request| async {
+                    let response = hyper::Response::builder()
+                        .header(header::SET_COOKIE, "a=1; Path=/")
+                        .header(header::SET_COOKIE, "b=2; HttpOnly")
+                        .header(
+                            "x-odd",
+                            HeaderValue::from_bytes(b"caf\xe9\tau lait").unwrap(),
+                        )
+                        .body(http_body_util::Full::new(Bytes::from("raw")))
+                        .unwrap();
+                    Ok::<_, Infallible>(response)
+                });
+                let connection = hyper::server::conn::http1::Builder::new()
+                    .serve_connection(hyper_util::rt::TokioIo::new(stream), service);
+                tokio::spawn(connection);
+            }
+        });
+        format!("http://{addr}").parse().unwrap()
+    }
+
+    #[tokio::test]
+    async fn unusual_and_repeated_headers_survive() {
+        let app = app(Proxy::new(
+            Client::new(),
+            raw_upstream().await,
+            HashMap::new(),
+        ));
+
+        let request = Request::get("/").body(Body::empty()).unwrap();
+        let response = app.oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let cookies: Vec<_> = response
+            .headers()
+            .get_all(header::SET_COOKIE)
+            .iter()
+            .collect();
+        assert_eq!(cookies, ["a=1; Path=/", "b=2; HttpOnly"]);
+        assert_eq!(response.headers()["x-odd"].as_bytes(), b"caf\xe9\tau lait");
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "raw");
+    }
+
+    #[test]
+    fn parses_services() {
+        let services =
+            parse_services("users=http://127.0.0.1:4001, billing=http://127.0.0.1:4002/api")
+                .unwrap();
+        assert_eq!(services.len(), 2);
+        assert_eq!(services["users"].as_str(), "http://127.0.0.1:4001/");
+        assert_eq!(services["billing"].as_str(), "http://127.0.0.1:4002/api");
+        assert!(parse_services("").unwrap().is_empty());
+
+        let err = parse_services("users").unwrap_err();
+        assert!(matches!(err, ServicesError::MissingName(_)), "{err}");
+        let err = parse_services("users=not a url").unwrap_err();
+        assert!(matches!(err, ServicesError::InvalidUrl { .. }), "{err}");
+    }
+
+    #[test]
+    fn splits_the_service_name_off() {
+        assert_eq!(split_service("/svc/users/7"), Some(("users", "/7")));
+        assert_eq!(split_service("/svc/users/"), Some(("users", "/")));
+        assert_eq!(split_service("/svc/users"), Some(("users", "")));
+        assert_eq!(split_service("/users"), None);
+    }
+
+    #[tokio::test]
+    async fn routes_by_service_prefix() {
+        let services = HashMap::from([
+            ("users".to_owned(), echo_upstream().await),
+            ("billing".to_owned(), echo_upstream().await),
+        ]);
+        let ports: HashMap<_, _> = services
+            .iter()
+            .map(|(name, url)| (url.port().unwrap().to_string(), name.clone()))
+            .collect();
+        let app = app(Proxy::new(Client::new(), closed_url(), services));
+
+        let cases = [
+            ("/svc/users/7?full=true", "users", "/7?full=true"),
+            ("/svc/billing/invoices/", "billing", "/invoices/"),
+            ("/svc/billing/", "billing", "/"),
+            ("/svc/users", "users", "/"),
+            ("/svc/users?page=2", "users", "/?page=2"),
+        ];
+        for (uri, service, forwarded) in cases {
+            let request = Request::get(uri).body(Body::empty()).unwrap();
+            let (status, echoed) = send(&app, request).await;
+            assert_eq!(status, StatusCode::OK, "{uri} {echoed:?}");
+            let echoed: Value = serde_json::from_slice(&echoed).unwrap();
+            assert_eq!(echoed["uri"], forwarded, "{uri}");
+            // Which upstream answered, by the port it was reached on.
+            let host = echoed["headers"]["host"].as_str().unwrap();
+            let port = host.rsplit(':').next().unwrap();
+            assert_eq!(ports[port], service, "{uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn unknown_services_are_not_found() {
+        let services = HashMap::from([("users".to_owned(), echo_upstream().await)]);
+        let app = app(Proxy::new(Client::new(), closed_url(), services));
+
+        for uri in ["/svc/orders/1", "/svc/"] {
+            let request = Request::get(uri).body(Body::empty()).unwrap();
+            let (status, body) = send(&app, request).await;
+            let body: Value = serde_json::from_slice(&body).unwrap();
+
+            assert_eq!(status, StatusCode::NOT_FOUND, "{uri}");
+            assert_eq!(body["error"], "unknown_service");
+        }
+    }
+
+    #[tokio::test]
+    async fn unreachable_services_are_a_bad_gateway() {
+        let services = HashMap::from([("users".to_owned(), closed_url())]);
+        let app = app(Proxy::new(Client::new(), closed_url(), services));
+        let request = Request::get("/svc/users/1").body(Body::empty()).unwrap();
+        let (status, _) = send(&app, request).await;
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+    }
+}
@@ -0,0 +1,251 @@
+use std::time::Duration;
+
+use axum::{
+    body::{Body, Bytes, HttpBody},
+    http::{Method, StatusCode},
+};
+
+use crate::timeouts::env_millis;
+
+/// Bodies up to this size are buffered when `RetryPolicy::retry_bodies` is
+/// on, so they can be sent again.
+pub const MAX_BUFFERED_BODY: usize = 64 * 1024;
+
+/// How the proxy retries requests that failed in a way that's likely to go
+/// away, a refused connection or a 502 or 503 from the upstream.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Attempts after the first one.
+    pub retries: u32,
+    /// Before the first retry, doubling for every one after it.
+    pub backoff: Duration,
+    /// Also retry requests with a body, of any method, by buffering bodies of
+    /// up to `MAX_BUFFERED_BODY` bytes. Without this, only `GET`, `HEAD` and
+    /// `OPTIONS` requests without a body are retried.
+    pub retry_bodies: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            retries: 2,
+            backoff: Duration::from_millis(100),
+            retry_bodies: false,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// From `PROXY_RETRIES`, `PROXY_RETRY_BACKOFF_MS` and
+    /// `PROXY_RETRY_BODIES=true`, with the defaults for those that aren't set.
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            retries: std::env::var("PROXY_RETRIES")
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(defaults.retries),
+            backoff: env_millis("PROXY_RETRY_BACKOFF_MS").unwrap_or(defaults.backoff),
+            retry_bodies: std::env::var("PROXY_RETRY_BODIES").is_ok_and(|value| value == "true"),
+        }
+    }
+
+    /// How long to wait after `attempt` (counting from 1) failed, somewhere
+    /// between half and all of the exponential backoff so that clients
+    /// retrying together spread out.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let full = self.backoff.saturating_mul(1 << (attempt - 1).min(16));
+        let half = full / 2;
+        half + half.mul_f64(fastrand::f64())
+    }
+}
+
+/// Whether an attempt ended in a way worth retrying.
+pub fn is_transient(result: &reqwest::Result<reqwest::Response>) -> bool {
+    match result {
+        Ok(response) => matches!(
+            response.status(),
+            StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE
+        ),
+        // Nothing was sent, unlike after a read timeout.
+        Err(err) => err.is_connect() && !err.is_timeout(),
+    }
+}
+
+/// A request body, kept around when the request can be sent more than once.
+pub enum Outgoing {
+    Empty,
+    Buffered(Bytes),
+    /// Sent as it arrives, so it's gone after the first attempt.
+    Streaming(Option<Body>),
+}
+
+impl Outgoing {
+    /// Buffer `body` if the policy retries bodies and its length is known to
+    /// be small enough, otherwise leave it to be streamed.
+    pub async fn new(body: Body, policy: &RetryPolicy) -> Result<Self, axum::Error> {
+        if body.is_end_stream() {
+            return Ok(Self::Empty);
+        }
+        let fits = body
+            .size_hint()
+            .upper()
+            .is_some_and(|len| len <= MAX_BUFFERED_BODY as u64);
+        if policy.retry_bodies && fits {
+            let bytes = axum::body::to_bytes(body, MAX_BUFFERED_BODY).await?;
+            return Ok(Self::Buffered(bytes));
+        }
+        Ok(Self::Streaming(Some(body)))
+    }
+
+    /// Whether `policy` allows retrying `method` with this body.
+    pub fn retryable(&self, method: &Method, policy: &RetryPolicy) -> bool {
+        match self {
+            Self::Empty if policy.retry_bodies => true,
+            Self::Empty => matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS),
+            Self::Buffered(_) => true,
+            Self::Streaming(_) => false,
+        }
+    }
+
+    /// The length of a streamed body, when the client sent it.
+    pub fn streamed_length(&self) -> Option<u64> {
+        match self {
+            Self::Streaming(Some(body)) => body.size_hint().exact(),
+            _ => None,
+        }
+    }
+
+    /// The body for the next attempt, `None` when there's nothing to send.
+    pub fn for_attempt(&mut self) -> Option<reqwest::Body> {
+        match self {
+            Self::Empty => None,
+            Self::Buffered(bytes) => Some(bytes.clone().into()),
+            Self::Streaming(body) => {
+                let body = body.take()?;
+                Some(reqwest::Body::wrap_stream(body.into_data_stream()))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+    };
+
+    use axum::{
+        extract::Request,
+        http::{Response, StatusCode},
+        Router,
+    };
+    use http_body_util::BodyExt;
+    use reqwest::{Client, Url};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{
+        app,
+        proxy::Proxy,
+        test_support::{closed_url, spawn_upstream},
+    };
+
+    const QUICK: RetryPolicy = RetryPolicy {
+        retries: 2,
+        backoff: Duration::from_millis(10),
+        retry_bodies: false,
+    };
+
+    /// Answers the first request with a 503 and the ones after it with their
+    /// body.
+    async fn flaky_upstream() -> Url {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let router = Router::new().fallback(move |request: Request| async move {
+            if calls.fetch_add(1, Ordering::SeqCst) == 0 {
+                return (StatusCode::SERVICE_UNAVAILABLE, Bytes::new());
+            }
+            let body = request.into_body().collect().await.unwrap().to_bytes();
+            (StatusCode::OK, body)
+        });
+        spawn_upstream(router).await
+    }
+
+    async fn attempt(
+        url: Url,
+        policy: RetryPolicy,
+        request: Request<Body>,
+    ) -> (StatusCode, String, Bytes) {
+        let app = app(Proxy::new(Client::new(), url, HashMap::new()).with_retry(policy));
+        let response: Response<Body> = app.oneshot(request).await.unwrap();
+        let status = response.status();
+        let attempts = response.headers()["x-proxy-attempts"]
+            .to_str()
+            .unwrap()
+            .to_owned();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, attempts, body)
+    }
+
+    #[tokio::test]
+    async fn idempotent_requests_are_retried() {
+        let request = Request::get("/").body(Body::empty()).unwrap();
+        let (status, attempts, _) = attempt(flaky_upstream().await, QUICK, request).await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(attempts, "2");
+    }
+
+    #[tokio::test]
+    async fn posts_are_not_retried() {
+        for body in ["", "{\"id\":1}"] {
+            let request = Request::post("/").body(Body::from(body)).unwrap();
+            let (status, attempts, _) = attempt(flaky_upstream().await, QUICK, request).await;
+
+            assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE, "{body:?}");
+            assert_eq!(attempts, "1", "{body:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn buffered_bodies_are_retried_when_enabled() {
+        let policy = RetryPolicy {
+            retry_bodies: true,
+            ..QUICK
+        };
+        let request = Request::post("/").body(Body::from("{\"id\":1}")).unwrap();
+        let (status, attempts, body) = attempt(flaky_upstream().await, policy, request).await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(attempts, "2");
+        assert_eq!(body, "{\"id\":1}");
+    }
+
+    #[tokio::test]
+    async fn refused_connections_are_retried_until_giving_up() {
+        let request = Request::get("/").body(Body::empty()).unwrap();
+        let (status, attempts, _) = attempt(closed_url(), QUICK, request).await;
+
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+        assert_eq!(attempts, "3");
+    }
+
+    #[test]
+    fn backoff_doubles_with_jitter() {
+        let policy = RetryPolicy {
+            backoff: Duration::from_millis(100),
+            ..QUICK
+        };
+        for (attempt, full) in [(1, 100), (2, 200), (3, 400)] {
+            let full = Duration::from_millis(full);
+            for _ in 0..20 {
+                let backoff = policy.backoff(attempt);
+                assert!(full / 2 <= backoff && backoff <= full, "{backoff:?}");
+            }
+        }
+    }
+}
@@ -0,0 +1,78 @@
+//! Helpers shared by the tests.
+
+use std::net::{SocketAddr, TcpListener};
+
+use axum::{
+    body::{Body, Bytes},
+    extract::Request,
+    http::StatusCode,
+    response::Response,
+    Json, Router,
+};
+use http_body_util::BodyExt;
+use reqwest::Url;
+use serde_json::{json, Value};
+use tower::ServiceExt;
+
+/// Serve `router` on an ephemeral local port, returning its base URL.
+pub async fn spawn_upstream(router: Router) -> Url {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(
+            listener,
+            router.into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .await
+        .unwrap();
+    });
+    format!("http://{addr}").parse().unwrap()
+}
+
+/// An upstream answering every request with its method, URI, headers and
+/// body as JSON. Repeated headers are joined with commas.
+pub async fn echo_upstream() -> Url {
+    spawn_upstream(Router::new().fallback(echo)).await
+}
+
+async fn echo(request: Request) -> Json<Value> {
+    let (parts, body) = request.into_parts();
+    let headers: serde_json::Map<_, _> = parts
+        .headers
+        .keys()
+        .map(|name| {
+            let values: Vec<_> = parts
+                .headers
+                .get_all(name)
+                .iter()
+                .map(|value| String::from_utf8_lossy(value.as_bytes()))
+                .collect();
+            (name.to_string(), Value::String(values.join(", ")))
+        })
+        .collect();
+    let body = body.collect().await.unwrap().to_bytes();
+    Json(json!({
+        "method": parts.method.as_str(),
+        "uri": parts.uri.to_string(),
+        "headers": headers,
+        "body": String::from_utf8_lossy(&body),
+    }))
+}
+
+/// A URL nothing is listening on.
+pub fn closed_url() -> Url {
+    let port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    format!("http://127.0.0.1:{port}").parse().unwrap()
+}
+
+/// Send `request` to `app`, returning the status and the whole body.
+pub async fn send(app: &Router, request: Request<Body>) -> (StatusCode, Bytes) {
+    let response: Response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    (status, body)
+}
@@ -0,0 +1,166 @@
+use std::time::Duration;
+
+use reqwest::Client;
+
+/// How long the proxy waits on upstreams.
+#[derive(Debug, Clone, Copy)]
+pub struct Timeouts {
+    /// For the TCP (and TLS) connection to be established.
+    pub connect: Duration,
+    /// For the next bytes from the upstream, both while waiting for the
+    /// response headers and between chunks of the body.
+    pub read: Duration,
+    /// For the response headers, from sending the request.
+    pub deadline: Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            connect: Duration::from_secs(5),
+            read: Duration::from_secs(30),
+            deadline: Duration::from_secs(60),
+        }
+    }
+}
+
+impl Timeouts {
+    /// From `PROXY_CONNECT_TIMEOUT_MS`, `PROXY_READ_TIMEOUT_MS` and
+    /// `PROXY_DEADLINE_MS`, with the defaults for those that aren't set.
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            connect: env_millis("PROXY_CONNECT_TIMEOUT_MS").unwrap_or(defaults.connect),
+            read: env_millis("PROXY_READ_TIMEOUT_MS").unwrap_or(defaults.read),
+            deadline: env_millis("PROXY_DEADLINE_MS").unwrap_or(defaults.deadline),
+        }
+    }
+
+    /// A client enforcing the connect and read timeouts. The deadline is up to
+    /// the proxy, since reqwest's own would cover streaming the body too.
+    pub fn client(&self) -> Client {
+        Client::builder()
+            .connect_timeout(self.connect)
+            .read_timeout(self.read)
+            .build()
+            .unwrap()
+    }
+}
+
+pub fn env_millis(name: &str) -> Option<Duration> {
+    let value = std::env::var(name).ok()?;
+    value.parse().ok().map(Duration::from_millis)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, convert::Infallible, time::Instant};
+
+    use axum::{
+        body::{Body, Bytes},
+        http::{Request, StatusCode},
+        routing::get,
+        Router,
+    };
+    use http_body_util::BodyExt;
+    use reqwest::Url;
+    use serde_json::Value;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{
+        app,
+        proxy::Proxy,
+        test_support::{send, spawn_upstream},
+    };
+
+    const SHORT: Duration = Duration::from_millis(200);
+
+    /// `/slow` takes longer than any of the timeouts to respond, `/stall`
+    /// sends the headers and a first chunk, and then nothing.
+    async fn slow_upstream() -> Url {
+        let router = Router::new()
+            .route(
+                "/slow",
+                get(|| async {
+                    tokio::time::sleep(Duration::from_secs(10)).await;
+                    "finally"
+                }),
+            )
+            .route(
+                "/stall",
+                get(|| async { Body::from_stream(stall_after("first")) }),
+            );
+        spawn_upstream(router).await
+    }
+
+    /// Yields `chunk`, and then never ends.
+    fn stall_after(
+        chunk: &'static str,
+    ) -> impl tokio_stream::Stream<Item = Result<Bytes, Infallible>> {
+        use tokio_stream::StreamExt;
+
+        tokio_stream::once(Ok(Bytes::from(chunk))).chain(tokio_stream::pending())
+    }
+
+    fn proxy_to(url: Url, timeouts: Timeouts) -> Router {
+        let proxy = Proxy::new(timeouts.client(), url, HashMap::new());
+        app(proxy.with_deadline(timeouts.deadline))
+    }
+
+    async fn timed_out_phase(app: &Router, uri: &str) -> String {
+        let start = Instant::now();
+        let (status, body) = send(app, Request::get(uri).body(Body::empty()).unwrap()).await;
+        let elapsed = start.elapsed();
+
+        assert_eq!(status, StatusCode::GATEWAY_TIMEOUT);
+        assert!(elapsed < SHORT + Duration::from_secs(1), "{elapsed:?}");
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["error"], "gateway_timeout");
+        body["phase"].as_str().unwrap().to_owned()
+    }
+
+    #[tokio::test]
+    async fn the_deadline_is_a_gateway_timeout() {
+        let timeouts = Timeouts {
+            deadline: SHORT,
+            ..Timeouts::default()
+        };
+        let app = proxy_to(slow_upstream().await, timeouts);
+
+        assert_eq!(timed_out_phase(&app, "/slow").await, "deadline");
+    }
+
+    #[tokio::test]
+    async fn the_read_timeout_is_a_gateway_timeout() {
+        let timeouts = Timeouts {
+            read: SHORT,
+            ..Timeouts::default()
+        };
+        let app = proxy_to(slow_upstream().await, timeouts);
+
+        assert_eq!(timed_out_phase(&app, "/slow").await, "read");
+    }
+
+    #[tokio::test]
+    async fn stalled_bodies_are_aborted() {
+        let timeouts = Timeouts {
+            read: SHORT,
+            ..Timeouts::default()
+        };
+        let app = proxy_to(slow_upstream().await, timeouts);
+
+        let response = app
+            .oneshot(Request::get("/stall").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let start = Instant::now();
+        let mut body = response.into_body();
+        let first = body.frame().await.unwrap().unwrap().into_data().unwrap();
+        assert_eq!(first, "first");
+        assert!(body.frame().await.unwrap().is_err());
+        assert!(start.elapsed() < SHORT + Duration::from_secs(1));
+    }
+}
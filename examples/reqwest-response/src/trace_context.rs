@@ -0,0 +1,266 @@
+use std::fmt;
+
+use axum::{
+    extract::Request,
+    http::{HeaderMap, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use tracing::Span;
+
+/// The W3C trace context, https://www.w3.org/TR/trace-context/.
+pub const TRACEPARENT: &str = "traceparent";
+/// Vendor-specific trace data, meaningless without the `traceparent` it came
+/// with.
+pub const TRACESTATE: &str = "tracestate";
+pub const X_REQUEST_ID: &str = "x-request-id";
+
+/// Request ids longer than this are replaced.
+const MAX_REQUEST_ID: usize = 128;
+
+/// A version `00` `traceparent`, `00-<trace id>-<parent id>-<flags>`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TraceParent {
+    pub trace_id: u128,
+    pub parent_id: u64,
+    pub flags: u8,
+}
+
+impl TraceParent {
+    /// `None` for anything but lowercase hex fields of the right lengths, and
+    /// for the all-zero ids the spec says are invalid.
+    pub fn parse(value: &str) -> Option<Self> {
+        let mut fields = value.split('-');
+        let (Some("00"), Some(trace_id), Some(parent_id), Some(flags), None) = (
+            fields.next(),
+            fields.next(),
+            fields.next(),
+            fields.next(),
+            fields.next(),
+        ) else {
+            return None;
+        };
+
+        let trace_id = hex(trace_id, 32).and_then(|id| u128::from_str_radix(id, 16).ok())?;
+        let parent_id = hex(parent_id, 16).and_then(|id| u64::from_str_radix(id, 16).ok())?;
+        let flags = hex(flags, 2).and_then(|flags| u8::from_str_radix(flags, 16).ok())?;
+        if trace_id == 0 || parent_id == 0 {
+            return None;
+        }
+        Some(Self {
+            trace_id,
+            parent_id,
+            flags,
+        })
+    }
+
+    /// A new trace, sampled.
+    pub fn generate() -> Self {
+        Self {
+            trace_id: fastrand::u128(1..),
+            parent_id: fastrand::u64(1..),
+            flags: 0x01,
+        }
+    }
+}
+
+fn hex(field: &str, len: usize) -> Option<&str> {
+    let valid = field.len() == len
+        && field
+            .bytes()
+            .all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'));
+    valid.then_some(field)
+}
+
+impl fmt::Display for TraceParent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "00-{:032x}-{:016x}-{:02x}",
+            self.trace_id, self.parent_id, self.flags
+        )
+    }
+}
+
+/// The incoming request id, if it's short and made of letters, digits and
+/// `-_.:` only, otherwise a new one.
+pub fn request_id(incoming: Option<&HeaderValue>) -> HeaderValue {
+    let valid = incoming.filter(|id| {
+        let id = id.as_bytes();
+        !id.is_empty()
+            && id.len() <= MAX_REQUEST_ID
+            && id
+                .iter()
+                .all(|b| b.is_ascii_alphanumeric() || b"-_.:".contains(b))
+    });
+    match valid {
+        Some(id) => id.clone(),
+        None => HeaderValue::try_from(format!("{:032x}", fastrand::u128(..))).unwrap(),
+    }
+}
+
+/// Make sure the request has a `traceparent` and an `x-request-id` for the
+/// upstream to log, keeping the client's when they're valid, record them on
+/// the request's span, and send the request id back.
+pub async fn propagate(mut request: Request, next: Next) -> Response {
+    let headers = request.headers_mut();
+    let traceparent = ensure_traceparent(headers);
+    let id = request_id(headers.get(X_REQUEST_ID));
+    headers.insert(X_REQUEST_ID, id.clone());
+
+    let span = Span::current();
+    span.record("trace_id", format!("{:032x}", traceparent.trace_id));
+    // Always ASCII, having been checked or generated.
+    span.record("request_id", id.to_str().unwrap_or_default());
+
+    let mut response = next.run(request).await;
+    response.headers_mut().insert(X_REQUEST_ID, id);
+    response
+}
+
+fn ensure_traceparent(headers: &mut HeaderMap) -> TraceParent {
+    let incoming = headers
+        .get(TRACEPARENT)
+        .and_then(|value| value.to_str().ok())
+        .and_then(TraceParent::parse);
+    if let Some(traceparent) = incoming {
+        return traceparent;
+    }
+
+    if let Some(garbage) = headers.get(TRACEPARENT) {
+        tracing::debug!(?garbage, "replacing an invalid traceparent");
+    }
+    let traceparent = TraceParent::generate();
+    let value = HeaderValue::try_from(traceparent.to_string()).unwrap();
+    headers.insert(TRACEPARENT, value);
+    headers.remove(TRACESTATE);
+    traceparent
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use axum::body::Body;
+    use reqwest::Client;
+    use serde_json::Value;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{app, proxy::Proxy, test_support::echo_upstream};
+
+    /// The request id the client got back and the headers the upstream got.
+    async fn forwarded(request: Request) -> (HeaderValue, Value) {
+        let app = app(Proxy::new(
+            Client::new(),
+            echo_upstream().await,
+            HashMap::new(),
+        ));
+        let response = app.oneshot(request).await.unwrap();
+        let id = response.headers()[X_REQUEST_ID].clone();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let echoed: Value = serde_json::from_slice(&body).unwrap();
+        (id, echoed["headers"].clone())
+    }
+
+    #[tokio::test]
+    async fn generated_ids_reach_the_upstream() {
+        let (id, headers) = forwarded(Request::get("/").body(Body::empty()).unwrap()).await;
+
+        assert_eq!(headers[X_REQUEST_ID], id.to_str().unwrap());
+        let traceparent = headers[TRACEPARENT].as_str().unwrap();
+        assert!(TraceParent::parse(traceparent).is_some(), "{traceparent}");
+    }
+
+    #[tokio::test]
+    async fn valid_ids_are_forwarded_unchanged() {
+        let traceparent = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        let request = Request::get("/")
+            .header(TRACEPARENT, traceparent)
+            .header(TRACESTATE, "vendor=1")
+            .header(X_REQUEST_ID, "req-42")
+            .body(Body::empty())
+            .unwrap();
+        let (id, headers) = forwarded(request).await;
+
+        assert_eq!(id, "req-42");
+        assert_eq!(headers[X_REQUEST_ID], "req-42");
+        assert_eq!(headers[TRACEPARENT], traceparent);
+        assert_eq!(headers[TRACESTATE], "vendor=1");
+    }
+
+    #[tokio::test]
+    async fn invalid_traceparents_are_replaced() {
+        let request = Request::get("/")
+            .header(TRACEPARENT, "garbage")
+            .header(TRACESTATE, "vendor=1")
+            .body(Body::empty())
+            .unwrap();
+        let (_, headers) = forwarded(request).await;
+
+        let traceparent = headers[TRACEPARENT].as_str().unwrap();
+        assert!(TraceParent::parse(traceparent).is_some(), "{traceparent}");
+        assert!(headers.get(TRACESTATE).is_none(), "{headers}");
+    }
+
+    #[test]
+    fn parses_valid_traceparents() {
+        let value = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        let traceparent = TraceParent::parse(value).unwrap();
+        assert_eq!(traceparent.trace_id, 0x4bf92f3577b34da6a3ce929d0e0e4736);
+        assert_eq!(traceparent.parent_id, 0x00f067aa0ba902b7);
+        assert_eq!(traceparent.flags, 1);
+        assert_eq!(traceparent.to_string(), value);
+    }
+
+    #[test]
+    fn rejects_garbage() {
+        for value in [
+            "",
+            "garbage",
+            // Uppercase.
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00F067AA0BA902B7-01",
+            // Another version.
+            "01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            // All-zero ids.
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+            // Wrong lengths.
+            "00-4bf92f3577b34da6a3ce929d0e0e473-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-1",
+            // Signs, which `from_str_radix` would take.
+            "00-+bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            // Trailing fields.
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-00",
+        ] {
+            assert_eq!(TraceParent::parse(value), None, "{value:?}");
+        }
+    }
+
+    #[test]
+    fn generated_traceparents_parse() {
+        for _ in 0..100 {
+            let traceparent = TraceParent::generate();
+            assert_eq!(
+                TraceParent::parse(&traceparent.to_string()),
+                Some(traceparent)
+            );
+        }
+    }
+
+    #[test]
+    fn keeps_valid_request_ids() {
+        let id = HeaderValue::from_static("req-42_a.b:c");
+        assert_eq!(request_id(Some(&id)), id);
+
+        for garbage in ["", "has spaces", "new\tline", &"x".repeat(129)] {
+            let garbage = HeaderValue::from_str(garbage).unwrap();
+            let id = request_id(Some(&garbage));
+            assert_ne!(id, garbage);
+            assert_eq!(id.len(), 32);
+        }
+        assert_ne!(request_id(None), request_id(None));
+    }
+}
@@ -0,0 +1,280 @@
+use axum::{
+    body::Body,
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::Response,
+};
+use hyper::upgrade::OnUpgrade;
+use hyper_util::rt::TokioIo;
+use tracing::{Instrument, Span};
+
+/// A protocol the client asked to switch to.
+#[derive(Debug, PartialEq)]
+pub enum Upgrade {
+    WebSocket,
+    /// Anything else, which the proxy doesn't support.
+    Other(String),
+}
+
+impl Upgrade {
+    /// `None` unless `Connection` has `upgrade` in it and there's an
+    /// `Upgrade` header.
+    pub fn requested(headers: &HeaderMap) -> Option<Self> {
+        let connection_upgrade = headers
+            .get_all(header::CONNECTION)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .any(|option| option.trim().eq_ignore_ascii_case("upgrade"));
+        if !connection_upgrade {
+            return None;
+        }
+
+        let protocol = headers.get(header::UPGRADE)?.to_str().unwrap_or_default();
+        if protocol.trim().eq_ignore_ascii_case("websocket") {
+            Some(Self::WebSocket)
+        } else {
+            Some(Self::Other(protocol.to_owned()))
+        }
+    }
+}
+
+/// Put back the headers asking for a WebSocket, which are dropped along with
+/// the other hop-by-hop headers.
+pub fn websocket_headers(headers: &mut HeaderMap) {
+    headers.insert(header::CONNECTION, HeaderValue::from_static("upgrade"));
+    headers.insert(header::UPGRADE, HeaderValue::from_static("websocket"));
+}
+
+/// Answer the client's handshake with the upstream's, and once both
+/// connections are upgraded, copy between them until either side closes.
+///
+/// Close frames are copied like everything else, so it's the endpoints that
+/// close the connections after them. Then, and when either connection breaks,
+/// the other one is closed too.
+pub fn splice(client: OnUpgrade, upstream: reqwest::Response, mut headers: HeaderMap) -> Response {
+    websocket_headers(&mut headers);
+
+    let copy = async move {
+        let mut upstream = match upstream.upgrade().await {
+            Ok(upstream) => upstream,
+            Err(err) => return tracing::warn!(%err, "upstream websocket upgrade failed"),
+        };
+        let mut client = match client.await {
+            Ok(client) => TokioIo::new(client),
+            Err(err) => return tracing::warn!(%err, "client websocket upgrade failed"),
+        };
+        tracing::debug!("websocket open");
+        match tokio::io::copy_bidirectional(&mut client, &mut upstream).await {
+            Ok((sent, received)) => {
+                tracing::debug!(sent, received, "websocket closed");
+            }
+            Err(err) => tracing::debug!(%err, "websocket broken"),
+        }
+    };
+    tokio::spawn(copy.instrument(Span::current()));
+
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = StatusCode::SWITCHING_PROTOCOLS;
+    *response.headers_mut() = headers;
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::{HashMap, HashSet},
+        sync::{Arc, Mutex},
+    };
+
+    use axum::{
+        extract::{
+            ws::{Message, WebSocket},
+            State, WebSocketUpgrade,
+        },
+        http::Request,
+        response::IntoResponse,
+        routing::get,
+        Router,
+    };
+    use futures::{SinkExt, StreamExt};
+    use reqwest::{Client, Url};
+    use tokio::sync::broadcast;
+    use tokio_tungstenite::tungstenite;
+
+    use super::*;
+    use crate::{
+        app,
+        proxy::Proxy,
+        test_support::{send, spawn_upstream},
+    };
+
+    /// The chat example's server, in short: the first message is a name, and
+    /// every message after it goes to everyone, with `<name> joined.` and
+    /// `<name> left.` when someone comes and goes.
+    async fn chat_upstream() -> Url {
+        #[derive(Clone)]
+        struct Chat {
+            names: Arc<Mutex<HashSet<String>>>,
+            tx: broadcast::Sender<String>,
+        }
+
+        async fn websocket_handler(
+            ws: WebSocketUpgrade,
+            State(chat): State<Chat>,
+        ) -> impl IntoResponse {
+            ws.on_upgrade(|socket| websocket(socket, chat))
+        }
+
+        async fn websocket(stream: WebSocket, chat: Chat) {
+            let (mut sender, mut receiver) = stream.split();
+            let Some(Ok(Message::Text(name))) = receiver.next().await else {
+                return;
+            };
+            if !chat.names.lock().unwrap().insert(name.clone()) {
+                let _ = sender
+                    .send(Message::Text("Username already taken.".into()))
+                    .await;
+                return;
+            }
+
+            let mut rx = chat.tx.subscribe();
+            let _ = chat.tx.send(format!("{name} joined."));
+            let mut send_task = tokio::spawn(async move {
+                while let Ok(msg) = rx.recv().await {
+                    if sender.send(Message::Text(msg)).await.is_err() {
+                        break;
+                    }
+                }
+            });
+            let tx = chat.tx.clone();
+            let who = name.clone();
+            let mut recv_task = tokio::spawn(async move {
+                while let Some(Ok(Message::Text(text))) = receiver.next().await {
+                    let _ = tx.send(format!("{who}: {text}"));
+                }
+            });
+            tokio::select! {
+                _ = &mut send_task => recv_task.abort(),
+                _ = &mut recv_task => send_task.abort(),
+            }
+
+            let _ = chat.tx.send(format!("{name} left."));
+            chat.names.lock().unwrap().remove(&name);
+        }
+
+        let chat = Chat {
+            names: Arc::default(),
+            tx: broadcast::channel(100).0,
+        };
+        let router = Router::new()
+            .route("/websocket", get(websocket_handler))
+            .with_state(chat);
+        spawn_upstream(router).await
+    }
+
+    /// The proxy in front of the chat server, served for real so that
+    /// connections can be upgraded.
+    async fn proxied_chat() -> Url {
+        let proxy = Proxy::new(Client::new(), chat_upstream().await, HashMap::new());
+        let mut url = spawn_upstream(app(proxy)).await.join("/websocket").unwrap();
+        url.set_scheme("ws").unwrap();
+        url
+    }
+
+    async fn next_text<S>(socket: &mut S) -> String
+    where
+        S: futures::Stream<Item = Result<tungstenite::Message, tungstenite::Error>> + Unpin,
+    {
+        let message = tokio::time::timeout(std::time::Duration::from_secs(5), socket.next())
+            .await
+            .expect("no message within 5 seconds")
+            .unwrap()
+            .unwrap();
+        message.into_text().unwrap()
+    }
+
+    #[tokio::test]
+    async fn messages_pass_through() {
+        let url = proxied_chat().await;
+        let (mut ann, response) = tokio_tungstenite::connect_async(url.as_str())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::SWITCHING_PROTOCOLS);
+
+        ann.send(tungstenite::Message::text("ann")).await.unwrap();
+        assert_eq!(next_text(&mut ann).await, "ann joined.");
+        ann.send(tungstenite::Message::text("hello")).await.unwrap();
+        assert_eq!(next_text(&mut ann).await, "ann: hello");
+
+        ann.close(None).await.unwrap();
+        // The chat server hangs up on the close frame, without answering it,
+        // which the client sees as the end of the stream.
+        let closed = tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            while let Some(Ok(message)) = ann.next().await {
+                assert!(message.is_close(), "{message:?}");
+            }
+        });
+        closed.await.expect("still open after 5 seconds");
+    }
+
+    #[tokio::test]
+    async fn disconnects_reach_the_upstream() {
+        let url = proxied_chat().await;
+        let (mut ann, _) = tokio_tungstenite::connect_async(url.as_str())
+            .await
+            .unwrap();
+        ann.send(tungstenite::Message::text("ann")).await.unwrap();
+        assert_eq!(next_text(&mut ann).await, "ann joined.");
+        let (mut bob, _) = tokio_tungstenite::connect_async(url.as_str())
+            .await
+            .unwrap();
+        bob.send(tungstenite::Message::text("bob")).await.unwrap();
+        assert_eq!(next_text(&mut bob).await, "bob joined.");
+
+        // Without a close frame.
+        drop(ann);
+        assert_eq!(next_text(&mut bob).await, "ann left.");
+    }
+
+    #[tokio::test]
+    async fn other_upgrades_are_not_implemented() {
+        let proxy = Proxy::new(Client::new(), chat_upstream().await, HashMap::new());
+        let request = Request::get("/")
+            .header(header::CONNECTION, "Upgrade")
+            .header(header::UPGRADE, "h2c")
+            .body(Body::empty())
+            .unwrap();
+        let (status, _) = send(&app(proxy), request).await;
+        assert_eq!(status, StatusCode::NOT_IMPLEMENTED);
+    }
+
+    #[test]
+    fn detects_upgrades() {
+        let headers = |pairs: &[(header::HeaderName, &'static str)]| -> HeaderMap {
+            pairs
+                .iter()
+                .map(|(name, value)| (name.clone(), HeaderValue::from_static(value)))
+                .collect()
+        };
+
+        let cases = [
+            (vec![], None),
+            (vec![(header::UPGRADE, "websocket")], None),
+            (vec![(header::CONNECTION, "upgrade")], None),
+            (
+                vec![
+                    (header::CONNECTION, "keep-alive, Upgrade"),
+                    (header::UPGRADE, "WebSocket"),
+                ],
+                Some(Upgrade::WebSocket),
+            ),
+            (
+                vec![(header::CONNECTION, "upgrade"), (header::UPGRADE, "h2c")],
+                Some(Upgrade::Other("h2c".to_owned())),
+            ),
+        ];
+        for (pairs, expected) in cases {
+            assert_eq!(Upgrade::requested(&headers(&pairs)), expected, "{pairs:?}");
+        }
+    }
+}
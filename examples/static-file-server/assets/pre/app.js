@@ -0,0 +1,32 @@
+console.log("Hello from a precompressed file!");
+console.log("Hello from a precompressed file!");
+console.log("Hello from a precompressed file!");
+console.log("Hello from a precompressed file!");
+console.log("Hello from a precompressed file!");
+console.log("Hello from a precompressed file!");
+console.log("Hello from a precompressed file!");
+console.log("Hello from a precompressed file!");
+console.log("Hello from a precompressed file!");
+console.log("Hello from a precompressed file!");
+console.log("Hello from a precompressed file!");
+console.log("Hello from a precompressed file!");
+console.log("Hello from a precompressed file!");
+console.log("Hello from a precompressed file!");
+console.log("Hello from a precompressed file!");
+console.log("Hello from a precompressed file!");
+console.log("Hello from a precompressed file!");
+console.log("Hello from a precompressed file!");
+console.log("Hello from a precompressed file!");
+console.log("Hello from a precompressed file!");
+console.log("Hello from a precompressed file!");
+console.log("Hello from a precompressed file!");
+console.log("Hello from a precompressed file!");
+console.log("Hello from a precompressed file!");
+console.log("Hello from a precompressed file!");
+console.log("Hello from a precompressed file!");
+console.log("Hello from a precompressed file!");
+console.log("Hello from a precompressed file!");
+console.log("Hello from a precompressed file!");
+console.log("Hello from a precompressed file!");
+console.log("Hello from a precompressed file!");
+console.log("Hello from a precompressed file!");
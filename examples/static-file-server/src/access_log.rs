@@ -0,0 +1,235 @@
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use axum::body::Bytes;
+use axum::http::{HeaderMap, Method, Request, Response, StatusCode};
+use http_body::Body;
+use tower_http::classify::{
+    ClassifiedResponse, ClassifyEos, ClassifyResponse, ServerErrorsFailureClass, SharedClassifier,
+};
+use tower_http::trace::{
+    DefaultOnFailure, DefaultOnRequest, MakeSpan, OnBodyChunk, OnEos, OnResponse, TraceLayer,
+};
+use tracing::Span;
+use tracing_subscriber::registry::{LookupSpan, Registry};
+
+/// A `TraceLayer` that logs one line per request with the method, path,
+/// status, number of body bytes sent and the total latency.
+///
+/// Responses whose body fails or is dropped before its end, e.g. because the
+/// client went away, are still logged when the request span closes, with
+/// `complete=false`.
+///
+/// The byte count is accumulated in the request span's extensions as the body
+/// is streamed, so this requires a `tracing_subscriber::Registry` based
+/// subscriber.
+pub fn layer() -> TraceLayer<
+    SharedClassifier<ClassifyAtEos>,
+    AccessLog,
+    DefaultOnRequest,
+    AccessLog,
+    AccessLog,
+    AccessLog,
+    DefaultOnFailure,
+> {
+    TraceLayer::new(SharedClassifier::new(ClassifyAtEos))
+        .make_span_with(AccessLog)
+        .on_response(AccessLog)
+        .on_body_chunk(AccessLog)
+        .on_eos(AccessLog)
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct AccessLog;
+
+/// Classifies server errors as failures, like `ServerErrorsAsFailures`, but
+/// only once the body has ended.
+///
+/// `TraceLayer` only calls `on_eos` for responses whose classification
+/// requires the end of the stream, and that's where the byte count is final.
+#[derive(Debug, Clone, Copy)]
+pub struct ClassifyAtEos;
+
+impl ClassifyResponse for ClassifyAtEos {
+    type FailureClass = ServerErrorsFailureClass;
+    type ClassifyEos = StatusAtEos;
+
+    fn classify_response<B>(
+        self,
+        res: &Response<B>,
+    ) -> ClassifiedResponse<Self::FailureClass, Self::ClassifyEos> {
+        ClassifiedResponse::RequiresEos(StatusAtEos(res.status()))
+    }
+
+    fn classify_error<E>(self, error: &E) -> Self::FailureClass
+    where
+        E: fmt::Display + 'static,
+    {
+        ServerErrorsFailureClass::Error(error.to_string())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct StatusAtEos(StatusCode);
+
+impl ClassifyEos for StatusAtEos {
+    type FailureClass = ServerErrorsFailureClass;
+
+    fn classify_eos(self, _trailers: Option<&HeaderMap>) -> Result<(), Self::FailureClass> {
+        if self.0.is_server_error() {
+            Err(ServerErrorsFailureClass::StatusCode(self.0))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn classify_error<E>(self, error: &E) -> Self::FailureClass
+    where
+        E: fmt::Display + 'static,
+    {
+        ServerErrorsFailureClass::Error(error.to_string())
+    }
+}
+
+/// What's known about a request so far, stored in its span.
+struct Entry {
+    method: Method,
+    path: String,
+    start: Instant,
+    status: Option<StatusCode>,
+    bytes: u64,
+    logged: bool,
+}
+
+impl Entry {
+    /// Mark the entry as logged, returning the line to log unless that
+    /// already happened.
+    fn finish(&mut self) -> Option<Line> {
+        self.line(true)
+    }
+
+    fn line(&mut self, complete: bool) -> Option<Line> {
+        if std::mem::replace(&mut self.logged, true) {
+            return None;
+        }
+
+        Some(Line {
+            method: self.method.clone(),
+            path: self.path.clone(),
+            status: self.status,
+            bytes: self.bytes,
+            latency: self.start.elapsed(),
+            complete,
+        })
+    }
+}
+
+/// The span's extensions are dropped when it closes, after the response body
+/// is. The registry no longer holds them by then, so logging is fine.
+impl Drop for Entry {
+    fn drop(&mut self) {
+        if let Some(line) = self.line(false) {
+            line.log();
+        }
+    }
+}
+
+struct Line {
+    method: Method,
+    path: String,
+    status: Option<StatusCode>,
+    bytes: u64,
+    latency: Duration,
+    /// Whether the whole body was sent.
+    complete: bool,
+}
+
+impl Line {
+    fn log(self) {
+        tracing::info!(
+            method = %self.method,
+            path = %self.path,
+            status = self.status.map(|status| status.as_u16()),
+            bytes = self.bytes,
+            latency = ?self.latency,
+            complete = self.complete,
+            "access",
+        );
+    }
+}
+
+fn with_entry<T>(span: &Span, f: impl FnOnce(&mut Entry) -> T) -> Option<T> {
+    span.with_subscriber(|(id, dispatch)| {
+        let span = dispatch
+            .downcast_ref::<Registry>()
+            .and_then(|registry| registry.span(id))?;
+        let mut extensions = span.extensions_mut();
+        extensions.get_mut::<Entry>().map(f)
+    })
+    .flatten()
+}
+
+/// Run `f` and log the line it returns, if any. The event has to be emitted
+/// after the span's extensions are unlocked since formatting it reads them.
+fn finish(span: &Span, f: impl FnOnce(&mut Entry) -> Option<Line>) {
+    if let Some(line) = with_entry(span, f).flatten() {
+        line.log();
+    }
+}
+
+impl<B> MakeSpan<B> for AccessLog {
+    fn make_span(&mut self, request: &Request<B>) -> Span {
+        let span = tracing::info_span!("request");
+
+        span.with_subscriber(|(id, dispatch)| {
+            if let Some(span) = dispatch
+                .downcast_ref::<Registry>()
+                .and_then(|registry| registry.span(id))
+            {
+                span.extensions_mut().insert(Entry {
+                    method: request.method().clone(),
+                    path: request.uri().path().to_owned(),
+                    start: Instant::now(),
+                    status: None,
+                    bytes: 0,
+                    logged: false,
+                });
+            }
+        });
+
+        span
+    }
+}
+
+impl<B: Body> OnResponse<B> for AccessLog {
+    fn on_response(self, response: &Response<B>, _latency: Duration, span: &Span) {
+        let status = response.status();
+
+        finish(span, |entry| {
+            entry.status = Some(status);
+
+            // These never have a body worth counting, and hyper won't poll an
+            // empty body so `on_eos` wouldn't be called.
+            let bodyless = entry.method == Method::HEAD
+                || status == StatusCode::NOT_MODIFIED
+                || response.body().is_end_stream();
+            if bodyless {
+                entry.finish()
+            } else {
+                None
+            }
+        });
+    }
+}
+
+impl OnBodyChunk<Bytes> for AccessLog {
+    fn on_body_chunk(&mut self, chunk: &Bytes, _latency: Duration, span: &Span) {
+        with_entry(span, |entry| entry.bytes += chunk.len() as u64);
+    }
+}
+
+impl OnEos for AccessLog {
+    fn on_eos(self, _trailers: Option<&HeaderMap>, _stream_duration: Duration, span: &Span) {
+        finish(span, Entry::finish);
+    }
+}
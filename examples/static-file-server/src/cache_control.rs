@@ -0,0 +1,85 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use axum::http::{header, HeaderValue, Request, Response};
+use tower::{Layer, Service};
+
+/// Sets `Cache-Control` on successful responses based on the requested file's
+/// extension.
+///
+/// - Fingerprinted assets such as `app.3f2a9c1d.js` never change, so they can
+///   be cached for a year.
+/// - Images are cached for an hour.
+/// - HTML must always be revalidated so new deploys are picked up.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CacheControlLayer;
+
+impl<S> Layer<S> for CacheControlLayer {
+    type Service = CacheControl<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        CacheControl { inner }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CacheControl<S> {
+    inner: S,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for CacheControl<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        // The path has to be looked at before the request is moved into the
+        // inner service.
+        let cache_control = cache_control_for(req.uri().path());
+        let future = self.inner.call(req);
+
+        Box::pin(async move {
+            let mut res = future.await?;
+            let cacheable = res.status().is_success() || res.status().is_redirection();
+            if let (Some(value), true) = (cache_control, cacheable) {
+                res.headers_mut()
+                    .insert(header::CACHE_CONTROL, HeaderValue::from_static(value));
+            }
+            Ok(res)
+        })
+    }
+}
+
+fn cache_control_for(path: &str) -> Option<&'static str> {
+    // Directory requests are answered with `index.html`.
+    if path.ends_with('/') {
+        return Some("no-cache");
+    }
+
+    let file_name = path.rsplit('/').next().unwrap_or_default();
+    let (stem, extension) = file_name.rsplit_once('.')?;
+
+    match extension.to_ascii_lowercase().as_str() {
+        "js" | "css" if is_fingerprinted(stem) => Some("public, max-age=31536000, immutable"),
+        "png" | "jpg" | "jpeg" | "gif" | "svg" | "webp" | "avif" | "ico" => {
+            Some("public, max-age=3600")
+        }
+        "html" | "htm" => Some("no-cache"),
+        _ => None,
+    }
+}
+
+/// Whether a file stem ends in a content hash, like `app.3f2a9c1d`.
+fn is_fingerprinted(stem: &str) -> bool {
+    stem.rsplit_once('.')
+        .is_some_and(|(_, hash)| hash.len() >= 8 && hash.chars().all(|c| c.is_ascii_hexdigit()))
+}
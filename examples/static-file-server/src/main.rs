@@ -1,43 +1,196 @@
+use std::collections::HashMap;
+use std::fmt;
 use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
-use axum::extract::Request;
-use axum::handler::HandlerWithoutStateExt;
-use axum::http::StatusCode;
-use axum::routing::get;
+use axum::extract::{Host, Path as UrlPath, Query, Request, State};
+use axum::handler::{Handler, HandlerWithoutStateExt};
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode, Uri};
+use axum::middleware::{self, Next};
+use axum::response::{Html, IntoResponse, Response};
+use axum::routing::{get, put};
+use axum::Json;
 use axum::Router;
-use tower::ServiceExt;
+use axum_extra::headers::authorization::Basic;
+use axum_extra::headers::Authorization;
+use axum_extra::TypedHeader;
+use minijinja::{context, Environment};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use serde_json::json;
+use subtle::ConstantTimeEq;
+use tower::{Layer, ServiceExt};
+use tower_http::limit::RequestBodyLimitLayer;
 use tower_http::services::{ServeDir, ServeFile};
-use tower_http::trace::TraceLayer;
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::util::SubscriberInitExt;
 
+use crate::cache_control::CacheControlLayer;
+use crate::upload::Uploads;
+
+mod access_log;
+mod cache_control;
+mod upload;
+
+/// The ports used when `PORTS` isn't set, one per variant in `main`.
+const DEFAULT_PORTS: std::ops::RangeInclusive<u16> = 3001..=3016;
+
 #[tokio::main]
 async fn main() {
     tracing_subscriber::registry()
         .with(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| "example_static_file_server=debug,tower_http=debug".into()),
+            tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| {
+                format!("{}=debug,tower_http=debug", env!("CARGO_CRATE_NAME")).into()
+            }),
         )
         .with(tracing_subscriber::fmt::layer())
         .init();
 
-    tokio::join!(
-        serve(using_serve_dir(), 3001),
-        serve(using_serve_dir_with_assets_fallback(), 3002),
-        serve(using_serve_dir_only_from_root_via_fallback(), 3003),
-        serve(using_serve_dir_with_handler_as_service(), 3004),
-        serve(two_serve_dirs(), 3005),
-        serve(calling_serve_dir_from_a_handler(), 3006),
-        serve(using_serve_file_from_a_route(), 3007)
-    );
+    let config = match Config::from_env() {
+        Ok(config) => config,
+        Err(err) => {
+            tracing::error!("{err}");
+            std::process::exit(1);
+        }
+    };
+    let root = config.root.as_path();
+
+    let variants = [
+        ("serve_dir", using_serve_dir(root)),
+        (
+            "serve_dir_with_assets_fallback",
+            using_serve_dir_with_assets_fallback(root),
+        ),
+        (
+            "serve_dir_only_from_root_via_fallback",
+            using_serve_dir_only_from_root_via_fallback(root),
+        ),
+        (
+            "serve_dir_with_handler_as_service",
+            using_serve_dir_with_handler_as_service(root),
+        ),
+        ("two_serve_dirs", two_serve_dirs(root)),
+        (
+            "calling_serve_dir_from_a_handler",
+            calling_serve_dir_from_a_handler(root),
+        ),
+        (
+            "serve_file_from_a_route",
+            using_serve_file_from_a_route(root),
+        ),
+        (
+            "precompressed_serve_dir",
+            using_precompressed_serve_dir(root),
+        ),
+        ("serve_dir_with_listing", serve_dir_with_listing(root)),
+        (
+            "serve_dir_with_cache_control",
+            serve_dir_with_cache_control(root),
+        ),
+        ("serve_dir_with_upload", serve_dir_with_upload(root)),
+        (
+            "serve_dir_with_private_area",
+            serve_dir_with_private_area(root, Credentials::from_env()),
+        ),
+        ("vhost", vhost_router(root)),
+        ("spa_with_api", spa_with_api(root)),
+        (
+            "serve_dir_with_templated_404",
+            using_serve_dir_with_templated_404(root),
+        ),
+        ("embedded_assets", using_embedded_assets()),
+    ];
+
+    if config.ports.len() != variants.len() {
+        tracing::error!(
+            "PORTS lists {} ports but there are {} variants",
+            config.ports.len(),
+            variants.len()
+        );
+        std::process::exit(1);
+    }
+
+    let servers = variants
+        .into_iter()
+        .zip(config.ports)
+        .map(|((label, app), port)| serve(app, port, label));
+    futures_util::future::join_all(servers).await;
 }
 
-fn using_serve_dir() -> Router {
-    Router::new().nest_service("/assets", ServeDir::new("assets"))
+struct Config {
+    root: PathBuf,
+    ports: Vec<u16>,
 }
 
-fn using_serve_dir_with_assets_fallback() -> Router {
-    let serve_dir = ServeDir::new("assets").not_found_service(ServeFile::new("assets/index.html"));
+impl Config {
+    /// Read `STATIC_ROOT` (default `assets`) and the comma separated `PORTS`
+    /// (default 3001-3016).
+    fn from_env() -> Result<Self, ConfigError> {
+        let root = std::env::var_os("STATIC_ROOT")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from("assets"));
+        let ports = match std::env::var("PORTS") {
+            Ok(ports) => parse_ports(&ports)?,
+            Err(_) => DEFAULT_PORTS.collect(),
+        };
+
+        Ok(Self {
+            root: check_root(&root)?,
+            ports,
+        })
+    }
+}
+
+/// Make sure the root is a directory, so a typo doesn't turn into a server
+/// that 404s on everything.
+fn check_root(root: &Path) -> Result<PathBuf, ConfigError> {
+    let missing = || ConfigError::MissingRoot(root.to_owned());
+
+    let canonical = root.canonicalize().map_err(|_| missing())?;
+    if !canonical.is_dir() {
+        return Err(missing());
+    }
+    Ok(canonical)
+}
+
+fn parse_ports(ports: &str) -> Result<Vec<u16>, ConfigError> {
+    ports
+        .split(',')
+        .map(|port| {
+            port.trim()
+                .parse()
+                .map_err(|_| ConfigError::InvalidPort(port.trim().to_owned()))
+        })
+        .collect()
+}
+
+#[derive(Debug)]
+enum ConfigError {
+    MissingRoot(PathBuf),
+    InvalidPort(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingRoot(root) => write!(
+                f,
+                "static root {} is not a directory, set STATIC_ROOT to the directory to serve",
+                root.display()
+            ),
+            Self::InvalidPort(port) => write!(f, "invalid port `{port}` in PORTS"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+fn using_serve_dir(root: &Path) -> Router {
+    Router::new().nest_service("/assets", ServeDir::new(root))
+}
+
+fn using_serve_dir_with_assets_fallback(root: &Path) -> Router {
+    let serve_dir = ServeDir::new(root).not_found_service(ServeFile::new(root.join("index.html")));
 
     Router::new()
         .route("/foo", get(|| async { "Hi from /foo" }))
@@ -45,57 +198,1375 @@ fn using_serve_dir_with_assets_fallback() -> Router {
         .fallback_service(serve_dir)
 }
 
-fn using_serve_dir_only_from_root_via_fallback() -> Router {
-    let serve_dir = ServeDir::new("assets").not_found_service(ServeFile::new("assets/index.html"));
+fn using_serve_dir_only_from_root_via_fallback(root: &Path) -> Router {
+    let serve_dir = ServeDir::new(root).not_found_service(ServeFile::new(root.join("index.html")));
 
     Router::new()
         .route("/foo", get(|| async { "Hi from /foo" }))
         .fallback_service(serve_dir)
 }
 
-fn using_serve_dir_with_handler_as_service() -> Router {
+fn using_serve_dir_with_handler_as_service(root: &Path) -> Router {
     async fn handle_404() -> (StatusCode, &'static str) {
         (StatusCode::NOT_FOUND, "Not found")
     }
 
     let service = handle_404.into_service();
 
-    let serve_dir = ServeDir::new("assets").not_found_service(service);
+    let serve_dir = ServeDir::new(root).not_found_service(service);
+
+    Router::new()
+        .route("/foo", get(|| async { "Hi from /foo" }))
+        .fallback_service(serve_dir)
+}
+
+fn using_serve_dir_with_templated_404(root: &Path) -> Router {
+    // Templates with an `.html` name are auto-escaped, which keeps the path
+    // from being reflected back as markup.
+    let mut env = Environment::new();
+    env.add_template("404.html", include_str!("../templates/404.html"))
+        .unwrap();
+
+    let service = handle_404_page.with_state(Arc::new(env));
+
+    let serve_dir = ServeDir::new(root).not_found_service(service);
 
     Router::new()
         .route("/foo", get(|| async { "Hi from /foo" }))
         .fallback_service(serve_dir)
 }
 
-fn two_serve_dirs() -> Router {
-    let serve_dir_from_assets = ServeDir::new("assets");
-    let serve_dir_from_dist = ServeDir::new("dist");
+async fn handle_404_page(
+    State(env): State<Arc<Environment<'static>>>,
+    uri: Uri,
+) -> Result<(StatusCode, Html<String>), StatusCode> {
+    let path = percent_decode_str(uri.path()).decode_utf8_lossy();
+
+    let rendered = env
+        .get_template("404.html")
+        .and_then(|template| template.render(context! { path }))
+        .map_err(|err| {
+            tracing::error!("failed to render 404 page: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    Ok((StatusCode::NOT_FOUND, Html(rendered)))
+}
+
+/// Serve `root` and the `dist` directory next to it.
+fn two_serve_dirs(root: &Path) -> Router {
+    let serve_dir_from_assets = ServeDir::new(root);
+    let serve_dir_from_dist = ServeDir::new(root.with_file_name("dist"));
 
     Router::new()
         .nest_service("/assets", serve_dir_from_assets)
         .nest_service("/dist", serve_dir_from_dist)
 }
 
-fn calling_serve_dir_from_a_handler() -> Router {
+fn calling_serve_dir_from_a_handler(root: &Path) -> Router {
+    let root = root.to_owned();
     Router::new().nest_service(
         "/foo",
-        get(|request: Request| async {
-            let service = ServeDir::new("assets");
+        get(|request: Request| async move {
+            let service = ServeDir::new(root);
             let result = service.oneshot(request).await;
             result
         }),
     )
 }
 
-fn using_serve_file_from_a_route() -> Router {
-    Router::new().route_service("/foo", ServeFile::new("assets/index.html"))
+fn using_serve_file_from_a_route(root: &Path) -> Router {
+    Router::new().route_service("/foo", ServeFile::new(root.join("index.html")))
 }
 
-async fn serve(app: Router, port: u16) {
+fn using_precompressed_serve_dir(root: &Path) -> Router {
+    // `ServeDir` picks `app.js.br` or `app.js.gz` over `app.js` when the client
+    // accepts that encoding and the precompressed file exists.
+    let serve_dir = ServeDir::new(root.join("pre"))
+        .precompressed_br()
+        .precompressed_gzip();
+
+    Router::new().nest_service("/pre", serve_dir)
+}
+
+fn serve_dir_with_listing(root: &Path) -> Router {
+    // `fallback` rather than `not_found_service`, which would force a 404 status
+    // onto the listing too.
+    let listing = list_directory.with_state(Arc::<Path>::from(root));
+    let serve_dir = ServeDir::new(root).fallback(listing);
+
+    Router::new().fallback_service(serve_dir)
+}
+
+fn serve_dir_with_cache_control(root: &Path) -> Router {
+    Router::new().nest_service("/assets", CacheControlLayer.layer(ServeDir::new(root)))
+}
+
+/// Maximum size of a single upload.
+const UPLOAD_LIMIT: usize = 10 * 1024 * 1024;
+
+fn serve_dir_with_upload(root: &Path) -> Router {
+    Router::new()
+        .route(
+            "/upload/*path",
+            put(upload::upload).layer(RequestBodyLimitLayer::new(UPLOAD_LIMIT)),
+        )
+        .nest_service("/assets", ServeDir::new(root))
+        .with_state(Arc::new(Uploads::new(root)))
+}
+
+/// Serve `root` publicly, except for its `private` directory which requires
+/// HTTP Basic auth.
+fn serve_dir_with_private_area(root: &Path, credentials: Credentials) -> Router {
+    let private = Router::new()
+        .nest_service("/assets/private", ServeDir::new(root.join("private")))
+        .layer(middleware::from_fn_with_state(
+            Arc::new(credentials),
+            basic_auth,
+        ));
+
+    // The more specific `/assets/private` route wins over `/assets`, but the
+    // router matches on the raw path, so `/assets/%70rivate/...` would still
+    // reach the public `ServeDir`. Hide the private tree from it explicitly.
+    let public = ServeDir::new(root);
+    let public = Router::new()
+        .nest_service("/assets", public)
+        .layer(middleware::from_fn(hide_private_tree));
+
+    public.merge(private)
+}
+
+#[derive(Clone)]
+struct Credentials {
+    username: String,
+    password: String,
+}
+
+impl Credentials {
+    /// Read the credentials from `PRIVATE_USERNAME` and `PRIVATE_PASSWORD`.
+    fn from_env() -> Self {
+        let var = |name, default: &str| {
+            std::env::var(name).unwrap_or_else(|_| {
+                tracing::warn!("{name} not set, defaulting to `{default}`");
+                default.to_owned()
+            })
+        };
+
+        Self {
+            username: var("PRIVATE_USERNAME", "user"),
+            password: var("PRIVATE_PASSWORD", "password"),
+        }
+    }
+
+    fn matches(&self, basic: &Basic) -> bool {
+        // Compare both fields without short-circuiting so the response time
+        // doesn't reveal which one was wrong.
+        let username = self.username.as_bytes().ct_eq(basic.username().as_bytes());
+        let password = self.password.as_bytes().ct_eq(basic.password().as_bytes());
+        (username & password).into()
+    }
+}
+
+async fn basic_auth(
+    State(credentials): State<Arc<Credentials>>,
+    auth: Option<TypedHeader<Authorization<Basic>>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    match auth {
+        Some(TypedHeader(Authorization(basic))) if credentials.matches(&basic) => {
+            next.run(request).await
+        }
+        _ => (
+            StatusCode::UNAUTHORIZED,
+            [(header::WWW_AUTHENTICATE, r#"Basic realm="private""#)],
+            "Unauthorized",
+        )
+            .into_response(),
+    }
+}
+
+/// Resolves the path like `ServeDir` does, which skips empty and `.`
+/// segments, so `/assets/./private` is caught too. `ServeDir` refuses `..`
+/// anyway.
+async fn hide_private_tree(request: Request, next: Next) -> Response {
+    let path = percent_decode_str(request.uri().path()).decode_utf8_lossy();
+    let mut segments = path
+        .trim_start_matches("/assets")
+        .split('/')
+        .filter(|segment| !segment.is_empty() && *segment != ".");
+
+    let first_segment = segments.clone().next();
+    if first_segment == Some("private") || segments.any(|segment| segment == "..") {
+        return (StatusCode::NOT_FOUND, "Not found").into_response();
+    }
+    next.run(request).await
+}
+
+/// Serve a different directory depending on the `Host` header.
+fn vhost_router(root: &Path) -> Router {
+    let sites: HashMap<String, ServeDir> = [
+        ("site-a.localhost", "site-a"),
+        ("site-b.localhost", "site-b"),
+    ]
+    .into_iter()
+    .map(|(host, dir)| (host.to_owned(), ServeDir::new(root.join(dir))))
+    .collect();
+    let sites = Arc::new(sites);
+
+    Router::new().fallback(|Host(host): Host, request: Request| async move {
+        let Some(serve_dir) = sites.get(&normalize_host(&host)) else {
+            return (StatusCode::MISDIRECTED_REQUEST, "Unknown host").into_response();
+        };
+        serve_dir.clone().oneshot(request).await.into_response()
+    })
+}
+
+/// Lowercase the host and strip the port, if any.
+fn normalize_host(host: &str) -> String {
+    let host = match host.rsplit_once(':') {
+        // Don't mistake the end of a bracketed IPv6 address for a port.
+        Some((host, port)) if !port.contains(']') => host,
+        _ => host,
+    };
+    host.to_ascii_lowercase()
+}
+
+/// Like `using_serve_dir_with_assets_fallback` but unknown `/api` routes get a
+/// JSON 404 instead of `index.html`.
+fn spa_with_api(root: &Path) -> Router {
+    let api = Router::new()
+        .route("/health", get(|| async { Json(json!({ "status": "ok" })) }))
+        .route(
+            "/users",
+            get(|| async { Json(json!([{ "id": 1, "name": "Ferris" }])) }),
+        )
+        .fallback(|| async {
+            (
+                StatusCode::NOT_FOUND,
+                Json(json!({ "error": "no such API route" })),
+            )
+        });
+
+    // `fallback` rather than `not_found_service` so client side routes get a 200.
+    let spa = ServeDir::new(root).fallback(ServeFile::new(root.join("index.html")));
+
+    Router::new()
+        .nest("/api", api)
+        .nest_service("/assets", ServeDir::new(root))
+        .fallback_service(spa)
+}
+
+/// A file compiled into the binary.
+struct EmbeddedAsset {
+    contents: &'static [u8],
+    content_type: &'static str,
+    etag: &'static str,
+}
+
+/// FNV-1a, which is simple enough to run at compile time.
+const fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash = 0xcbf29ce484222325;
+    let mut i = 0;
+    while i < bytes.len() {
+        hash ^= bytes[i] as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+        i += 1;
+    }
+    hash
+}
+
+/// Embed a file from `assets` along with an ETag computed at compile time.
+macro_rules! embed {
+    ($path:literal, $content_type:literal) => {{
+        const CONTENTS: &[u8] = include_bytes!(concat!("../assets/", $path));
+        const HASH: u64 = fnv1a(CONTENTS);
+        const ETAG: [u8; 18] = {
+            let mut etag = [b'"'; 18];
+            let mut i = 0;
+            while i < 16 {
+                etag[16 - i] = b"0123456789abcdef"[((HASH >> (i * 4)) & 0xf) as usize];
+                i += 1;
+            }
+            etag
+        };
+        (
+            $path,
+            EmbeddedAsset {
+                contents: CONTENTS,
+                content_type: $content_type,
+                etag: match std::str::from_utf8(&ETAG) {
+                    Ok(etag) => etag,
+                    Err(_) => panic!("ETag is ASCII"),
+                },
+            },
+        )
+    }};
+}
+
+/// Serve assets compiled into the binary, for deployments without the
+/// `assets` directory.
+fn using_embedded_assets() -> Router {
+    let assets: HashMap<&'static str, EmbeddedAsset> = HashMap::from([
+        embed!("index.html", "text/html"),
+        embed!("script.js", "text/javascript"),
+        embed!("style.css", "text/css"),
+    ]);
+
+    Router::new()
+        .route("/embedded", get(embedded_asset))
+        .route("/embedded/", get(embedded_asset))
+        .route("/embedded/*path", get(embedded_asset))
+        .with_state(Arc::new(assets))
+}
+
+async fn embedded_asset(
+    State(assets): State<Arc<HashMap<&'static str, EmbeddedAsset>>>,
+    path: Option<UrlPath<String>>,
+    headers: HeaderMap,
+) -> Response {
+    let path = match path {
+        Some(UrlPath(path)) if !path.is_empty() && !path.ends_with('/') => path,
+        Some(UrlPath(dir)) => format!("{dir}index.html"),
+        None => "index.html".to_owned(),
+    };
+
+    let Some(asset) = assets.get(path.as_str()) else {
+        return (StatusCode::NOT_FOUND, "Not found").into_response();
+    };
+    let etag = HeaderValue::from_static(asset.etag);
+
+    let not_modified = headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|tag| tag.trim().trim_start_matches("W/"))
+        .any(|tag| tag == "*" || tag == asset.etag);
+    if not_modified {
+        return (StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response();
+    }
+
+    (
+        [
+            (
+                header::CONTENT_TYPE,
+                HeaderValue::from_static(asset.content_type),
+            ),
+            (header::ETAG, etag),
+        ],
+        asset.contents,
+    )
+        .into_response()
+}
+
+/// Characters that don't need escaping in a single path segment.
+pub(crate) const PATH_SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+struct DirEntry {
+    name: String,
+    is_dir: bool,
+    size: u64,
+    modified: Option<std::time::SystemTime>,
+}
+
+/// Render an HTML listing for directories under `root` that don't have an
+/// `index.html`. Anything else is a plain 404.
+///
+/// Dotfiles are hidden unless the query string contains `all=1`.
+async fn list_directory(
+    State(root): State<Arc<Path>>,
+    uri: Uri,
+    Query(params): Query<HashMap<String, String>>,
+) -> Response {
+    let not_found = (StatusCode::NOT_FOUND, "Not found").into_response();
+
+    let Some(dir) = resolve_dir(&root, uri.path()).await else {
+        return not_found;
+    };
+    let show_hidden = params.get("all").is_some_and(|all| all == "1");
+
+    let Ok(mut read_dir) = tokio::fs::read_dir(&dir).await else {
+        return not_found;
+    };
+    let mut entries = Vec::new();
+    while let Ok(Some(entry)) = read_dir.next_entry().await {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with('.') && !show_hidden {
+            continue;
+        }
+        let Ok(metadata) = entry.metadata().await else {
+            continue;
+        };
+        entries.push(DirEntry {
+            name,
+            is_dir: metadata.is_dir(),
+            size: metadata.len(),
+            modified: metadata.modified().ok(),
+        });
+    }
+    entries.sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then_with(|| a.name.cmp(&b.name)));
+
+    let base = uri.path().trim_end_matches('/');
+    let title = escape_html(&format!("{base}/"));
+    let mut html = format!(
+        "<!doctype html>\n<title>Index of {title}</title>\n<h1>Index of {title}</h1>\n<table>\n"
+    );
+    for entry in entries {
+        let slash = if entry.is_dir { "/" } else { "" };
+        let href = format!(
+            "{base}/{}{slash}",
+            utf8_percent_encode(&entry.name, PATH_SEGMENT)
+        );
+        let size = if entry.is_dir {
+            "-".to_owned()
+        } else {
+            entry.size.to_string()
+        };
+        let modified = entry
+            .modified
+            .map(httpdate::fmt_http_date)
+            .unwrap_or_default();
+        html.push_str(&format!(
+            "<tr><td><a href=\"{}\">{}{slash}</a></td><td>{size}</td><td>{modified}</td></tr>\n",
+            escape_html(&href),
+            escape_html(&entry.name),
+        ));
+    }
+    html.push_str("</table>\n");
+
+    Html(html).into_response()
+}
+
+/// Map a request path to a directory inside `root`, refusing anything that
+/// escapes it once `..` components and symlinks are resolved.
+async fn resolve_dir(root: &Path, request_path: &str) -> Option<PathBuf> {
+    let decoded = percent_decode_str(request_path).decode_utf8().ok()?;
+    let root = tokio::fs::canonicalize(root).await.ok()?;
+    let path = tokio::fs::canonicalize(root.join(decoded.trim_start_matches('/')))
+        .await
+        .ok()?;
+
+    let is_dir = tokio::fs::metadata(&path).await.ok()?.is_dir();
+    (path.starts_with(&root) && is_dir).then_some(path)
+}
+
+fn escape_html(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+async fn serve(app: Router, port: u16, label: &str) {
     let addr = SocketAddr::from(([127, 0, 0, 1], port));
     let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
-    tracing::debug!("listening on {}", listener.local_addr().unwrap());
-    axum::serve(listener, app.layer(TraceLayer::new_for_http()))
+    tracing::debug!("{label} listening on {}", listener.local_addr().unwrap());
+    axum::serve(listener, app.layer(access_log::layer()))
         .await
         .unwrap();
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Read, Write};
+
+    use axum::body::Body;
+    use axum::http::{header, HeaderValue};
+    use http_body_util::BodyExt;
+
+    use super::*;
+
+    const SCRIPT_JS: &str = "console.log(\"Hi from script.js\");\n";
+
+    /// A static root in a temporary directory, removed on drop.
+    struct Fixtures {
+        _dir: tempfile::TempDir,
+        root: PathBuf,
+    }
+
+    /// Generate the files the tests expect. The root is a subdirectory so
+    /// that siblings like `uploads.tmp` end up in the temporary directory too.
+    fn fixtures() -> Fixtures {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("assets");
+
+        let files: [(&str, &[u8]); 16] = [
+            ("index.html", b"Hi from index.html"),
+            ("script.js", SCRIPT_JS.as_bytes()),
+            ("style.css", b"body { font-family: sans-serif; }\n"),
+            ("range.txt", &range_txt()),
+            ("listing/a.txt", b"alpha\n"),
+            ("listing/b.txt", b"bravo\n"),
+            ("listing/.hidden", b"hidden\n"),
+            ("listing/sub/c.txt", b"charlie\n"),
+            ("cache/app.3f2a9c1d.js", b"// fingerprinted\n"),
+            ("cache/style.deadbeef42.css", b"/* fingerprinted */\n"),
+            ("cache/app.js", b"// not fingerprinted\n"),
+            (
+                "cache/logo.svg",
+                b"<svg xmlns=\"http://www.w3.org/2000/svg\"/>\n",
+            ),
+            ("cache/page.html", b"<p>page</p>\n"),
+            ("private/secret.txt", b"top secret\n"),
+            ("site-a/index.html", b"Hi from site A\n"),
+            ("site-b/index.html", b"Hi from site B\n"),
+        ];
+        for (path, contents) in files {
+            let path = root.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        }
+        write_precompressed_assets(&root.join("pre")).unwrap();
+
+        Fixtures { _dir: dir, root }
+    }
+
+    /// Write `app.js` along with gzip and brotli compressed copies into `dir`,
+    /// like the ones checked in under `assets/pre`.
+    fn write_precompressed_assets(dir: &Path) -> io::Result<()> {
+        let js = "console.log(\"Hello from a precompressed file!\");\n".repeat(32);
+
+        std::fs::create_dir_all(dir)?;
+        std::fs::write(dir.join("app.js"), &js)?;
+
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+        gzip.write_all(js.as_bytes())?;
+        std::fs::write(dir.join("app.js.gz"), gzip.finish()?)?;
+
+        let mut br = brotli::CompressorWriter::new(Vec::new(), 4096, 11, 22);
+        br.write_all(js.as_bytes())?;
+        std::fs::write(dir.join("app.js.br"), br.into_inner())?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn checked_in_precompressed_assets_match() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("assets/pre");
+        let js = std::fs::read(dir.join("app.js")).unwrap();
+
+        let mut gunzipped = Vec::new();
+        flate2::read::GzDecoder::new(&std::fs::read(dir.join("app.js.gz")).unwrap()[..])
+            .read_to_end(&mut gunzipped)
+            .unwrap();
+        assert_eq!(gunzipped, js);
+
+        let mut unbrotlied = Vec::new();
+        brotli::Decompressor::new(&std::fs::read(dir.join("app.js.br")).unwrap()[..], 4096)
+            .read_to_end(&mut unbrotlied)
+            .unwrap();
+        assert_eq!(unbrotlied, js);
+    }
+
+    fn range_txt() -> Vec<u8> {
+        (0..128)
+            .flat_map(|i| format!("{i:07}\n").into_bytes())
+            .collect()
+    }
+
+    #[test]
+    fn parse_ports_list() {
+        assert_eq!(parse_ports("3001, 3002,4000").unwrap(), [3001, 3002, 4000]);
+
+        let err = parse_ports("3001,http").unwrap_err();
+        assert!(matches!(err, ConfigError::InvalidPort(ref port) if port == "http"));
+        assert!(parse_ports("70000").is_err());
+    }
+
+    #[test]
+    fn missing_root_fails_fast() {
+        let fixtures = fixtures();
+        assert!(check_root(&fixtures.root).is_ok());
+
+        for root in [fixtures.root.join("nope"), fixtures.root.join("index.html")] {
+            let err = check_root(&root).unwrap_err();
+            assert!(matches!(err, ConfigError::MissingRoot(_)), "{err:?}");
+            assert!(err.to_string().contains("STATIC_ROOT"));
+        }
+    }
+
+    async fn get_app_js(
+        root: &Path,
+        accept_encoding: Option<&str>,
+    ) -> (Option<HeaderValue>, Vec<u8>) {
+        let mut request = Request::get("/pre/app.js");
+        if let Some(accept_encoding) = accept_encoding {
+            request = request.header(header::ACCEPT_ENCODING, accept_encoding);
+        }
+
+        let response = using_precompressed_serve_dir(root)
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "text/javascript");
+
+        let encoding = response.headers().get(header::CONTENT_ENCODING).cloned();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (encoding, body.to_vec())
+    }
+
+    #[tokio::test]
+    async fn precompressed_gzip() {
+        let fixtures = fixtures();
+        let (encoding, body) = get_app_js(&fixtures.root, Some("gzip")).await;
+
+        assert_eq!(encoding.unwrap(), "gzip");
+        assert_eq!(
+            body,
+            std::fs::read(fixtures.root.join("pre/app.js.gz")).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn precompressed_br() {
+        let fixtures = fixtures();
+        let (encoding, body) = get_app_js(&fixtures.root, Some("br")).await;
+
+        assert_eq!(encoding.unwrap(), "br");
+        assert_eq!(
+            body,
+            std::fs::read(fixtures.root.join("pre/app.js.br")).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn precompressed_without_accept_encoding() {
+        let fixtures = fixtures();
+        let (encoding, body) = get_app_js(&fixtures.root, None).await;
+
+        assert!(encoding.is_none());
+        assert_eq!(
+            body,
+            std::fs::read(fixtures.root.join("pre/app.js")).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn precompressed_falls_back_to_identity() {
+        let fixtures = fixtures();
+        let (encoding, body) = get_app_js(&fixtures.root, Some("zstd")).await;
+
+        assert!(encoding.is_none());
+        assert_eq!(
+            body,
+            std::fs::read(fixtures.root.join("pre/app.js")).unwrap()
+        );
+    }
+
+    async fn get(app: Router, uri: &str) -> (StatusCode, String) {
+        let response = app
+            .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn listing_directories_first() {
+        let fixtures = fixtures();
+        let (status, body) = get(serve_dir_with_listing(&fixtures.root), "/listing/").await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("<h1>Index of /listing/</h1>"));
+        assert!(body.contains(r#"<a href="/listing/sub/">sub/</a></td><td>-</td>"#));
+        assert!(body.contains(r#"<a href="/listing/a.txt">a.txt</a></td><td>6</td>"#));
+
+        let sub = body.find("sub/").unwrap();
+        let a = body.find("a.txt").unwrap();
+        let b = body.find("b.txt").unwrap();
+        assert!(sub < a && a < b);
+    }
+
+    #[tokio::test]
+    async fn listing_hides_dotfiles_by_default() {
+        let fixtures = fixtures();
+        let app = serve_dir_with_listing(&fixtures.root);
+
+        let (_, body) = get(app.clone(), "/listing/").await;
+        assert!(!body.contains(".hidden"));
+
+        let (status, body) = get(app, "/listing/?all=1").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains(r#"<a href="/listing/.hidden">.hidden</a>"#));
+    }
+
+    #[tokio::test]
+    async fn listing_still_serves_files() {
+        let fixtures = fixtures();
+        let (status, body) =
+            get(serve_dir_with_listing(&fixtures.root), "/listing/sub/c.txt").await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "charlie\n");
+    }
+
+    #[tokio::test]
+    async fn listing_missing_path_is_not_found() {
+        let fixtures = fixtures();
+        let (status, body) = get(serve_dir_with_listing(&fixtures.root), "/listing/nope/").await;
+
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body, "Not found");
+    }
+
+    #[tokio::test]
+    async fn listing_rejects_path_traversal() {
+        let fixtures = fixtures();
+        for uri in [
+            "/..%2f..%2fsecret",
+            "/..%2fsrc/",
+            "/listing/..%2f..%2f",
+            "/listing/%2e%2e/%2e%2e/",
+        ] {
+            let (status, body) = get(serve_dir_with_listing(&fixtures.root), uri).await;
+
+            assert_eq!(status, StatusCode::NOT_FOUND, "{uri}");
+            assert_eq!(body, "Not found", "{uri}");
+        }
+    }
+
+    async fn cache_control(uri: &str) -> (StatusCode, Option<HeaderValue>) {
+        let fixtures = fixtures();
+        let response = serve_dir_with_cache_control(&fixtures.root)
+            .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let cache_control = response.headers().get(header::CACHE_CONTROL).cloned();
+        (response.status(), cache_control)
+    }
+
+    #[tokio::test]
+    async fn cache_control_by_file_type() {
+        for (uri, expected) in [
+            (
+                "/assets/cache/app.3f2a9c1d.js",
+                Some("public, max-age=31536000, immutable"),
+            ),
+            (
+                "/assets/cache/style.deadbeef42.css",
+                Some("public, max-age=31536000, immutable"),
+            ),
+            ("/assets/cache/logo.svg", Some("public, max-age=3600")),
+            ("/assets/cache/page.html", Some("no-cache")),
+            ("/assets/", Some("no-cache")),
+            ("/assets/cache/app.js", None),
+        ] {
+            let (status, cache_control) = cache_control(uri).await;
+
+            assert_eq!(status, StatusCode::OK, "{uri}");
+            assert_eq!(
+                cache_control.as_ref().map(|v| v.to_str().unwrap()),
+                expected,
+                "{uri}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn cache_control_not_set_on_not_found() {
+        let (status, cache_control) = cache_control("/assets/cache/missing.0123abcd.js").await;
+
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert!(cache_control.is_none());
+    }
+
+    async fn put_upload(root: &Path, uri: &str, body: impl Into<Body>) -> (StatusCode, String) {
+        let response = serve_dir_with_upload(root)
+            .oneshot(Request::put(uri).body(body.into()).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn upload_then_download() {
+        let fixtures = fixtures();
+        let root = fixtures.root.as_path();
+        let name = "nested/upload.bin";
+        let contents: Vec<u8> = (0..=255).cycle().take(100_000).collect();
+
+        let (status, url) = put_upload(root, &format!("/upload/{name}"), contents.clone()).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(url, format!("/assets/uploads/{name}"));
+
+        let response = serve_dir_with_upload(root)
+            .oneshot(Request::get(&url).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, contents);
+
+        let (status, _) = put_upload(root, &format!("/upload/{name}"), "again").await;
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        let (status, _) =
+            put_upload(root, &format!("/upload/{name}?overwrite=true"), "again").await;
+        assert_eq!(status, StatusCode::CREATED);
+        let path = root.join("uploads").join(name);
+        assert_eq!(std::fs::read(&path).unwrap(), b"again");
+    }
+
+    #[tokio::test]
+    async fn concurrent_uploads_to_one_path() {
+        let fixtures = fixtures();
+        let root = fixtures.root.as_path();
+
+        let uploads = (0..8).map(|n| put_upload(root, "/upload/race.txt", format!("upload {n}")));
+        let statuses: Vec<_> = futures_util::future::join_all(uploads)
+            .await
+            .into_iter()
+            .map(|(status, _)| status)
+            .collect();
+
+        let created = statuses
+            .iter()
+            .filter(|s| **s == StatusCode::CREATED)
+            .count();
+        assert_eq!(created, 1, "{statuses:?}");
+        assert!(statuses
+            .iter()
+            .all(|s| *s == StatusCode::CREATED || *s == StatusCode::CONFLICT));
+        assert_eq!(
+            std::fs::read_dir(root.with_file_name("uploads.tmp"))
+                .unwrap()
+                .count(),
+            0
+        );
+    }
+
+    #[tokio::test]
+    async fn upload_urls_are_encoded() {
+        let fixtures = fixtures();
+        let root = fixtures.root.as_path();
+
+        let (status, url) = put_upload(root, "/upload/my%20notes/50%25%3F.txt", "notes").await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(url, "/assets/uploads/my%20notes/50%25%3F.txt");
+        assert!(root.join("uploads/my notes/50%?.txt").exists());
+
+        let response = serve_dir_with_upload(root)
+            .oneshot(Request::get(&url).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn upload_rejects_invalid_paths() {
+        let fixtures = fixtures();
+        for uri in [
+            "/upload/../../etc/passwd",
+            "/upload/..%2F..%2Fetc%2Fpasswd",
+            "/upload//etc/passwd",
+            "/upload/a//b",
+            "/upload/a/./b",
+            "/upload/dir/",
+        ] {
+            let (status, _) = put_upload(&fixtures.root, uri, "nope").await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn upload_too_large() {
+        let fixtures = fixtures();
+        let root = fixtures.root.as_path();
+        let (status, _) =
+            put_upload(root, "/upload/too-large.bin", vec![0; UPLOAD_LIMIT + 1]).await;
+
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+        assert!(!root.join("uploads").join("too-large.bin").exists());
+    }
+
+    fn private_area(root: &Path) -> Router {
+        serve_dir_with_private_area(
+            root,
+            Credentials {
+                username: "user".to_owned(),
+                password: "hunter2".to_owned(),
+            },
+        )
+    }
+
+    async fn get_private(uri: &str, authorization: Option<&str>) -> Response {
+        let mut request = Request::get(uri);
+        if let Some(authorization) = authorization {
+            request = request.header(header::AUTHORIZATION, authorization);
+        }
+        let fixtures = fixtures();
+        private_area(&fixtures.root)
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+    }
+
+    fn basic(username: &str, password: &str) -> String {
+        use base64::Engine;
+        let encoded =
+            base64::engine::general_purpose::STANDARD.encode(format!("{username}:{password}"));
+        format!("Basic {encoded}")
+    }
+
+    #[tokio::test]
+    async fn private_area_without_credentials() {
+        let response = get_private("/assets/private/secret.txt", None).await;
+
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            response.headers()[header::WWW_AUTHENTICATE],
+            r#"Basic realm="private""#
+        );
+    }
+
+    #[tokio::test]
+    async fn private_area_with_wrong_password() {
+        let auth = basic("user", "wrong");
+        let response = get_private("/assets/private/secret.txt", Some(&auth)).await;
+
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            response.headers()[header::WWW_AUTHENTICATE],
+            r#"Basic realm="private""#
+        );
+    }
+
+    #[tokio::test]
+    async fn private_area_with_credentials() {
+        let auth = basic("user", "hunter2");
+        let response = get_private("/assets/private/secret.txt", Some(&auth)).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(&body[..], b"top secret\n");
+    }
+
+    #[tokio::test]
+    async fn private_area_leaves_public_tree_open() {
+        let response = get_private("/assets/index.html", None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        for uri in [
+            "/assets/%70rivate/secret.txt",
+            "/assets//private/secret.txt",
+            "/assets/./private/secret.txt",
+            "/assets/%2e/private/secret.txt",
+            "/assets/%2E/./%2e//private/secret.txt",
+            "/assets/x/../private/secret.txt",
+        ] {
+            let response = get_private(uri, None).await;
+            assert_eq!(response.status(), StatusCode::NOT_FOUND, "{uri}");
+        }
+    }
+
+    async fn get_with_host(host: &str) -> (StatusCode, String) {
+        let fixtures = fixtures();
+        let response = vhost_router(&fixtures.root)
+            .oneshot(
+                Request::get("/index.html")
+                    .header(header::HOST, host)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn vhost_picks_directory_by_host() {
+        assert_eq!(
+            get_with_host("site-a.localhost").await,
+            (StatusCode::OK, "Hi from site A\n".to_owned())
+        );
+        assert_eq!(
+            get_with_host("SITE-B.localhost:3013").await,
+            (StatusCode::OK, "Hi from site B\n".to_owned())
+        );
+
+        let (status, _) = get_with_host("example.com").await;
+        assert_eq!(status, StatusCode::MISDIRECTED_REQUEST);
+    }
+
+    #[test]
+    fn normalize_host_strips_port() {
+        assert_eq!(normalize_host("Site-A.localhost:8080"), "site-a.localhost");
+        assert_eq!(normalize_host("site-a.localhost"), "site-a.localhost");
+        assert_eq!(normalize_host("[::1]:3000"), "[::1]");
+        assert_eq!(normalize_host("[::1]"), "[::1]");
+    }
+
+    async fn get_spa(uri: &str) -> (StatusCode, Option<HeaderValue>, String) {
+        let fixtures = fixtures();
+        let response = spa_with_api(&fixtures.root)
+            .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let content_type = response.headers().get(header::CONTENT_TYPE).cloned();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (
+            status,
+            content_type,
+            String::from_utf8(body.to_vec()).unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn spa_unknown_api_route_is_json_404() {
+        let (status, content_type, body) = get_spa("/api/unknown").await;
+
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(content_type.unwrap(), "application/json");
+        assert_eq!(body, r#"{"error":"no such API route"}"#);
+    }
+
+    #[tokio::test]
+    async fn spa_api_route() {
+        let (status, content_type, body) = get_spa("/api/health").await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(content_type.unwrap(), "application/json");
+        assert_eq!(body, r#"{"status":"ok"}"#);
+    }
+
+    #[tokio::test]
+    async fn spa_client_side_route_is_index_html() {
+        let (status, content_type, body) = get_spa("/some/spa/route").await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(content_type.unwrap(), "text/html");
+        assert_eq!(body, "Hi from index.html");
+    }
+
+    #[tokio::test]
+    async fn spa_serves_real_files() {
+        let (status, content_type, body) = get_spa("/assets/style.css").await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(content_type.unwrap(), "text/css");
+        assert_eq!(body, "body { font-family: sans-serif; }\n");
+    }
+
+    #[tokio::test]
+    async fn templated_404_escapes_path() {
+        let fixtures = fixtures();
+        let response = using_serve_dir_with_templated_404(&fixtures.root)
+            .oneshot(
+                Request::get("/does/not/%3Cscript%3E")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "text/html; charset=utf-8"
+        );
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains("&lt;script&gt;"));
+        assert!(!body.contains("<script>"));
+        assert!(body.contains(r#"<a href="/">"#));
+    }
+
+    /// Collects everything written by a `fmt` subscriber.
+    #[derive(Clone, Default)]
+    struct CapturedLogs(Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl Write for CapturedLogs {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    async fn access_log_line(root: &Path, request: Request<Body>) -> String {
+        access_log_line_from(using_serve_dir(root), request, true).await
+    }
+
+    /// The access log line for `request` to `app`, dropping the response body
+    /// unread unless `read_body` is set.
+    async fn access_log_line_from(app: Router, request: Request<Body>, read_body: bool) -> String {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let logs = CapturedLogs::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::registry().with(
+            tracing_subscriber::fmt::layer()
+                .with_ansi(false)
+                .with_writer(move || writer.clone()),
+        );
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let response = app
+            .layer(access_log::layer())
+            .oneshot(request)
+            .await
+            .unwrap();
+        if read_body {
+            // Errors are logged too, see `access_log_body_fails`.
+            let _ = response.into_body().collect().await;
+        } else {
+            drop(response);
+        }
+
+        let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        let mut lines = logs.lines().filter(|line| line.contains("access"));
+        let line = lines.next().expect("no access log line").to_owned();
+        assert!(lines.next().is_none(), "more than one access log line");
+        line
+    }
+
+    #[tokio::test]
+    async fn access_log_counts_body_bytes() {
+        let fixtures = fixtures();
+        let line = access_log_line(
+            &fixtures.root,
+            Request::get("/assets/script.js")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+
+        assert!(line.contains("method=GET"), "{line}");
+        assert!(line.contains("path=/assets/script.js"), "{line}");
+        assert!(line.contains("status=200"), "{line}");
+        let bytes = format!("bytes={}", SCRIPT_JS.len());
+        assert!(line.contains(&bytes), "{line}");
+        assert!(line.contains("latency="), "{line}");
+        assert!(line.contains("complete=true"), "{line}");
+    }
+
+    #[tokio::test]
+    async fn access_log_body_dropped_early() {
+        let fixtures = fixtures();
+        let line = access_log_line_from(
+            using_serve_dir(&fixtures.root),
+            Request::get("/assets/script.js")
+                .body(Body::empty())
+                .unwrap(),
+            false,
+        )
+        .await;
+
+        assert!(line.contains("status=200"), "{line}");
+        assert!(line.contains("bytes=0"), "{line}");
+        assert!(line.contains("complete=false"), "{line}");
+    }
+
+    #[tokio::test]
+    async fn access_log_body_fails() {
+        let app = Router::new().route(
+            "/broken",
+            axum::routing::get(|| async {
+                let chunks: [Result<&str, io::Error>; 2] =
+                    [Ok("partial"), Err(io::Error::other("disk gone"))];
+                Body::from_stream(futures_util::stream::iter(chunks))
+            }),
+        );
+        let request = Request::get("/broken").body(Body::empty()).unwrap();
+        let line = access_log_line_from(app, request, true).await;
+
+        assert!(line.contains("bytes=7"), "{line}");
+        assert!(line.contains("complete=false"), "{line}");
+    }
+
+    #[tokio::test]
+    async fn access_log_head_is_zero_bytes() {
+        let fixtures = fixtures();
+        let line = access_log_line(
+            &fixtures.root,
+            Request::head("/assets/script.js")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+
+        assert!(line.contains("method=HEAD"), "{line}");
+        assert!(line.contains("bytes=0"), "{line}");
+    }
+
+    #[tokio::test]
+    async fn access_log_not_modified_is_zero_bytes() {
+        let fixtures = fixtures();
+        let response = using_serve_dir(&fixtures.root)
+            .oneshot(
+                Request::get("/assets/script.js")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let last_modified = response.headers()[header::LAST_MODIFIED].clone();
+
+        let line = access_log_line(
+            &fixtures.root,
+            Request::get("/assets/script.js")
+                .header(header::IF_MODIFIED_SINCE, last_modified)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+
+        assert!(line.contains("status=304"), "{line}");
+        assert!(line.contains("bytes=0"), "{line}");
+    }
+
+    async fn get_embedded(uri: &str, if_none_match: Option<&str>) -> Response {
+        let mut request = Request::get(uri);
+        if let Some(if_none_match) = if_none_match {
+            request = request.header(header::IF_NONE_MATCH, if_none_match);
+        }
+        using_embedded_assets()
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn embedded_content_type() {
+        for (uri, content_type, contents) in [
+            ("/embedded/script.js", "text/javascript", "assets/script.js"),
+            ("/embedded/style.css", "text/css", "assets/style.css"),
+            ("/embedded/index.html", "text/html", "assets/index.html"),
+        ] {
+            let response = get_embedded(uri, None).await;
+
+            assert_eq!(response.status(), StatusCode::OK, "{uri}");
+            assert_eq!(response.headers()[header::CONTENT_TYPE], content_type);
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            assert_eq!(body, std::fs::read(contents).unwrap(), "{uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn embedded_directory_paths_serve_index_html() {
+        for uri in ["/embedded", "/embedded/"] {
+            let response = get_embedded(uri, None).await;
+
+            assert_eq!(response.status(), StatusCode::OK, "{uri}");
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            assert_eq!(&body[..], b"Hi from index.html", "{uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn embedded_not_modified() {
+        let response = get_embedded("/embedded/script.js", None).await;
+        let etag = response.headers()[header::ETAG]
+            .to_str()
+            .unwrap()
+            .to_owned();
+        assert!(etag.starts_with('"') && etag.ends_with('"'), "{etag}");
+
+        let response = get_embedded("/embedded/script.js", Some(&etag)).await;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(response.headers()[header::ETAG], etag.as_str());
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert!(body.is_empty());
+
+        let response = get_embedded("/embedded/script.js", Some(r#""stale""#)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn embedded_unknown_path() {
+        let response = get_embedded("/embedded/nope.js", None).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    async fn get_range(range: &str) -> Response {
+        let fixtures = fixtures();
+        calling_serve_dir_from_a_handler(&fixtures.root)
+            .oneshot(
+                Request::get("/foo/range.txt")
+                    .header(header::RANGE, range)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn range_from_start() {
+        let response = get_range("bytes=0-99").await;
+
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(response.headers()[header::CONTENT_RANGE], "bytes 0-99/1024");
+        assert_eq!(response.headers()[header::CONTENT_LENGTH], "100");
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, range_txt()[..100]);
+    }
+
+    #[tokio::test]
+    async fn open_ended_range() {
+        let response = get_range("bytes=100-").await;
+
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(
+            response.headers()[header::CONTENT_RANGE],
+            "bytes 100-1023/1024"
+        );
+        assert_eq!(response.headers()[header::CONTENT_LENGTH], "924");
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, range_txt()[100..]);
+    }
+
+    #[tokio::test]
+    async fn multiple_ranges_are_not_satisfiable() {
+        // `ServeDir` doesn't do multipart/byteranges responses.
+        let response = get_range("bytes=0-9,20-29").await;
+
+        assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(response.headers()[header::CONTENT_RANGE], "bytes */1024");
+    }
+
+    #[tokio::test]
+    async fn out_of_bounds_range() {
+        let response = get_range("bytes=2000-3000").await;
+
+        assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(response.headers()[header::CONTENT_RANGE], "bytes */1024");
+    }
+
+    #[tokio::test]
+    async fn head_advertises_ranges() {
+        let fixtures = fixtures();
+        let response = calling_serve_dir_from_a_handler(&fixtures.root)
+            .oneshot(Request::head("/foo/range.txt").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::ACCEPT_RANGES], "bytes");
+        assert_eq!(response.headers()[header::CONTENT_LENGTH], "1024");
+    }
+}
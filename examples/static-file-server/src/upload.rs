@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use axum::extract::{self, Query, Request, State};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use futures_util::TryStreamExt;
+use http_body_util::LengthLimitError;
+use percent_encoding::utf8_percent_encode;
+use tokio::fs::File;
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio_util::io::StreamReader;
+
+use crate::PATH_SEGMENT;
+
+/// Where uploads are stored on disk.
+pub struct Uploads {
+    /// Inside the static root so uploads are served by the `ServeDir` nested
+    /// at `/assets`.
+    dir: PathBuf,
+    /// Uploads are written here first and then renamed into place, so a
+    /// partial upload is never served. It has to be on the same filesystem as
+    /// `dir` for the rename to work, so it sits next to the static root.
+    temp_dir: PathBuf,
+}
+
+impl Uploads {
+    pub fn new(root: &Path) -> Self {
+        Self {
+            dir: root.join("uploads"),
+            temp_dir: root.with_file_name("uploads.tmp"),
+        }
+    }
+}
+
+/// Stream the request body to `<root>/uploads/<path>`.
+///
+/// Responds with 409 if the file already exists, unless `?overwrite=true` is
+/// given.
+pub async fn upload(
+    State(uploads): State<Arc<Uploads>>,
+    extract::Path(path): extract::Path<String>,
+    Query(params): Query<HashMap<String, String>>,
+    request: Request,
+) -> Response {
+    let Some(relative) = sanitize_path(&path) else {
+        return (StatusCode::BAD_REQUEST, "Invalid path").into_response();
+    };
+    let overwrite = params.get("overwrite").is_some_and(|v| v == "true");
+
+    let destination = uploads.dir.join(&relative);
+    // Saves streaming the body in the common case, `stream_to_file` checks
+    // again when it's too late for another upload to sneak in.
+    if !overwrite && tokio::fs::try_exists(&destination).await.unwrap_or(false) {
+        return (StatusCode::CONFLICT, "File already exists").into_response();
+    }
+
+    if let Err(err) = stream_to_file(&uploads.temp_dir, &destination, overwrite, request).await {
+        if err.kind() == io::ErrorKind::AlreadyExists {
+            return (StatusCode::CONFLICT, "File already exists").into_response();
+        }
+        if is_length_limit_error(&err) {
+            return (StatusCode::PAYLOAD_TOO_LARGE, "Upload too large").into_response();
+        }
+        tracing::error!("failed to store upload {}: {err}", destination.display());
+        return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response();
+    }
+
+    let segments: Vec<_> = path
+        .split('/')
+        .map(|segment| utf8_percent_encode(segment, PATH_SEGMENT).to_string())
+        .collect();
+    let url = format!("/assets/uploads/{}", segments.join("/"));
+    (StatusCode::CREATED, [(header::LOCATION, url.clone())], url).into_response()
+}
+
+/// Fails with `AlreadyExists` if `destination` exists and `overwrite` isn't
+/// set, even when another upload created it while this one was streaming.
+async fn stream_to_file(
+    temp_dir: &Path,
+    destination: &Path,
+    overwrite: bool,
+    request: Request,
+) -> io::Result<()> {
+    tokio::fs::create_dir_all(temp_dir).await?;
+    let temp_path = temp_file_path(temp_dir);
+
+    let result = async {
+        let body = request
+            .into_body()
+            .into_data_stream()
+            .map_err(io::Error::other);
+        let mut body_reader = StreamReader::new(body);
+
+        let mut file = BufWriter::new(File::create(&temp_path).await?);
+        tokio::io::copy(&mut body_reader, &mut file).await?;
+        file.flush().await?;
+
+        if let Some(parent) = destination.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        if overwrite {
+            return tokio::fs::rename(&temp_path, destination).await;
+        }
+        // Unlike `rename`, linking never replaces an existing file, so only
+        // one of two uploads to the same path wins.
+        tokio::fs::hard_link(&temp_path, destination).await
+    }
+    .await;
+
+    if result.is_err() || !overwrite {
+        let _ = tokio::fs::remove_file(&temp_path).await;
+    }
+    result
+}
+
+fn temp_file_path(temp_dir: &Path) -> PathBuf {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    let n = NEXT.fetch_add(1, Ordering::Relaxed);
+    temp_dir.join(format!("{}-{n}.part", std::process::id()))
+}
+
+/// Turn the wildcard part of the URL into a relative path, rejecting anything
+/// that could escape the upload directory.
+fn sanitize_path(path: &str) -> Option<PathBuf> {
+    let mut relative = PathBuf::new();
+    for segment in path.split('/') {
+        if segment.is_empty() || segment == "." || segment == ".." || segment.contains('\\') {
+            return None;
+        }
+        relative.push(segment);
+    }
+    Some(relative)
+}
+
+fn is_length_limit_error(err: &io::Error) -> bool {
+    let mut source = err
+        .get_ref()
+        .map(|err| err as &(dyn std::error::Error + 'static));
+    while let Some(err) = source {
+        if err.is::<LengthLimitError>() {
+            return true;
+        }
+        source = err.source();
+    }
+    false
+}
@@ -0,0 +1,102 @@
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use sha2::{Digest, Sha256};
+
+/// The example's static files, served at `/static`.
+pub const DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/static");
+
+/// The static files, with a hash of each as it was at startup, so that their
+/// URLs change whenever they do and they can be cached for good.
+#[derive(Debug, Default)]
+pub struct Assets {
+    dir: PathBuf,
+    /// The first 8 hex digits of each file's SHA-256, by its path under `dir`
+    /// with `/` between the parts.
+    hashes: HashMap<String, String>,
+}
+
+impl Assets {
+    /// Hash every file under `dir`.
+    pub fn load(dir: impl Into<PathBuf>) -> io::Result<Self> {
+        let dir = dir.into();
+        let mut hashes = HashMap::new();
+        hash_files(&dir, "", &mut hashes)?;
+        Ok(Self { dir, hashes })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// `static_url(name)` in templates: the URL of `name` with its hash, or
+    /// without if there's no such file.
+    pub fn url(&self, name: &str) -> String {
+        match self.hashes.get(name) {
+            Some(hash) => format!("/static/{name}?v={hash}"),
+            None => {
+                eprintln!("warning: no static file {name:?} to version the URL of");
+                format!("/static/{name}")
+            }
+        }
+    }
+}
+
+fn hash_files(dir: &Path, prefix: &str, hashes: &mut HashMap<String, String>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = format!("{prefix}{}", entry.file_name().to_string_lossy());
+        if entry.file_type()?.is_dir() {
+            hash_files(&entry.path(), &format!("{name}/"), hashes)?;
+        } else {
+            let hash = Sha256::digest(fs::read(entry.path())?);
+            hashes.insert(name, hex::encode(&hash[..4]));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(css: &str) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("css")).unwrap();
+        fs::write(dir.path().join("app.css"), css).unwrap();
+        fs::write(dir.path().join("css").join("print.css"), css).unwrap();
+        dir
+    }
+
+    #[test]
+    fn urls_carry_the_hash_of_the_file() {
+        let dir = fixture("body { color: black; }\n");
+        let assets = Assets::load(dir.path()).unwrap();
+        // sha256sum of the fixture.
+        assert_eq!(assets.url("app.css"), "/static/app.css?v=494f4abf");
+        assert_eq!(
+            assets.url("css/print.css"),
+            "/static/css/print.css?v=494f4abf"
+        );
+    }
+
+    #[test]
+    fn changed_files_get_another_hash() {
+        let dir = fixture("body { color: black; }\n");
+        let before = Assets::load(dir.path()).unwrap().url("app.css");
+        fs::write(dir.path().join("app.css"), "body { color: navy; }\n").unwrap();
+        let after = Assets::load(dir.path()).unwrap().url("app.css");
+        assert_ne!(before, after);
+        assert!(after.starts_with("/static/app.css?v="), "{after}");
+    }
+
+    #[test]
+    fn unknown_files_are_unversioned() {
+        let dir = fixture("");
+        let assets = Assets::load(dir.path()).unwrap();
+        assert_eq!(assets.url("missing.css"), "/static/missing.css");
+    }
+}
@@ -0,0 +1,206 @@
+use std::collections::BTreeMap;
+
+use axum::{
+    extract::Query,
+    http::StatusCode,
+    response::{IntoResponse, Redirect, Response},
+    Form,
+};
+use minijinja::context;
+use serde::{Deserialize, Serialize};
+
+use crate::template::Template;
+
+/// Messages shorter or longer than this, in characters, are turned away.
+const MESSAGE_LENGTH: std::ops::RangeInclusive<usize> = 10..=1000;
+
+/// Missing fields are empty, so that they're told apart by `validate` like
+/// any other mistake rather than rejected as a malformed form.
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ContactInput {
+    name: String,
+    email: String,
+    message: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ContactQuery {
+    sent: Option<u8>,
+}
+
+/// What's wrong with each field, by name, if anything is.
+fn validate(input: &ContactInput) -> BTreeMap<&'static str, String> {
+    let mut errors = BTreeMap::new();
+    if input.name.trim().is_empty() {
+        errors.insert("name", "Please enter your name.".to_owned());
+    }
+    if !plausible_email(input.email.trim()) {
+        errors.insert("email", "Please enter a valid email address.".to_owned());
+    }
+    if !MESSAGE_LENGTH.contains(&input.message.trim().chars().count()) {
+        let message = format!(
+            "Please enter a message of {} to {} characters.",
+            MESSAGE_LENGTH.start(),
+            MESSAGE_LENGTH.end(),
+        );
+        errors.insert("message", message);
+    }
+    errors
+}
+
+/// Something, an `@`, and a domain with a dot in it, which is as much as can
+/// be checked without sending an email.
+fn plausible_email(email: &str) -> bool {
+    let Some((local, domain)) = email.split_once('@') else {
+        return false;
+    };
+    let dotted = domain
+        .split_once('.')
+        .is_some_and(|(host, tld)| !host.is_empty() && !tld.is_empty());
+    !local.is_empty() && !domain.contains('@') && dotted && !email.contains(char::is_whitespace)
+}
+
+pub async fn show_form(Query(query): Query<ContactQuery>) -> Template {
+    Template::new(
+        "contact",
+        context! {
+            title => "Contact",
+            sent => query.sent == Some(1),
+            values => ContactInput::default(),
+            errors => BTreeMap::<&str, String>::new(),
+        },
+    )
+}
+
+/// Send the message and redirect to the form, so that reloading the page
+/// doesn't send it again, or show the form again with what was wrong.
+pub async fn submit_form(Form(input): Form<ContactInput>) -> Response {
+    let errors = validate(&input);
+    if errors.is_empty() {
+        println!(
+            "message from {} <{}>",
+            input.name.trim(),
+            input.email.trim()
+        );
+        return Redirect::to("/contact?sent=1").into_response();
+    }
+
+    let page = Template::new(
+        "contact",
+        context! {
+            title => "Contact",
+            values => input,
+            errors,
+        },
+    );
+    (StatusCode::UNPROCESSABLE_ENTITY, page).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{header, Request},
+    };
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{app, tests};
+
+    async fn send(request: Request<Body>) -> (Response<()>, String) {
+        let app = app(tests::state());
+        let response = app.oneshot(request).await.unwrap();
+        let (parts, body) = response.into_parts();
+        let body = body.collect().await.unwrap().to_bytes();
+        (
+            Response::from_parts(parts, ()),
+            String::from_utf8(body.to_vec()).unwrap(),
+        )
+    }
+
+    fn post(form: &str) -> Request<Body> {
+        Request::post("/contact")
+            .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(Body::from(form.to_owned()))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn invalid_forms_are_shown_again() {
+        let (response, body) = send(post(
+            "name=+&email=ferris%40localhost&message=%3Cb%3Ehi%3C%2Fb%3E",
+        ))
+        .await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(body.contains("Please enter your name."), "{body}");
+        assert!(
+            body.contains("Please enter a valid email address."),
+            "{body}"
+        );
+        assert!(
+            body.contains("Please enter a message of 10 to 1000 characters."),
+            "{body}"
+        );
+        assert!(body.contains(r#"value="ferris@localhost""#), "{body}");
+        assert!(
+            body.contains("<textarea name=\"message\">&lt;b&gt;hi&lt;&#x2f;b&gt;</textarea>"),
+            "{body}"
+        );
+        assert!(!body.contains("<b>hi</b>"), "{body}");
+        assert!(!body.contains("banner"), "{body}");
+    }
+
+    #[tokio::test]
+    async fn missing_fields_are_invalid_too() {
+        let (response, body) = send(post("name=Ferris")).await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(body.contains(r#"value="Ferris""#), "{body}");
+        assert!(!body.contains("Please enter your name."), "{body}");
+        assert!(
+            body.contains("Please enter a valid email address."),
+            "{body}"
+        );
+    }
+
+    #[tokio::test]
+    async fn valid_forms_redirect_to_a_confirmation() {
+        let (response, _) = send(post(
+            "name=Ferris&email=ferris%40example.com&message=Hello+from+the+form%21",
+        ))
+        .await;
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        assert_eq!(response.headers()[header::LOCATION], "/contact?sent=1");
+
+        let request = Request::get("/contact?sent=1").body(Body::empty()).unwrap();
+        let (response, body) = send(request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(body.contains("your message was sent"), "{body}");
+        assert!(!body.contains("class=\"error\""), "{body}");
+
+        let request = Request::get("/contact").body(Body::empty()).unwrap();
+        let (_, body) = send(request).await;
+        assert!(!body.contains("your message was sent"), "{body}");
+    }
+
+    #[test]
+    fn emails_are_checked_loosely() {
+        for email in ["ferris@example.com", "a.b+c@mail.example.org"] {
+            assert!(plausible_email(email), "{email}");
+        }
+        for email in [
+            "",
+            "ferris",
+            "@example.com",
+            "ferris@",
+            "ferris@localhost",
+            "ferris@.com",
+            "ferris@example.",
+            "a@b@example.com",
+            "fer ris@example.com",
+        ] {
+            assert!(!plausible_email(email), "{email}");
+        }
+    }
+}
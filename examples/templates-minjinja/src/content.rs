@@ -0,0 +1,209 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{rejection::QueryRejection, Path, Query, State},
+    http::Uri,
+    response::{IntoResponse, Response},
+};
+use minijinja::context;
+use serde::{Deserialize, Serialize};
+
+use crate::{error::AppError, not_found, template::Template, AppState};
+
+/// Pages are never longer than this, whatever `per_page` asks for.
+const MAX_PER_PAGE: usize = 50;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Entry {
+    id: usize,
+    title: String,
+    body: String,
+    created_at: String,
+}
+
+/// `count` made-up entries, one a day from the start of 2024, numbered from 1.
+pub fn generate(count: usize) -> Vec<Entry> {
+    (1..=count)
+        .map(|id| {
+            let day = id - 1;
+            Entry {
+                id,
+                title: format!("Entry {id}"),
+                body: format!("This is entry number {id}."),
+                created_at: format!("2024-{:02}-{:02}", day / 28 % 12 + 1, day % 28 + 1),
+            }
+        })
+        .collect()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Pagination {
+    #[serde(default = "first_page")]
+    page: usize,
+    #[serde(default = "default_per_page")]
+    per_page: usize,
+}
+
+fn first_page() -> usize {
+    1
+}
+
+fn default_per_page() -> usize {
+    10
+}
+
+/// A page of the entries, which is empty past the last one, or only the list
+/// for htmx, to swap in for the one on the page.
+pub async fn list(
+    State(state): State<Arc<AppState>>,
+    pagination: Result<Query<Pagination>, QueryRejection>,
+) -> Result<Template, AppError> {
+    let bad_request = || {
+        AppError::bad_request(
+            &state.templates,
+            "page and per_page have to be whole numbers from 1.",
+        )
+    };
+    let Query(Pagination { page, per_page }) = pagination.map_err(|_| bad_request())?;
+    if page == 0 || per_page == 0 {
+        return Err(bad_request());
+    }
+    let per_page = per_page.min(MAX_PER_PAGE);
+
+    let total_pages = state.entries.len().div_ceil(per_page);
+    let entries = state
+        .entries
+        .iter()
+        .skip((page - 1).saturating_mul(per_page))
+        .take(per_page)
+        .collect::<Vec<_>>();
+    let url = |page: usize| format!("/content?page={page}&per_page={per_page}");
+    let previous_url = (page > 1).then(|| url((page - 1).min(total_pages.max(1))));
+    let next_url = (page < total_pages).then(|| url(page + 1));
+
+    let page = Template::new(
+        "content",
+        context! {
+            entries,
+            page,
+            total_pages,
+            previous_url,
+            next_url,
+        },
+    )
+    .fragment("content_fragment");
+    Ok(page)
+}
+
+/// Just the entry, for htmx to swap in.
+pub async fn item(State(state): State<Arc<AppState>>, Path(id): Path<usize>, uri: Uri) -> Response {
+    match state.entries.iter().find(|entry| entry.id == id) {
+        Some(entry) => Template::new("item_fragment", context! { entry }).into_response(),
+        None => not_found(uri).await.into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+    };
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    use crate::{app, tests};
+
+    async fn get_page(uri: &str) -> (StatusCode, String) {
+        let app = app(tests::state());
+        let request = Request::get(uri).body(Body::empty()).unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    fn items(body: &str) -> usize {
+        body.matches("<li id=\"item-").count()
+    }
+
+    #[tokio::test]
+    async fn the_first_page_is_the_default() {
+        let (status, body) = get_page("/content").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(items(&body), 10);
+        assert!(body.contains(r#"<li id="item-1""#), "{body}");
+        assert!(body.contains(r#"<li id="item-10""#), "{body}");
+        assert!(body.contains("Page 1 of 10"), "{body}");
+        assert!(!body.contains(r#"rel="prev""#), "{body}");
+        assert!(
+            body.contains(r#"<a rel="next" href="&#x2f;content?page=2&amp;per_page=10">"#),
+            "{body}"
+        );
+    }
+
+    #[tokio::test]
+    async fn middle_pages_link_both_ways() {
+        let (status, body) = get_page("/content?page=3&per_page=20").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(items(&body), 20);
+        assert!(body.contains(r#"<li id="item-41""#), "{body}");
+        assert!(body.contains("Page 3 of 5"), "{body}");
+        assert!(
+            body.contains(r#"<a rel="prev" href="&#x2f;content?page=2&amp;per_page=20">"#),
+            "{body}"
+        );
+        assert!(
+            body.contains(r#"<a rel="next" href="&#x2f;content?page=4&amp;per_page=20">"#),
+            "{body}"
+        );
+    }
+
+    #[tokio::test]
+    async fn the_last_page_has_no_next() {
+        let (status, body) = get_page("/content?page=10").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(items(&body), 10);
+        assert!(body.contains(r#"<li id="item-100""#), "{body}");
+        assert!(
+            body.contains(r#"<a rel="prev" href="&#x2f;content?page=9&amp;per_page=10">"#),
+            "{body}"
+        );
+        assert!(!body.contains(r#"rel="next""#), "{body}");
+    }
+
+    #[tokio::test]
+    async fn pages_are_at_most_50_long() {
+        let (status, body) = get_page("/content?per_page=1000").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(items(&body), 50);
+        assert!(body.contains("Page 1 of 2"), "{body}");
+    }
+
+    #[tokio::test]
+    async fn pages_past_the_end_are_empty() {
+        let (status, body) = get_page("/content?page=11").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(items(&body), 0);
+        assert!(body.contains("No entries here."), "{body}");
+        assert!(
+            body.contains(r#"<a rel="prev" href="&#x2f;content?page=10&amp;per_page=10">"#),
+            "{body}"
+        );
+    }
+
+    #[tokio::test]
+    async fn bad_numbers_are_a_400_page() {
+        for uri in [
+            "/content?page=abc",
+            "/content?page=0",
+            "/content?per_page=-1",
+            "/content?per_page=0",
+        ] {
+            let (status, body) = get_page(uri).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{uri}");
+            assert!(body.contains("<h1>400 Bad Request</h1>"), "{body}");
+            assert!(body.contains("whole numbers"), "{body}");
+        }
+    }
+}
@@ -0,0 +1,122 @@
+use std::error::Error;
+
+use axum::{
+    http::StatusCode,
+    response::{Html, IntoResponse, Response},
+};
+use minijinja::context;
+use uuid::Uuid;
+
+use crate::{
+    locale::Locale,
+    template::{base_context, merge_context},
+    templates::Templates,
+};
+
+/// A failed request, answered with `error.jinja`, or in plain text if even
+/// that can't be rendered.
+#[derive(Debug)]
+pub struct AppError {
+    status: StatusCode,
+    id: Uuid,
+    page: Option<String>,
+}
+
+impl AppError {
+    /// Log `err` under a new id, which the page shows so that it can be found
+    /// in the logs, without showing `err` itself.
+    pub fn internal(templates: &Templates, err: &dyn Error) -> Self {
+        let id = Uuid::new_v4();
+        eprintln!("error {id}: {}", describe(err));
+        Self::new(
+            templates,
+            StatusCode::INTERNAL_SERVER_ERROR,
+            id,
+            "Something went wrong on our end.",
+        )
+    }
+
+    /// Something the client has to fix, which `message` tells them.
+    pub fn bad_request(templates: &Templates, message: &str) -> Self {
+        Self::new(templates, StatusCode::BAD_REQUEST, Uuid::new_v4(), message)
+    }
+
+    fn new(templates: &Templates, status: StatusCode, id: Uuid, message: &str) -> Self {
+        let ctx = context! {
+            status => status.to_string(),
+            message,
+            id => id.to_string(),
+        };
+        // Without the request at hand, in English and with nothing active.
+        let ctx = merge_context(base_context(Locale::default(), None, None), ctx);
+        // Not through `AppState::render`, so that a broken error page doesn't
+        // lead to another one.
+        let page = match templates.render("error", ctx) {
+            Ok(page) => Some(page),
+            Err(err) => {
+                eprintln!(
+                    "error {id}: failed to render the error page: {}",
+                    describe(&err)
+                );
+                None
+            }
+        };
+        Self { status, id, page }
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        match self.page {
+            Some(page) => (self.status, Html(page)).into_response(),
+            None => (
+                self.status,
+                format!("{}\nError id: {}", self.status, self.id),
+            )
+                .into_response(),
+        }
+    }
+}
+
+/// `err` and everything that led to it.
+fn describe(err: &dyn Error) -> String {
+    let mut description = err.to_string();
+    let mut source = err.source();
+    while let Some(err) = source {
+        description.push_str(": ");
+        description.push_str(&err.to_string());
+        source = err.source();
+    }
+    description
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path, sync::Arc};
+
+    use http_body_util::BodyExt;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn broken_error_pages_fall_back_to_plain_text() {
+        let dir = tempfile::tempdir().unwrap();
+        let templates = Path::new(env!("CARGO_MANIFEST_DIR")).join("templates");
+        for entry in fs::read_dir(templates).unwrap() {
+            let path = entry.unwrap().path();
+            fs::copy(&path, dir.path().join(path.file_name().unwrap())).unwrap();
+        }
+        fs::write(dir.path().join("error.jinja"), "{{ id | no_such_filter }}").unwrap();
+        let templates = Templates::reloading(dir.path(), Arc::default()).unwrap();
+
+        let err = templates.render("missing", context! {}).unwrap_err();
+        let response = AppError::internal(&templates, &err).into_response();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(
+            body.starts_with("500 Internal Server Error\nError id: "),
+            "{body}"
+        );
+    }
+}
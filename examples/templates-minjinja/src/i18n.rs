@@ -0,0 +1,78 @@
+use minijinja::State;
+
+use crate::locale::Locale;
+
+/// Every text in English, by key.
+const EN: &[(&str, &str)] = &[
+    ("nav.home", "Home"),
+    ("nav.content", "Content"),
+    ("nav.about", "About"),
+    ("nav.contact", "Contact"),
+    ("home.title", "Home"),
+    ("home.welcome", "Hello World"),
+    ("content.title", "Content"),
+    ("about.title", "About"),
+    (
+        "about.text",
+        "Simple demonstration layout for an axum project with minijinja as templating engine.",
+    ),
+];
+
+/// The texts there's a German translation for so far.
+const DE: &[(&str, &str)] = &[
+    ("nav.home", "Start"),
+    ("nav.content", "Inhalt"),
+    ("nav.about", "Über uns"),
+    ("home.title", "Start"),
+    ("home.welcome", "Hallo Welt"),
+    ("content.title", "Inhalt"),
+    ("about.title", "Über uns"),
+    (
+        "about.text",
+        "Einfaches Beispiel-Layout für ein axum-Projekt mit minijinja als Template-Engine.",
+    ),
+];
+
+/// The text for `key` in `locale`, in English if it isn't translated yet, or
+/// the key itself if there's no such text at all.
+pub fn translate(locale: Locale, key: &str) -> &str {
+    let texts = match locale {
+        Locale::En => EN,
+        Locale::De => DE,
+    };
+    let find = |texts: &[(&str, &'static str)]| {
+        texts
+            .iter()
+            .find_map(|(k, text)| (*k == key).then_some(*text))
+    };
+    find(texts).or_else(|| find(EN)).unwrap_or(key)
+}
+
+/// `t(key)` in templates, in the `locale` of the context.
+pub fn t(state: &State, key: &str) -> String {
+    let locale = state
+        .lookup("locale")
+        .and_then(|locale| Locale::from_tag(locale.as_str()?))
+        .unwrap_or_default();
+    translate(locale, key).to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn falls_back_to_english_per_key() {
+        assert_eq!(translate(Locale::De, "home.welcome"), "Hallo Welt");
+        assert_eq!(translate(Locale::De, "nav.contact"), "Contact");
+        assert_eq!(translate(Locale::En, "home.welcome"), "Hello World");
+        assert_eq!(translate(Locale::De, "no.such.key"), "no.such.key");
+    }
+
+    #[test]
+    fn every_german_text_has_an_english_one() {
+        for (key, _) in DE {
+            assert!(EN.iter().any(|(k, _)| k == key), "{key}");
+        }
+    }
+}
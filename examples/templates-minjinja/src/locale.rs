@@ -0,0 +1,248 @@
+use std::convert::Infallible;
+
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Query, Request},
+    http::{header, request::Parts, HeaderMap, HeaderValue, Uri},
+    middleware::Next,
+    response::Response,
+};
+use serde::Deserialize;
+
+/// The languages there are translations for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Locale {
+    #[default]
+    En,
+    De,
+}
+
+impl Locale {
+    /// From a language tag like `de` or `de-CH`, if it's one of ours.
+    pub fn from_tag(tag: &str) -> Option<Self> {
+        let primary = tag.split('-').next().unwrap_or_default();
+        if primary.eq_ignore_ascii_case("en") {
+            Some(Self::En)
+        } else if primary.eq_ignore_ascii_case("de") {
+            Some(Self::De)
+        } else {
+            None
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::En => "en",
+            Self::De => "de",
+        }
+    }
+}
+
+/// The locale to render in, from the `lang` query parameter, then the `lang`
+/// cookie, then `Accept-Language`, and English if none of them has one of
+/// ours.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PreferredLocale(pub Locale);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for PreferredLocale
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let locale = from_query(&parts.uri)
+            .or_else(|| from_cookie(&parts.headers))
+            .or_else(|| {
+                let accept_language = parts.headers.get(header::ACCEPT_LANGUAGE)?;
+                from_accept_language(accept_language.to_str().ok()?)
+            })
+            .unwrap_or_default();
+        Ok(Self(locale))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct LangQuery {
+    lang: Option<String>,
+}
+
+fn from_query(uri: &Uri) -> Option<Locale> {
+    let Query(query) = Query::<LangQuery>::try_from_uri(uri).ok()?;
+    Locale::from_tag(&query.lang?)
+}
+
+fn from_cookie(headers: &HeaderMap) -> Option<Locale> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(name, _)| *name == "lang")
+        .and_then(|(_, value)| Locale::from_tag(value))
+}
+
+/// The one of ours the client likes best, going by the q-values, with `*`
+/// standing for English.
+pub fn from_accept_language(accept_language: &str) -> Option<Locale> {
+    let mut ranges = accept_language
+        .split(',')
+        .filter_map(|range| {
+            let mut params = range.split(';');
+            let tag = params.next()?.trim();
+            let q = params
+                .find_map(|param| param.trim().strip_prefix("q="))
+                .map_or(Some(1.0), |q| q.trim().parse::<f32>().ok())?;
+            let locale = if tag == "*" {
+                Locale::default()
+            } else {
+                Locale::from_tag(tag)?
+            };
+            (q > 0.0).then_some((locale, q))
+        })
+        .collect::<Vec<_>>();
+    // Stable, so that the first of equally liked ones wins.
+    ranges.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+    ranges.first().map(|(locale, _)| *locale)
+}
+
+/// Remember the locale picked with `?lang=` in a cookie, for the pages after.
+pub async fn remember_locale(request: Request, next: Next) -> Response {
+    let picked = from_query(request.uri());
+    let mut response = next.run(request).await;
+    if let Some(locale) = picked {
+        let cookie = format!("lang={}; Path=/; SameSite=Lax", locale.as_str());
+        let cookie = HeaderValue::from_str(&cookie).unwrap();
+        response.headers_mut().append(header::SET_COOKIE, cookie);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{app, tests};
+
+    async fn get_page(
+        uri: &str,
+        headers: &[(header::HeaderName, &'static str)],
+    ) -> Response<String> {
+        let mut request = Request::get(uri).body(Body::empty()).unwrap();
+        for (name, value) in headers {
+            request
+                .headers_mut()
+                .insert(name, HeaderValue::from_static(value));
+        }
+        let app = app(tests::state());
+        let response = app.oneshot(request).await.unwrap();
+        let (parts, body) = response.into_parts();
+        let body = body.collect().await.unwrap().to_bytes();
+        Response::from_parts(parts, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn pages_are_in_the_accepted_language() {
+        let response = get_page("/", &[(header::ACCEPT_LANGUAGE, "de-DE, en;q=0.5")]).await;
+        let body = response.body();
+        assert!(body.contains(r#"<html lang="de">"#), "{body}");
+        assert!(body.contains("<p>Hallo Welt</p>"), "{body}");
+        assert!(body.contains(">Über uns</a>"), "{body}");
+        // Not translated yet.
+        assert!(body.contains(">Contact</a>"), "{body}");
+        assert!(response.headers().get(header::SET_COOKIE).is_none());
+
+        let response = get_page("/about", &[(header::ACCEPT_LANGUAGE, "fr")]).await;
+        let body = response.body();
+        assert!(body.contains(r#"<html lang="en">"#), "{body}");
+        assert!(body.contains("<h1>About</h1>"), "{body}");
+    }
+
+    #[tokio::test]
+    async fn the_lang_parameter_sets_a_cookie() {
+        let response = get_page("/?lang=de", &[(header::ACCEPT_LANGUAGE, "en")]).await;
+        assert!(response.body().contains("<p>Hallo Welt</p>"));
+        assert_eq!(
+            response.headers()[header::SET_COOKIE],
+            "lang=de; Path=/; SameSite=Lax"
+        );
+
+        let response = get_page("/?lang=fr", &[]).await;
+        assert!(response.body().contains("<p>Hello World</p>"));
+        assert!(response.headers().get(header::SET_COOKIE).is_none());
+    }
+
+    #[tokio::test]
+    async fn the_cookie_wins_over_the_header() {
+        let headers = [(header::ACCEPT_LANGUAGE, "en"), (header::COOKIE, "lang=de")];
+        let response = get_page("/content", &headers).await;
+        let body = response.body();
+        assert!(body.contains("<h1>Inhalt</h1>"), "{body}");
+
+        // And the parameter over the cookie.
+        let response = get_page("/content?lang=en", &headers).await;
+        let body = response.body();
+        assert!(body.contains("<h1>Content</h1>"), "{body}");
+    }
+
+    #[test]
+    fn picks_the_best_liked_locale() {
+        let cases = [
+            ("de", Some(Locale::De)),
+            ("de-CH, en;q=0.5", Some(Locale::De)),
+            ("en;q=0.5, de;q=0.8", Some(Locale::De)),
+            ("EN-us, de", Some(Locale::En)),
+            ("de;q=0.9, en;q=0.9", Some(Locale::De)),
+            ("en;q=0.9, de;q=0.9", Some(Locale::En)),
+        ];
+        for (header, expected) in cases {
+            assert_eq!(from_accept_language(header), expected, "{header}");
+        }
+    }
+
+    #[test]
+    fn skips_unknown_and_unwanted_tags() {
+        let cases = [
+            ("fr, de;q=0.1", Some(Locale::De)),
+            ("fr, es;q=0.5", None),
+            ("de;q=0, en;q=0.1", Some(Locale::En)),
+            ("de;q=0", None),
+            ("de;q=nope, en;q=0.1", Some(Locale::En)),
+            ("", None),
+        ];
+        for (header, expected) in cases {
+            assert_eq!(from_accept_language(header), expected, "{header}");
+        }
+    }
+
+    #[test]
+    fn wildcards_are_english() {
+        let cases = [
+            ("*", Some(Locale::En)),
+            ("fr, *;q=0.5", Some(Locale::En)),
+            ("*;q=0.1, de;q=0.5", Some(Locale::De)),
+        ];
+        for (header, expected) in cases {
+            assert_eq!(from_accept_language(header), expected, "{header}");
+        }
+    }
+
+    #[test]
+    fn reads_the_lang_cookie() {
+        let headers = HeaderMap::from_iter([(
+            header::COOKIE,
+            HeaderValue::from_static("session=abc; lang=de"),
+        )]);
+        assert_eq!(from_cookie(&headers), Some(Locale::De));
+        let headers = HeaderMap::from_iter([(
+            header::COOKIE,
+            HeaderValue::from_static("language=de; lang=fr"),
+        )]);
+        assert_eq!(from_cookie(&headers), None);
+    }
+}
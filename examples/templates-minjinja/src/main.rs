@@ -1,35 +1,68 @@
 use std::sync::Arc;
 
-use axum::extract::State;
-use axum::http::StatusCode;
+use axum::http::{StatusCode, Uri};
 use axum::response::Html;
 use axum::routing::get;
-use axum::Router;
-use minijinja::{context, Environment};
+use axum::{middleware, Router};
+use minijinja::{context, Value};
+
+use tower_http::services::ServeDir;
+
+use crate::assets::Assets;
+use crate::error::AppError;
+use crate::template::Template;
+use crate::templates::Templates;
+
+mod assets;
+mod contact;
+mod content;
+mod error;
+mod i18n;
+mod locale;
+mod template;
+mod templates;
 
 struct AppState {
-    env: Environment<'static>,
+    templates: Templates,
+    assets: Arc<Assets>,
+    entries: Vec<content::Entry>,
+}
+
+impl AppState {
+    fn new(templates: Templates, assets: Arc<Assets>) -> Self {
+        Self {
+            templates,
+            assets,
+            entries: content::generate(100),
+        }
+    }
+
+    fn render(&self, name: &str, ctx: Value) -> Result<Html<String>, AppError> {
+        self.templates
+            .render(name, ctx)
+            .map(Html)
+            .map_err(|err| AppError::internal(&self.templates, &err))
+    }
 }
 
 #[tokio::main]
 async fn main() {
-    let mut env = Environment::new();
-    env.add_template("layout", include_str!("../templates/layout.jinja"))
-        .unwrap();
-    env.add_template("home", include_str!("../templates/home.jinja"))
-        .unwrap();
-    env.add_template("content", include_str!("../templates/content.jinja"))
-        .unwrap();
-    env.add_template("about", include_str!("../templates/about.jinja"))
-        .unwrap();
-
-    let app_state = Arc::new(AppState { env });
-
-    let app = Router::new()
-        .route("/", get(handler_home))
-        .route("/content", get(handler_content))
-        .route("/about", get(handler_about))
-        .with_state(app_state);
+    let assets = match Assets::load(assets::DIR) {
+        Ok(assets) => Arc::new(assets),
+        Err(err) => {
+            eprintln!("failed to hash the static files: {err}");
+            std::process::exit(1);
+        }
+    };
+    let templates = match Templates::from_env(assets.clone()) {
+        Ok(templates) => templates,
+        Err(err) => {
+            eprintln!("failed to load the templates: {err}");
+            std::process::exit(1);
+        }
+    };
+
+    let app = app(Arc::new(AppState::new(templates, assets)));
 
     let listener = tokio::net::TcpListener::bind("127.0.0.1:3000")
         .await
@@ -39,41 +72,137 @@ async fn main() {
     axum::serve(listener, app).await.unwrap();
 }
 
-async fn handler_home(State(state): State<Arc<AppState>>) -> Result<Html<String>, StatusCode> {
-    let template = state.env.get_template("home").unwrap();
-
-    let rendered = template
-        .render(context! {
-            title => "Home",
-            welcome_text => "Hello World"
-        })
-        .unwrap();
-
-    Ok(Html(rendered))
+fn app(state: Arc<AppState>) -> Router {
+    Router::new()
+        .route("/", get(handler_home))
+        .route("/content", get(content::list))
+        .route("/content/items/:id", get(content::item))
+        .route("/about", get(handler_about))
+        .route(
+            "/contact",
+            get(contact::show_form).post(contact::submit_form),
+        )
+        .nest_service("/static", ServeDir::new(state.assets.dir()))
+        .fallback(not_found)
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            template::render_templates,
+        ))
+        .layer(middleware::from_fn(locale::remember_locale))
+        .with_state(state)
 }
 
-async fn handler_content(State(state): State<Arc<AppState>>) -> Result<Html<String>, StatusCode> {
-    let template = state.env.get_template("content").unwrap();
-
-    let some_example_entries = vec!["Data 1", "Data 2", "Date 3"];
-
-    let rendered = template
-        .render(context! {
-            title => "Content",
-            entries => some_example_entries
-        })
-        .unwrap();
-
-    Ok(Html(rendered))
+async fn handler_home() -> Template {
+    Template::new("home", context! {})
 }
 
-async fn handler_about(State(state): State<Arc<AppState>>) -> Result<Html<String>, StatusCode> {
-    let template = state.env.get_template("about").unwrap();
+async fn handler_about() -> Template {
+    Template::new("about", context! {})
+}
 
-    let rendered = template.render(context! {
-        title => "About",
-        about_text => "Simple demonstration layout for an axum project with minijinja as templating engine."
-    }).unwrap();
+async fn not_found(uri: Uri) -> (StatusCode, Template) {
+    let page = Template::new("404", context! { path => uri.path() });
+    (StatusCode::NOT_FOUND, page)
+}
 
-    Ok(Html(rendered))
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+    use axum::extract::State;
+    use axum::http::{header, Request};
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    use super::*;
+
+    /// The app's state with the embedded templates.
+    pub(crate) fn state() -> Arc<AppState> {
+        let assets = Arc::new(Assets::load(assets::DIR).unwrap());
+        Arc::new(AppState::new(Templates::embedded(assets.clone()), assets))
+    }
+
+    async fn get_page(app: Router, uri: &str) -> (StatusCode, String, String) {
+        let request = Request::get(uri).body(Body::empty()).unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        let status = response.status();
+        let content_type = response.headers()[header::CONTENT_TYPE]
+            .to_str()
+            .unwrap()
+            .to_owned();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (
+            status,
+            content_type,
+            String::from_utf8(body.to_vec()).unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn unknown_paths_are_a_404_page() {
+        let (status, content_type, body) = get_page(app(state()), "/%3Cscript%3E").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(content_type, "text/html; charset=utf-8");
+        assert!(body.contains("<h1>Not Found</h1>"), "{body}");
+        assert!(body.contains("<code>&#x2f;%3Cscript%3E</code>"), "{body}");
+
+        // Escaped even if it arrives undecoded.
+        let app = Router::new()
+            .route(
+                "/*path",
+                get(|State(state): State<Arc<AppState>>| async move {
+                    state.render("404", context! { path => "/<script>" })
+                }),
+            )
+            .with_state(state());
+        let (_, _, body) = get_page(app, "/anything").await;
+        assert!(body.contains("<code>&#x2f;&lt;script&gt;</code>"), "{body}");
+    }
+
+    #[tokio::test]
+    async fn static_files_are_served_and_linked_with_their_hash() {
+        let (status, content_type, body) = get_page(app(state()), "/static/app.css").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(content_type, "text/css");
+        let css = std::fs::read_to_string(state().assets.dir().join("app.css")).unwrap();
+        assert_eq!(body, css);
+
+        let (_, _, body) = get_page(app(state()), "/").await;
+        let url = state().assets.url("app.css").replace('/', "&#x2f;");
+        assert!(url.contains("?v="), "{url}");
+        assert!(
+            body.contains(&format!(r#"<link rel="stylesheet" href="{url}">"#)),
+            "{body}"
+        );
+    }
+
+    #[tokio::test]
+    async fn render_failures_are_a_500_page() {
+        let app = Router::new()
+            .route(
+                "/missing",
+                get(|State(state): State<Arc<AppState>>| async move {
+                    state.render("missing", context! {})
+                }),
+            )
+            .route(
+                "/broken",
+                get(|State(state): State<Arc<AppState>>| async move {
+                    // Not something that can be looped over.
+                    state.render("content", context! { title => "Content", entries => 42 })
+                }),
+            )
+            .with_state(state());
+
+        for uri in ["/missing", "/broken"] {
+            let (status, content_type, body) = get_page(app.clone(), uri).await;
+            assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR, "{uri}");
+            assert_eq!(content_type, "text/html; charset=utf-8", "{uri}");
+            assert!(
+                body.contains("<h1>500 Internal Server Error</h1>"),
+                "{body}"
+            );
+            assert!(body.contains("Error id: <code>"), "{body}");
+            assert!(!body.contains("missing"), "{body}");
+        }
+    }
 }
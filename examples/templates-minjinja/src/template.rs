@@ -0,0 +1,382 @@
+use std::{convert::Infallible, sync::Arc};
+
+use axum::{
+    async_trait,
+    body::Body,
+    extract::{FromRequestParts, MatchedPath, Request, State},
+    http::{header, request::Parts, HeaderValue},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use minijinja::{context, Value};
+use time::OffsetDateTime;
+
+use crate::{
+    i18n,
+    locale::{Locale, PreferredLocale},
+    AppState,
+};
+
+pub const APP_NAME: &str = "Website Name";
+
+/// The pages in the navigation, by the key of their label and their route.
+const NAV: [(&str, &str); 4] = [
+    ("nav.home", "/"),
+    ("nav.content", "/content"),
+    ("nav.about", "/about"),
+    ("nav.contact", "/contact"),
+];
+
+/// A page for `render_templates` to render, so that handlers don't need the
+/// templates at hand.
+///
+/// Its status and headers, like `(StatusCode::NOT_FOUND, template)`, are kept.
+#[derive(Debug, Clone)]
+pub struct Template {
+    name: &'static str,
+    /// Rendered instead of `name` for htmx, which only swaps in part of the
+    /// page.
+    fragment: Option<&'static str>,
+    ctx: Value,
+}
+
+impl Template {
+    pub fn new(name: &'static str, ctx: Value) -> Self {
+        Self {
+            name,
+            fragment: None,
+            ctx,
+        }
+    }
+
+    /// Render `fragment`, with the same context, when `IsHtmx`.
+    pub fn fragment(mut self, fragment: &'static str) -> Self {
+        self.fragment = Some(fragment);
+        self
+    }
+}
+
+/// Whether the request was sent by htmx, which says so with `HX-Request: true`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IsHtmx(pub bool);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for IsHtmx
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let htmx = parts
+            .headers
+            .get("hx-request")
+            .is_some_and(|value| value == "true");
+        Ok(Self(htmx))
+    }
+}
+
+impl IntoResponse for Template {
+    fn into_response(self) -> Response {
+        let mut response = Response::new(Body::empty());
+        response.extensions_mut().insert(self);
+        response
+    }
+}
+
+/// What `layout.jinja` needs on every page, with the navigation item for
+/// `matched_path`, the route the request went to, marked active.
+pub fn base_context(
+    locale: Locale,
+    current_path: Option<&str>,
+    matched_path: Option<&str>,
+) -> Value {
+    let nav = NAV
+        .iter()
+        .map(|&(key, href)| {
+            context! {
+                label => i18n::translate(locale, key),
+                href,
+                active => matched_path == Some(href),
+            }
+        })
+        .collect::<Vec<_>>();
+    context! {
+        locale => locale.as_str(),
+        current_path,
+        app_name => APP_NAME,
+        year => OffsetDateTime::now_utc().year(),
+        nav,
+    }
+}
+
+/// `ctx` with every key of `base` it doesn't have itself.
+pub fn merge_context(base: Value, ctx: Value) -> Value {
+    context! { ..ctx, ..base }
+}
+
+/// Render the `Template` a handler answered with, in the `base_context`, or
+/// the error page if that fails.
+pub async fn render_templates(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let (mut parts, body) = request.into_parts();
+    let Ok(PreferredLocale(locale)) = PreferredLocale::from_request_parts(&mut parts, &()).await;
+    let Ok(IsHtmx(htmx)) = IsHtmx::from_request_parts(&mut parts, &()).await;
+    let base = base_context(
+        locale,
+        Some(parts.uri.path()),
+        parts
+            .extensions
+            .get::<MatchedPath>()
+            .map(MatchedPath::as_str),
+    );
+
+    let mut response = next.run(Request::from_parts(parts, body)).await;
+    let Some(template) = response.extensions_mut().remove::<Template>() else {
+        return response;
+    };
+
+    let (mut parts, _) = response.into_parts();
+    if template.fragment.is_some() {
+        // For caches, since the same URL has two answers.
+        parts
+            .headers
+            .append(header::VARY, HeaderValue::from_static("HX-Request"));
+    }
+    let name = match template.fragment {
+        Some(fragment) if htmx => fragment,
+        _ => template.name,
+    };
+    match state.render(name, merge_context(base, template.ctx)) {
+        Ok(page) => {
+            let (page_parts, body) = page.into_response().into_parts();
+            parts.headers.extend(page_parts.headers);
+            Response::from_parts(parts, body)
+        }
+        Err(err) => err.into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        http::{header, StatusCode},
+        middleware,
+        routing::get,
+        Router,
+    };
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{app, tests::state};
+
+    async fn get_page(app: Router, uri: &str) -> (Response<()>, String) {
+        let request = Request::get(uri).body(Body::empty()).unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        let (parts, body) = response.into_parts();
+        let body = body.collect().await.unwrap().to_bytes();
+        (
+            Response::from_parts(parts, ()),
+            String::from_utf8(body.to_vec()).unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn pages_render_as_before() {
+        let templates = &state().templates;
+        let pages = [
+            ("/", "home", base_context(Locale::En, Some("/"), Some("/"))),
+            (
+                "/about",
+                "about",
+                base_context(Locale::En, Some("/about"), Some("/about")),
+            ),
+        ];
+        for (uri, name, ctx) in pages {
+            let (response, body) = get_page(app(state()), uri).await;
+            assert_eq!(response.status(), StatusCode::OK, "{uri}");
+            assert_eq!(
+                response.headers()[header::CONTENT_TYPE],
+                "text/html; charset=utf-8",
+                "{uri}"
+            );
+            assert_eq!(body, templates.render(name, ctx).unwrap(), "{uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn the_current_page_is_active_in_the_nav() {
+        let (_, body) = get_page(app(state()), "/about").await;
+        assert!(
+            body.contains(r#"<a href="&#x2f;about" class="active" aria-current="page">About</a>"#),
+            "{body}"
+        );
+        assert!(body.contains(r#"<a href="&#x2f;">Home</a>"#), "{body}");
+        assert_eq!(body.matches("aria-current").count(), 1, "{body}");
+
+        let (_, body) = get_page(app(state()), "/").await;
+        assert!(
+            body.contains(r#"<a href="&#x2f;about">About</a>"#),
+            "{body}"
+        );
+        assert!(
+            body.contains(r#"<a href="&#x2f;" class="active" aria-current="page">Home</a>"#),
+            "{body}"
+        );
+
+        // Nothing is, where there's no route.
+        let (_, body) = get_page(app(state()), "/nowhere").await;
+        assert!(!body.contains("aria-current"), "{body}");
+    }
+
+    #[test]
+    fn handler_context_wins_over_the_base() {
+        let base = context! { app_name => "App", year => 2024, nav => vec![1, 2] };
+        let ctx = context! { app_name => "Other", title => "Page" };
+        let merged = merge_context(base, ctx);
+        assert_eq!(merged.get_attr("app_name").unwrap().as_str(), Some("Other"));
+        assert_eq!(merged.get_attr("title").unwrap().as_str(), Some("Page"));
+        assert_eq!(merged.get_attr("year").unwrap(), Value::from(2024));
+        assert_eq!(merged.get_attr("nav").unwrap().len(), Some(2));
+    }
+
+    #[test]
+    fn the_base_context_has_the_nav() {
+        let base = base_context(Locale::De, Some("/about"), Some("/about"));
+        assert_eq!(
+            base.get_attr("current_path").unwrap().as_str(),
+            Some("/about")
+        );
+        assert_eq!(base.get_attr("app_name").unwrap().as_str(), Some(APP_NAME));
+        let nav = base.get_attr("nav").unwrap();
+        let about = nav.get_item(&Value::from(2)).unwrap();
+        assert_eq!(about.get_attr("label").unwrap().as_str(), Some("Über uns"));
+        assert!(about.get_attr("active").unwrap().is_true());
+        let home = nav.get_item(&Value::from(0)).unwrap();
+        assert!(!home.get_attr("active").unwrap().is_true());
+    }
+
+    #[tokio::test]
+    async fn statuses_and_headers_are_kept() {
+        let state = state();
+        let app = Router::new()
+            .route(
+                "/gone",
+                get(|| async {
+                    let page = Template::new("404", context! { path => "/gone" });
+                    (
+                        StatusCode::GONE,
+                        [(header::CACHE_CONTROL, "no-store")],
+                        page,
+                    )
+                }),
+            )
+            .layer(middleware::from_fn_with_state(
+                state.clone(),
+                render_templates,
+            ))
+            .with_state(state);
+
+        let (response, body) = get_page(app, "/gone").await;
+        assert_eq!(response.status(), StatusCode::GONE);
+        assert_eq!(response.headers()[header::CACHE_CONTROL], "no-store");
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "text/html; charset=utf-8"
+        );
+        assert!(body.contains("<h1>Not Found</h1>"), "{body}");
+    }
+
+    #[tokio::test]
+    async fn render_failures_are_the_error_page() {
+        let state = state();
+        let app = Router::new()
+            .route(
+                "/missing",
+                get(|| async { Template::new("missing", context! {}) }),
+            )
+            .layer(middleware::from_fn_with_state(
+                state.clone(),
+                render_templates,
+            ))
+            .with_state(state);
+
+        let (response, body) = get_page(app, "/missing").await;
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "text/html; charset=utf-8"
+        );
+        assert!(
+            body.contains("<h1>500 Internal Server Error</h1>"),
+            "{body}"
+        );
+    }
+
+    async fn get_htmx(uri: &str, htmx: bool) -> (Response<()>, String) {
+        let mut request = Request::get(uri);
+        if htmx {
+            request = request.header("hx-request", "true");
+        }
+        let response = app(state())
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let (parts, body) = response.into_parts();
+        let body = body.collect().await.unwrap().to_bytes();
+        (
+            Response::from_parts(parts, ()),
+            String::from_utf8(body.to_vec()).unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn htmx_gets_only_the_fragment() {
+        let (response, full) = get_htmx("/content", false).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::VARY], "HX-Request");
+        assert!(full.contains("<html"), "{full}");
+        assert!(full.contains(r#"<ul id="entries">"#), "{full}");
+
+        let (response, fragment) = get_htmx("/content", true).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::VARY], "HX-Request");
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "text/html; charset=utf-8"
+        );
+        assert!(!fragment.contains("<html"), "{fragment}");
+        assert!(!fragment.contains("<nav>"), "{fragment}");
+        assert!(fragment.starts_with(r#"<ul id="entries">"#), "{fragment}");
+        assert!(full.contains(&fragment), "{full}");
+    }
+
+    #[tokio::test]
+    async fn pages_without_a_fragment_are_whole_for_htmx() {
+        let (response, body) = get_htmx("/about", true).await;
+        assert!(response.headers().get(header::VARY).is_none());
+        assert!(body.contains("<html"), "{body}");
+    }
+
+    #[tokio::test]
+    async fn items_are_fragments() {
+        let (response, body) = get_htmx("/content/items/2", true).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            body,
+            r#"<li id="item-2" hx-get="/content/items/2" hx-swap="outerHTML">
+    <strong>Entry 2</strong> <time>2024-01-02</time>
+    <p>This is entry number 2.</p>
+</li>"#
+        );
+
+        for uri in ["/content/items/0", "/content/items/101"] {
+            let (response, _) = get_htmx(uri, true).await;
+            assert_eq!(response.status(), StatusCode::NOT_FOUND, "{uri}");
+        }
+    }
+}
@@ -0,0 +1,263 @@
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::SystemTime,
+};
+
+use minijinja::{AutoEscape, Environment, ErrorKind, Value};
+
+use crate::{assets::Assets, i18n};
+
+/// Every template, each in `templates/<name>.jinja`.
+const NAMES: [&str; 9] = [
+    "layout",
+    "home",
+    "content",
+    "content_fragment",
+    "item_fragment",
+    "about",
+    "contact",
+    "404",
+    "error",
+];
+
+/// The templates, either built in or read from a directory and read again
+/// whenever one of them changes there.
+pub struct Templates {
+    loaded: RwLock<Loaded>,
+    /// Where the templates are reloaded from, if they are.
+    dir: Option<PathBuf>,
+    /// For `static_url`, in every environment loaded.
+    assets: Arc<Assets>,
+}
+
+struct Loaded {
+    env: Environment<'static>,
+    /// When each template was last changed, and how long it was, as of the
+    /// last load.
+    versions: Vec<(SystemTime, u64)>,
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    Read { path: PathBuf, source: io::Error },
+    Parse(minijinja::Error),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Read { path, source } => {
+                write!(f, "failed to read {}: {source}", path.display())
+            }
+            LoadError::Parse(err) => write!(f, "failed to parse a template: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LoadError::Read { source, .. } => Some(source),
+            LoadError::Parse(err) => Some(err),
+        }
+    }
+}
+
+impl Templates {
+    /// The templates as they were when the example was built.
+    pub fn embedded(assets: Arc<Assets>) -> Self {
+        let mut env = environment(&assets);
+        env.add_template("layout", include_str!("../templates/layout.jinja"))
+            .unwrap();
+        env.add_template("home", include_str!("../templates/home.jinja"))
+            .unwrap();
+        env.add_template("content", include_str!("../templates/content.jinja"))
+            .unwrap();
+        env.add_template(
+            "content_fragment",
+            include_str!("../templates/content_fragment.jinja"),
+        )
+        .unwrap();
+        env.add_template(
+            "item_fragment",
+            include_str!("../templates/item_fragment.jinja"),
+        )
+        .unwrap();
+        env.add_template("about", include_str!("../templates/about.jinja"))
+            .unwrap();
+        env.add_template("contact", include_str!("../templates/contact.jinja"))
+            .unwrap();
+        env.add_template("404", include_str!("../templates/404.jinja"))
+            .unwrap();
+        env.add_template("error", include_str!("../templates/error.jinja"))
+            .unwrap();
+        Self {
+            loaded: RwLock::new(Loaded {
+                env,
+                versions: Vec::new(),
+            }),
+            dir: None,
+            assets,
+        }
+    }
+
+    /// The templates in `dir`, read again whenever one of them changes.
+    pub fn reloading(dir: impl Into<PathBuf>, assets: Arc<Assets>) -> Result<Self, LoadError> {
+        let dir = dir.into();
+        Ok(Self {
+            loaded: RwLock::new(load(&dir, &assets)?),
+            dir: Some(dir),
+            assets,
+        })
+    }
+
+    /// Reloading from the example's `templates` directory if `TEMPLATE_RELOAD`
+    /// is `true`, or it's unset in a debug build, and embedded otherwise.
+    pub fn from_env(assets: Arc<Assets>) -> Result<Self, LoadError> {
+        let reload = match std::env::var("TEMPLATE_RELOAD") {
+            Ok(flag) => flag == "true",
+            Err(_) => cfg!(debug_assertions),
+        };
+        if reload {
+            Self::reloading(concat!(env!("CARGO_MANIFEST_DIR"), "/templates"), assets)
+        } else {
+            Ok(Self::embedded(assets))
+        }
+    }
+
+    pub fn render(&self, name: &str, ctx: Value) -> Result<String, minijinja::Error> {
+        if let Some(dir) = &self.dir {
+            self.reload_if_changed(dir)?;
+        }
+        let loaded = self.loaded.read().unwrap();
+        loaded.env.get_template(name)?.render(ctx)
+    }
+
+    /// A failed reload keeps the templates as they were, and is tried again on
+    /// the next render.
+    fn reload_if_changed(&self, dir: &Path) -> Result<(), minijinja::Error> {
+        let reload_failed = |err: LoadError| {
+            minijinja::Error::new(ErrorKind::InvalidOperation, "failed to reload templates")
+                .with_source(err)
+        };
+
+        let versions = versions(dir).map_err(reload_failed)?;
+        if self.loaded.read().unwrap().versions == versions {
+            return Ok(());
+        }
+        *self.loaded.write().unwrap() = load(dir, &self.assets).map_err(reload_failed)?;
+        Ok(())
+    }
+}
+
+/// HTML-escaping everything, which minijinja only does by default for
+/// templates named `*.html` and the like.
+fn environment(assets: &Arc<Assets>) -> Environment<'static> {
+    let mut env = Environment::new();
+    env.set_auto_escape_callback(|_| AutoEscape::Html);
+    env.add_function("t", i18n::t);
+    let assets = assets.clone();
+    env.add_function("static_url", move |name: &str| assets.url(name));
+    env
+}
+
+/// The environment owns the sources, so they're dropped with it on the next
+/// reload.
+fn load(dir: &Path, assets: &Arc<Assets>) -> Result<Loaded, LoadError> {
+    let mut env = environment(assets);
+    let mut versions = Vec::new();
+    for name in NAMES {
+        let path = dir.join(format!("{name}.jinja"));
+        let read = |path: &Path| Ok::<_, io::Error>((fs::read_to_string(path)?, version(path)?));
+        let (source, version) = read(&path).map_err(|source| LoadError::Read { path, source })?;
+        env.add_template_owned(name, source)
+            .map_err(LoadError::Parse)?;
+        versions.push(version);
+    }
+    Ok(Loaded { env, versions })
+}
+
+fn versions(dir: &Path) -> Result<Vec<(SystemTime, u64)>, LoadError> {
+    NAMES
+        .iter()
+        .map(|name| {
+            let path = dir.join(format!("{name}.jinja"));
+            version(&path).map_err(|source| LoadError::Read { path, source })
+        })
+        .collect()
+}
+
+/// The length too, since two writes in a row can have the same time.
+fn version(path: &Path) -> io::Result<(SystemTime, u64)> {
+    let metadata = fs::metadata(path)?;
+    Ok((metadata.modified()?, metadata.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use minijinja::context;
+
+    use super::*;
+    use crate::assets;
+
+    fn assets() -> Arc<Assets> {
+        Arc::new(Assets::load(assets::DIR).unwrap())
+    }
+
+    /// A copy of the example's templates.
+    fn copied() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        for name in NAMES {
+            let file = format!("{name}.jinja");
+            let source = Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("templates")
+                .join(&file);
+            fs::copy(source, dir.path().join(file)).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn edits_show_up_on_the_next_render() {
+        let dir = copied();
+        let templates = Templates::reloading(dir.path(), assets()).unwrap();
+        let ctx = || context! { title => "Home", welcome_text => "Hello World" };
+        assert_eq!(
+            templates.render("home", ctx()).unwrap(),
+            Templates::embedded(assets()).render("home", ctx()).unwrap()
+        );
+
+        let edited =
+            r#"{% extends "layout" %}{% block body %}<p>Hi, {{ welcome_text }}!</p>{% endblock %}"#;
+        fs::write(dir.path().join("home.jinja"), edited).unwrap();
+        let rendered = templates.render("home", ctx()).unwrap();
+        assert!(rendered.contains("<p>Hi, Hello World!</p>"), "{rendered}");
+        assert!(rendered.contains("<nav>"), "{rendered}");
+    }
+
+    #[test]
+    fn broken_edits_fail_until_fixed() {
+        let dir = copied();
+        let templates = Templates::reloading(dir.path(), assets()).unwrap();
+        let ctx = || context! { title => "About", about_text => "text" };
+
+        fs::write(dir.path().join("about.jinja"), "{% block %}").unwrap();
+        let err = templates.render("about", ctx()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidOperation);
+
+        fs::write(dir.path().join("about.jinja"), "{{ about_text }}").unwrap();
+        assert_eq!(templates.render("about", ctx()).unwrap(), "text");
+    }
+
+    #[test]
+    fn missing_templates_are_an_error() {
+        let dir = copied();
+        fs::remove_file(dir.path().join("content.jinja")).unwrap();
+        let err = Templates::reloading(dir.path(), assets()).err().unwrap();
+        assert!(matches!(err, LoadError::Read { .. }), "{err:?}");
+        let message = err.to_string();
+        assert!(message.contains("content.jinja"), "{message}");
+    }
+}
@@ -0,0 +1,163 @@
+use axum::{extract::Query, http::StatusCode, Json};
+use serde::Deserialize;
+
+use crate::{
+    error::{api_error, db_error_to_response, ApiError},
+    transaction::DatabaseTransaction,
+};
+
+/// Run once at startup.
+pub const CREATE_TABLE: &str = "
+    CREATE TABLE IF NOT EXISTS accounts (
+        id serial PRIMARY KEY,
+        owner text NOT NULL,
+        balance bigint NOT NULL CHECK (balance >= 0)
+    )
+";
+
+#[derive(Debug, Deserialize)]
+pub struct Transfer {
+    from: i32,
+    to: i32,
+    amount: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TransferParams {
+    /// Fail between the two updates, to show that the first is rolled back.
+    #[serde(default)]
+    fail: bool,
+}
+
+/// `POST /transfer`, move `amount` from one account to another. Either both
+/// balances change or neither does.
+pub async fn transfer(
+    tx: DatabaseTransaction,
+    Query(params): Query<TransferParams>,
+    Json(transfer): Json<Transfer>,
+) -> Result<StatusCode, ApiError> {
+    if transfer.amount <= 0 {
+        return Err(api_error(
+            StatusCode::BAD_REQUEST,
+            "amount must be positive",
+        ));
+    }
+
+    // Returning early anywhere below drops `tx`, rolling back.
+    update_balance(&tx, transfer.from, -transfer.amount).await?;
+
+    if params.fail {
+        return Err(api_error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "failing as requested",
+        ));
+    }
+
+    update_balance(&tx, transfer.to, transfer.amount).await?;
+
+    tx.commit().await.map_err(db_error_to_response)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn update_balance(tx: &DatabaseTransaction, id: i32, change: i64) -> Result<(), ApiError> {
+    let updated = tx
+        .execute(
+            "UPDATE accounts SET balance = balance + $1 WHERE id = $2",
+            &[&change, &id],
+        )
+        .await
+        .map_err(db_error_to_response)?;
+
+    if updated == 0 {
+        return Err(api_error(
+            StatusCode::NOT_FOUND,
+            format!("account {id} not found"),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::Method;
+    use serde_json::json;
+
+    use crate::{
+        app,
+        test_support::{send, test_pool},
+        ConnectionPool,
+    };
+
+    use super::*;
+
+    /// Two accounts with 100 each.
+    async fn accounts(pool: &ConnectionPool) -> (i32, i32) {
+        let conn = pool.get().await.unwrap();
+        let insert = "INSERT INTO accounts (owner, balance) VALUES ($1, 100) RETURNING id";
+        let ann = conn.query_one(insert, &[&"ann"]).await.unwrap().get(0);
+        let bob = conn.query_one(insert, &[&"bob"]).await.unwrap().get(0);
+        (ann, bob)
+    }
+
+    async fn balances(pool: &ConnectionPool, ids: (i32, i32)) -> (i64, i64) {
+        let conn = pool.get().await.unwrap();
+        let select = "SELECT balance FROM accounts WHERE id = $1";
+        let a = conn.query_one(select, &[&ids.0]).await.unwrap().get(0);
+        let b = conn.query_one(select, &[&ids.1]).await.unwrap().get(0);
+        (a, b)
+    }
+
+    #[tokio::test]
+    async fn transfer_commits_both_updates() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let (ann, bob) = accounts(&pool).await;
+        let app = app(pool.clone().into());
+
+        let body = json!({"from": ann, "to": bob, "amount": 30});
+        let (status, _) = send(&app, Method::POST, "/transfer", body).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+
+        assert_eq!(balances(&pool, (ann, bob)).await, (70, 130));
+    }
+
+    #[tokio::test]
+    async fn failures_roll_back() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let (ann, bob) = accounts(&pool).await;
+        let app = app(pool.clone().into());
+
+        let cases = [
+            (
+                "/transfer?fail=true",
+                json!({"from": ann, "to": bob, "amount": 30}),
+            ),
+            // Fails on the second update.
+            ("/transfer", json!({"from": ann, "to": 0, "amount": 30})),
+        ];
+        for (uri, body) in cases {
+            let (status, _) = send(&app, Method::POST, uri, body.clone()).await;
+            assert!(!status.is_success(), "{body}");
+            assert_eq!(balances(&pool, (ann, bob)).await, (100, 100), "{body}");
+        }
+    }
+
+    #[tokio::test]
+    async fn overdraft_is_a_conflict() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let (ann, bob) = accounts(&pool).await;
+        let app = app(pool.clone().into());
+
+        let body = json!({"from": ann, "to": bob, "amount": 101});
+        let (status, body) = send(&app, Method::POST, "/transfer", body).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body["constraint"], "accounts_balance_check");
+
+        assert_eq!(balances(&pool, (ann, bob)).await, (100, 100));
+    }
+}
@@ -0,0 +1,123 @@
+use std::fmt;
+
+use axum::{http::StatusCode, Json};
+use bb8::RunError;
+use serde::Serialize;
+use tokio_postgres::error::SqlState;
+
+/// The body of every error response from the handlers that query the database.
+#[derive(Debug, Serialize)]
+pub struct ErrorBody {
+    pub error: String,
+    /// The violated constraint, for conflicts.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub constraint: Option<String>,
+}
+
+pub type ApiError = (StatusCode, Json<ErrorBody>);
+
+pub fn api_error(status: StatusCode, message: impl Into<String>) -> ApiError {
+    let body = ErrorBody {
+        error: message.into(),
+        constraint: None,
+    };
+    (status, Json(body))
+}
+
+/// Errors the client caused get a 4xx status, everything else is logged and
+/// becomes a 500 that doesn't leak the details.
+pub fn db_error_to_response(err: tokio_postgres::Error) -> ApiError {
+    let Some(db_error) = err.as_db_error() else {
+        tracing::error!("database error: {err}");
+        return internal();
+    };
+
+    let status = match *db_error.code() {
+        // Check constraints guard state the request would break, like a
+        // transfer overdrawing an account.
+        SqlState::UNIQUE_VIOLATION
+        | SqlState::FOREIGN_KEY_VIOLATION
+        | SqlState::CHECK_VIOLATION => StatusCode::CONFLICT,
+        SqlState::INVALID_TEXT_REPRESENTATION => StatusCode::BAD_REQUEST,
+        _ => {
+            tracing::error!("database error: {db_error}");
+            return internal();
+        }
+    };
+    let body = ErrorBody {
+        error: db_error.message().to_owned(),
+        constraint: db_error.constraint().map(ToOwned::to_owned),
+    };
+    (status, Json(body))
+}
+
+/// Checking out a connection timing out means the pool is exhausted or the
+/// database is down, which is worth retrying.
+pub fn pool_error<E: fmt::Display>(err: RunError<E>) -> ApiError {
+    match err {
+        RunError::User(err) => {
+            tracing::error!("failed to connect to the database: {err}");
+            internal()
+        }
+        RunError::TimedOut => api_error(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "no database connection available",
+        ),
+    }
+}
+
+fn internal() -> ApiError {
+    api_error(StatusCode::INTERNAL_SERVER_ERROR, "internal server error")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::test_pool;
+
+    #[tokio::test]
+    async fn maps_sqlstates_to_statuses() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let conn = pool.get().await.unwrap();
+        conn.batch_execute(
+            "CREATE TABLE parents (id int PRIMARY KEY);
+             CREATE TABLE children (parent int REFERENCES parents (id));
+             CREATE TABLE positives (n int CHECK (n > 0));
+             INSERT INTO users (name, email) VALUES ('ann', 'ann@example.com');",
+        )
+        .await
+        .unwrap();
+
+        let cases = [
+            (
+                "INSERT INTO users (name, email) VALUES ('ann', 'ann@example.com')",
+                StatusCode::CONFLICT,
+                Some("users_email_key"),
+            ),
+            (
+                "INSERT INTO children VALUES (1)",
+                StatusCode::CONFLICT,
+                Some("children_parent_fkey"),
+            ),
+            (
+                "INSERT INTO positives VALUES (0)",
+                StatusCode::CONFLICT,
+                Some("positives_n_check"),
+            ),
+            ("SELECT 'one'::int", StatusCode::BAD_REQUEST, None),
+            ("SELECT 1 / 0", StatusCode::INTERNAL_SERVER_ERROR, None),
+        ];
+        for (sql, status, constraint) in cases {
+            let err = conn.batch_execute(sql).await.unwrap_err();
+            let (actual, Json(body)) = db_error_to_response(err);
+            assert_eq!(actual, status, "{sql}");
+            assert_eq!(body.constraint.as_deref(), constraint, "{sql}");
+        }
+
+        let (_, Json(body)) =
+            db_error_to_response(conn.batch_execute("SELECT 1 / 0").await.unwrap_err());
+        assert_eq!(body.error, "internal server error");
+    }
+}
@@ -0,0 +1,203 @@
+use std::{convert::Infallible, time::Duration};
+
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::sse::{Event, KeepAlive, Sse},
+    routing::{get, post},
+    Router,
+};
+use futures::{stream, Stream, StreamExt};
+use tokio::{sync::mpsc, task::JoinHandle};
+use tokio_postgres::{AsyncMessage, Config, Notification};
+use tokio_postgres_rustls::MakeRustlsConnect;
+
+use crate::{
+    error::{api_error, db_error_to_response, ApiError},
+    pools::WriteConnection,
+    ConnectionPool,
+};
+
+const CHANNEL: &str = "app_events";
+
+/// Postgres rejects payloads of 8000 bytes or more.
+const MAX_PAYLOAD: usize = 7999;
+
+/// Relaying Postgres notifications to Server-Sent Events.
+///
+/// Every subscriber gets a connection of its own made with `config` and `tls`,
+/// since a pooled one would keep listening after going back to the pool.
+pub fn routes(config: Config, tls: MakeRustlsConnect, pool: ConnectionPool) -> Router {
+    Router::new()
+        .route("/events", get(events).with_state((config, tls)))
+        .route("/notify", post(notify).with_state(pool))
+}
+
+/// Aborts the task when dropped.
+struct AbortOnDrop(JoinHandle<()>);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// `GET /events`, the payloads sent to `app_events`.
+async fn events(
+    State((config, tls)): State<(Config, MakeRustlsConnect)>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, ApiError> {
+    let (client, mut connection) = config.connect(tls).await.map_err(db_error_to_response)?;
+
+    // Notifications only arrive by polling the connection, which also does
+    // all of its IO, so it has to run before `LISTEN` can complete.
+    let (tx, rx) = mpsc::unbounded_channel::<Notification>();
+    let driver = tokio::spawn(async move {
+        let mut messages = stream::poll_fn(move |cx| connection.poll_message(cx));
+        while let Some(message) = messages.next().await {
+            match message {
+                Ok(AsyncMessage::Notification(notification)) => {
+                    if tx.send(notification).is_err() {
+                        break;
+                    }
+                }
+                Ok(_) => {}
+                Err(err) => {
+                    tracing::warn!("listening connection failed: {err}");
+                    break;
+                }
+            }
+        }
+    });
+    let driver = AbortOnDrop(driver);
+
+    client
+        .batch_execute(&format!("LISTEN {CHANNEL}"))
+        .await
+        .map_err(db_error_to_response)?;
+    tracing::debug!("listening on `{CHANNEL}`");
+
+    // The stream owns the client and the task driving its connection, so
+    // both are dropped, closing the connection, when the client disconnects.
+    // The stream ends when the connection is lost, ending the response.
+    let events = stream::unfold(
+        (rx, client, driver),
+        |(mut rx, client, driver)| async move {
+            let notification = rx.recv().await?;
+            let event = Event::default()
+                .event(CHANNEL)
+                .data(sse_data(notification.payload()));
+            Some((Ok(event), (rx, client, driver)))
+        },
+    );
+
+    Ok(Sse::new(events).keep_alive(
+        KeepAlive::new()
+            .interval(Duration::from_secs(15))
+            .text("keep-alive"),
+    ))
+}
+
+/// `Event::data` panics on carriage returns, so they're turned into the line
+/// breaks SSE clients would see anyway.
+fn sse_data(payload: &str) -> String {
+    payload.replace("\r\n", "\n").replace('\r', "\n")
+}
+
+/// `POST /notify`, send the body to everyone listening on `app_events`.
+async fn notify(
+    WriteConnection(conn): WriteConnection,
+    payload: String,
+) -> Result<StatusCode, ApiError> {
+    if payload.len() > MAX_PAYLOAD {
+        return Err(api_error(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("payloads can be at most {MAX_PAYLOAD} bytes"),
+        ));
+    }
+
+    conn.execute("SELECT pg_notify($1, $2)", &[&CHANNEL, &payload])
+        .await
+        .map_err(db_error_to_response)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use axum::{body::Body, http::Request};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{
+        test_support::{database_url, test_pool},
+        tls::TlsSettings,
+    };
+
+    #[test]
+    fn carriage_returns_become_line_breaks() {
+        assert_eq!(sse_data("a\r\nb\rc\nd"), "a\nb\nc\nd");
+        // Doesn't panic.
+        let _ = Event::default().data(sse_data("a\r\nb\rc"));
+    }
+
+    #[tokio::test]
+    async fn notifications_are_relayed() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let mut config = Config::from_str(&database_url().unwrap()).unwrap();
+        let tls = TlsSettings::default().apply(&mut config).unwrap();
+        let app = routes(config, tls, pool);
+
+        // Listening before the response starts.
+        let response = app
+            .clone()
+            .oneshot(Request::get("/events").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["content-type"], "text/event-stream");
+        let mut events = response.into_body().into_data_stream();
+
+        let response = app
+            .oneshot(
+                Request::post("/notify")
+                    .body(Body::from("hello\r\nworld"))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        let chunk = tokio::time::timeout(Duration::from_secs(5), events.next())
+            .await
+            .expect("no event within 5 seconds")
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            std::str::from_utf8(&chunk).unwrap(),
+            "event: app_events\ndata: hello\ndata: world\n\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn oversized_payloads_are_rejected() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let mut config = Config::new();
+        let tls = TlsSettings::default().apply(&mut config).unwrap();
+        let app = routes(config, tls, pool);
+
+        let response = app
+            .oneshot(
+                Request::post("/notify")
+                    .body(Body::from("x".repeat(MAX_PAYLOAD + 1)))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+}
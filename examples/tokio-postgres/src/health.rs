@@ -0,0 +1,218 @@
+use std::time::{Duration, Instant};
+
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use bb8::RunError;
+use serde::Serialize;
+
+use crate::ConnectionPool;
+
+/// The longest `/health` takes, even when every connection is checked out.
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Checks the write pool, i.e. the primary.
+pub fn routes(pool: ConnectionPool) -> Router {
+    Router::new().route("/health", get(health)).with_state(pool)
+}
+
+#[derive(Debug, Serialize)]
+struct Healthy {
+    database: &'static str,
+    latency_ms: u64,
+    pool: PoolStats,
+}
+
+#[derive(Debug, Serialize)]
+struct Unhealthy {
+    database: &'static str,
+    error: Failure,
+    detail: String,
+    pool: PoolStats,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Failure {
+    /// No connection could be checked out in time.
+    PoolTimeout,
+    /// The database refused or dropped the connection being checked out.
+    ConnectionError,
+    /// `SELECT 1` failed or timed out.
+    QueryError,
+}
+
+#[derive(Debug, Serialize)]
+struct PoolStats {
+    connections: u32,
+    idle: u32,
+}
+
+impl PoolStats {
+    fn of(pool: &ConnectionPool) -> Self {
+        let state = pool.state();
+        Self {
+            connections: state.connections,
+            idle: state.idle_connections,
+        }
+    }
+}
+
+/// `GET /health`, `SELECT 1` through the pool.
+async fn health(State(pool): State<ConnectionPool>) -> Response {
+    let start = Instant::now();
+    match select_one(&pool).await {
+        Ok(()) => Json(Healthy {
+            database: "ok",
+            latency_ms: start.elapsed().as_millis() as u64,
+            pool: PoolStats::of(&pool),
+        })
+        .into_response(),
+        Err((error, detail)) => {
+            tracing::warn!("health check failed: {detail}");
+            let unhealthy = Unhealthy {
+                database: "unavailable",
+                error,
+                detail,
+                pool: PoolStats::of(&pool),
+            };
+            (StatusCode::SERVICE_UNAVAILABLE, Json(unhealthy)).into_response()
+        }
+    }
+}
+
+async fn select_one(pool: &ConnectionPool) -> Result<(), (Failure, String)> {
+    let deadline = tokio::time::Instant::now() + TIMEOUT;
+
+    // The pool's own timeout defaults to 30 seconds, too long for a health
+    // check. Giving up on `get` is fine, a connection still being opened goes
+    // to the pool rather than being lost.
+    let conn = match tokio::time::timeout_at(deadline, pool.get()).await {
+        Ok(Ok(conn)) => conn,
+        Ok(Err(RunError::User(err))) => return Err((Failure::ConnectionError, err.to_string())),
+        Ok(Err(RunError::TimedOut)) => {
+            let detail = "timed out waiting for a connection".to_owned();
+            return Err((Failure::PoolTimeout, detail));
+        }
+        Err(_) => {
+            let detail = format!("no connection available within {TIMEOUT:?}");
+            return Err((Failure::PoolTimeout, detail));
+        }
+    };
+
+    match tokio::time::timeout_at(deadline, conn.simple_query("SELECT 1")).await {
+        Ok(Ok(_)) => Ok(()),
+        Ok(Err(err)) => Err((Failure::QueryError, err.to_string())),
+        Err(_) => Err((Failure::QueryError, format!("no result within {TIMEOUT:?}"))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use axum::body::Body;
+    use axum::http::Request;
+    use bb8::Pool;
+    use bb8_postgres::PostgresConnectionManager;
+    use http_body_util::BodyExt;
+    use serde_json::{json, Value};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{connect::parse_config, test_support::test_pool, tls::TlsSettings};
+
+    #[test]
+    fn healthy_shape() {
+        let healthy = Healthy {
+            database: "ok",
+            latency_ms: 3,
+            pool: PoolStats {
+                connections: 2,
+                idle: 1,
+            },
+        };
+        assert_eq!(
+            serde_json::to_value(healthy).unwrap(),
+            json!({"database": "ok", "latency_ms": 3, "pool": {"connections": 2, "idle": 1}})
+        );
+    }
+
+    #[test]
+    fn unhealthy_shape() {
+        for (error, category) in [
+            (Failure::PoolTimeout, "pool_timeout"),
+            (Failure::ConnectionError, "connection_error"),
+            (Failure::QueryError, "query_error"),
+        ] {
+            let unhealthy = Unhealthy {
+                database: "unavailable",
+                error,
+                detail: "connection refused".to_owned(),
+                pool: PoolStats {
+                    connections: 0,
+                    idle: 0,
+                },
+            };
+            assert_eq!(
+                serde_json::to_value(unhealthy).unwrap(),
+                json!({
+                    "database": "unavailable",
+                    "error": category,
+                    "detail": "connection refused",
+                    "pool": {"connections": 0, "idle": 0},
+                })
+            );
+        }
+    }
+
+    async fn check(pool: ConnectionPool) -> (StatusCode, Value) {
+        let response = routes(pool)
+            .oneshot(Request::get("/health").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn unreachable_database_times_out() {
+        // Nothing listens on a port that was just released.
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let url = format!("postgres://postgres@127.0.0.1:{port}/postgres");
+        let (config, tls) = parse_config(&url, &TlsSettings::default()).unwrap();
+        let pool = Pool::builder().build_unchecked(PostgresConnectionManager::new(config, tls));
+        let start = Instant::now();
+
+        let (status, body) = check(pool).await;
+
+        assert!(start.elapsed() < TIMEOUT + Duration::from_secs(1));
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["database"], "unavailable");
+        assert_eq!(body["error"], "pool_timeout");
+    }
+
+    #[tokio::test]
+    async fn live_database_is_healthy() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+
+        let (status, body) = check(pool).await;
+
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(body["database"], "ok");
+        assert!(body["latency_ms"].is_u64());
+        assert!(body["pool"]["connections"].as_u64().unwrap() >= 1);
+        assert_eq!(body["pool"]["idle"], body["pool"]["connections"]);
+    }
+}
@@ -1,37 +1,50 @@
+use std::time::Duration;
+
 use axum::extract::{FromRef, FromRequestParts, State};
 use axum::http::request::Parts;
-use axum::http::StatusCode;
-use axum::routing::get;
+use axum::routing::{get, post};
 use axum::{async_trait, Router};
 use bb8::{Pool, PooledConnection};
 use bb8_postgres::PostgresConnectionManager;
-use tokio_postgres::NoTls;
+use tokio_postgres::Client;
+use tokio_postgres_rustls::MakeRustlsConnect;
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::util::SubscriberInitExt;
 
+use crate::connect::StartupError;
+use crate::error::{db_error_to_response, pool_error, ApiError};
+use crate::pools::AppState;
+use crate::tls::TlsSettings;
+
+mod accounts;
+mod connect;
+mod error;
+mod events;
+mod health;
+mod pools;
+#[cfg(test)]
+mod test_support;
+mod tls;
+mod transaction;
+mod users;
+
 #[tokio::main]
 async fn main() {
     tracing_subscriber::registry()
         .with(
             tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| "example_tokio_postgres=debug".into()),
+                .unwrap_or_else(|_| format!("{}=debug", env!("CARGO_CRATE_NAME")).into()),
         )
         .with(tracing_subscriber::fmt::layer())
         .init();
 
-    let manager = PostgresConnectionManager::new_from_stringlike(
-        "host=localhost user=postgres password=123456",
-        NoTls,
-    )
-    .unwrap();
-    let pool = Pool::builder().build(manager).await.unwrap();
-
-    let app = Router::new()
-        .route(
-            "/",
-            get(using_connection_pool_extractor).post(using_connection_extractor),
-        )
-        .with_state(pool);
+    let app = match startup().await {
+        Ok(app) => app,
+        Err(err) => {
+            tracing::error!("{err}");
+            std::process::exit(1);
+        }
+    };
 
     let listener = tokio::net::TcpListener::bind("127.0.0.1:3000")
         .await
@@ -40,23 +53,107 @@ async fn main() {
     axum::serve(listener, app).await.unwrap();
 }
 
-type ConnectionPool = Pool<PostgresConnectionManager<NoTls>>;
+/// How long reads wait for a replica connection before going to the primary,
+/// when `DB_READ_FALLBACK=true`.
+const READ_FALLBACK_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Connect to `DATABASE_URL`, and `DATABASE_READ_URL` if it's set, retrying
+/// for up to `DB_CONNECT_DEADLINE_SECS`, and create the tables.
+async fn startup() -> Result<Router, StartupError> {
+    let url = std::env::var("DATABASE_URL")
+        .unwrap_or_else(|_| "host=localhost user=postgres password=123456".to_owned());
+    let read_url = std::env::var("DATABASE_READ_URL").ok();
+    let read_fallback = std::env::var("DB_READ_FALLBACK").is_ok_and(|value| value == "true");
+    let deadline = Duration::from_secs(env_or("DB_CONNECT_DEADLINE_SECS", 30));
+    let tls_settings = TlsSettings::from_env().map_err(StartupError::Tls)?;
+    let (config, tls) = connect::parse_config(&url, &tls_settings)?;
+
+    tracing::debug!(
+        "connecting to {} with sslmode={:?}",
+        connect::describe(&config),
+        config.get_ssl_mode()
+    );
+    let pool = connect::build_pool(&config, tls.clone(), deadline, Pool::builder()).await?;
+    tracing::debug!("successfully connected to postgres");
+
+    let read_pool = match read_url {
+        Some(read_url) => {
+            let (config, tls) = connect::parse_config(&read_url, &tls_settings)?;
+            tracing::debug!("reading from {}", connect::describe(&config));
+            let mut builder = Pool::builder();
+            if read_fallback {
+                builder = builder.connection_timeout(READ_FALLBACK_TIMEOUT);
+            }
+            Some(connect::build_pool(&config, tls, deadline, builder).await?)
+        }
+        None => None,
+    };
+
+    let conn = pool
+        .get()
+        .await
+        .map_err(|err| StartupError::CreateTables(err.to_string()))?;
+    create_tables(&conn)
+        .await
+        .map_err(|err| StartupError::CreateTables(err.to_string()))?;
+    drop(conn);
+
+    let state = AppState::new(pool.clone(), read_pool, read_fallback);
+    Ok(health::routes(pool.clone())
+        .merge(app(state))
+        .merge(events::routes(config, tls, pool)))
+}
+
+fn env_or(name: &str, default: u64) -> u64 {
+    std::env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+async fn create_tables(conn: &Client) -> Result<(), tokio_postgres::Error> {
+    conn.batch_execute(users::CREATE_TABLE).await?;
+    conn.batch_execute(accounts::CREATE_TABLE).await
+}
+
+fn app(state: AppState) -> Router {
+    Router::new()
+        .route(
+            "/",
+            get(using_connection_pool_extractor).post(using_connection_extractor),
+        )
+        .route("/users", get(users::list_users).post(users::create_user))
+        .route("/users/export", get(users::export_users))
+        .route("/users/page", get(users::users_page))
+        .route(
+            "/users/:id",
+            get(users::get_user).delete(users::delete_user),
+        )
+        .route("/transfer", post(accounts::transfer))
+        .with_state(state)
+}
+
+/// Connections are made with TLS or without it, depending on the `sslmode`
+/// they're configured with, see `tls`.
+type ConnectionManager = PostgresConnectionManager<MakeRustlsConnect>;
+
+type ConnectionPool = Pool<ConnectionManager>;
 
 async fn using_connection_pool_extractor(
     State(pool): State<ConnectionPool>,
-) -> Result<String, (StatusCode, String)> {
-    let conn = pool.get().await.map_err(internal_error)?;
+) -> Result<String, ApiError> {
+    let conn = pool.get().await.map_err(pool_error)?;
 
     let row = conn
         .query_one("select 1 + 1", &[])
         .await
-        .map_err(internal_error)?;
-    let two: i32 = row.try_get(0).map_err(internal_error)?;
+        .map_err(db_error_to_response)?;
+    let two: i32 = row.try_get(0).map_err(db_error_to_response)?;
 
     Ok(two.to_string())
 }
 
-struct DatabaseConnection(PooledConnection<'static, PostgresConnectionManager<NoTls>>);
+struct DatabaseConnection(PooledConnection<'static, ConnectionManager>);
 
 #[async_trait]
 impl<S> FromRequestParts<S> for DatabaseConnection
@@ -64,12 +161,12 @@ where
     ConnectionPool: FromRef<S>,
     S: Send + Sync,
 {
-    type Rejection = (StatusCode, String);
+    type Rejection = ApiError;
 
     async fn from_request_parts(_parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
         let pool = ConnectionPool::from_ref(state);
 
-        let conn = pool.get_owned().await.map_err(internal_error)?;
+        let conn = pool.get_owned().await.map_err(pool_error)?;
 
         Ok(Self(conn))
     }
@@ -77,19 +174,12 @@ where
 
 async fn using_connection_extractor(
     DatabaseConnection(conn): DatabaseConnection,
-) -> Result<String, (StatusCode, String)> {
+) -> Result<String, ApiError> {
     let row = conn
         .query_one("select 1 + 1", &[])
         .await
-        .map_err(internal_error)?;
-    let two: i32 = row.try_get(0).map_err(internal_error)?;
+        .map_err(db_error_to_response)?;
+    let two: i32 = row.try_get(0).map_err(db_error_to_response)?;
 
     Ok(two.to_string())
 }
-
-fn internal_error<E>(err: E) -> (StatusCode, String)
-where
-    E: std::error::Error,
-{
-    (StatusCode::INTERNAL_SERVER_ERROR, err.to_string())
-}
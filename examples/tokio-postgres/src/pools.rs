@@ -0,0 +1,246 @@
+use std::fmt;
+
+use axum::{
+    async_trait,
+    extract::{FromRef, FromRequestParts},
+    http::request::Parts,
+};
+use bb8::{ManageConnection, Pool, PooledConnection, RunError};
+
+use crate::{
+    error::{pool_error, ApiError},
+    ConnectionManager,
+};
+
+/// A pool for writes, going to the primary, and one for reads, which can go
+/// to a replica.
+///
+/// The write pool is what `State<ConnectionPool>`, `DatabaseConnection` and
+/// `DatabaseTransaction` get. Generic over the connection manager so the tests
+/// can use in-memory stand-ins.
+pub struct AppState<M: ManageConnection = ConnectionManager> {
+    pub write: Pool<M>,
+    pub read: ReadPool<M>,
+}
+
+// Derived, these would require `M: Clone`.
+impl<M: ManageConnection> Clone for AppState<M> {
+    fn clone(&self) -> Self {
+        Self {
+            write: self.write.clone(),
+            read: self.read.clone(),
+        }
+    }
+}
+
+impl<M: ManageConnection> AppState<M> {
+    /// Without `read`, reads share the write pool. With `read_fallback`, reads
+    /// go to the write pool when no connection to the replica could be
+    /// checked out in time.
+    pub fn new(write: Pool<M>, read: Option<Pool<M>>, read_fallback: bool) -> Self {
+        let read = match read {
+            Some(read) => ReadPool {
+                pool: read,
+                fallback: read_fallback.then(|| write.clone()),
+            },
+            None => ReadPool {
+                pool: write.clone(),
+                fallback: None,
+            },
+        };
+        Self { write, read }
+    }
+}
+
+/// A single pool for both.
+impl<M: ManageConnection> From<Pool<M>> for AppState<M> {
+    fn from(pool: Pool<M>) -> Self {
+        Self::new(pool, None, false)
+    }
+}
+
+impl<M: ManageConnection> FromRef<AppState<M>> for Pool<M> {
+    fn from_ref(state: &AppState<M>) -> Self {
+        state.write.clone()
+    }
+}
+
+impl<M: ManageConnection> FromRef<AppState<M>> for ReadPool<M> {
+    fn from_ref(state: &AppState<M>) -> Self {
+        state.read.clone()
+    }
+}
+
+pub struct ReadPool<M: ManageConnection = ConnectionManager> {
+    pool: Pool<M>,
+    fallback: Option<Pool<M>>,
+}
+
+impl<M: ManageConnection> Clone for ReadPool<M> {
+    fn clone(&self) -> Self {
+        Self {
+            pool: self.pool.clone(),
+            fallback: self.fallback.clone(),
+        }
+    }
+}
+
+impl<M: ManageConnection> ReadPool<M> {
+    pub async fn get_owned(&self) -> Result<PooledConnection<'static, M>, RunError<M::Error>> {
+        match (self.pool.get_owned().await, &self.fallback) {
+            (Err(RunError::TimedOut), Some(fallback)) => {
+                tracing::warn!("read pool exhausted, reading from the write pool");
+                fallback.get_owned().await
+            }
+            (result, _) => result,
+        }
+    }
+}
+
+/// A connection from the read pool, for handlers that don't change anything.
+pub struct ReadConnection<M: ManageConnection = ConnectionManager>(
+    pub PooledConnection<'static, M>,
+);
+
+#[async_trait]
+impl<S, M> FromRequestParts<S> for ReadConnection<M>
+where
+    M: ManageConnection,
+    M::Error: fmt::Display,
+    ReadPool<M>: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(_parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let pool = ReadPool::from_ref(state);
+
+        let conn = pool.get_owned().await.map_err(pool_error)?;
+
+        Ok(Self(conn))
+    }
+}
+
+/// A connection from the write pool.
+pub struct WriteConnection<M: ManageConnection = ConnectionManager>(
+    pub PooledConnection<'static, M>,
+);
+
+#[async_trait]
+impl<S, M> FromRequestParts<S> for WriteConnection<M>
+where
+    M: ManageConnection,
+    M::Error: fmt::Display,
+    Pool<M>: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(_parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let pool = Pool::from_ref(state);
+
+        let conn = pool.get_owned().await.map_err(pool_error)?;
+
+        Ok(Self(conn))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use axum::{body::Body, http::Request, http::StatusCode, routing::get, Router};
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::test_support::Named;
+
+    /// A pool of one connection, timing out quickly when it's checked out.
+    async fn pool(name: &'static str) -> Pool<Named> {
+        Pool::builder()
+            .max_size(1)
+            .connection_timeout(Duration::from_millis(50))
+            .build(Named(name))
+            .await
+            .unwrap()
+    }
+
+    fn test_app(state: AppState<Named>) -> Router {
+        Router::new()
+            .route(
+                "/read",
+                get(|ReadConnection(conn): ReadConnection<Named>| async move { *conn }),
+            )
+            .route(
+                "/write",
+                get(|WriteConnection(conn): WriteConnection<Named>| async move { *conn }),
+            )
+            .with_state(state)
+    }
+
+    async fn visit(app: &Router, uri: &str) -> (StatusCode, String) {
+        let response = app
+            .clone()
+            .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn reads_and_writes_go_to_their_pools() {
+        let state = AppState::new(pool("primary").await, Some(pool("replica").await), false);
+        assert_eq!(*Pool::from_ref(&state).get().await.unwrap(), "primary");
+        assert_eq!(
+            *ReadPool::from_ref(&state).get_owned().await.unwrap(),
+            "replica"
+        );
+
+        let app = test_app(state);
+        assert_eq!(
+            visit(&app, "/read").await,
+            (StatusCode::OK, "replica".to_owned())
+        );
+        assert_eq!(
+            visit(&app, "/write").await,
+            (StatusCode::OK, "primary".to_owned())
+        );
+    }
+
+    #[tokio::test]
+    async fn a_single_pool_is_shared() {
+        let app = test_app(pool("primary").await.into());
+
+        assert_eq!(
+            visit(&app, "/read").await,
+            (StatusCode::OK, "primary".to_owned())
+        );
+        assert_eq!(
+            visit(&app, "/write").await,
+            (StatusCode::OK, "primary".to_owned())
+        );
+    }
+
+    #[tokio::test]
+    async fn exhausted_reads_fall_back_when_enabled() {
+        let replica = pool("replica").await;
+        let _held = replica.get_owned().await.unwrap();
+
+        let app = test_app(AppState::new(
+            pool("primary").await,
+            Some(replica.clone()),
+            true,
+        ));
+        assert_eq!(
+            visit(&app, "/read").await,
+            (StatusCode::OK, "primary".to_owned())
+        );
+
+        let app = test_app(AppState::new(pool("primary").await, Some(replica), false));
+        let (status, _) = visit(&app, "/read").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    }
+}
@@ -0,0 +1,111 @@
+//! Pools in throwaway schemas and request helpers for the tests that need
+//! a real Postgres.
+
+use std::{
+    convert::Infallible,
+    str::FromStr,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use axum::async_trait;
+use axum::{
+    body::Body,
+    http::{header, Method, Request, StatusCode},
+    Router,
+};
+use bb8::{ManageConnection, Pool};
+use bb8_postgres::PostgresConnectionManager;
+use http_body_util::BodyExt;
+use serde_json::Value;
+use tokio_postgres::Config;
+use tower::ServiceExt;
+
+use crate::{create_tables, tls::TlsSettings, ConnectionManager, ConnectionPool};
+
+/// The database to run the tests against, from `DATABASE_URL`. Tests that
+/// need Postgres pass without checking anything when it isn't set.
+pub fn database_url() -> Option<String> {
+    match std::env::var("DATABASE_URL") {
+        Ok(url) => Some(url),
+        Err(_) => {
+            eprintln!("no Postgres to test against, set DATABASE_URL to a scratch database");
+            None
+        }
+    }
+}
+
+/// A pool whose connections use a schema of their own, so tests running at
+/// the same time don't see each other's rows. The schemas are left behind, so
+/// point `DATABASE_URL` at a scratch database.
+pub async fn test_pool() -> Option<ConnectionPool> {
+    test_pool_with(Pool::builder()).await
+}
+
+/// Like `test_pool`, configured by `builder`.
+pub async fn test_pool_with(builder: bb8::Builder<ConnectionManager>) -> Option<ConnectionPool> {
+    static SCHEMAS: AtomicU32 = AtomicU32::new(0);
+
+    let mut config = Config::from_str(&database_url()?).unwrap();
+    let schema = format!(
+        "test_{}_{}",
+        std::process::id(),
+        SCHEMAS.fetch_add(1, Ordering::Relaxed)
+    );
+
+    let tls = TlsSettings::default().apply(&mut config).unwrap();
+    let (client, connection) = config.connect(tls.clone()).await.unwrap();
+    tokio::spawn(connection);
+    client
+        .batch_execute(&format!("CREATE SCHEMA {schema}"))
+        .await
+        .unwrap();
+
+    config.options(format!("-c search_path={schema}"));
+    let manager = PostgresConnectionManager::new(config, tls);
+    let pool = builder.build(manager).await.unwrap();
+    create_tables(&pool.get().await.unwrap()).await.unwrap();
+    Some(pool)
+}
+
+/// A connection manager whose "connections" are its name, standing in for a
+/// database.
+#[derive(Debug, Clone, Copy)]
+pub struct Named(pub &'static str);
+
+#[async_trait]
+impl ManageConnection for Named {
+    type Connection = &'static str;
+    type Error = Infallible;
+
+    async fn connect(&self) -> Result<Self::Connection, Self::Error> {
+        Ok(self.0)
+    }
+
+    async fn is_valid(&self, _conn: &mut Self::Connection) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn has_broken(&self, _conn: &mut Self::Connection) -> bool {
+        false
+    }
+}
+
+/// Send `json` to `uri`, or no body if it's `null`. The response body is
+/// parsed as JSON, or returned as a string if it isn't.
+pub async fn send(app: &Router, method: Method, uri: &str, json: Value) -> (StatusCode, Value) {
+    let request = Request::builder().method(method).uri(uri);
+    let request = if json.is_null() {
+        request.body(Body::empty())
+    } else {
+        request
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(json.to_string()))
+    };
+
+    let response = app.clone().oneshot(request.unwrap()).await.unwrap();
+    let status = response.status();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let body = serde_json::from_slice(&body)
+        .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(&body).into_owned()));
+    (status, body)
+}
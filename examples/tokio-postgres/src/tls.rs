@@ -0,0 +1,216 @@
+use std::{fmt, path::PathBuf, sync::Arc};
+
+use rustls::{
+    pki_types::{pem::PemObject, CertificateDer},
+    ClientConfig, RootCertStore,
+};
+use tokio_postgres::config::{Config, SslMode};
+use tokio_postgres_rustls::MakeRustlsConnect;
+
+/// The TLS settings from `PGSSLMODE` and `PGSSLROOTCERT`.
+#[derive(Debug, Default, PartialEq)]
+pub struct TlsSettings {
+    /// `None` leaves it to the connection string.
+    pub mode: Option<SslMode>,
+    /// A PEM bundle of CA certificates, used instead of the system's.
+    pub root_cert: Option<PathBuf>,
+}
+
+impl TlsSettings {
+    pub fn from_env() -> Result<Self, TlsError> {
+        Self::parse(
+            std::env::var("PGSSLMODE").ok().as_deref(),
+            std::env::var("PGSSLROOTCERT").ok().as_deref(),
+        )
+    }
+
+    /// Only `disable` and `require` are supported. The server's certificate
+    /// is always verified, so there's no falling back to plain text the way
+    /// `prefer` would after a failed handshake.
+    fn parse(mode: Option<&str>, root_cert: Option<&str>) -> Result<Self, TlsError> {
+        let mode = match mode {
+            None | Some("") => None,
+            Some("disable") => Some(SslMode::Disable),
+            Some("require") => Some(SslMode::Require),
+            Some(other) => return Err(TlsError::InvalidMode(other.to_owned())),
+        };
+        let root_cert = root_cert.filter(|path| !path.is_empty()).map(Into::into);
+        Ok(Self { mode, root_cert })
+    }
+
+    /// Set the mode `config` connects with and make the connector for it.
+    ///
+    /// `PGSSLMODE` wins over the connection string. Without it, TLS is only
+    /// used if the connection string asks for `sslmode=require`, since
+    /// tokio-postgres can't tell an explicit `prefer` from its default.
+    pub fn apply(&self, config: &mut Config) -> Result<MakeRustlsConnect, TlsError> {
+        let mode = match self.mode {
+            Some(mode) => mode,
+            None if config.get_ssl_mode() == SslMode::Require => SslMode::Require,
+            None => SslMode::Disable,
+        };
+        config.ssl_mode(mode);
+
+        let roots = match mode {
+            // Never used, so there's no need to load any.
+            SslMode::Disable => RootCertStore::empty(),
+            _ => self.roots()?,
+        };
+        Ok(make_tls(roots))
+    }
+
+    fn roots(&self) -> Result<RootCertStore, TlsError> {
+        let mut roots = RootCertStore::empty();
+
+        let Some(path) = &self.root_cert else {
+            let native = rustls_native_certs::load_native_certs();
+            for err in &native.errors {
+                tracing::warn!("failed to load a system root certificate: {err}");
+            }
+            roots.add_parsable_certificates(native.certs);
+            return Ok(roots);
+        };
+
+        let certs = CertificateDer::pem_file_iter(path)
+            .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+            .map_err(|err| TlsError::RootCert {
+                path: path.clone(),
+                reason: err.to_string(),
+            })?;
+        if certs.is_empty() {
+            return Err(TlsError::RootCert {
+                path: path.clone(),
+                reason: "no certificates found".to_owned(),
+            });
+        }
+        for cert in certs {
+            roots.add(cert).map_err(|err| TlsError::RootCert {
+                path: path.clone(),
+                reason: err.to_string(),
+            })?;
+        }
+        Ok(roots)
+    }
+}
+
+/// A connector that trusts `roots`.
+fn make_tls(roots: RootCertStore) -> MakeRustlsConnect {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let config = ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .expect("the ring provider supports the default versions")
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    MakeRustlsConnect::new(config)
+}
+
+#[derive(Debug)]
+pub enum TlsError {
+    InvalidMode(String),
+    RootCert { path: PathBuf, reason: String },
+}
+
+impl fmt::Display for TlsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidMode(mode) => write!(
+                f,
+                "invalid PGSSLMODE `{mode}`, expected `disable` or `require`"
+            ),
+            Self::RootCert { path, reason } => write!(
+                f,
+                "failed to load CA certificates from PGSSLROOTCERT `{}`: {reason}",
+                path.display()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TlsError {}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    #[test]
+    fn parses_the_mode() {
+        for (value, mode) in [
+            (None, None),
+            (Some(""), None),
+            (Some("disable"), Some(SslMode::Disable)),
+            (Some("require"), Some(SslMode::Require)),
+        ] {
+            let settings = TlsSettings::parse(value, None).unwrap();
+            assert_eq!(settings.mode, mode, "{value:?}");
+        }
+
+        for value in ["prefer", "verify-full", "REQUIRE"] {
+            let err = TlsSettings::parse(Some(value), None).unwrap_err();
+            assert!(matches!(&err, TlsError::InvalidMode(mode) if mode == value));
+        }
+    }
+
+    #[test]
+    fn parses_the_root_cert() {
+        let settings = TlsSettings::parse(None, Some("/etc/ca.pem")).unwrap();
+        assert_eq!(settings.root_cert, Some(PathBuf::from("/etc/ca.pem")));
+
+        let settings = TlsSettings::parse(None, Some("")).unwrap();
+        assert_eq!(settings.root_cert, None);
+    }
+
+    #[test]
+    fn combines_with_the_connection_string() {
+        let cases = [
+            ("host=db", None, SslMode::Disable),
+            ("host=db sslmode=prefer", None, SslMode::Disable),
+            ("host=db sslmode=require", None, SslMode::Require),
+            ("host=db", Some(SslMode::Require), SslMode::Require),
+            (
+                "host=db sslmode=require",
+                Some(SslMode::Disable),
+                SslMode::Disable,
+            ),
+        ];
+        for (conn_str, mode, expected) in cases {
+            let mut config = Config::from_str(conn_str).unwrap();
+            let settings = TlsSettings {
+                mode,
+                root_cert: None,
+            };
+            settings.apply(&mut config).unwrap();
+            assert_eq!(config.get_ssl_mode(), expected, "{conn_str} {mode:?}");
+        }
+    }
+
+    #[test]
+    fn bad_root_certs_are_reported() {
+        let dir = std::env::temp_dir().join(format!("tls-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let empty = dir.join("empty.pem");
+        std::fs::write(&empty, "not a certificate").unwrap();
+
+        for path in [dir.join("missing.pem"), empty] {
+            let settings = TlsSettings {
+                mode: Some(SslMode::Require),
+                root_cert: Some(path.clone()),
+            };
+            let err = settings.apply(&mut Config::new()).map(|_| ()).unwrap_err();
+            assert!(
+                err.to_string()
+                    .starts_with("failed to load CA certificates from PGSSLROOTCERT"),
+                "{err}"
+            );
+            assert!(err.to_string().contains(&*path.to_string_lossy()), "{err}");
+        }
+
+        // Not loaded when TLS is off.
+        let settings = TlsSettings {
+            mode: Some(SslMode::Disable),
+            root_cert: Some(dir.join("missing.pem")),
+        };
+        assert!(settings.apply(&mut Config::new()).is_ok());
+    }
+}
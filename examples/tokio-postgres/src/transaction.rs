@@ -0,0 +1,142 @@
+use std::ops::Deref;
+
+use axum::{
+    async_trait,
+    extract::{FromRef, FromRequestParts},
+    http::{request::Parts, StatusCode},
+    response::{IntoResponse, Response},
+};
+use bb8::{PooledConnection, RunError};
+use tokio_postgres::Client;
+
+use crate::{ConnectionManager, ConnectionPool};
+
+/// A pooled connection with a transaction open on it.
+///
+/// Queries run through `Deref` to the `Client`. The transaction is committed
+/// with `commit`, and rolled back if this is dropped without that, e.g. when
+/// the handler returns early with an error.
+pub struct DatabaseTransaction {
+    /// Only `None` once committed.
+    conn: Option<PooledConnection<'static, ConnectionManager>>,
+}
+
+impl DatabaseTransaction {
+    pub async fn commit(mut self) -> Result<(), tokio_postgres::Error> {
+        let conn = self.conn.take().unwrap();
+        // Postgres ends the transaction even if this fails, so there's
+        // nothing to roll back either way.
+        conn.batch_execute("COMMIT").await
+    }
+}
+
+impl Deref for DatabaseTransaction {
+    type Target = Client;
+
+    fn deref(&self) -> &Client {
+        self.conn.as_ref().unwrap()
+    }
+}
+
+impl Drop for DatabaseTransaction {
+    fn drop(&mut self) {
+        let Some(conn) = self.conn.take() else {
+            return;
+        };
+        // `Drop` can't wait for the rollback, so hand the connection to a
+        // task that does. It only goes back to the pool after that.
+        tokio::spawn(async move {
+            if let Err(err) = conn.batch_execute("ROLLBACK").await {
+                tracing::warn!("rolling back failed: {err}");
+            }
+        });
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for DatabaseTransaction
+where
+    ConnectionPool: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = TransactionRejection;
+
+    async fn from_request_parts(_parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let pool = ConnectionPool::from_ref(state);
+
+        let conn = pool.get_owned().await.map_err(TransactionRejection::Pool)?;
+        conn.batch_execute("BEGIN")
+            .await
+            .map_err(TransactionRejection::Begin)?;
+
+        Ok(Self { conn: Some(conn) })
+    }
+}
+
+#[derive(Debug)]
+pub enum TransactionRejection {
+    /// No connection could be checked out.
+    Pool(RunError<tokio_postgres::Error>),
+    /// `BEGIN` failed.
+    Begin(tokio_postgres::Error),
+}
+
+impl IntoResponse for TransactionRejection {
+    fn into_response(self) -> Response {
+        match self {
+            Self::Pool(RunError::TimedOut) => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "no database connection available".to_owned(),
+            ),
+            Self::Pool(RunError::User(err)) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("failed to connect to the database: {err}"),
+            ),
+            Self::Begin(err) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("failed to begin a transaction: {err}"),
+            ),
+        }
+        .into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use axum::{body::Body, http::Request, routing::post, Router};
+    use bb8::Pool;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::test_support::test_pool_with;
+
+    #[tokio::test]
+    async fn pool_exhaustion_is_unavailable() {
+        let builder = Pool::builder()
+            .max_size(1)
+            .connection_timeout(Duration::from_millis(100));
+        let Some(pool) = test_pool_with(builder).await else {
+            return;
+        };
+        let app = Router::new()
+            .route("/", post(|_tx: DatabaseTransaction| async {}))
+            .with_state(pool.clone());
+
+        let held = pool.get().await.unwrap();
+        let response = app
+            .clone()
+            .oneshot(Request::post("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        drop(held);
+        let response = app
+            .oneshot(Request::post("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
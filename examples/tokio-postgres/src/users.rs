@@ -0,0 +1,441 @@
+use std::io;
+
+use axum::{
+    body::{Body, Bytes},
+    extract::{Path, Query},
+    http::{header, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use futures::{stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio_postgres::Row;
+
+use crate::{
+    error::{api_error, db_error_to_response, ApiError},
+    pools::{ReadConnection, WriteConnection},
+};
+
+/// Run once at startup.
+pub const CREATE_TABLE: &str = "
+    CREATE TABLE IF NOT EXISTS users (
+        id serial PRIMARY KEY,
+        name text NOT NULL,
+        email text UNIQUE
+    )
+";
+
+const DEFAULT_LIMIT: u32 = 20;
+const MAX_LIMIT: u32 = 100;
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct User {
+    pub id: i32,
+    pub name: String,
+    pub email: Option<String>,
+}
+
+/// Expects the `id`, `name` and `email` columns.
+impl TryFrom<Row> for User {
+    type Error = tokio_postgres::Error;
+
+    fn try_from(row: Row) -> Result<Self, Self::Error> {
+        Ok(Self {
+            id: row.try_get("id")?,
+            name: row.try_get("name")?,
+            email: row.try_get("email")?,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateUser {
+    name: String,
+    email: Option<String>,
+}
+
+pub async fn create_user(
+    WriteConnection(conn): WriteConnection,
+    Json(user): Json<CreateUser>,
+) -> Result<(StatusCode, Json<User>), ApiError> {
+    let row = conn
+        .query_one(
+            "INSERT INTO users (name, email) VALUES ($1, $2) RETURNING id, name, email",
+            &[&user.name, &user.email],
+        )
+        .await
+        .map_err(db_error_to_response)?;
+    let user = User::try_from(row).map_err(db_error_to_response)?;
+
+    Ok((StatusCode::CREATED, Json(user)))
+}
+
+pub async fn get_user(
+    ReadConnection(conn): ReadConnection,
+    Path(id): Path<i32>,
+) -> Result<Json<User>, ApiError> {
+    let row = conn
+        .query_opt("SELECT id, name, email FROM users WHERE id = $1", &[&id])
+        .await
+        .map_err(db_error_to_response)?
+        .ok_or_else(|| not_found(id))?;
+    let user = User::try_from(row).map_err(db_error_to_response)?;
+
+    Ok(Json(user))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListParams {
+    limit: Option<u32>,
+    offset: Option<u32>,
+}
+
+/// `GET /users?limit=&offset=`, ordered by id. The limit defaults to 20 and is
+/// clamped to 100.
+pub async fn list_users(
+    ReadConnection(conn): ReadConnection,
+    Query(params): Query<ListParams>,
+) -> Result<Json<Vec<User>>, ApiError> {
+    let limit = i64::from(params.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT));
+    let offset = i64::from(params.offset.unwrap_or(0));
+
+    let rows = conn
+        .query(
+            "SELECT id, name, email FROM users ORDER BY id LIMIT $1 OFFSET $2",
+            &[&limit, &offset],
+        )
+        .await
+        .map_err(db_error_to_response)?;
+    let users = rows
+        .into_iter()
+        .map(User::try_from)
+        .collect::<Result<_, _>>()
+        .map_err(db_error_to_response)?;
+
+    Ok(Json(users))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PageParams {
+    /// Unsigned, so a negative cursor is rejected along with a non-numeric one.
+    after_id: Option<u32>,
+    limit: Option<u32>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UsersPage {
+    pub items: Vec<User>,
+    /// The `after_id` for the next page, `null` once there isn't one.
+    pub next_after_id: Option<i32>,
+}
+
+/// `GET /users/page?after_id=&limit=`, the users with an id greater than
+/// `after_id`. Unlike an offset, the cursor doesn't skip or repeat users when
+/// others are inserted or deleted in between pages.
+pub async fn users_page(
+    ReadConnection(conn): ReadConnection,
+    Query(params): Query<PageParams>,
+) -> Result<Json<UsersPage>, ApiError> {
+    let limit = params.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT);
+    // Ids are `int4`, so there's nothing after one that doesn't fit.
+    let after_id = i32::try_from(params.after_id.unwrap_or(0)).unwrap_or(i32::MAX);
+
+    let rows = conn
+        .query(
+            "SELECT id, name, email FROM users WHERE id > $1 ORDER BY id ASC LIMIT $2",
+            &[&after_id, &i64::from(limit)],
+        )
+        .await
+        .map_err(db_error_to_response)?;
+    let items: Vec<User> = rows
+        .into_iter()
+        .map(User::try_from)
+        .collect::<Result<_, _>>()
+        .map_err(db_error_to_response)?;
+
+    let next_after_id = match items.last() {
+        Some(last) if items.len() == limit as usize => Some(last.id),
+        _ => None,
+    };
+    Ok(Json(UsersPage {
+        items,
+        next_after_id,
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ExportParams {
+    limit: Option<u32>,
+}
+
+/// `GET /users/export?limit=`, every user ordered by id as newline-delimited
+/// JSON. Rows are sent as they arrive from Postgres rather than collected
+/// first, so the export can be any size.
+pub async fn export_users(
+    ReadConnection(conn): ReadConnection,
+    Query(params): Query<ExportParams>,
+) -> Result<impl IntoResponse, ApiError> {
+    // `LIMIT NULL` is no limit.
+    let limit = params.limit.map(i64::from);
+    let rows = conn
+        .query_raw(
+            "SELECT id, name, email FROM users ORDER BY id LIMIT $1",
+            [limit],
+        )
+        .await
+        .map_err(db_error_to_response)?;
+
+    // The connection is moved into the stream so it's only returned to the
+    // pool once the rows have all been read, or the client has gone away.
+    let lines = stream::unfold(Some((Box::pin(rows), conn)), |state| async move {
+        let (mut rows, conn) = state?;
+        match rows.next().await? {
+            Ok(row) => match ndjson_line(row) {
+                Ok(line) => Some((Ok(line), Some((rows, conn)))),
+                Err(err) => Some((Err(err), None)),
+            },
+            Err(err) => {
+                tracing::error!("exporting users failed: {err}");
+                Some((Err(io::Error::other(err)), None))
+            }
+        }
+    });
+
+    // The headers have been sent by the time anything can go wrong, so errors
+    // end the body early instead, which the client sees as a broken transfer.
+    Ok((
+        [(header::CONTENT_TYPE, "application/x-ndjson")],
+        Body::from_stream(lines),
+    ))
+}
+
+fn ndjson_line(row: Row) -> Result<Bytes, io::Error> {
+    let user = User::try_from(row).map_err(|err| {
+        tracing::error!("exporting users failed: {err}");
+        io::Error::other(err)
+    })?;
+    let mut line = serde_json::to_vec(&user).map_err(|err| {
+        tracing::error!("exporting users failed: {err}");
+        io::Error::other(err)
+    })?;
+    line.push(b'\n');
+    Ok(line.into())
+}
+
+pub async fn delete_user(
+    WriteConnection(conn): WriteConnection,
+    Path(id): Path<i32>,
+) -> Result<StatusCode, ApiError> {
+    let deleted = conn
+        .execute("DELETE FROM users WHERE id = $1", &[&id])
+        .await
+        .map_err(db_error_to_response)?;
+
+    if deleted == 0 {
+        return Err(not_found(id));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+fn not_found(id: i32) -> ApiError {
+    api_error(StatusCode::NOT_FOUND, format!("user {id} not found"))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{Method, Request};
+    use http_body_util::BodyExt;
+    use serde_json::{json, Value};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{
+        app,
+        test_support::{send, test_pool},
+    };
+
+    #[tokio::test]
+    async fn create_get_delete() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let app = app(pool.into());
+
+        let (status, created) = send(
+            &app,
+            Method::POST,
+            "/users",
+            json!({"name": "Ann", "email": "ann@example.com"}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+        let created: User = serde_json::from_value(created).unwrap();
+        assert_eq!(created.name, "Ann");
+        assert_eq!(created.email.as_deref(), Some("ann@example.com"));
+
+        let uri = format!("/users/{}", created.id);
+        let (status, fetched) = send(&app, Method::GET, &uri, Value::Null).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(serde_json::from_value::<User>(fetched).unwrap(), created);
+
+        let (status, _) = send(&app, Method::DELETE, &uri, Value::Null).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+
+        let (status, _) = send(&app, Method::GET, &uri, Value::Null).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = send(&app, Method::DELETE, &uri, Value::Null).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn duplicate_emails_conflict() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let app = app(pool.into());
+
+        let user = json!({"name": "Ann", "email": "ann@example.com"});
+        let (status, _) = send(&app, Method::POST, "/users", user.clone()).await;
+        assert_eq!(status, StatusCode::CREATED);
+
+        let (status, body) = send(&app, Method::POST, "/users", user).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body["constraint"], "users_email_key");
+        assert!(body["error"].as_str().unwrap().contains("duplicate key"));
+    }
+
+    #[tokio::test]
+    async fn list_with_limit_and_offset() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let app = app(pool.into());
+
+        for name in ["a", "b", "c", "d"] {
+            let (status, _) = send(&app, Method::POST, "/users", json!({ "name": name })).await;
+            assert_eq!(status, StatusCode::CREATED);
+        }
+
+        let (status, users) = send(&app, Method::GET, "/users?limit=2&offset=1", Value::Null).await;
+        assert_eq!(status, StatusCode::OK);
+        let names: Vec<_> = users
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|user| user["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, ["b", "c"]);
+
+        let (status, _) = send(&app, Method::GET, "/users?offset=-1", Value::Null).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn export_streams_one_user_per_line() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        pool.get()
+            .await
+            .unwrap()
+            .execute(
+                "INSERT INTO users (name) SELECT 'user ' || n FROM generate_series(1, 300) n",
+                &[],
+            )
+            .await
+            .unwrap();
+        let app = app(pool.into());
+
+        for (uri, count) in [("/users/export", 300), ("/users/export?limit=10", 10)] {
+            let response = app
+                .clone()
+                .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK, "{uri}");
+            assert_eq!(
+                response.headers()[header::CONTENT_TYPE],
+                "application/x-ndjson"
+            );
+
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            let body = std::str::from_utf8(&body).unwrap();
+            assert!(body.ends_with('\n'));
+            let users: Vec<User> = body
+                .lines()
+                .map(|line| serde_json::from_str(line).unwrap())
+                .collect();
+            assert_eq!(users.len(), count, "{uri}");
+            assert_eq!(users[0].name, "user 1");
+            assert_eq!(users[count - 1].name, format!("user {count}"));
+        }
+    }
+
+    #[tokio::test]
+    async fn pages_cover_every_user_once() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let conn = pool.get().await.unwrap();
+        conn.batch_execute(
+            "INSERT INTO users (name) SELECT 'user ' || n FROM generate_series(1, 50) n;
+             DELETE FROM users WHERE id % 3 = 0 OR id BETWEEN 20 AND 30;",
+        )
+        .await
+        .unwrap();
+        let expected: Vec<i32> = conn
+            .query("SELECT id FROM users ORDER BY id", &[])
+            .await
+            .unwrap()
+            .iter()
+            .map(|row| row.get(0))
+            .collect();
+        let app = app(pool.clone().into());
+
+        let mut seen = Vec::new();
+        let mut uri = "/users/page?limit=7".to_owned();
+        loop {
+            let (status, page) = send(&app, Method::GET, &uri, Value::Null).await;
+            assert_eq!(status, StatusCode::OK, "{uri}");
+            let page: UsersPage = serde_json::from_value(page).unwrap();
+            assert!(page.items.len() <= 7);
+            seen.extend(page.items.iter().map(|user| user.id));
+
+            let Some(after_id) = page.next_after_id else {
+                break;
+            };
+            uri = format!("/users/page?limit=7&after_id={after_id}");
+        }
+        assert_eq!(seen, expected);
+
+        for uri in ["/users/page?after_id=-1", "/users/page?after_id=abc"] {
+            let (status, _) = send(&app, Method::GET, uri, Value::Null).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{uri}");
+        }
+        let (status, page) = send(
+            &app,
+            Method::GET,
+            "/users/page?after_id=4294967295",
+            Value::Null,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(page, json!({"items": [], "next_after_id": null}));
+    }
+
+    #[tokio::test]
+    async fn parameters_are_not_interpolated() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let app = app(pool.into());
+
+        let name = "Robert'); DROP TABLE users; --";
+        let (status, created) = send(&app, Method::POST, "/users", json!({ "name": name })).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(created["name"], name);
+
+        let (status, _) = send(&app, Method::GET, "/users", Value::Null).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+}
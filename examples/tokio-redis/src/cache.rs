@@ -0,0 +1,356 @@
+use std::{sync::Arc, time::Duration};
+
+use axum::{
+    async_trait,
+    extract::{Path, State},
+    http::header,
+    response::{IntoResponse, Response},
+    routing::get,
+    BoxError, Router,
+};
+use rand::Rng;
+use redis::AsyncCommands;
+use serde::Serialize;
+use tokio::time::Instant;
+use uuid::Uuid;
+
+use crate::{lock::LockStore, ConnectionPool};
+
+/// How long results are cached for, before jitter.
+const CACHE_TTL: Duration = Duration::from_secs(60);
+
+/// Up to this many seconds are added to `CACHE_TTL`, so entries cached at the
+/// same time don't all expire, and get recomputed, at the same time.
+const MAX_JITTER_SECS: u64 = 10;
+
+/// Expiry of the lock taken while computing, in case its holder never
+/// releases it. A computation can outlast it, so the lock is only released
+/// with the token it was taken with.
+const LOCK_TTL: Duration = Duration::from_secs(5);
+
+/// How often, and for how long, requests that didn't get the lock check
+/// whether the result has been cached before computing it themselves.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+const POLL_FOR: Duration = Duration::from_secs(2);
+
+/// Where computed results are cached.
+#[async_trait]
+pub trait Cache: Send + Sync {
+    async fn get(&self, key: &str) -> Result<Option<String>, BoxError>;
+
+    async fn set(&self, key: &str, value: &str, ttl: Duration) -> Result<(), BoxError>;
+}
+
+pub struct RedisCache {
+    pool: ConnectionPool,
+}
+
+impl RedisCache {
+    pub fn new(pool: ConnectionPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl Cache for RedisCache {
+    async fn get(&self, key: &str) -> Result<Option<String>, BoxError> {
+        let mut conn = self.pool.get().await?;
+        Ok(conn.get(key).await?)
+    }
+
+    async fn set(&self, key: &str, value: &str, ttl: Duration) -> Result<(), BoxError> {
+        let mut conn = self.pool.get().await?;
+        conn.set_ex::<_, _, ()>(key, value, ttl.as_secs()).await?;
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+struct CacheState {
+    cache: Arc<dyn Cache>,
+    locks: Arc<dyn LockStore>,
+    compute_time: Duration,
+}
+
+/// `GET /expensive/:id`, a slow computation taking `compute_time` whose
+/// results are cached in `cache`.
+///
+/// Responses have an `X-Cache` header saying whether they came from the
+/// cache. Concurrent requests for an uncached id only compute it once, the
+/// one holding its lock in `locks`.
+pub fn routes(
+    cache: impl Cache + 'static,
+    locks: impl LockStore + 'static,
+    compute_time: Duration,
+) -> Router {
+    let state = CacheState {
+        cache: Arc::new(cache),
+        locks: Arc::new(locks),
+        compute_time,
+    };
+    Router::new()
+        .route("/expensive/:id", get(expensive))
+        .with_state(state)
+}
+
+#[derive(Debug, Serialize)]
+struct Expensive {
+    id: u32,
+    square: u64,
+}
+
+async fn compute(id: u32, compute_time: Duration) -> String {
+    tokio::time::sleep(compute_time).await;
+    let result = Expensive {
+        id,
+        square: u64::from(id) * u64::from(id),
+    };
+    serde_json::to_string(&result).unwrap()
+}
+
+/// Cache failures aren't errors, the result is computed without the cache.
+async fn expensive(State(state): State<CacheState>, Path(id): Path<u32>) -> Response {
+    let cache = &*state.cache;
+    let key = format!("cache:expensive:{id}");
+
+    match cache.get(&key).await {
+        Ok(Some(json)) => return cached(json, true),
+        Ok(None) => {}
+        Err(err) => {
+            tracing::warn!("reading `{key}` from the cache failed: {err}");
+            return cached(compute(id, state.compute_time).await, false);
+        }
+    }
+
+    let lock = format!("{key}:lock");
+    let token = Uuid::new_v4().to_string();
+    match state.locks.try_acquire(&lock, &token, LOCK_TTL).await {
+        Ok(true) => {
+            let json = compute(id, state.compute_time).await;
+            let ttl =
+                CACHE_TTL + Duration::from_secs(rand::thread_rng().gen_range(0..=MAX_JITTER_SECS));
+            if let Err(err) = cache.set(&key, &json, ttl).await {
+                tracing::warn!("caching `{key}` failed: {err}");
+            }
+            match state.locks.release(&lock, &token).await {
+                Ok(true) => {}
+                Ok(false) => tracing::warn!("`{lock}` expired before computing `{key}` finished"),
+                Err(err) => tracing::warn!("releasing `{lock}` failed: {err}"),
+            }
+            cached(json, false)
+        }
+        Ok(false) => {
+            // Another request is computing it.
+            let give_up = Instant::now() + POLL_FOR;
+            while Instant::now() < give_up {
+                tokio::time::sleep(POLL_INTERVAL).await;
+                if let Ok(Some(json)) = cache.get(&key).await {
+                    return cached(json, true);
+                }
+            }
+            tracing::warn!("`{key}` wasn't cached in time, computing it again");
+            cached(compute(id, state.compute_time).await, false)
+        }
+        Err(err) => {
+            tracing::warn!("taking `{lock}` failed: {err}");
+            cached(compute(id, state.compute_time).await, false)
+        }
+    }
+}
+
+fn cached(json: String, hit: bool) -> Response {
+    (
+        [
+            (header::CONTENT_TYPE, "application/json"),
+            (
+                header::HeaderName::from_static("x-cache"),
+                if hit { "HIT" } else { "MISS" },
+            ),
+        ],
+        json,
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+    };
+    use futures::future::join_all;
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::test_support::{InMemoryCache, InMemoryLocks};
+
+    const COMPUTE_TIME: Duration = Duration::from_millis(100);
+
+    /// The status, `X-Cache` header and body of `GET uri`.
+    async fn get(app: &Router, uri: &str) -> (StatusCode, String, String) {
+        let response = app
+            .clone()
+            .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let x_cache = response
+            .headers()
+            .get("x-cache")
+            .map(|value| value.to_str().unwrap().to_owned())
+            .unwrap_or_default();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, x_cache, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn miss_then_hit() {
+        let cache = InMemoryCache::default();
+        let locks = InMemoryLocks::default();
+        let app = routes(cache.clone(), locks.clone(), COMPUTE_TIME);
+
+        let (status, x_cache, body) = get(&app, "/expensive/12").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(x_cache, "MISS");
+        assert_eq!(body, r#"{"id":12,"square":144}"#);
+
+        let ttl = cache.ttl("cache:expensive:12").unwrap();
+        assert!((CACHE_TTL..=CACHE_TTL + Duration::from_secs(MAX_JITTER_SECS)).contains(&ttl));
+        let lock = locks.remaining("cache:expensive:12:lock").await.unwrap();
+        assert_eq!(lock, None);
+
+        let start = Instant::now();
+        let (status, x_cache, cached) = get(&app, "/expensive/12").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(x_cache, "HIT");
+        assert_eq!(cached, body);
+        assert!(start.elapsed() < COMPUTE_TIME);
+    }
+
+    #[tokio::test]
+    async fn concurrent_misses_compute_once() {
+        let cache = InMemoryCache::default();
+        let app = routes(cache.clone(), InMemoryLocks::default(), COMPUTE_TIME);
+
+        let responses = join_all((0..5).map(|_| get(&app, "/expensive/7"))).await;
+
+        let misses = responses
+            .iter()
+            .filter(|(_, x_cache, _)| x_cache == "MISS")
+            .count();
+        assert_eq!(misses, 1, "{responses:?}");
+        for (status, _, body) in &responses {
+            assert_eq!(*status, StatusCode::OK);
+            assert_eq!(body, r#"{"id":7,"square":49}"#);
+        }
+        assert_eq!(cache.sets(), 1);
+    }
+
+    struct Unavailable;
+
+    #[async_trait]
+    impl Cache for Unavailable {
+        async fn get(&self, _key: &str) -> Result<Option<String>, BoxError> {
+            Err("connection refused".into())
+        }
+
+        async fn set(&self, _key: &str, _value: &str, _ttl: Duration) -> Result<(), BoxError> {
+            Err("connection refused".into())
+        }
+    }
+
+    #[async_trait]
+    impl LockStore for Unavailable {
+        async fn try_acquire(
+            &self,
+            _key: &str,
+            _token: &str,
+            _ttl: Duration,
+        ) -> Result<bool, BoxError> {
+            Err("connection refused".into())
+        }
+
+        async fn remaining(&self, _key: &str) -> Result<Option<Duration>, BoxError> {
+            Err("connection refused".into())
+        }
+
+        async fn release(&self, _key: &str, _token: &str) -> Result<bool, BoxError> {
+            Err("connection refused".into())
+        }
+    }
+
+    #[tokio::test]
+    async fn computes_without_the_cache() {
+        let app = routes(Unavailable, Unavailable, COMPUTE_TIME);
+
+        for _ in 0..2 {
+            let (status, x_cache, body) = get(&app, "/expensive/3").await;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(x_cache, "MISS");
+            assert_eq!(body, r#"{"id":3,"square":9}"#);
+        }
+    }
+
+    #[tokio::test]
+    async fn invalid_id_is_rejected() {
+        let app = routes(
+            InMemoryCache::default(),
+            InMemoryLocks::default(),
+            COMPUTE_TIME,
+        );
+
+        let (status, _, _) = get(&app, "/expensive/abc").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    /// Locks that expire long before the computation finishes.
+    struct ShortLived(InMemoryLocks);
+
+    #[async_trait]
+    impl LockStore for ShortLived {
+        async fn try_acquire(
+            &self,
+            key: &str,
+            token: &str,
+            _ttl: Duration,
+        ) -> Result<bool, BoxError> {
+            self.0
+                .try_acquire(key, token, Duration::from_millis(1))
+                .await
+        }
+
+        async fn remaining(&self, key: &str) -> Result<Option<Duration>, BoxError> {
+            self.0.remaining(key).await
+        }
+
+        async fn release(&self, key: &str, token: &str) -> Result<bool, BoxError> {
+            self.0.release(key, token).await
+        }
+    }
+
+    #[tokio::test]
+    async fn expired_lock_taken_by_another_holder_is_kept() {
+        let locks = InMemoryLocks::default();
+        let app = routes(
+            InMemoryCache::default(),
+            ShortLived(locks.clone()),
+            COMPUTE_TIME,
+        );
+        let key = "cache:expensive:5:lock";
+
+        let request = tokio::spawn(async move { get(&app, "/expensive/5").await });
+        tokio::time::sleep(COMPUTE_TIME / 4).await;
+        let ttl = Duration::from_secs(10);
+        assert!(locks.try_acquire(key, "other", ttl).await.unwrap());
+
+        let (status, x_cache, _) = request.await.unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(x_cache, "MISS");
+        assert!(
+            locks.remaining(key).await.unwrap().is_some(),
+            "released someone else's lock"
+        );
+        assert!(locks.release(key, "other").await.unwrap());
+    }
+}
@@ -0,0 +1,154 @@
+use std::{
+    fmt,
+    time::{Duration, Instant},
+};
+
+use bb8::{ManageConnection, Pool};
+use bb8_redis::RedisConnectionManager;
+use redis::RedisError;
+
+use crate::ConnectionPool;
+
+/// Delay before the second attempt, doubled for every attempt after that.
+const INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+
+const MAX_BACKOFF: Duration = Duration::from_secs(5);
+
+/// How long to wait after the failed attempt number `attempt`, counting from
+/// zero.
+pub fn backoff(attempt: u32) -> Duration {
+    INITIAL_BACKOFF
+        .saturating_mul(2u32.saturating_pow(attempt))
+        .min(MAX_BACKOFF)
+}
+
+/// Connect to the Redis at `url` and `PING` it, retrying with exponential
+/// backoff until `deadline` has passed.
+///
+/// Redis often isn't accepting connections yet when the app starts, e.g. when
+/// both are started by docker-compose.
+pub async fn connect_with_retry(
+    url: &str,
+    deadline: Duration,
+) -> Result<ConnectionPool, ConnectError> {
+    // A malformed URL won't get any better by retrying.
+    let manager = RedisConnectionManager::new(url).map_err(ConnectError::InvalidUrl)?;
+
+    let start = Instant::now();
+    let mut attempt = 0;
+    loop {
+        let remaining = deadline.saturating_sub(start.elapsed());
+        let err = match tokio::time::timeout(remaining, ping(&manager)).await {
+            Ok(Ok(())) => break,
+            Ok(Err(err)) => err.to_string(),
+            Err(_) => "timed out".to_owned(),
+        };
+        attempt += 1;
+
+        let remaining = deadline.saturating_sub(start.elapsed());
+        if remaining.is_zero() {
+            return Err(ConnectError::Deadline {
+                attempts: attempt,
+                deadline,
+                last_error: err,
+            });
+        }
+        let delay = backoff(attempt - 1).min(remaining);
+        tracing::warn!(
+            "connecting to redis failed (attempt {attempt}), retrying in {delay:?}: {err}"
+        );
+        tokio::time::sleep(delay).await;
+    }
+
+    // Doesn't connect, the pool opens connections as they're needed.
+    Ok(Pool::builder().build_unchecked(manager))
+}
+
+async fn ping(manager: &RedisConnectionManager) -> Result<(), RedisError> {
+    let mut conn = manager.connect().await?;
+    manager.is_valid(&mut conn).await
+}
+
+#[derive(Debug)]
+pub enum ConnectError {
+    InvalidUrl(RedisError),
+    Deadline {
+        attempts: u32,
+        deadline: Duration,
+        last_error: String,
+    },
+}
+
+impl fmt::Display for ConnectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidUrl(err) => write!(f, "invalid REDIS_URL: {err}"),
+            Self::Deadline {
+                attempts,
+                deadline,
+                last_error,
+            } => write!(
+                f,
+                "couldn't connect to redis within {deadline:?} ({attempts} attempts), \
+                 last error: {last_error}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ConnectError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reserved for documentation, so nothing answers there.
+    const UNROUTABLE: &str = "redis://192.0.2.1:6379";
+
+    #[test]
+    fn backoff_doubles_up_to_the_maximum() {
+        let schedule: Vec<_> = (0..8).map(|attempt| backoff(attempt).as_millis()).collect();
+        assert_eq!(schedule, [100, 200, 400, 800, 1600, 3200, 5000, 5000]);
+        assert_eq!(backoff(u32::MAX), MAX_BACKOFF);
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_the_deadline() {
+        let deadline = Duration::from_millis(300);
+        let start = Instant::now();
+
+        let Err(err) = connect_with_retry(UNROUTABLE, deadline).await else {
+            panic!("connected to {UNROUTABLE}");
+        };
+
+        let elapsed = start.elapsed();
+        assert!(elapsed >= deadline, "{elapsed:?}");
+        assert!(elapsed < deadline + Duration::from_secs(1), "{elapsed:?}");
+        assert!(
+            matches!(err, ConnectError::Deadline { attempts, .. } if attempts >= 1),
+            "{err}"
+        );
+        assert!(err
+            .to_string()
+            .starts_with("couldn't connect to redis within 300ms"));
+    }
+
+    #[tokio::test]
+    async fn zero_deadline_makes_one_attempt() {
+        let Err(err) = connect_with_retry(UNROUTABLE, Duration::ZERO).await else {
+            panic!("connected to {UNROUTABLE}");
+        };
+        assert!(
+            matches!(err, ConnectError::Deadline { attempts: 1, .. }),
+            "{err}"
+        );
+    }
+
+    #[tokio::test]
+    async fn invalid_url_is_not_retried() {
+        let Err(err) = connect_with_retry("http://localhost", Duration::from_secs(60)).await else {
+            panic!("accepted an http URL");
+        };
+        assert!(matches!(err, ConnectError::InvalidUrl(_)), "{err}");
+    }
+}
@@ -0,0 +1,278 @@
+use axum::{
+    async_trait,
+    extract::{rejection::JsonRejection, Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+use bb8::{ManageConnection, Pool};
+use redis::{aio::ConnectionLike, AsyncCommands, RedisResult};
+use serde::{Deserialize, Serialize};
+
+use crate::{internal_error, DatabaseConnection};
+
+/// Most leaderboard entries returned at once.
+const MAX_TOP: usize = 100;
+
+/// The Redis commands the counter and leaderboard routes need.
+#[async_trait]
+pub trait CounterStore: Send {
+    /// Add `by` to the counter `key`, returning the new value. Missing
+    /// counters start at zero.
+    async fn incr_by(&mut self, key: &str, by: i64) -> RedisResult<i64>;
+
+    async fn counter(&mut self, key: &str) -> RedisResult<Option<i64>>;
+
+    /// Set the score of `member` in the sorted set `key`.
+    async fn add_score(&mut self, key: &str, member: &str, score: f64) -> RedisResult<()>;
+
+    /// The `count` highest scoring members of `key`, highest first.
+    async fn top(&mut self, key: &str, count: usize) -> RedisResult<Vec<(String, f64)>>;
+}
+
+#[async_trait]
+impl<C> CounterStore for C
+where
+    C: ConnectionLike + Send,
+{
+    async fn incr_by(&mut self, key: &str, by: i64) -> RedisResult<i64> {
+        self.incr(key, by).await
+    }
+
+    async fn counter(&mut self, key: &str) -> RedisResult<Option<i64>> {
+        self.get(key).await
+    }
+
+    async fn add_score(&mut self, key: &str, member: &str, score: f64) -> RedisResult<()> {
+        self.zadd(key, member, score).await
+    }
+
+    async fn top(&mut self, key: &str, count: usize) -> RedisResult<Vec<(String, f64)>> {
+        // The stop index is inclusive, and -1 would mean the whole set.
+        if count == 0 {
+            return Ok(Vec::new());
+        }
+        self.zrevrange_withscores(key, 0, count as isize - 1).await
+    }
+}
+
+fn counter_key(name: &str) -> String {
+    format!("counter:{name}")
+}
+
+fn leaderboard_key(board: &str) -> String {
+    format!("leaderboard:{board}")
+}
+
+#[derive(Debug, Deserialize)]
+pub struct IncrParams {
+    by: Option<u64>,
+}
+
+/// `POST /counter/:name/incr?by=n`, responding with the new value.
+pub async fn incr<M>(
+    DatabaseConnection(mut conn): DatabaseConnection<M>,
+    Path(name): Path<String>,
+    Query(params): Query<IncrParams>,
+) -> Result<String, (StatusCode, String)>
+where
+    M: ManageConnection,
+    M::Connection: CounterStore,
+{
+    let by = i64::try_from(params.by.unwrap_or(1))
+        .map_err(|_| (StatusCode::BAD_REQUEST, "`by` is too large".to_owned()))?;
+
+    let value = conn
+        .incr_by(&counter_key(&name), by)
+        .await
+        .map_err(internal_error)?;
+    Ok(value.to_string())
+}
+
+/// `GET /counter/:name`, zero if it was never incremented.
+pub async fn get_counter<M>(
+    State(pool): State<Pool<M>>,
+    Path(name): Path<String>,
+) -> Result<String, (StatusCode, String)>
+where
+    M: ManageConnection,
+    M::Connection: CounterStore,
+    M::Error: std::error::Error,
+{
+    let mut conn = pool.get().await.map_err(internal_error)?;
+    let value = conn
+        .counter(&counter_key(&name))
+        .await
+        .map_err(internal_error)?;
+    Ok(value.unwrap_or(0).to_string())
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct Entry {
+    member: String,
+    score: f64,
+}
+
+/// `POST /leaderboard/:board` with `{"member": ..., "score": ...}`, replacing
+/// any previous score of the member.
+pub async fn add_score<M>(
+    DatabaseConnection(mut conn): DatabaseConnection<M>,
+    Path(board): Path<String>,
+    entry: Result<Json<Entry>, JsonRejection>,
+) -> Result<StatusCode, (StatusCode, String)>
+where
+    M: ManageConnection,
+    M::Connection: CounterStore,
+{
+    // A score of the wrong type would be a 422 otherwise.
+    let Json(entry) =
+        entry.map_err(|rejection| (StatusCode::BAD_REQUEST, rejection.body_text()))?;
+    if entry.score < 0.0 {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "`score` can't be negative".to_owned(),
+        ));
+    }
+
+    conn.add_score(&leaderboard_key(&board), &entry.member, entry.score)
+        .await
+        .map_err(internal_error)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TopParams {
+    top: Option<usize>,
+}
+
+/// `GET /leaderboard/:board?top=n`, the `n` best entries, highest score
+/// first. `n` defaults to 10 and is clamped to 1 through 100.
+pub async fn top<M>(
+    State(pool): State<Pool<M>>,
+    Path(board): Path<String>,
+    Query(params): Query<TopParams>,
+) -> Result<Json<Vec<Entry>>, (StatusCode, String)>
+where
+    M: ManageConnection,
+    M::Connection: CounterStore,
+    M::Error: std::error::Error,
+{
+    let count = params.top.unwrap_or(10).clamp(1, MAX_TOP);
+
+    let mut conn = pool.get().await.map_err(internal_error)?;
+    let top = conn
+        .top(&leaderboard_key(&board), count)
+        .await
+        .map_err(internal_error)?;
+    Ok(Json(
+        top.into_iter()
+            .map(|(member, score)| Entry { member, score })
+            .collect(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::Method;
+    use bb8_redis::RedisConnectionManager;
+    use serde_json::{json, Value};
+
+    use crate::test_support::{live_redis_url, send, send_json, test_app, InMemoryRedis};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn counters() {
+        let app = test_app(&InMemoryRedis::default()).await;
+
+        let (status, body) = send(&app, Method::GET, "/counter/hits", "").await;
+        assert_eq!((status, body.as_str()), (StatusCode::OK, "0"));
+
+        let (_, body) = send(&app, Method::POST, "/counter/hits/incr", "").await;
+        assert_eq!(body, "1");
+        let (_, body) = send(&app, Method::POST, "/counter/hits/incr?by=5", "").await;
+        assert_eq!(body, "6");
+
+        let (_, body) = send(&app, Method::GET, "/counter/hits", "").await;
+        assert_eq!(body, "6");
+    }
+
+    #[tokio::test]
+    async fn invalid_increments_are_rejected() {
+        let app = test_app(&InMemoryRedis::default()).await;
+
+        for by in ["-1", "one", "9223372036854775808"] {
+            let uri = format!("/counter/hits/incr?by={by}");
+            let (status, _) = send(&app, Method::POST, &uri, "").await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{by}");
+        }
+        let (_, body) = send(&app, Method::GET, "/counter/hits", "").await;
+        assert_eq!(body, "0");
+    }
+
+    #[tokio::test]
+    async fn leaderboard() {
+        let app = test_app(&InMemoryRedis::default()).await;
+
+        for (member, score) in [("ann", 10.0), ("bob", 30.5), ("cy", 20.0), ("ann", 40.0)] {
+            let entry = json!({"member": member, "score": score});
+            let (status, _) = send_json(&app, "/leaderboard/game", entry).await;
+            assert_eq!(status, StatusCode::NO_CONTENT);
+        }
+
+        let (status, body) = send(&app, Method::GET, "/leaderboard/game?top=2", "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            serde_json::from_str::<Value>(&body).unwrap(),
+            json!([{"member": "ann", "score": 40.0}, {"member": "bob", "score": 30.5}])
+        );
+
+        let (_, body) = send(&app, Method::GET, "/leaderboard/game?top=0", "").await;
+        let top: Vec<Entry> = serde_json::from_str(&body).unwrap();
+        assert_eq!(top.len(), 1);
+        let (_, body) = send(&app, Method::GET, "/leaderboard/game?top=1000", "").await;
+        let top: Vec<Entry> = serde_json::from_str(&body).unwrap();
+        assert_eq!(top.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn invalid_scores_are_rejected() {
+        let app = test_app(&InMemoryRedis::default()).await;
+
+        for entry in [
+            json!({"member": "ann", "score": -1}),
+            json!({"member": "ann", "score": "high"}),
+            json!({"member": "ann"}),
+        ] {
+            let (status, _) = send_json(&app, "/leaderboard/game", entry.clone()).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{entry}");
+        }
+        let (_, body) = send(&app, Method::GET, "/leaderboard/game", "").await;
+        assert_eq!(body, "[]");
+    }
+
+    #[tokio::test]
+    async fn redis_counters_and_leaderboards() {
+        let Some(url) = live_redis_url() else {
+            return;
+        };
+        let manager = RedisConnectionManager::new(url).unwrap();
+        let pool = Pool::builder().build(manager).await.unwrap();
+        let mut conn = pool.get().await.unwrap();
+        let counter = format!("counter:test:{}", std::process::id());
+        let board = format!("leaderboard:test:{}", std::process::id());
+
+        assert_eq!(conn.counter(&counter).await.unwrap(), None);
+        assert_eq!(conn.incr_by(&counter, 2).await.unwrap(), 2);
+        assert_eq!(conn.incr_by(&counter, 3).await.unwrap(), 5);
+        assert_eq!(conn.counter(&counter).await.unwrap(), Some(5));
+
+        conn.add_score(&board, "ann", 1.0).await.unwrap();
+        conn.add_score(&board, "bob", 2.5).await.unwrap();
+        assert_eq!(
+            conn.top(&board, 10).await.unwrap(),
+            [("bob".to_owned(), 2.5), ("ann".to_owned(), 1.0)]
+        );
+
+        conn.del::<_, ()>(&[counter, board]).await.unwrap();
+    }
+}
@@ -0,0 +1,162 @@
+use std::{convert::Infallible, time::Duration};
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::sse::{Event, KeepAlive, Sse},
+    routing::{get, post},
+    Router,
+};
+use futures::{Stream, StreamExt};
+use redis::AsyncCommands;
+
+use crate::{internal_error, ConnectionPool};
+
+/// Relaying Redis pub/sub to Server-Sent Events.
+///
+/// Publishing goes through the pool, but every subscriber gets a dedicated
+/// connection from `client` since a connection in subscriber mode can't run
+/// other commands.
+pub fn routes(client: redis::Client, pool: ConnectionPool) -> Router {
+    Router::new()
+        .route("/events/:channel", get(subscribe).with_state(client))
+        .route("/publish/:channel", post(publish).with_state(pool))
+}
+
+async fn subscribe(
+    State(client): State<redis::Client>,
+    Path(channel): Path<String>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, (StatusCode, String)> {
+    check_channel(&channel)?;
+
+    let mut pubsub = client.get_async_pubsub().await.map_err(internal_error)?;
+    pubsub.subscribe(&channel).await.map_err(internal_error)?;
+    tracing::debug!("subscribed to `{channel}`");
+
+    // The stream owns the connection, so it's closed when the client
+    // disconnects and the response body is dropped. It also ends when Redis
+    // closes the connection, which ends the response.
+    let events = pubsub.into_on_message().map(|msg| {
+        let data = sse_data(msg.get_payload_bytes());
+        Ok(Event::default().event(msg.get_channel_name()).data(data))
+    });
+
+    Ok(Sse::new(events).keep_alive(KeepAlive::new().interval(Duration::from_secs(15))))
+}
+
+/// Publish the body to `channel`, responding with the number of subscribers
+/// that received it.
+async fn publish(
+    State(pool): State<ConnectionPool>,
+    Path(channel): Path<String>,
+    message: String,
+) -> Result<String, (StatusCode, String)> {
+    check_channel(&channel)?;
+
+    let mut conn = pool.get().await.map_err(internal_error)?;
+    let receivers: u64 = conn
+        .publish(&channel, message)
+        .await
+        .map_err(internal_error)?;
+    Ok(receivers.to_string())
+}
+
+/// The payload as SSE data. `Event::data` panics on carriage returns, so
+/// they're turned into line breaks like the ones SSE clients would see.
+fn sse_data(payload: &[u8]) -> String {
+    String::from_utf8_lossy(payload)
+        .replace("\r\n", "\n")
+        .replace('\r', "\n")
+}
+
+/// The channel is used as the SSE event name, which can't span lines.
+fn check_channel(channel: &str) -> Result<(), (StatusCode, String)> {
+    if channel.contains(['\r', '\n']) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "channel names can't contain line breaks".to_owned(),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, http::Request};
+    use bb8::Pool;
+    use bb8_redis::RedisConnectionManager;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::test_support::live_redis_url;
+
+    async fn live_routes(url: &str) -> Router {
+        let client = redis::Client::open(url).unwrap();
+        let manager = RedisConnectionManager::new(url).unwrap();
+        let pool = Pool::builder().build(manager).await.unwrap();
+        routes(client, pool)
+    }
+
+    #[test]
+    fn carriage_returns_become_line_breaks() {
+        assert_eq!(sse_data(b"a\r\nb\rc\nd"), "a\nb\nc\nd");
+        // Doesn't panic.
+        let _ = Event::default().data(sse_data(b"a\r\nb\rc"));
+    }
+
+    #[tokio::test]
+    async fn channel_with_line_break_is_rejected() {
+        // Rejected before connecting, so this doesn't need Redis.
+        let app = live_routes("redis://127.0.0.1:1").await;
+
+        let response = app
+            .oneshot(Request::get("/events/a%0Ab").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn published_messages_are_relayed() {
+        let Some(url) = live_redis_url() else {
+            return;
+        };
+        let app = live_routes(&url).await;
+        let channel = format!("tokio-redis-test-{}", std::process::id());
+
+        // The subscription is made before the response starts.
+        let response = app
+            .clone()
+            .oneshot(
+                Request::get(format!("/events/{channel}"))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["content-type"], "text/event-stream");
+        let mut events = response.into_body().into_data_stream();
+
+        let response = app
+            .oneshot(
+                Request::post(format!("/publish/{channel}"))
+                    .body(Body::from("hello\nworld"))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let chunk = tokio::time::timeout(Duration::from_secs(5), events.next())
+            .await
+            .expect("no event within 5 seconds")
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            std::str::from_utf8(&chunk).unwrap(),
+            format!("event: {channel}\ndata: hello\ndata: world\n\n")
+        );
+    }
+}
@@ -0,0 +1,202 @@
+use std::time::{Duration, Instant};
+
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use serde::Serialize;
+
+use crate::ConnectionPool;
+
+/// The longest `/health` takes, even when every connection is checked out.
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+pub fn routes(pool: ConnectionPool) -> Router {
+    Router::new().route("/health", get(health)).with_state(pool)
+}
+
+#[derive(Debug, Serialize)]
+struct Healthy {
+    redis: &'static str,
+    latency_ms: u64,
+    pool: PoolStats,
+}
+
+#[derive(Debug, Serialize)]
+struct Unhealthy {
+    redis: &'static str,
+    error: Failure,
+    detail: String,
+    pool: PoolStats,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Failure {
+    /// No connection could be checked out in time.
+    PoolTimeout,
+    /// `PING` failed or timed out.
+    Command,
+}
+
+#[derive(Debug, Serialize)]
+struct PoolStats {
+    connections: u32,
+    idle: u32,
+}
+
+impl PoolStats {
+    fn of(pool: &ConnectionPool) -> Self {
+        let state = pool.state();
+        Self {
+            connections: state.connections,
+            idle: state.idle_connections,
+        }
+    }
+}
+
+/// `GET /health`, `PING` Redis through the pool.
+async fn health(State(pool): State<ConnectionPool>) -> Response {
+    let start = Instant::now();
+    match ping(&pool).await {
+        Ok(()) => Json(Healthy {
+            redis: "ok",
+            latency_ms: start.elapsed().as_millis() as u64,
+            pool: PoolStats::of(&pool),
+        })
+        .into_response(),
+        Err((error, detail)) => {
+            tracing::warn!("health check failed: {detail}");
+            let unhealthy = Unhealthy {
+                redis: "unavailable",
+                error,
+                detail,
+                pool: PoolStats::of(&pool),
+            };
+            (StatusCode::SERVICE_UNAVAILABLE, Json(unhealthy)).into_response()
+        }
+    }
+}
+
+async fn ping(pool: &ConnectionPool) -> Result<(), (Failure, String)> {
+    let deadline = tokio::time::Instant::now() + TIMEOUT;
+
+    // The pool's own timeout defaults to 30 seconds, too long for a health
+    // check.
+    let mut conn = match tokio::time::timeout_at(deadline, pool.get()).await {
+        Ok(Ok(conn)) => conn,
+        Ok(Err(err)) => return Err((Failure::PoolTimeout, err.to_string())),
+        Err(_) => {
+            let detail = format!("no connection available within {TIMEOUT:?}");
+            return Err((Failure::PoolTimeout, detail));
+        }
+    };
+
+    let ping = redis::cmd("PING");
+    match tokio::time::timeout_at(deadline, ping.query_async::<_, String>(&mut *conn)).await {
+        Ok(Ok(_)) => Ok(()),
+        Ok(Err(err)) => Err((Failure::Command, err.to_string())),
+        Err(_) => Err((Failure::Command, format!("no PONG within {TIMEOUT:?}"))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+    use axum::http::Request;
+    use bb8::Pool;
+    use bb8_redis::RedisConnectionManager;
+    use http_body_util::BodyExt;
+    use serde_json::{json, Value};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::test_support::live_redis_url;
+
+    #[test]
+    fn healthy_shape() {
+        let healthy = Healthy {
+            redis: "ok",
+            latency_ms: 3,
+            pool: PoolStats {
+                connections: 2,
+                idle: 1,
+            },
+        };
+        assert_eq!(
+            serde_json::to_value(healthy).unwrap(),
+            json!({"redis": "ok", "latency_ms": 3, "pool": {"connections": 2, "idle": 1}})
+        );
+    }
+
+    #[test]
+    fn unhealthy_shape() {
+        for (error, category) in [
+            (Failure::PoolTimeout, "pool_timeout"),
+            (Failure::Command, "command"),
+        ] {
+            let unhealthy = Unhealthy {
+                redis: "unavailable",
+                error,
+                detail: "connection refused".to_owned(),
+                pool: PoolStats {
+                    connections: 0,
+                    idle: 0,
+                },
+            };
+            assert_eq!(
+                serde_json::to_value(unhealthy).unwrap(),
+                json!({
+                    "redis": "unavailable",
+                    "error": category,
+                    "detail": "connection refused",
+                    "pool": {"connections": 0, "idle": 0},
+                })
+            );
+        }
+    }
+
+    async fn check(url: &str) -> (StatusCode, Value) {
+        let manager = RedisConnectionManager::new(url).unwrap();
+        let pool = Pool::builder().build_unchecked(manager);
+
+        let response = routes(pool)
+            .oneshot(Request::get("/health").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn unreachable_redis_times_out() {
+        let start = Instant::now();
+
+        // Reserved for documentation, so nothing answers there.
+        let (status, body) = check("redis://192.0.2.1:6379").await;
+
+        assert!(start.elapsed() < TIMEOUT + Duration::from_secs(1));
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["redis"], "unavailable");
+        assert_eq!(body["error"], "pool_timeout");
+    }
+
+    #[tokio::test]
+    async fn live_redis_is_healthy() {
+        let Some(url) = live_redis_url() else {
+            return;
+        };
+
+        let (status, body) = check(&url).await;
+
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(body["redis"], "ok");
+        assert!(body["latency_ms"].is_u64());
+        assert_eq!(body["pool"]["connections"], 1);
+        assert_eq!(body["pool"]["idle"], 1);
+    }
+}
@@ -0,0 +1,286 @@
+use std::{collections::BTreeMap, time::Instant};
+
+use axum::{
+    async_trait,
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+use bb8::{ManageConnection, Pool};
+use redis::{aio::ConnectionLike, AsyncCommands, RedisResult};
+use serde::Deserialize;
+
+use crate::{internal_error, DatabaseConnection};
+
+/// The Redis commands the key routes need. Implemented for every Redis
+/// connection, and for an in-memory fake in the tests.
+#[async_trait]
+pub trait KvStore: Send {
+    /// The value of `key`, or `None` if it doesn't exist.
+    async fn get_value(&mut self, key: &str) -> RedisResult<Option<String>>;
+
+    /// Set `key`, expiring it after `ttl` seconds if given.
+    async fn set_value(&mut self, key: &str, value: &str, ttl: Option<u64>) -> RedisResult<()>;
+
+    /// Delete `key`, returning whether it existed.
+    async fn delete(&mut self, key: &str) -> RedisResult<bool>;
+
+    /// The values of `keys` in the same order, `None` for the missing ones.
+    async fn get_values(&mut self, keys: &[String]) -> RedisResult<Vec<Option<String>>>;
+
+    /// Set all of `values` at once.
+    async fn set_values(&mut self, values: &[(String, String)]) -> RedisResult<()>;
+}
+
+#[async_trait]
+impl<C> KvStore for C
+where
+    C: ConnectionLike + Send,
+{
+    async fn get_value(&mut self, key: &str) -> RedisResult<Option<String>> {
+        // Missing keys are a nil reply, which only converts to `None`. Asking
+        // for a `String` would make them an error instead.
+        self.get(key).await
+    }
+
+    async fn set_value(&mut self, key: &str, value: &str, ttl: Option<u64>) -> RedisResult<()> {
+        let mut cmd = redis::cmd("SET");
+        cmd.arg(key).arg(value);
+        if let Some(ttl) = ttl {
+            cmd.arg("EX").arg(ttl);
+        }
+        cmd.query_async(self).await
+    }
+
+    async fn delete(&mut self, key: &str) -> RedisResult<bool> {
+        let deleted: u64 = self.del(key).await?;
+        Ok(deleted > 0)
+    }
+
+    async fn get_values(&mut self, keys: &[String]) -> RedisResult<Vec<Option<String>>> {
+        redis::cmd("MGET").arg(keys).query_async(self).await
+    }
+
+    async fn set_values(&mut self, values: &[(String, String)]) -> RedisResult<()> {
+        redis::cmd("MSET").arg(values).query_async(self).await
+    }
+}
+
+pub async fn get_key<M>(
+    State(pool): State<Pool<M>>,
+    Path(key): Path<String>,
+) -> Result<String, (StatusCode, String)>
+where
+    M: ManageConnection,
+    M::Connection: KvStore,
+    M::Error: std::error::Error,
+{
+    let mut conn = pool.get().await.map_err(internal_error)?;
+    conn.get_value(&key)
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(|| not_found(&key))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetParams {
+    ttl: Option<u64>,
+}
+
+pub async fn put_key<M>(
+    DatabaseConnection(mut conn): DatabaseConnection<M>,
+    Path(key): Path<String>,
+    Query(params): Query<SetParams>,
+    value: String,
+) -> Result<StatusCode, (StatusCode, String)>
+where
+    M: ManageConnection,
+    M::Connection: KvStore,
+{
+    // Redis rejects `EX 0`.
+    if params.ttl == Some(0) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "ttl must be at least 1 second".to_owned(),
+        ));
+    }
+
+    conn.set_value(&key, &value, params.ttl)
+        .await
+        .map_err(internal_error)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn delete_key<M>(
+    DatabaseConnection(mut conn): DatabaseConnection<M>,
+    Path(key): Path<String>,
+) -> Result<StatusCode, (StatusCode, String)>
+where
+    M: ManageConnection,
+    M::Connection: KvStore,
+{
+    if conn.delete(&key).await.map_err(internal_error)? {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(not_found(&key))
+    }
+}
+
+/// Most keys `mget` and `mset` take at once.
+const MAX_KEYS: usize = 100;
+
+/// `POST /kv/mget`, the values of a JSON array of keys in one round trip.
+/// Missing keys map to `null`.
+pub async fn mget<M>(
+    State(pool): State<Pool<M>>,
+    Json(keys): Json<Vec<String>>,
+) -> Result<Json<BTreeMap<String, Option<String>>>, (StatusCode, String)>
+where
+    M: ManageConnection,
+    M::Connection: KvStore,
+    M::Error: std::error::Error,
+{
+    check_count(keys.len())?;
+
+    let mut conn = pool.get().await.map_err(internal_error)?;
+    let start = Instant::now();
+    let values = conn.get_values(&keys).await.map_err(internal_error)?;
+    tracing::debug!("MGET of {} keys took {:?}", keys.len(), start.elapsed());
+
+    Ok(Json(keys.into_iter().zip(values).collect()))
+}
+
+/// `POST /kv/mset`, set every key in a JSON object in one round trip.
+pub async fn mset<M>(
+    State(pool): State<Pool<M>>,
+    Json(values): Json<BTreeMap<String, String>>,
+) -> Result<StatusCode, (StatusCode, String)>
+where
+    M: ManageConnection,
+    M::Connection: KvStore,
+    M::Error: std::error::Error,
+{
+    check_count(values.len())?;
+    let values: Vec<_> = values.into_iter().collect();
+
+    let mut conn = pool.get().await.map_err(internal_error)?;
+    let start = Instant::now();
+    conn.set_values(&values).await.map_err(internal_error)?;
+    tracing::debug!("MSET of {} keys took {:?}", values.len(), start.elapsed());
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+fn check_count(count: usize) -> Result<(), (StatusCode, String)> {
+    if count == 0 {
+        return Err((StatusCode::BAD_REQUEST, "no keys given".to_owned()));
+    }
+    if count > MAX_KEYS {
+        return Err((
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("at most {MAX_KEYS} keys can be given, got {count}"),
+        ));
+    }
+    Ok(())
+}
+
+fn not_found(key: &str) -> (StatusCode, String) {
+    (StatusCode::NOT_FOUND, format!("key `{key}` not found"))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::Method;
+    use redis::{FromRedisValue, Value};
+    use serde_json::json;
+
+    use crate::test_support::{send, send_json, test_app, InMemoryRedis};
+
+    use super::*;
+
+    #[test]
+    fn nil_is_none_not_an_error() {
+        assert_eq!(Option::<String>::from_redis_value(&Value::Nil), Ok(None));
+        assert!(String::from_redis_value(&Value::Nil).is_err());
+    }
+
+    #[tokio::test]
+    async fn put_get_delete() {
+        let redis = InMemoryRedis::default();
+        let app = test_app(&redis).await;
+
+        let (status, _) = send(&app, Method::PUT, "/kv/greeting", "hello").await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+
+        let (status, body) = send(&app, Method::GET, "/kv/greeting", "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "hello");
+
+        let (status, _) = send(&app, Method::DELETE, "/kv/greeting", "").await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+
+        let (status, _) = send(&app, Method::GET, "/kv/greeting", "").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn missing_keys_are_not_found() {
+        let app = test_app(&InMemoryRedis::default()).await;
+
+        let (status, body) = send(&app, Method::GET, "/kv/nope", "").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body, "key `nope` not found");
+
+        let (status, _) = send(&app, Method::DELETE, "/kv/nope", "").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn put_with_ttl() {
+        let redis = InMemoryRedis::default();
+        let app = test_app(&redis).await;
+
+        let (status, _) = send(&app, Method::PUT, "/kv/session?ttl=30", "abc").await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert_eq!(
+            redis.values.lock().unwrap()["session"],
+            ("abc".to_owned(), Some(30))
+        );
+
+        for ttl in ["0", "-1", "soon"] {
+            let (status, _) = send(&app, Method::PUT, &format!("/kv/k?ttl={ttl}"), "v").await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{ttl}");
+        }
+        assert!(!redis.values.lock().unwrap().contains_key("k"));
+    }
+
+    #[tokio::test]
+    async fn mget_returns_null_for_missing_keys() {
+        let app = test_app(&InMemoryRedis::default()).await;
+
+        let (status, _) = send_json(&app, "/kv/mset", json!({"a": "1", "c": "3"})).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+
+        let (status, body) = send_json(&app, "/kv/mget", json!(["a", "b", "c"])).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, json!({"a": "1", "b": null, "c": "3"}));
+    }
+
+    #[tokio::test]
+    async fn mget_and_mset_check_the_key_count() {
+        let app = test_app(&InMemoryRedis::default()).await;
+
+        let (status, _) = send_json(&app, "/kv/mget", json!([])).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _) = send_json(&app, "/kv/mset", json!({})).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let keys: Vec<_> = (0..=MAX_KEYS).map(|i| i.to_string()).collect();
+        let (status, _) = send_json(&app, "/kv/mget", json!(keys)).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+
+        let values: serde_json::Map<_, _> = keys.into_iter().map(|key| (key, json!("v"))).collect();
+        let (status, _) = send_json(&app, "/kv/mset", values.into()).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+    }
+}
@@ -0,0 +1,331 @@
+use std::{sync::Arc, time::Duration};
+
+use axum::{
+    async_trait,
+    body::Bytes,
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::post,
+    BoxError, Json, Router,
+};
+use redis::{AsyncCommands, Script};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::ConnectionPool;
+
+const DEFAULT_TTL_MS: u64 = 10_000;
+const MAX_TTL_MS: u64 = 60_000;
+
+/// Header the token can be sent in when releasing, instead of the body.
+const TOKEN_HEADER: &str = "x-lock-token";
+
+/// Locks that expire, identified by a token only their holder knows.
+#[async_trait]
+pub trait LockStore: Send + Sync {
+    /// Take `key` with `token` unless it's held, returning whether it was
+    /// taken.
+    async fn try_acquire(&self, key: &str, token: &str, ttl: Duration) -> Result<bool, BoxError>;
+
+    /// How long until `key` expires, `None` if it isn't held.
+    async fn remaining(&self, key: &str) -> Result<Option<Duration>, BoxError>;
+
+    /// Release `key` if it's held with `token`, returning whether it was.
+    async fn release(&self, key: &str, token: &str) -> Result<bool, BoxError>;
+}
+
+pub struct RedisLocks {
+    pool: ConnectionPool,
+    release: Script,
+}
+
+impl RedisLocks {
+    pub fn new(pool: ConnectionPool) -> Self {
+        // A `GET` and then a `DEL` could delete a lock someone else took after
+        // ours expired in between, so they have to run as one script.
+        let release = Script::new(
+            r"
+            if redis.call('GET', KEYS[1]) == ARGV[1] then
+                return redis.call('DEL', KEYS[1])
+            end
+            return 0
+            ",
+        );
+        Self { pool, release }
+    }
+}
+
+#[async_trait]
+impl LockStore for RedisLocks {
+    async fn try_acquire(&self, key: &str, token: &str, ttl: Duration) -> Result<bool, BoxError> {
+        let mut conn = self.pool.get().await?;
+        // `SET NX` replies nil when the key already exists.
+        let set: Option<String> = redis::cmd("SET")
+            .arg(key)
+            .arg(token)
+            .arg("NX")
+            .arg("PX")
+            .arg(ttl.as_millis() as u64)
+            .query_async(&mut *conn)
+            .await?;
+        Ok(set.is_some())
+    }
+
+    async fn remaining(&self, key: &str) -> Result<Option<Duration>, BoxError> {
+        let mut conn = self.pool.get().await?;
+        // Negative when the key doesn't exist or has no expiry, which a lock
+        // always has.
+        let pttl: i64 = conn.pttl(key).await?;
+        Ok(u64::try_from(pttl).ok().map(Duration::from_millis))
+    }
+
+    async fn release(&self, key: &str, token: &str) -> Result<bool, BoxError> {
+        let mut conn = self.pool.get().await?;
+        let deleted: u64 = self
+            .release
+            .key(key)
+            .arg(token)
+            .invoke_async(&mut *conn)
+            .await?;
+        Ok(deleted > 0)
+    }
+}
+
+/// `POST /lock/:name` to take a lock, and `DELETE /lock/:name` with its token
+/// to release it.
+pub fn routes(locks: impl LockStore + 'static) -> Router {
+    let locks: Arc<dyn LockStore> = Arc::new(locks);
+    Router::new()
+        .route("/lock/:name", post(acquire).delete(release))
+        .with_state(locks)
+}
+
+fn lock_key(name: &str) -> String {
+    format!("lock:{name}")
+}
+
+#[derive(Debug, Deserialize)]
+struct AcquireParams {
+    ttl_ms: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+struct Acquired {
+    token: String,
+    ttl_ms: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct Held {
+    error: String,
+    /// Only how long the lock is held for, never the holder's token.
+    remaining_ms: u64,
+}
+
+async fn acquire(
+    State(locks): State<Arc<dyn LockStore>>,
+    Path(name): Path<String>,
+    Query(params): Query<AcquireParams>,
+) -> Result<Response, (StatusCode, String)> {
+    let ttl_ms = params.ttl_ms.unwrap_or(DEFAULT_TTL_MS);
+    if !(1..=MAX_TTL_MS).contains(&ttl_ms) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("ttl_ms must be between 1 and {MAX_TTL_MS}"),
+        ));
+    }
+
+    let key = lock_key(&name);
+    let token = Uuid::new_v4().to_string();
+    let ttl = Duration::from_millis(ttl_ms);
+    if locks
+        .try_acquire(&key, &token, ttl)
+        .await
+        .map_err(lock_error)?
+    {
+        return Ok(Json(Acquired { token, ttl_ms }).into_response());
+    }
+
+    // Zero if it expired since, retrying right away is fine then.
+    let remaining = locks.remaining(&key).await.map_err(lock_error)?;
+    let held = Held {
+        error: format!("lock `{name}` is held"),
+        remaining_ms: remaining.unwrap_or_default().as_millis() as u64,
+    };
+    Ok((StatusCode::CONFLICT, Json(held)).into_response())
+}
+
+#[derive(Debug, Deserialize)]
+struct ReleaseBody {
+    token: String,
+}
+
+/// Takes the token from the `X-Lock-Token` header, or a `{"token": ...}` body.
+async fn release(
+    State(locks): State<Arc<dyn LockStore>>,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let token = match headers.get(TOKEN_HEADER) {
+        Some(token) => token
+            .to_str()
+            .map_err(|_| (StatusCode::BAD_REQUEST, "invalid token".to_owned()))?
+            .to_owned(),
+        None => {
+            let body: ReleaseBody = serde_json::from_slice(&body).map_err(|_| {
+                (
+                    StatusCode::BAD_REQUEST,
+                    format!("expected the token in `{TOKEN_HEADER}` or a JSON body"),
+                )
+            })?;
+            body.token
+        }
+    };
+
+    if locks
+        .release(&lock_key(&name), &token)
+        .await
+        .map_err(lock_error)?
+    {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        // Whether it expired or someone else holds it, it isn't ours.
+        Err((
+            StatusCode::CONFLICT,
+            format!("lock `{name}` isn't held with this token"),
+        ))
+    }
+}
+
+fn lock_error(err: BoxError) -> (StatusCode, String) {
+    (StatusCode::INTERNAL_SERVER_ERROR, err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, http::Request};
+    use bb8::Pool;
+    use bb8_redis::RedisConnectionManager;
+    use http_body_util::BodyExt;
+    use serde_json::{json, Value};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::test_support::{live_redis_url, InMemoryLocks};
+
+    async fn send(app: &Router, request: Request<Body>) -> (StatusCode, Value) {
+        let response = app.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, serde_json::from_slice(&body).unwrap_or_default())
+    }
+
+    fn post_lock(uri: &str) -> Request<Body> {
+        Request::post(uri).body(Body::empty()).unwrap()
+    }
+
+    fn delete_lock(uri: &str, token: &str) -> Request<Body> {
+        Request::delete(uri)
+            .body(Body::from(json!({ "token": token }).to_string()))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn acquire_contend_release() {
+        let app = routes(InMemoryLocks::default());
+
+        let (status, body) = send(&app, post_lock("/lock/report")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["ttl_ms"], DEFAULT_TTL_MS);
+        let token = body["token"].as_str().unwrap().to_owned();
+
+        let (status, body) = send(&app, post_lock("/lock/report")).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        let remaining = body["remaining_ms"].as_u64().unwrap();
+        assert!((1..=DEFAULT_TTL_MS).contains(&remaining), "{body}");
+        assert!(!body.to_string().contains(&token), "leaked the token");
+
+        let (status, _) = send(&app, delete_lock("/lock/report", &token)).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+
+        let (status, _) = send(&app, post_lock("/lock/report")).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn release_with_wrong_token() {
+        let app = routes(InMemoryLocks::default());
+
+        let (_, body) = send(&app, post_lock("/lock/report")).await;
+        let token = body["token"].as_str().unwrap();
+
+        let (status, _) = send(&app, delete_lock("/lock/report", "not-the-token")).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        let (status, _) = send(&app, delete_lock("/lock/other", token)).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        let (status, _) = send(&app, post_lock("/lock/report")).await;
+        assert_eq!(status, StatusCode::CONFLICT, "lock was released");
+
+        let by_header = Request::delete("/lock/report")
+            .header(TOKEN_HEADER, token)
+            .body(Body::empty())
+            .unwrap();
+        let (status, _) = send(&app, by_header).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+
+        let (status, _) = send(
+            &app,
+            Request::delete("/lock/report").body(Body::empty()).unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn locks_expire() {
+        let app = routes(InMemoryLocks::default());
+
+        let (status, body) = send(&app, post_lock("/lock/report?ttl_ms=50")).await;
+        assert_eq!(status, StatusCode::OK);
+        let token = body["token"].as_str().unwrap().to_owned();
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let (status, _) = send(&app, post_lock("/lock/report")).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = send(&app, delete_lock("/lock/report", &token)).await;
+        assert_eq!(status, StatusCode::CONFLICT, "released someone else's lock");
+    }
+
+    #[tokio::test]
+    async fn ttl_is_checked() {
+        let app = routes(InMemoryLocks::default());
+
+        for ttl in ["0", "60001", "-1", "soon"] {
+            let (status, _) = send(&app, post_lock(&format!("/lock/report?ttl_ms={ttl}"))).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{ttl}");
+        }
+    }
+
+    #[tokio::test]
+    async fn redis_release_checks_the_token() {
+        let Some(url) = live_redis_url() else {
+            return;
+        };
+        let manager = RedisConnectionManager::new(url).unwrap();
+        let locks = RedisLocks::new(Pool::builder().build(manager).await.unwrap());
+        let key = format!("lock:test:{}", std::process::id());
+        let ttl = Duration::from_secs(10);
+
+        assert!(locks.try_acquire(&key, "a", ttl).await.unwrap());
+        assert!(!locks.try_acquire(&key, "b", ttl).await.unwrap());
+        let remaining = locks.remaining(&key).await.unwrap().unwrap();
+        assert!(remaining <= ttl && !remaining.is_zero());
+
+        assert!(!locks.release(&key, "b").await.unwrap());
+        assert!(locks.release(&key, "a").await.unwrap());
+        assert_eq!(locks.remaining(&key).await.unwrap(), None);
+    }
+}
@@ -1,72 +1,171 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
 use axum::extract::{FromRef, FromRequestParts, State};
 use axum::http::request::Parts;
 use axum::http::StatusCode;
-use axum::routing::get;
+use axum::middleware;
+use axum::routing::{get, post};
 use axum::{async_trait, Router};
-use bb8::{Pool, PooledConnection};
+use bb8::{ManageConnection, Pool, PooledConnection};
 use bb8_redis::RedisConnectionManager;
 use redis::AsyncCommands;
 use tracing_subscriber::layer::SubscriberExt;
-use tracing_subscriber::registry::Data;
 use tracing_subscriber::util::SubscriberInitExt;
 
+use crate::cache::RedisCache;
+use crate::counters::CounterStore;
+use crate::kv::KvStore;
+use crate::lock::RedisLocks;
+use crate::rate_limit::{RateLimit, RedisLimiter};
+use crate::session::{RedisSessionStore, Sessions};
+
+mod cache;
+mod connect;
+mod counters;
+mod events;
+mod health;
+mod kv;
+mod lock;
+mod rate_limit;
+mod session;
+#[cfg(test)]
+mod test_support;
+
 #[tokio::main]
 async fn main() {
     tracing_subscriber::registry()
         .with(
             tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| "example_tokio_redis=debug".into()),
+                .unwrap_or_else(|_| format!("{}=debug", env!("CARGO_CRATE_NAME")).into()),
         )
         .with(tracing_subscriber::fmt::layer())
         .init();
 
+    let redis_url = std::env::var("REDIS_URL").unwrap_or_else(|_| "redis://localhost".to_owned());
+    let deadline = Duration::from_secs(env_or("REDIS_CONNECT_DEADLINE_SECS", 30));
+
     tracing::debug!("connecting to redis");
-    let manager = RedisConnectionManager::new("redis://localhost").unwrap();
-    let pool = bb8::Pool::builder().build(manager).await.unwrap();
+    let pool = match connect::connect_with_retry(&redis_url, deadline).await {
+        Ok(pool) => pool,
+        Err(err) => {
+            tracing::error!("{err}");
+            std::process::exit(1);
+        }
+    };
+    tracing::debug!("successfully connected to redis and pinged it");
+    let client = redis::Client::open(redis_url).unwrap();
 
-    {
+    if std::env::var("SEED_DEMO_KEY").is_ok_and(|seed| seed == "true") {
         let mut conn = pool.get().await.unwrap();
         conn.set::<&str, &str, ()>("foo", "bar").await.unwrap();
-        let result: String = conn.get("foo").await.unwrap();
-        assert_eq!(result, "bar");
+        tracing::debug!("set `foo` to `bar`");
     }
-    tracing::debug!("successfully connected to redis and pinged it");
 
-    let app = Router::new()
-        .route(
-            "/",
-            get(using_connection_pool_extractor).post(using_connection_extractor),
-        )
-        .with_state(pool);
+    let per_minute = env_or("RATE_LIMIT_PER_MINUTE", 60);
+    let session_ttl = Duration::from_secs(env_or("SESSION_TTL_SECS", 60 * 60));
+    let rate_limit = RateLimit::new(RedisLimiter::new(pool.clone()), per_minute);
+
+    let app = app(pool.clone(), rate_limit)
+        .merge(events::routes(client, pool.clone()))
+        .merge(cache::routes(
+            RedisCache::new(pool.clone()),
+            RedisLocks::new(pool.clone()),
+            Duration::from_millis(500),
+        ))
+        .merge(session::routes(Sessions::new(
+            RedisSessionStore::new(pool.clone()),
+            session_ttl,
+        )))
+        .merge(health::routes(pool.clone()))
+        .merge(lock::routes(RedisLocks::new(pool)));
 
     let listener = tokio::net::TcpListener::bind("127.0.0.1:3000")
         .await
         .unwrap();
     tracing::debug!("listening on {}", listener.local_addr().unwrap());
-    axum::serve(listener, app).await.unwrap();
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+    .unwrap();
 }
 
-type ConnectionPool = Pool<RedisConnectionManager>;
+/// Parse the environment variable `name`, `default` if it isn't set or valid.
+fn env_or(name: &str, default: u64) -> u64 {
+    std::env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
 
-async fn using_connection_pool_extractor(
-    State(pool): State<ConnectionPool>,
-) -> Result<String, (StatusCode, String)> {
+/// The routes, generic over the connection manager so the tests can use an
+/// in-memory fake instead of Redis. Only `/` is rate limited.
+fn app<M>(pool: Pool<M>, rate_limit: RateLimit) -> Router
+where
+    M: ManageConnection,
+    M::Connection: KvStore + CounterStore,
+    M::Error: std::error::Error,
+{
+    Router::new()
+        .route(
+            "/",
+            get(using_connection_pool_extractor::<M>)
+                .post(using_connection_extractor::<M>)
+                .route_layer(middleware::from_fn_with_state(
+                    rate_limit,
+                    rate_limit::rate_limit,
+                )),
+        )
+        // Takes precedence over `/kv/:key`.
+        .route("/kv/mget", post(kv::mget::<M>))
+        .route("/kv/mset", post(kv::mset::<M>))
+        .route(
+            "/kv/:key",
+            get(kv::get_key::<M>)
+                .put(kv::put_key::<M>)
+                .delete(kv::delete_key::<M>),
+        )
+        .route("/counter/:name", get(counters::get_counter::<M>))
+        .route("/counter/:name/incr", post(counters::incr::<M>))
+        .route(
+            "/leaderboard/:board",
+            get(counters::top::<M>).post(counters::add_score::<M>),
+        )
+        .with_state(pool)
+}
+
+type ConnectionPool<M = RedisConnectionManager> = Pool<M>;
+
+async fn using_connection_pool_extractor<M>(
+    State(pool): State<ConnectionPool<M>>,
+) -> Result<String, (StatusCode, String)>
+where
+    M: ManageConnection,
+    M::Connection: KvStore,
+    M::Error: std::error::Error,
+{
     let mut conn = pool.get().await.map_err(internal_error)?;
-    let result: String = conn.get("foo").await.map_err(internal_error)?;
-    Ok(result)
+    let result = conn.get_value("foo").await.map_err(internal_error)?;
+    result.ok_or_else(foo_not_found)
 }
 
-struct DatabaseConnection(PooledConnection<'static, RedisConnectionManager>);
+struct DatabaseConnection<M: ManageConnection = RedisConnectionManager>(
+    PooledConnection<'static, M>,
+);
 
 #[async_trait]
-impl<S> FromRequestParts<S> for DatabaseConnection
+impl<S, M> FromRequestParts<S> for DatabaseConnection<M>
 where
-    ConnectionPool: FromRef<S>,
+    M: ManageConnection,
+    M::Error: std::error::Error,
+    ConnectionPool<M>: FromRef<S>,
     S: Send + Sync,
 {
     type Rejection = (StatusCode, String);
 
-    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+    async fn from_request_parts(_parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
         let pool = ConnectionPool::from_ref(state);
 
         let conn = pool.get_owned().await.map_err(internal_error)?;
@@ -75,12 +174,20 @@ where
     }
 }
 
-async fn using_connection_extractor(
-    DatabaseConnection(mut conn): DatabaseConnection,
-) -> Result<String, (StatusCode, String)> {
-    let result: String = conn.get("foo").await.map_err(internal_error)?;
+async fn using_connection_extractor<M>(
+    DatabaseConnection(mut conn): DatabaseConnection<M>,
+) -> Result<String, (StatusCode, String)>
+where
+    M: ManageConnection,
+    M::Connection: KvStore,
+{
+    let result = conn.get_value("foo").await.map_err(internal_error)?;
+
+    result.ok_or_else(foo_not_found)
+}
 
-    Ok(result)
+fn foo_not_found() -> (StatusCode, String) {
+    (StatusCode::NOT_FOUND, "`foo` isn't set".to_owned())
 }
 
 fn internal_error<E>(err: E) -> (StatusCode, String)
@@ -0,0 +1,246 @@
+use std::{
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, Request, State},
+    http::{HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    BoxError,
+};
+use redis::Script;
+
+use crate::ConnectionPool;
+
+/// Length of a rate limit window, in seconds.
+const WINDOW_SECS: u64 = 60;
+
+/// How long to wait for the limiter before letting a request through anyway.
+const LIMITER_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Counts hits per key in fixed windows.
+#[async_trait]
+pub trait Limiter: Send + Sync {
+    /// Count a hit on `key`, returning the number of hits so far. The count
+    /// is dropped `window` after the first hit.
+    async fn hit(&self, key: &str, window: Duration) -> Result<u64, BoxError>;
+}
+
+/// Counts hits in Redis, so the limit holds across instances.
+pub struct RedisLimiter {
+    pool: ConnectionPool,
+    script: Script,
+}
+
+impl RedisLimiter {
+    pub fn new(pool: ConnectionPool) -> Self {
+        // Setting the expiry in the same script means a key can't be left
+        // without one if the connection drops between the two commands.
+        let script = Script::new(
+            r"
+            local count = redis.call('INCR', KEYS[1])
+            if count == 1 then
+                redis.call('EXPIRE', KEYS[1], ARGV[1])
+            end
+            return count
+            ",
+        );
+        Self { pool, script }
+    }
+}
+
+#[async_trait]
+impl Limiter for RedisLimiter {
+    async fn hit(&self, key: &str, window: Duration) -> Result<u64, BoxError> {
+        let mut conn = self.pool.get().await?;
+        let count = self
+            .script
+            .key(key)
+            .arg(window.as_secs())
+            .invoke_async(&mut *conn)
+            .await?;
+        Ok(count)
+    }
+}
+
+/// State for the `rate_limit` middleware.
+#[derive(Clone)]
+pub struct RateLimit {
+    limiter: Arc<dyn Limiter>,
+    per_minute: u64,
+}
+
+impl RateLimit {
+    /// Allow each client `per_minute` requests per clock minute.
+    pub fn new(limiter: impl Limiter + 'static, per_minute: u64) -> Self {
+        Self {
+            limiter: Arc::new(limiter),
+            per_minute,
+        }
+    }
+}
+
+/// Reject clients that made too many requests this minute with
+/// `429 Too Many Requests`.
+///
+/// Requests are let through if the limiter fails, so Redis being down doesn't
+/// take every route down with it.
+pub async fn rate_limit(
+    State(state): State<RateLimit>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let key = format!("rl:{}:{}", addr.ip(), now / WINDOW_SECS);
+
+    let hit = tokio::time::timeout(
+        LIMITER_TIMEOUT,
+        state.limiter.hit(&key, Duration::from_secs(WINDOW_SECS)),
+    )
+    .await;
+    let count = match hit {
+        Ok(Ok(count)) => count,
+        Ok(Err(err)) => {
+            tracing::warn!("rate limiter failed, allowing request: {err}");
+            return next.run(request).await;
+        }
+        Err(_) => {
+            tracing::warn!("rate limiter timed out, allowing request");
+            return next.run(request).await;
+        }
+    };
+
+    let remaining = state.per_minute.saturating_sub(count);
+    let mut response = if count > state.per_minute {
+        let retry_after = WINDOW_SECS - now % WINDOW_SECS;
+        (
+            StatusCode::TOO_MANY_REQUESTS,
+            [("retry-after", retry_after.to_string())],
+            "too many requests, try again later",
+        )
+            .into_response()
+    } else {
+        next.run(request).await
+    };
+    response
+        .headers_mut()
+        .insert("x-ratelimit-remaining", HeaderValue::from(remaining));
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use axum::{
+        body::Body,
+        extract::connect_info::MockConnectInfo,
+        http::{Request, StatusCode},
+        Router,
+    };
+    use bb8::Pool;
+    use bb8_redis::RedisConnectionManager;
+    use redis::AsyncCommands;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{
+        app,
+        kv::KvStore,
+        test_support::{live_redis_url, InMemoryLimiter, InMemoryRedis},
+    };
+
+    async fn limited_app(limit: RateLimit) -> Router {
+        let redis = InMemoryRedis::default();
+        redis.clone().set_value("foo", "bar", None).await.unwrap();
+        let pool = Pool::builder().build(redis).await.unwrap();
+        app(pool, limit).layer(MockConnectInfo(SocketAddr::from(([10, 0, 0, 1], 4000))))
+    }
+
+    async fn get(app: &Router, uri: &str) -> Response {
+        app.clone()
+            .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn rejects_once_over_the_limit() {
+        let limiter = InMemoryLimiter::default();
+        let app = limited_app(RateLimit::new(limiter.clone(), 2)).await;
+
+        for remaining in ["1", "0"] {
+            let response = get(&app, "/").await;
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(response.headers()["x-ratelimit-remaining"], remaining);
+        }
+
+        let response = get(&app, "/").await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()["x-ratelimit-remaining"], "0");
+        let retry_after: u64 = response.headers()["retry-after"]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!((1..=WINDOW_SECS).contains(&retry_after));
+
+        let keys = limiter.keys();
+        assert_eq!(keys.len(), 1, "{keys:?}");
+        assert!(keys[0].starts_with("rl:10.0.0.1:"), "{keys:?}");
+    }
+
+    #[tokio::test]
+    async fn only_the_root_routes_are_limited() {
+        let app = limited_app(RateLimit::new(InMemoryLimiter::default(), 0)).await;
+
+        assert_eq!(get(&app, "/").await.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(get(&app, "/kv/foo").await.status(), StatusCode::OK);
+    }
+
+    struct Unavailable;
+
+    #[async_trait]
+    impl Limiter for Unavailable {
+        async fn hit(&self, _key: &str, _window: Duration) -> Result<u64, BoxError> {
+            Err("connection refused".into())
+        }
+    }
+
+    #[tokio::test]
+    async fn fails_open() {
+        let app = limited_app(RateLimit::new(Unavailable, 0)).await;
+
+        let response = get(&app, "/").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(!response.headers().contains_key("x-ratelimit-remaining"));
+    }
+
+    #[tokio::test]
+    async fn redis_limiter_counts_and_expires() {
+        let Some(url) = live_redis_url() else {
+            return;
+        };
+        let manager = RedisConnectionManager::new(url).unwrap();
+        let pool = Pool::builder().build(manager).await.unwrap();
+        let limiter = RedisLimiter::new(pool.clone());
+        let key = format!("rl:test:{}", std::process::id());
+        let window = Duration::from_secs(WINDOW_SECS);
+
+        assert_eq!(limiter.hit(&key, window).await.unwrap(), 1);
+        assert_eq!(limiter.hit(&key, window).await.unwrap(), 2);
+
+        let mut conn = pool.get().await.unwrap();
+        let ttl: i64 = conn.ttl(&key).await.unwrap();
+        assert!((1..=WINDOW_SECS as i64).contains(&ttl), "{ttl}");
+        conn.del::<_, ()>(&key).await.unwrap();
+    }
+}
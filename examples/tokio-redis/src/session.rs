@@ -0,0 +1,373 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::{
+    async_trait,
+    extract::{FromRef, FromRequestParts, Request, State},
+    http::{header, request::Parts, HeaderMap, HeaderValue, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::get,
+    BoxError, Router,
+};
+use redis::AsyncCommands;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{Map, Value};
+
+use crate::{internal_error, ConnectionPool};
+
+const COOKIE_NAME: &str = "sid";
+
+/// Where session data is kept, as JSON.
+#[async_trait]
+pub trait SessionStore: Send + Sync {
+    async fn load(&self, id: &str) -> Result<Option<String>, BoxError>;
+
+    async fn store(&self, id: &str, data: &str, ttl: Duration) -> Result<(), BoxError>;
+
+    /// Push the expiry of an unchanged session back to `ttl` from now.
+    async fn touch(&self, id: &str, ttl: Duration) -> Result<(), BoxError>;
+}
+
+pub struct RedisSessionStore {
+    pool: ConnectionPool,
+}
+
+impl RedisSessionStore {
+    pub fn new(pool: ConnectionPool) -> Self {
+        Self { pool }
+    }
+}
+
+fn redis_key(id: &str) -> String {
+    format!("session:{id}")
+}
+
+#[async_trait]
+impl SessionStore for RedisSessionStore {
+    async fn load(&self, id: &str) -> Result<Option<String>, BoxError> {
+        let mut conn = self.pool.get().await?;
+        Ok(conn.get(redis_key(id)).await?)
+    }
+
+    async fn store(&self, id: &str, data: &str, ttl: Duration) -> Result<(), BoxError> {
+        let mut conn = self.pool.get().await?;
+        conn.set_ex::<_, _, ()>(redis_key(id), data, ttl.as_secs())
+            .await?;
+        Ok(())
+    }
+
+    async fn touch(&self, id: &str, ttl: Duration) -> Result<(), BoxError> {
+        let mut conn = self.pool.get().await?;
+        conn.expire::<_, ()>(redis_key(id), ttl.as_secs() as i64)
+            .await?;
+        Ok(())
+    }
+}
+
+/// The session store, and how long sessions last after their last use.
+///
+/// The `Session` extractor needs this in the router state, and
+/// `save_sessions` as its middleware state.
+#[derive(Clone)]
+pub struct Sessions {
+    store: Arc<dyn SessionStore>,
+    ttl: Duration,
+}
+
+impl Sessions {
+    pub fn new(store: impl SessionStore + 'static, ttl: Duration) -> Self {
+        Self {
+            store: Arc::new(store),
+            ttl,
+        }
+    }
+}
+
+/// `GET /session/visits`, counting the requests made with the session.
+pub fn routes(sessions: Sessions) -> Router {
+    Router::new()
+        .route("/session/visits", get(visits))
+        .layer(middleware::from_fn_with_state(
+            sessions.clone(),
+            save_sessions,
+        ))
+        .with_state(sessions)
+}
+
+async fn visits(session: Session) -> Result<String, (StatusCode, String)> {
+    let visits = session.get::<u64>("visits").unwrap_or(0) + 1;
+    session.insert("visits", visits).map_err(internal_error)?;
+    Ok(format!("{visits} visits"))
+}
+
+/// The session of the request, from the `sid` cookie.
+///
+/// Requests without a session, or with one that has expired, get a new one,
+/// which is only stored and sent to the client once something is inserted.
+/// Changes are kept in memory until `save_sessions` writes them back after the
+/// handler has run, so it has to be applied to the routes using this.
+#[derive(Debug, Clone)]
+pub struct Session(Arc<Mutex<SessionData>>);
+
+#[derive(Debug)]
+struct SessionData {
+    id: String,
+    values: Map<String, Value>,
+    is_new: bool,
+    changed: bool,
+}
+
+impl Session {
+    fn new() -> Self {
+        Self::with_values(new_id(), Map::new(), true)
+    }
+
+    fn with_values(id: String, values: Map<String, Value>, is_new: bool) -> Self {
+        Self(Arc::new(Mutex::new(SessionData {
+            id,
+            values,
+            is_new,
+            changed: false,
+        })))
+    }
+
+    /// The value of `key`, or `None` if it isn't set or isn't a `T`.
+    pub fn get<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+        let data = self.0.lock().unwrap();
+        let value = data.values.get(key)?.clone();
+        serde_json::from_value(value).ok()
+    }
+
+    pub fn insert<T: Serialize>(&self, key: &str, value: T) -> serde_json::Result<()> {
+        let value = serde_json::to_value(value)?;
+        let mut data = self.0.lock().unwrap();
+        data.values.insert(key.to_owned(), value);
+        data.changed = true;
+        Ok(())
+    }
+}
+
+/// 128 random bits, hex encoded.
+fn new_id() -> String {
+    format!("{:032x}", rand::random::<u128>())
+}
+
+/// Session ids come from the client, so only plausible ones are looked up.
+fn session_id(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|cookies| cookies.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(name, _)| *name == COOKIE_NAME)
+        .map(|(_, id)| id)
+        .filter(|id| id.len() == 32 && id.bytes().all(|b| b.is_ascii_hexdigit()))
+}
+
+/// Where `save_sessions` finds the session of the request, if the handler
+/// extracted one.
+#[derive(Clone, Default)]
+struct LoadedSession(Arc<Mutex<Option<Session>>>);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Session
+where
+    Sessions: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = (StatusCode, String);
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Some(loaded) = parts.extensions.get::<LoadedSession>().cloned() else {
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "`save_sessions` isn't applied to this route".to_owned(),
+            ));
+        };
+        if let Some(session) = loaded.0.lock().unwrap().clone() {
+            return Ok(session);
+        }
+
+        let sessions = Sessions::from_ref(state);
+        let session = match session_id(&parts.headers) {
+            Some(id) => match sessions.store.load(id).await {
+                Ok(Some(json)) => match serde_json::from_str(&json) {
+                    Ok(values) => Session::with_values(id.to_owned(), values, false),
+                    Err(err) => {
+                        tracing::warn!("discarding invalid session: {err}");
+                        Session::new()
+                    }
+                },
+                Ok(None) => Session::new(),
+                Err(err) => {
+                    return Err((StatusCode::INTERNAL_SERVER_ERROR, err.to_string()));
+                }
+            },
+            None => Session::new(),
+        };
+
+        *loaded.0.lock().unwrap() = Some(session.clone());
+        Ok(session)
+    }
+}
+
+/// Store the session the handler used if it changed, and refresh its expiry
+/// if it didn't. New sessions get their cookie once they're stored.
+pub async fn save_sessions(
+    State(sessions): State<Sessions>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let loaded = LoadedSession::default();
+    request.extensions_mut().insert(loaded.clone());
+
+    let mut response = next.run(request).await;
+
+    let Some(session) = loaded.0.lock().unwrap().take() else {
+        return response;
+    };
+    let (id, json, is_new, changed) = {
+        let data = session.0.lock().unwrap();
+        let json = Value::Object(data.values.clone()).to_string();
+        (data.id.clone(), json, data.is_new, data.changed)
+    };
+
+    if !changed {
+        if !is_new {
+            if let Err(err) = sessions.store.touch(&id, sessions.ttl).await {
+                tracing::warn!("refreshing a session failed: {err}");
+            }
+        }
+        return response;
+    }
+
+    if let Err(err) = sessions.store.store(&id, &json, sessions.ttl).await {
+        return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response();
+    }
+    if is_new {
+        let cookie = format!("{COOKIE_NAME}={id}; Path=/; HttpOnly; SameSite=Lax");
+        response
+            .headers_mut()
+            .append(header::SET_COOKIE, HeaderValue::from_str(&cookie).unwrap());
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, http::Request};
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::test_support::InMemorySessions;
+
+    const TTL: Duration = Duration::from_secs(600);
+
+    /// The body and `Set-Cookie` header of `GET uri`, sending `cookie`.
+    async fn visit(app: &Router, uri: &str, cookie: Option<&str>) -> (String, Option<String>) {
+        let mut request = Request::get(uri);
+        if let Some(cookie) = cookie {
+            request = request.header(header::COOKIE, cookie);
+        }
+        let response = app
+            .clone()
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let set_cookie = response
+            .headers()
+            .get(header::SET_COOKIE)
+            .map(|value| value.to_str().unwrap().to_owned());
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (String::from_utf8(body.to_vec()).unwrap(), set_cookie)
+    }
+
+    /// The `sid=...` part of a `Set-Cookie` header.
+    fn cookie(set_cookie: &str) -> &str {
+        set_cookie.split(';').next().unwrap()
+    }
+
+    #[tokio::test]
+    async fn new_sessions_get_a_cookie() {
+        let store = InMemorySessions::default();
+        let app = routes(Sessions::new(store.clone(), TTL));
+
+        let (body, set_cookie) = visit(&app, "/session/visits", None).await;
+        assert_eq!(body, "1 visits");
+
+        let set_cookie = set_cookie.expect("no cookie set");
+        assert!(set_cookie.contains("; HttpOnly"), "{set_cookie}");
+        assert!(set_cookie.contains("; SameSite=Lax"), "{set_cookie}");
+        let id = cookie(&set_cookie).strip_prefix("sid=").unwrap();
+        assert_eq!(store.get(id), Some((r#"{"visits":1}"#.to_owned(), TTL)));
+    }
+
+    #[tokio::test]
+    async fn sessions_persist_across_requests() {
+        let app = routes(Sessions::new(InMemorySessions::default(), TTL));
+
+        let (_, set_cookie) = visit(&app, "/session/visits", None).await;
+        let cookie = cookie(set_cookie.as_deref().unwrap());
+
+        let (body, set_cookie) = visit(&app, "/session/visits", Some(cookie)).await;
+        assert_eq!(body, "2 visits");
+        assert_eq!(set_cookie, None, "cookie set again");
+
+        let (body, _) = visit(
+            &app,
+            "/session/visits",
+            Some(&format!("theme=dark; {cookie}")),
+        )
+        .await;
+        assert_eq!(body, "3 visits");
+    }
+
+    #[tokio::test]
+    async fn unknown_sessions_are_replaced() {
+        let app = routes(Sessions::new(InMemorySessions::default(), TTL));
+
+        for unknown in ["sid=00000000000000000000000000000000", "sid=../../etc"] {
+            let (body, set_cookie) = visit(&app, "/session/visits", Some(unknown)).await;
+            assert_eq!(body, "1 visits");
+            assert_ne!(cookie(&set_cookie.unwrap()), unknown);
+        }
+    }
+
+    #[tokio::test]
+    async fn reading_refreshes_the_ttl() {
+        let store = InMemorySessions::default();
+        let sessions = Sessions::new(store.clone(), TTL);
+        let app = routes(sessions.clone()).merge(
+            Router::new()
+                .route(
+                    "/peek",
+                    get(|session: Session| async move {
+                        session.get::<u64>("visits").unwrap_or(0).to_string()
+                    }),
+                )
+                .layer(middleware::from_fn_with_state(
+                    sessions.clone(),
+                    save_sessions,
+                ))
+                .with_state(sessions),
+        );
+
+        let (body, _) = visit(&app, "/peek", None).await;
+        assert_eq!(body, "0");
+        assert_eq!(store.touches(), 0, "unsaved session touched");
+
+        let (_, set_cookie) = visit(&app, "/session/visits", None).await;
+        let cookie = cookie(set_cookie.as_deref().unwrap());
+        let (body, set_cookie) = visit(&app, "/peek", Some(cookie)).await;
+        assert_eq!(body, "1");
+        assert_eq!(set_cookie, None);
+        assert_eq!(store.touches(), 1);
+    }
+}
@@ -0,0 +1,331 @@
+//! Helpers shared by the tests.
+
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use axum::{
+    async_trait,
+    body::Body,
+    extract::connect_info::MockConnectInfo,
+    http::{header, Method, Request, StatusCode},
+    BoxError, Router,
+};
+use bb8::{ManageConnection, Pool};
+use http_body_util::BodyExt;
+use redis::{RedisError, RedisResult};
+use tower::ServiceExt;
+
+use crate::{
+    app,
+    cache::Cache,
+    counters::CounterStore,
+    kv::KvStore,
+    lock::LockStore,
+    rate_limit::{Limiter, RateLimit},
+    session::SessionStore,
+};
+
+/// Values with the ttl they were set with.
+type Values = HashMap<String, (String, Option<u64>)>;
+
+/// A connection manager whose "connections" share the same in-memory data.
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryRedis {
+    pub values: Arc<Mutex<Values>>,
+    /// Sorted sets, as the score of each member.
+    pub sorted_sets: Arc<Mutex<HashMap<String, HashMap<String, f64>>>>,
+}
+
+#[async_trait]
+impl ManageConnection for InMemoryRedis {
+    type Connection = InMemoryRedis;
+    type Error = RedisError;
+
+    async fn connect(&self) -> Result<Self::Connection, Self::Error> {
+        Ok(self.clone())
+    }
+
+    async fn is_valid(&self, _conn: &mut Self::Connection) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn has_broken(&self, _conn: &mut Self::Connection) -> bool {
+        false
+    }
+}
+
+#[async_trait]
+impl KvStore for InMemoryRedis {
+    async fn get_value(&mut self, key: &str) -> RedisResult<Option<String>> {
+        let values = self.values.lock().unwrap();
+        Ok(values.get(key).map(|(value, _)| value.clone()))
+    }
+
+    async fn set_value(&mut self, key: &str, value: &str, ttl: Option<u64>) -> RedisResult<()> {
+        let mut values = self.values.lock().unwrap();
+        values.insert(key.to_owned(), (value.to_owned(), ttl));
+        Ok(())
+    }
+
+    async fn delete(&mut self, key: &str) -> RedisResult<bool> {
+        Ok(self.values.lock().unwrap().remove(key).is_some())
+    }
+
+    async fn get_values(&mut self, keys: &[String]) -> RedisResult<Vec<Option<String>>> {
+        let values = self.values.lock().unwrap();
+        Ok(keys
+            .iter()
+            .map(|key| values.get(key).map(|(value, _)| value.clone()))
+            .collect())
+    }
+
+    async fn set_values(&mut self, pairs: &[(String, String)]) -> RedisResult<()> {
+        let mut values = self.values.lock().unwrap();
+        for (key, value) in pairs {
+            values.insert(key.clone(), (value.clone(), None));
+        }
+        Ok(())
+    }
+}
+
+/// Counts hits without ever expiring them.
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryLimiter {
+    counts: Arc<Mutex<HashMap<String, u64>>>,
+}
+
+impl InMemoryLimiter {
+    pub fn keys(&self) -> Vec<String> {
+        self.counts.lock().unwrap().keys().cloned().collect()
+    }
+}
+
+#[async_trait]
+impl Limiter for InMemoryLimiter {
+    async fn hit(&self, key: &str, _window: Duration) -> Result<u64, BoxError> {
+        let mut counts = self.counts.lock().unwrap();
+        let count = counts.entry(key.to_owned()).or_default();
+        *count += 1;
+        Ok(*count)
+    }
+}
+
+#[async_trait]
+impl CounterStore for InMemoryRedis {
+    async fn incr_by(&mut self, key: &str, by: i64) -> RedisResult<i64> {
+        let mut values = self.values.lock().unwrap();
+        let (value, _) = values.entry(key.to_owned()).or_default();
+        let incremented = value.parse().unwrap_or(0) + by;
+        *value = incremented.to_string();
+        Ok(incremented)
+    }
+
+    async fn counter(&mut self, key: &str) -> RedisResult<Option<i64>> {
+        let values = self.values.lock().unwrap();
+        Ok(values.get(key).map(|(value, _)| value.parse().unwrap()))
+    }
+
+    async fn add_score(&mut self, key: &str, member: &str, score: f64) -> RedisResult<()> {
+        let mut sorted_sets = self.sorted_sets.lock().unwrap();
+        let set = sorted_sets.entry(key.to_owned()).or_default();
+        set.insert(member.to_owned(), score);
+        Ok(())
+    }
+
+    async fn top(&mut self, key: &str, count: usize) -> RedisResult<Vec<(String, f64)>> {
+        let sorted_sets = self.sorted_sets.lock().unwrap();
+        let mut top: Vec<_> = sorted_sets
+            .get(key)
+            .map(|set| {
+                set.iter()
+                    .map(|(member, score)| (member.clone(), *score))
+                    .collect()
+            })
+            .unwrap_or_default();
+        top.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+        top.truncate(count);
+        Ok(top)
+    }
+}
+
+/// A cache that never expires anything, counting the values set.
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryCache {
+    values: Arc<Mutex<HashMap<String, (String, Duration)>>>,
+    sets: Arc<AtomicUsize>,
+}
+
+impl InMemoryCache {
+    /// The ttl `key` was set with.
+    pub fn ttl(&self, key: &str) -> Option<Duration> {
+        let values = self.values.lock().unwrap();
+        values.get(key).map(|(_, ttl)| *ttl)
+    }
+
+    pub fn sets(&self) -> usize {
+        self.sets.load(Ordering::SeqCst)
+    }
+}
+
+#[async_trait]
+impl Cache for InMemoryCache {
+    async fn get(&self, key: &str) -> Result<Option<String>, BoxError> {
+        let values = self.values.lock().unwrap();
+        Ok(values.get(key).map(|(value, _)| value.clone()))
+    }
+
+    async fn set(&self, key: &str, value: &str, ttl: Duration) -> Result<(), BoxError> {
+        let mut values = self.values.lock().unwrap();
+        values.insert(key.to_owned(), (value.to_owned(), ttl));
+        self.sets.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+/// Sessions with the ttl they were stored with, and a count of refreshes.
+#[derive(Debug, Clone, Default)]
+pub struct InMemorySessions {
+    sessions: Arc<Mutex<HashMap<String, (String, Duration)>>>,
+    touches: Arc<AtomicUsize>,
+}
+
+impl InMemorySessions {
+    pub fn get(&self, id: &str) -> Option<(String, Duration)> {
+        self.sessions.lock().unwrap().get(id).cloned()
+    }
+
+    pub fn touches(&self) -> usize {
+        self.touches.load(Ordering::SeqCst)
+    }
+}
+
+#[async_trait]
+impl SessionStore for InMemorySessions {
+    async fn load(&self, id: &str) -> Result<Option<String>, BoxError> {
+        Ok(self.get(id).map(|(data, _)| data))
+    }
+
+    async fn store(&self, id: &str, data: &str, ttl: Duration) -> Result<(), BoxError> {
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.insert(id.to_owned(), (data.to_owned(), ttl));
+        Ok(())
+    }
+
+    async fn touch(&self, _id: &str, _ttl: Duration) -> Result<(), BoxError> {
+        self.touches.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+/// Locks with their token and when they expire.
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryLocks {
+    locks: Arc<Mutex<HashMap<String, (String, Instant)>>>,
+}
+
+impl InMemoryLocks {
+    /// The token and expiry of `key` if it's held, dropping it if it expired.
+    fn held(
+        locks: &mut HashMap<String, (String, Instant)>,
+        key: &str,
+    ) -> Option<(String, Instant)> {
+        match locks.get(key) {
+            Some((_, expires)) if *expires <= Instant::now() => {
+                locks.remove(key);
+                None
+            }
+            held => held.cloned(),
+        }
+    }
+}
+
+#[async_trait]
+impl LockStore for InMemoryLocks {
+    async fn try_acquire(&self, key: &str, token: &str, ttl: Duration) -> Result<bool, BoxError> {
+        let mut locks = self.locks.lock().unwrap();
+        if Self::held(&mut locks, key).is_some() {
+            return Ok(false);
+        }
+        locks.insert(key.to_owned(), (token.to_owned(), Instant::now() + ttl));
+        Ok(true)
+    }
+
+    async fn remaining(&self, key: &str) -> Result<Option<Duration>, BoxError> {
+        let mut locks = self.locks.lock().unwrap();
+        Ok(Self::held(&mut locks, key).map(|(_, expires)| expires - Instant::now()))
+    }
+
+    async fn release(&self, key: &str, token: &str) -> Result<bool, BoxError> {
+        let mut locks = self.locks.lock().unwrap();
+        match Self::held(&mut locks, key) {
+            Some((held, _)) if held == token => {
+                locks.remove(key);
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+}
+
+/// The app backed by `redis`, without a meaningful rate limit.
+pub async fn test_app(redis: &InMemoryRedis) -> Router {
+    let pool = Pool::builder().build(redis.clone()).await.unwrap();
+    let rate_limit = RateLimit::new(InMemoryLimiter::default(), u64::MAX);
+    app(pool, rate_limit).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4000))))
+}
+
+/// The Redis to run the live tests against, from `TEST_REDIS_URL`. They're
+/// skipped when it isn't set.
+pub fn live_redis_url() -> Option<String> {
+    let url = std::env::var("TEST_REDIS_URL").ok();
+    if url.is_none() {
+        eprintln!("TEST_REDIS_URL isn't set, skipping");
+    }
+    url
+}
+
+pub async fn send(app: &Router, method: Method, uri: &str, body: &str) -> (StatusCode, String) {
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .body(Body::from(body.to_owned()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let status = response.status();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    (status, String::from_utf8(body.to_vec()).unwrap())
+}
+
+/// `POST` `json` to `uri`, returning the status and the body parsed as JSON,
+/// or `null` if it isn't.
+pub async fn send_json(
+    app: &Router,
+    uri: &str,
+    json: serde_json::Value,
+) -> (StatusCode, serde_json::Value) {
+    let response = app
+        .clone()
+        .oneshot(
+            Request::post(uri)
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(json.to_string()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let status = response.status();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    (status, serde_json::from_slice(&body).unwrap_or_default())
+}
@@ -0,0 +1,192 @@
+use std::net::{IpAddr, SocketAddr};
+
+use axum::http::{header, HeaderMap};
+
+/// User agents are cut off after this many characters in the logs.
+const MAX_USER_AGENT_CHARS: usize = 256;
+
+/// Who sent the request: `peer`, the address it came from, unless it came
+/// through proxies we trust to say who they got it from, in which case it's
+/// the left-most IP in `X-Forwarded-For`, or in `Forwarded: for=`.
+///
+/// Entries that aren't an IP, with or without a port, are skipped, and `peer`
+/// is used if there's none left. Whether the IP is a private one, which a
+/// client could have sent to hide behind, isn't checked.
+pub fn resolve_client_ip(headers: &HeaderMap, peer: IpAddr, trust_proxy: bool) -> IpAddr {
+    if !trust_proxy {
+        return peer;
+    }
+    let forwarded_for = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .find_map(parse_ip);
+    forwarded_for
+        .or_else(|| {
+            headers
+                .get_all(header::FORWARDED)
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .flat_map(|value| value.split(','))
+                .filter_map(|element| {
+                    element.split(';').find_map(|pair| {
+                        let (name, value) = pair.trim().split_once('=')?;
+                        name.eq_ignore_ascii_case("for").then_some(value)
+                    })
+                })
+                .find_map(|node| parse_ip(node.trim_matches('"')))
+        })
+        .unwrap_or(peer)
+}
+
+/// `192.0.2.1`, `192.0.2.1:80`, `2001:db8::1`, `[2001:db8::1]` or
+/// `[2001:db8::1]:80`.
+fn parse_ip(entry: &str) -> Option<IpAddr> {
+    let entry = entry.trim();
+    entry
+        .parse::<IpAddr>()
+        .or_else(|_| entry.parse::<SocketAddr>().map(|addr| addr.ip()))
+        .ok()
+        .or_else(|| entry.strip_prefix('[')?.strip_suffix(']')?.parse().ok())
+}
+
+/// The `User-Agent`, cut off so that a huge one doesn't bloat the logs.
+pub fn user_agent(headers: &HeaderMap) -> Option<String> {
+    let user_agent = headers.get(header::USER_AGENT)?;
+    let user_agent = String::from_utf8_lossy(user_agent.as_bytes());
+    Some(user_agent.chars().take(MAX_USER_AGENT_CHARS).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    const PEER: IpAddr = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|&(name, value)| {
+                (
+                    header::HeaderName::from_static(name),
+                    HeaderValue::from_static(value),
+                )
+            })
+            .collect()
+    }
+
+    fn resolve(pairs: &[(&'static str, &'static str)]) -> IpAddr {
+        resolve_client_ip(&headers(pairs), PEER, true)
+    }
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    #[test]
+    fn the_peer_without_trusted_proxies() {
+        let headers = headers(&[
+            ("x-forwarded-for", "203.0.113.7"),
+            ("forwarded", "for=203.0.113.8"),
+        ]);
+        assert_eq!(resolve_client_ip(&headers, PEER, false), PEER);
+    }
+
+    #[test]
+    fn the_peer_without_forwarding_headers() {
+        assert_eq!(resolve(&[]), PEER);
+        assert_eq!(resolve(&[("x-forwarded-for", "")]), PEER);
+    }
+
+    #[test]
+    fn the_left_most_forwarded_for() {
+        assert_eq!(
+            resolve(&[("x-forwarded-for", "203.0.113.7, 198.51.100.2, 10.0.0.2")]),
+            ip("203.0.113.7")
+        );
+        // Over as many headers as there are.
+        let mut headers = headers(&[("x-forwarded-for", "unknown")]);
+        headers.append("x-forwarded-for", HeaderValue::from_static("198.51.100.2"));
+        assert_eq!(resolve_client_ip(&headers, PEER, true), ip("198.51.100.2"));
+    }
+
+    #[test]
+    fn ipv6_and_ports() {
+        let cases = [
+            ("2001:db8::1", "2001:db8::1"),
+            ("[2001:db8::1]", "2001:db8::1"),
+            ("[2001:db8::1]:4711", "2001:db8::1"),
+            ("203.0.113.7:8080", "203.0.113.7"),
+            ("::ffff:203.0.113.7", "::ffff:203.0.113.7"),
+        ];
+        for (forwarded_for, expected) in cases {
+            assert_eq!(
+                resolve(&[("x-forwarded-for", forwarded_for)]),
+                ip(expected),
+                "{forwarded_for}"
+            );
+        }
+    }
+
+    #[test]
+    fn garbage_is_skipped() {
+        let cases = [
+            "unknown, 203.0.113.7",
+            "<script>, 203.0.113.7",
+            "999.0.0.1, 203.0.113.7",
+            "203.0.113, 203.0.113.7",
+            "_hidden,,203.0.113.7",
+            "2001:db8::1::2, 203.0.113.7",
+            "203.0.113.6:port, 203.0.113.7",
+        ];
+        for forwarded_for in cases {
+            assert_eq!(
+                resolve(&[("x-forwarded-for", forwarded_for)]),
+                ip("203.0.113.7"),
+                "{forwarded_for}"
+            );
+        }
+        assert_eq!(resolve(&[("x-forwarded-for", "nope, also nope")]), PEER);
+    }
+
+    #[test]
+    fn forwarded_for_nodes() {
+        assert_eq!(
+            resolve(&[("forwarded", "for=192.0.2.60;proto=http;by=203.0.113.43")]),
+            ip("192.0.2.60")
+        );
+        assert_eq!(
+            resolve(&[(
+                "forwarded",
+                r#"for=unknown, For="[2001:db8:cafe::17]:4711", for=192.0.2.61"#
+            )]),
+            ip("2001:db8:cafe::17")
+        );
+        assert_eq!(resolve(&[("forwarded", "by=192.0.2.60")]), PEER);
+        // `X-Forwarded-For` goes first.
+        assert_eq!(
+            resolve(&[
+                ("forwarded", "for=192.0.2.60"),
+                ("x-forwarded-for", "203.0.113.7"),
+            ]),
+            ip("203.0.113.7")
+        );
+    }
+
+    #[test]
+    fn user_agents_are_cut_off() {
+        assert_eq!(user_agent(&HeaderMap::new()), None);
+        let headers = headers(&[("user-agent", "curl/8.5.0")]);
+        assert_eq!(user_agent(&headers).as_deref(), Some("curl/8.5.0"));
+
+        let long = "a".repeat(300);
+        let headers =
+            HeaderMap::from_iter([(header::USER_AGENT, HeaderValue::from_str(&long).unwrap())]);
+        assert_eq!(user_agent(&headers).unwrap(), long[..256]);
+    }
+}
@@ -0,0 +1,72 @@
+use std::time::Duration;
+
+use axum::body::{self, Body};
+use axum::http::header;
+use axum::response::Response;
+use http_body::Body as _;
+
+use crate::request_info::RequestInfo;
+
+/// At most this much of a server error's body is logged.
+const MAX_SNIPPET_BYTES: usize = 512;
+
+/// The start of a server error's body, for `log_failure`.
+#[derive(Clone)]
+struct ErrorBody(String);
+
+/// Keep a copy of the body of server errors with it, to log what went wrong
+/// along with the status.
+///
+/// Only bodies known to fit in `MAX_SNIPPET_BYTES` are read, which leaves out
+/// streams, so that the response isn't held up or kept in memory for that.
+pub async fn capture_error_body(response: Response) -> Response {
+    if !response.status().is_server_error() {
+        return response;
+    }
+    let content_length = response
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|length| length.to_str().ok()?.parse::<u64>().ok());
+    let len = content_length.or(response.body().size_hint().exact());
+    if len.is_none_or(|len| len > MAX_SNIPPET_BYTES as u64) {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let Ok(bytes) = body::to_bytes(body, MAX_SNIPPET_BYTES).await else {
+        // It's gone either way, so all that's left is to say so.
+        tracing::error!("failed to read the body of a server error");
+        return Response::from_parts(parts, Body::empty());
+    };
+    let snippet = String::from_utf8_lossy(&bytes).into_owned();
+    parts.extensions.insert(ErrorBody(snippet));
+    Response::from_parts(parts, Body::from(bytes))
+}
+
+/// For `TraceLayer::on_response`: log server errors, with the start of their
+/// body if `capture_error_body` kept it, and client errors, without.
+pub fn log_failure(response: &Response, latency: Duration) {
+    let status = response.status();
+    let matched_path = RequestInfo::of(response).and_then(RequestInfo::matched_path);
+    let latency_ms = latency.as_millis() as u64;
+    if status.is_server_error() {
+        let body = response
+            .extensions()
+            .get::<ErrorBody>()
+            .map(|ErrorBody(body)| body.as_str());
+        tracing::error!(
+            status = status.as_u16(),
+            matched_path,
+            latency_ms,
+            body,
+            "request failed"
+        );
+    } else if status.is_client_error() {
+        tracing::warn!(
+            status = status.as_u16(),
+            matched_path,
+            latency_ms,
+            "request was rejected"
+        );
+    }
+}
@@ -0,0 +1,55 @@
+use std::sync::{Arc, Mutex};
+
+use axum::extract::State;
+use axum::http::StatusCode;
+use tracing_subscriber::filter::{EnvFilter, ParseError};
+use tracing_subscriber::{reload, Registry};
+
+use crate::AppState;
+
+/// The `EnvFilter` every event goes through, which can be swapped for another
+/// one while running, and what it was made from, since it can't be asked.
+#[derive(Clone)]
+pub struct LogLevel {
+    handle: reload::Handle<EnvFilter, Registry>,
+    directives: Arc<Mutex<String>>,
+}
+
+impl LogLevel {
+    /// The filter for `directives`, like `RUST_LOG`, to put in front of the
+    /// other layers, and the `LogLevel` to change it later.
+    pub fn new(directives: &str) -> Result<(reload::Layer<EnvFilter, Registry>, Self), ParseError> {
+        let (filter, handle) = reload::Layer::new(EnvFilter::try_new(directives)?);
+        let log_level = Self {
+            handle,
+            directives: Arc::new(Mutex::new(directives.to_owned())),
+        };
+        Ok((filter, log_level))
+    }
+}
+
+/// The directives the filter was made from.
+pub async fn show(State(state): State<AppState>) -> String {
+    state.log_level.directives.lock().unwrap().clone()
+}
+
+/// Filter with the directives in the body from now on, if they're valid.
+pub async fn set(
+    State(state): State<AppState>,
+    directives: String,
+) -> Result<String, (StatusCode, String)> {
+    let directives = directives.trim();
+    let filter = EnvFilter::try_new(directives)
+        .map_err(|err| (StatusCode::BAD_REQUEST, format!("{err}\n")))?;
+    let log_level = &state.log_level;
+    // Locked throughout, so that concurrent changes are stored in the order
+    // they're made.
+    let mut current = log_level.directives.lock().unwrap();
+    log_level.handle.reload(filter).map_err(|err| {
+        tracing::error!(%err, "failed to change the log level");
+        (StatusCode::INTERNAL_SERVER_ERROR, String::new())
+    })?;
+    tracing::info!(directives, "changed the log level");
+    directives.clone_into(&mut current);
+    Ok(current.clone())
+}
@@ -1,55 +1,784 @@
+use std::env;
+use std::fs::OpenOptions;
+use std::net::SocketAddr;
 use std::time::Duration;
 
 use axum::body::Bytes;
-use axum::extract::MatchedPath;
-use axum::http::{HeaderMap, Request};
-use axum::response::{Html, Response};
+use axum::extract::{ConnectInfo, Extension, MatchedPath, State};
+use axum::http::{HeaderMap, HeaderValue, Request, StatusCode};
+use axum::middleware;
+use axum::response::{Html, IntoResponse, Response};
 use axum::routing::get;
-use axum::Router;
+use axum::{Json, Router};
 use tokio::net::TcpListener;
+use tokio::signal;
+use tower::ServiceBuilder;
 use tower_http::classify::ServerErrorsFailureClass;
+use tower_http::request_id::{
+    MakeRequestUuid, PropagateRequestIdLayer, RequestId, SetRequestIdLayer,
+};
 use tower_http::trace::TraceLayer;
-use tracing::{info_span, Span};
+use tower_http::validate_request::ValidateRequestHeaderLayer;
+use tracing::{info_span, Span, Subscriber};
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::fmt::format::JsonFields;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
 use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::registry::LookupSpan;
 use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::Layer;
+
+use crate::flat_json::FlatJson;
+use crate::log_level::LogLevel;
+use crate::metrics::Metrics;
+use crate::slow::SlowRequests;
+use crate::trace_filter::TraceFilter;
+
+mod client;
+mod failures;
+mod flat_json;
+mod log_level;
+mod metrics;
+mod otel;
+mod request_info;
+mod slow;
+mod trace_filter;
+
+#[derive(Clone)]
+struct AppState {
+    http: reqwest::Client,
+    /// Where `/chained` calls `/`, as if it was another service.
+    hello_url: String,
+    slow_requests: SlowRequests,
+    metrics: Metrics,
+    /// Whether the proxies in front of us say who the client is, with
+    /// `X-Forwarded-For` or `Forwarded`.
+    trust_proxy: bool,
+    trace_filter: TraceFilter,
+    log_level: LogLevel,
+    /// The bearer token for the `/debug/log-level` routes, which aren't there
+    /// without one.
+    admin_token: Option<String>,
+}
+
+/// The filter if `RUST_LOG` isn't set, or isn't valid.
+///
+/// axum logs rejections from built-in extractors with the `axum::rejection`
+/// target, at `TRACE` level. `axum::rejection=trace` enables showing those events
+const DEFAULT_DIRECTIVES: &str = "tracing_aka_logging=debug,tower_http=debug,axum::rejection=trace";
 
 #[tokio::main]
 async fn main() {
+    // Dropping it would lose what's still to be written to `LOG_FILE`.
+    let (_guard, log_level) = init_tracing();
+
+    let listener = TcpListener::bind("127.0.0.1:3000").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tracing::debug!("listening on {addr}");
+    let admin_token = env::var("ADMIN_TOKEN")
+        .ok()
+        .filter(|token| !token.is_empty());
+    if admin_token.is_none() {
+        tracing::warn!("ADMIN_TOKEN isn't set, so /debug/log-level is turned off");
+    }
+    let state = AppState {
+        http: reqwest::Client::new(),
+        hello_url: format!("http://{addr}/"),
+        slow_requests: SlowRequests::from_env(),
+        metrics: Metrics::default(),
+        trust_proxy: env::var("TRUST_PROXY").is_ok_and(|trust| trust == "true"),
+        trace_filter: TraceFilter::from_env(),
+        log_level,
+        admin_token,
+    };
+    let app = app(state).into_make_service_with_connect_info::<SocketAddr>();
+    axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown_signal())
+        .await
+        .unwrap();
+
+    // Sends off the spans that haven't been exported yet, if any are.
+    opentelemetry::global::shutdown_tracer_provider();
+}
+
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        signal::ctrl_c()
+            .await
+            .expect("failed to install Ctrl+C handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        signal::unix::signal(signal::unix::SignalKind::terminate())
+            .expect("failed to install signal handler")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {}
+    }
+}
+
+/// Logs as JSON lines if `LOG_FORMAT` is `json`, and for people otherwise,
+/// with colors unless `LOG_ANSI` is `false`, to `LOG_FILE` if it's set and
+/// stdout if not. The spans are exported to `OTEL_EXPORTER_OTLP_ENDPOINT` as
+/// well, if it's set.
+fn init_tracing() -> (Option<WorkerGuard>, LogLevel) {
+    let (writer, guard) = match env::var_os("LOG_FILE") {
+        Some(path) => {
+            let file = OpenOptions::new().create(true).append(true).open(&path);
+            let file = file.unwrap_or_else(|err| {
+                eprintln!("failed to open {}: {err}", path.to_string_lossy());
+                std::process::exit(1);
+            });
+            let (writer, guard) = tracing_appender::non_blocking(file);
+            (BoxMakeWriter::new(writer), Some(guard))
+        }
+        None => (BoxMakeWriter::new(std::io::stdout), None),
+    };
+    let json = env::var("LOG_FORMAT").is_ok_and(|format| format == "json");
+    let ansi = env::var("LOG_ANSI").map_or(true, |ansi| ansi != "false");
+    let otel = env::var("OTEL_EXPORTER_OTLP_ENDPOINT")
+        .ok()
+        .map(|endpoint| {
+            let tracer = otel::tracer(&endpoint).unwrap_or_else(|err| {
+                eprintln!("failed to set up exporting to {endpoint}: {err}");
+                std::process::exit(1);
+            });
+            tracing_opentelemetry::layer().with_tracer(tracer)
+        });
+
+    let (filter, log_level) = env::var("RUST_LOG")
+        .ok()
+        .and_then(|directives| LogLevel::new(&directives).ok())
+        .unwrap_or_else(|| LogLevel::new(DEFAULT_DIRECTIVES).unwrap());
+
     tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| {
-                // axum logs rejections from built-in extractors with the `axum::rejection`
-                // target, at `TRACE` level. `axum::rejection=trace` enables showing those events
-                "example_tracing_aka_logging=debug,tower_http=debug,axum::rejection=trace".into()
-            }),
-        )
-        .with(tracing_subscriber::fmt::layer())
+        .with(filter)
+        .with(fmt_layer(json, ansi, writer))
+        .with(otel)
         .init();
+    (guard, log_level)
+}
+
+fn fmt_layer<S>(json: bool, ansi: bool, writer: BoxMakeWriter) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    if json {
+        tracing_subscriber::fmt::layer()
+            .fmt_fields(JsonFields::new())
+            .event_format(FlatJson)
+            .with_writer(writer)
+            .boxed()
+    } else {
+        tracing_subscriber::fmt::layer()
+            .with_ansi(ansi)
+            .with_writer(writer)
+            .boxed()
+    }
+}
 
-    let app = Router::new()
+fn app(state: AppState) -> Router {
+    let slow_requests = state.slow_requests.clone();
+    let trust_proxy = state.trust_proxy;
+    let trace_filter = state.trace_filter.clone();
+    let admin = match &state.admin_token {
+        Some(token) => Router::new()
+            .route("/debug/log-level", get(log_level::show).put(log_level::set))
+            .route_layer(ValidateRequestHeaderLayer::bearer(token)),
+        None => Router::new(),
+    };
+    Router::new()
         .route("/", get(handler))
+        .route("/healthz", get(healthz))
+        .route("/boom", get(boom))
+        .route("/chained", get(chained))
+        .route("/slow", get(slow::slow))
+        .route("/debug/slow-stats", get(slow::stats))
+        .route("/metrics", get(metrics::render))
+        .merge(admin)
         .layer(
-            TraceLayer::new_for_http()
-                .make_span_with(|request: &Request<_>| {
-                    let matched_path = request
-                        .extensions()
-                        .get::<MatchedPath>()
-                        .map(MatchedPath::as_str);
-
-                    info_span!("http_request", method = ?request.method(), matched_path, some_other_field = tracing::field::Empty)
-                })
-                .on_request(|_request: &Request<_>, _span: &Span| {})
-                .on_response(|_response: &Response, _latency: Duration, _span: &Span| {})
-                .on_body_chunk(|_chunk: &Bytes, _latency: Duration, _span: &Span| {})
-                .on_eos(|_trailers: Option<&HeaderMap>, _stream_duration: Duration, _span: &Span| {})
-                .on_failure(|_error: ServerErrorsFailureClass, _latency: Duration, _span: &Span| {})
-    );
+            // From the outside in: a request id is set first, unless the client
+            // sent a usable one, so that the span has it, and it's copied to the
+            // response last.
+            ServiceBuilder::new()
+                .layer(middleware::map_request(drop_invalid_request_id))
+                .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+                .layer(
+                    TraceLayer::new_for_http()
+                        .make_span_with(move |request: &Request<_>| {
+                            if !trace_filter.should_trace(request.uri().path()) {
+                                return Span::none();
+                            }
 
-    let listener = TcpListener::bind("127.0.0.1:3000").await.unwrap();
-    tracing::debug!("listening on {}", listener.local_addr().unwrap());
-    axum::serve(listener, app).await.unwrap();
+                            let matched_path = request
+                                .extensions()
+                                .get::<MatchedPath>()
+                                .map(MatchedPath::as_str);
+                            let request_id = request
+                                .extensions()
+                                .get::<RequestId>()
+                                .and_then(|id| id.header_value().to_str().ok());
+
+                            let client_ip = request
+                                .extensions()
+                                .get::<ConnectInfo<SocketAddr>>()
+                                .map(|ConnectInfo(peer)| {
+                                    client::resolve_client_ip(
+                                        request.headers(),
+                                        peer.ip(),
+                                        trust_proxy,
+                                    )
+                                });
+                            let user_agent = client::user_agent(request.headers());
+
+                            let span = info_span!(
+                                "http_request",
+                                method = ?request.method(),
+                                matched_path,
+                                request_id,
+                                client_ip = client_ip.map(tracing::field::display),
+                                user_agent,
+                            );
+                            // Part of the caller's trace, if it sent a `traceparent`.
+                            span.set_parent(otel::extract_context(request.headers()));
+                            span
+                        })
+                        .on_request(|_request: &Request<_>, _span: &Span| {})
+                        .on_response(move |response: &Response, latency: Duration, span: &Span| {
+                            // Even if the request isn't traced otherwise.
+                            failures::log_failure(response, latency);
+                            // Ignored or not sampled.
+                            if span.is_none() {
+                                return;
+                            }
+                            slow_requests.record(response, latency);
+                        })
+                        .on_body_chunk(|_chunk: &Bytes, _latency: Duration, _span: &Span| {})
+                        .on_eos(
+                            |_trailers: Option<&HeaderMap>,
+                             _stream_duration: Duration,
+                             _span: &Span| {},
+                        )
+                        .on_failure(
+                            |_error: ServerErrorsFailureClass, _latency: Duration, _span: &Span| {},
+                        ),
+                )
+                .layer(PropagateRequestIdLayer::x_request_id())
+                .layer(middleware::from_fn(request_info::remember))
+                .layer(middleware::map_response(failures::capture_error_body))
+                .layer(middleware::from_fn_with_state(
+                    state.metrics.clone(),
+                    metrics::track,
+                )),
+        )
+        .with_state(state)
+}
+
+/// A client's `x-request-id` is kept if it's 1 to 128 letters, digits, `-`,
+/// `_` or `.`, which is safe to log and show as is. Anything else is dropped,
+/// for `SetRequestIdLayer` to make a new one.
+async fn drop_invalid_request_id<B>(mut request: Request<B>) -> Request<B> {
+    let headers = request.headers_mut();
+    if headers
+        .get("x-request-id")
+        .is_some_and(|id| !is_valid_request_id(id))
+    {
+        headers.remove("x-request-id");
+    }
+    request
+}
+
+fn is_valid_request_id(id: &HeaderValue) -> bool {
+    let id = id.as_bytes();
+    (1..=128).contains(&id.len())
+        && id
+            .iter()
+            .all(|&b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.'))
+}
+
+async fn healthz() -> &'static str {
+    "ok"
+}
+
+/// Fails, to see what's logged when a handler does.
+async fn boom() -> impl IntoResponse {
+    let error = serde_json::json!({ "error": "boom", "detail": "the handler blew up" });
+    (StatusCode::INTERNAL_SERVER_ERROR, Json(error))
+}
+
+async fn handler(Extension(request_id): Extension<RequestId>) -> Html<String> {
+    let request_id = request_id.header_value().to_str().unwrap_or_default();
+    tracing::info!("saying hello");
+    Html(format!(
+        "<h1>Hello, World</h1>\n<p>Request id: <code>{request_id}</code></p>"
+    ))
 }
 
-async fn handler() -> Html<&'static str> {
-    Html("<h1>Hello, World</h1>")
+/// Calls `/` like it would another service, with the trace for it to go on
+/// with.
+async fn chained(State(state): State<AppState>) -> Result<Html<String>, StatusCode> {
+    let mut headers = HeaderMap::new();
+    otel::inject_context(&Span::current().context(), &mut headers);
+    let hello = async {
+        let response = state
+            .http
+            .get(&state.hello_url)
+            .headers(headers)
+            .send()
+            .await?;
+        response.error_for_status()?.text().await
+    };
+    match hello.await {
+        Ok(hello) => Ok(Html(format!("<p>/ said:</p>\n{hello}"))),
+        Err(err) => {
+            tracing::error!(%err, "calling / failed");
+            Err(StatusCode::BAD_GATEWAY)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::sync::{Arc, Mutex};
+
+    use axum::body::Body;
+    use axum::http::request::Builder;
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+    use tracing::subscriber::DefaultGuard;
+    use tracing_subscriber::fmt::MakeWriter;
+
+    use super::*;
+
+    pub(crate) fn state(hello_url: &str) -> AppState {
+        AppState {
+            http: reqwest::Client::new(),
+            hello_url: hello_url.to_owned(),
+            slow_requests: SlowRequests::new(Duration::from_secs(1)),
+            metrics: Metrics::default(),
+            trust_proxy: false,
+            trace_filter: TraceFilter::default(),
+            // Changes nothing, with the filter dropped.
+            log_level: LogLevel::new("debug").unwrap().1,
+            admin_token: Some("secret-token".to_owned()),
+        }
+    }
+
+    async fn get(request_id: Option<&str>) -> (Response<()>, String) {
+        let mut request = Request::get("/");
+        if let Some(id) = request_id {
+            request = request.header("x-request-id", id);
+        }
+        let response = app(state("http://127.0.0.1:3000/"))
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let (parts, body) = response.into_parts();
+        let body = body.collect().await.unwrap().to_bytes();
+        (
+            Response::from_parts(parts, ()),
+            String::from_utf8(body.to_vec()).unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn generated_ids_are_in_the_header_and_the_body() {
+        let (response, body) = get(None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let id = response.headers()["x-request-id"].to_str().unwrap();
+        assert_eq!(id.len(), 36, "{id}");
+        assert!(body.contains(&format!("<code>{id}</code>")), "{body}");
+
+        let (other, _) = get(None).await;
+        assert_ne!(other.headers()["x-request-id"], id);
+    }
+
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'a> MakeWriter<'a> for Captured {
+        type Writer = Self;
+
+        fn make_writer(&'a self) -> Self::Writer {
+            self.clone()
+        }
+    }
+
+    impl Captured {
+        /// Everything logged as JSON on this thread until the guard is dropped.
+        fn json_logs() -> (Self, DefaultGuard) {
+            let captured = Self::default();
+            let subscriber = tracing_subscriber::registry().with(fmt_layer(
+                true,
+                false,
+                BoxMakeWriter::new(captured.clone()),
+            ));
+            (captured, tracing::subscriber::set_default(subscriber))
+        }
+
+        fn lines(&self) -> Vec<serde_json::Value> {
+            let output = String::from_utf8(self.0.lock().unwrap().clone()).unwrap();
+            output
+                .lines()
+                .map(|line| serde_json::from_str(line).unwrap())
+                .collect()
+        }
+    }
+
+    #[tokio::test]
+    async fn json_lines_have_the_span_fields_at_the_top() {
+        let (captured, _default) = Captured::json_logs();
+
+        get(Some("abc123")).await;
+
+        let lines = captured.lines();
+        let line = lines
+            .iter()
+            .find(|line| line["message"] == "saying hello")
+            .unwrap_or_else(|| panic!("no such line in {lines:?}"));
+        assert_eq!(line["method"], "GET", "{line}");
+        assert_eq!(line["matched_path"], "/", "{line}");
+        assert_eq!(line["request_id"], "abc123", "{line}");
+        assert_eq!(line["level"], "INFO", "{line}");
+    }
+
+    /// The fields of the span `/` says hello in, with a request from 10.0.0.1
+    /// with `headers`.
+    async fn hello_span(trust_proxy: bool, headers: &[(&str, &str)]) -> serde_json::Value {
+        let (captured, _default) = Captured::json_logs();
+        let app = app(AppState {
+            trust_proxy,
+            ..state("http://127.0.0.1:3000/")
+        });
+
+        // As `into_make_service_with_connect_info` would.
+        let peer = SocketAddr::from(([10, 0, 0, 1], 4711));
+        let mut request = Request::get("/").extension(ConnectInfo(peer));
+        for &(name, value) in headers {
+            request = request.header(name, value);
+        }
+        app.oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        let lines = captured.lines();
+        lines
+            .into_iter()
+            .find(|line| line["message"] == "saying hello")
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn spans_have_the_client() {
+        let headers = [
+            ("user-agent", "curl/8.5.0"),
+            ("x-forwarded-for", "203.0.113.7, 10.0.0.2"),
+        ];
+        let line = hello_span(false, &headers).await;
+        assert_eq!(line["client_ip"], "10.0.0.1", "{line}");
+        assert_eq!(line["user_agent"], "curl/8.5.0", "{line}");
+
+        let line = hello_span(true, &headers).await;
+        assert_eq!(line["client_ip"], "203.0.113.7", "{line}");
+
+        let line = hello_span(true, &[("x-forwarded-for", "garbage")]).await;
+        assert_eq!(line["client_ip"], "10.0.0.1", "{line}");
+        assert!(line.get("user_agent").is_none(), "{line}");
+    }
+
+    /// What's logged for a request to `uri`, filtered by `trace_filter`.
+    async fn logged(trace_filter: TraceFilter, uri: &str) -> Vec<serde_json::Value> {
+        let (captured, _default) = Captured::json_logs();
+        let app = app(AppState {
+            trace_filter,
+            // Logged for every request, if it's traced.
+            slow_requests: SlowRequests::new(Duration::ZERO),
+            ..state("http://127.0.0.1:3000/")
+        });
+        let request = Request::get(uri).body(Body::empty()).unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        captured.lines()
+    }
+
+    #[tokio::test]
+    async fn ignored_paths_are_not_traced() {
+        let filter = TraceFilter::new(vec!["/healthz".to_owned()], 1.0);
+        let lines = logged(filter.clone(), "/healthz").await;
+        assert!(lines.is_empty(), "{lines:?}");
+
+        let lines = logged(filter, "/").await;
+        assert!(
+            lines.iter().any(|line| line["target"] == "slow_request"),
+            "{lines:?}"
+        );
+        let hello = lines.iter().find(|line| line["message"] == "saying hello");
+        assert_eq!(hello.unwrap()["matched_path"], "/", "{lines:?}");
+    }
+
+    #[tokio::test]
+    async fn only_sampled_requests_are_traced() {
+        for _ in 0..10 {
+            let lines = logged(TraceFilter::new(Vec::new(), 1.0), "/").await;
+            let hello = lines.iter().find(|line| line["message"] == "saying hello");
+            assert_eq!(hello.unwrap()["method"], "GET", "{lines:?}");
+        }
+
+        let lines = logged(TraceFilter::new(Vec::new(), 0.0), "/").await;
+        assert!(
+            !lines.iter().any(|line| line["target"] == "slow_request"),
+            "{lines:?}"
+        );
+        // The handler still logs, but not in a span.
+        let hello = lines.iter().find(|line| line["message"] == "saying hello");
+        assert!(hello.unwrap().get("method").is_none(), "{lines:?}");
+    }
+
+    #[tokio::test]
+    async fn server_errors_are_logged_with_their_body() {
+        let (captured, _default) = Captured::json_logs();
+        let request = Request::get("/boom").body(Body::empty()).unwrap();
+        let response = app(state("http://127.0.0.1:3000/"))
+            .oneshot(request)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains(r#""error":"boom""#), "{body}");
+
+        let lines = captured.lines();
+        let failed = lines
+            .iter()
+            .find(|line| line["message"] == "request failed")
+            .unwrap_or_else(|| panic!("{lines:?}"));
+        assert_eq!(failed["level"], "ERROR");
+        assert_eq!(failed["status"], 500);
+        assert_eq!(failed["matched_path"], "/boom");
+        assert!(failed["latency_ms"].is_u64(), "{failed}");
+        assert_eq!(failed["body"], body);
+    }
+
+    #[tokio::test]
+    async fn client_errors_are_logged_without_their_body() {
+        let (captured, _default) = Captured::json_logs();
+        let request = Request::get("/no/such/page").body(Body::empty()).unwrap();
+        let response = app(state("http://127.0.0.1:3000/"))
+            .oneshot(request)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let lines = captured.lines();
+        let rejected = lines
+            .iter()
+            .find(|line| line["message"] == "request was rejected")
+            .unwrap_or_else(|| panic!("{lines:?}"));
+        assert_eq!(rejected["level"], "WARN");
+        assert_eq!(rejected["status"], 404);
+        assert!(rejected.get("body").is_none(), "{rejected}");
+        assert!(
+            !lines.iter().any(|line| line["message"] == "request failed"),
+            "{lines:?}"
+        );
+    }
+
+    async fn send(app: &Router, request: Builder, body: &str) -> (StatusCode, String) {
+        let request = request.body(Body::from(body.to_owned())).unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    fn admin(request: Builder) -> Builder {
+        request.header("authorization", "Bearer secret-token")
+    }
+
+    #[tokio::test]
+    async fn the_log_level_changes_at_runtime() {
+        let captured = Captured::default();
+        let (filter, log_level) = LogLevel::new("debug").unwrap();
+        let subscriber = tracing_subscriber::registry().with(filter).with(fmt_layer(
+            true,
+            false,
+            BoxMakeWriter::new(captured.clone()),
+        ));
+        let _default = tracing::subscriber::set_default(subscriber);
+        let app = app(AppState {
+            log_level,
+            ..state("http://127.0.0.1:3000/")
+        });
+        let said_hello = || {
+            let said = captured
+                .lines()
+                .iter()
+                .filter(|line| line["message"] == "saying hello")
+                .count();
+            captured.0.lock().unwrap().clear();
+            said
+        };
+
+        let (status, body) = send(&app, admin(Request::put("/debug/log-level")), "error\n").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "error");
+        send(&app, Request::get("/"), "").await;
+        assert_eq!(said_hello(), 0);
+        let (_, body) = send(&app, admin(Request::get("/debug/log-level")), "").await;
+        assert_eq!(body, "error");
+
+        send(&app, admin(Request::put("/debug/log-level")), "debug").await;
+        send(&app, Request::get("/"), "").await;
+        assert_eq!(said_hello(), 1);
+    }
+
+    #[tokio::test]
+    async fn malformed_directives_are_a_400() {
+        let app = app(state("http://127.0.0.1:3000/"));
+        let (status, body) = send(
+            &app,
+            admin(Request::put("/debug/log-level")),
+            "tower_http=loud",
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(!body.is_empty());
+        let (_, body) = send(&app, admin(Request::get("/debug/log-level")), "").await;
+        assert_eq!(body, "debug");
+    }
+
+    #[tokio::test]
+    async fn the_log_level_needs_the_token() {
+        let app = app(state("http://127.0.0.1:3000/"));
+        for request in [
+            Request::get("/debug/log-level"),
+            Request::put("/debug/log-level"),
+            Request::put("/debug/log-level").header("authorization", "Bearer nope"),
+        ] {
+            let (status, _) = send(&app, request, "trace").await;
+            assert_eq!(status, StatusCode::UNAUTHORIZED);
+        }
+    }
+
+    #[tokio::test]
+    async fn the_log_level_is_off_without_a_token() {
+        let app = app(AppState {
+            admin_token: None,
+            ..state("http://127.0.0.1:3000/")
+        });
+        let (status, _) = send(&app, admin(Request::put("/debug/log-level")), "trace").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    async fn slow_stats(app: Router) -> serde_json::Value {
+        let request = Request::get("/debug/slow-stats")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn slow_requests_are_logged_and_counted() {
+        let (captured, _default) = Captured::json_logs();
+        let app = app(AppState {
+            slow_requests: SlowRequests::new(Duration::from_millis(1)),
+            ..state("http://127.0.0.1:3000/")
+        });
+
+        let request = Request::get("/slow?ms=5").body(Body::empty()).unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let lines = captured.lines();
+        let slow = lines
+            .iter()
+            .filter(|line| line["target"] == "slow_request")
+            .collect::<Vec<_>>();
+        assert_eq!(slow.len(), 1, "{lines:?}");
+        assert_eq!(slow[0]["level"], "WARN");
+        assert_eq!(slow[0]["matched_path"], "/slow");
+        assert_eq!(slow[0]["method"], "GET");
+        assert!(slow[0]["latency_ms"].as_u64().unwrap() >= 5, "{}", slow[0]);
+
+        assert_eq!(slow_stats(app).await, serde_json::json!({ "/slow": 1 }));
+    }
+
+    #[tokio::test]
+    async fn fast_requests_are_neither() {
+        let (captured, _default) = Captured::json_logs();
+        let app = app(state("http://127.0.0.1:3000/"));
+
+        let request = Request::get("/").body(Body::empty()).unwrap();
+        app.clone().oneshot(request).await.unwrap();
+
+        let lines = captured.lines();
+        assert!(
+            !lines.iter().any(|line| line["target"] == "slow_request"),
+            "{lines:?}"
+        );
+        assert_eq!(slow_stats(app).await, serde_json::json!({}));
+    }
+
+    #[tokio::test]
+    async fn chained_calls_the_first_route() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let hello_url = format!("http://{}/", listener.local_addr().unwrap());
+        let app = app(state(&hello_url));
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let response = reqwest::get(format!("{hello_url}chained")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.text().await.unwrap();
+        assert!(body.contains("<h1>Hello, World</h1>"), "{body}");
+    }
+
+    #[tokio::test]
+    async fn chained_fails_if_the_first_route_does() {
+        let request = Request::get("/chained").body(Body::empty()).unwrap();
+        let response = app(state("http://127.0.0.1:1/"))
+            .oneshot(request)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+    }
+
+    #[tokio::test]
+    async fn supplied_ids_are_propagated() {
+        let (response, body) = get(Some("abc123")).await;
+        assert_eq!(response.headers()["x-request-id"], "abc123");
+        assert!(body.contains("<code>abc123</code>"), "{body}");
+    }
+
+    #[tokio::test]
+    async fn invalid_supplied_ids_are_replaced() {
+        let too_long = "a".repeat(129);
+        for id in ["<script>", "a/b", &too_long] {
+            let (response, body) = get(Some(id)).await;
+            let replaced = response.headers()["x-request-id"].to_str().unwrap();
+            assert_eq!(replaced.len(), 36, "{id:?}");
+            assert!(!body.contains(id), "{body}");
+        }
+    }
 }
@@ -0,0 +1,303 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use axum::extract::{MatchedPath, Request, State};
+use axum::http::header;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+
+use crate::AppState;
+
+/// The upper bounds of the latency histogram's buckets, in milliseconds.
+const BUCKETS: [u64; 11] = [5, 10, 25, 50, 100, 250, 500, 1000, 2500, 5000, 10000];
+
+/// The `path` of requests that didn't match a route, rather than their URL,
+/// which would make a new series for every one of them.
+const UNMATCHED: &str = "unmatched";
+
+/// `(path, method)`
+type Route = (String, String);
+
+/// Request metrics by route, for `/metrics`.
+#[derive(Clone, Default)]
+pub struct Metrics(Arc<Registry>);
+
+#[derive(Default)]
+struct Registry {
+    requests: Series<(Route, u16), AtomicU64>,
+    in_flight: Series<Route, AtomicI64>,
+    durations: Series<Route, Histogram>,
+}
+
+/// One value per set of labels, added the first time it's used.
+struct Series<K, V>(RwLock<HashMap<K, V>>);
+
+impl<K, V> Default for Series<K, V> {
+    fn default() -> Self {
+        Self(RwLock::default())
+    }
+}
+
+impl<K: Eq + Hash + Clone + Ord, V: Default> Series<K, V> {
+    fn with(&self, labels: &K, f: impl FnOnce(&V)) {
+        if let Some(value) = self.0.read().unwrap().get(labels) {
+            return f(value);
+        }
+        f(self.0.write().unwrap().entry(labels.clone()).or_default());
+    }
+
+    /// Sorted by labels, so that `/metrics` doesn't shuffle its lines.
+    fn each(&self, mut f: impl FnMut(&K, &V)) {
+        let values = self.0.read().unwrap();
+        let mut labels = values.keys().collect::<Vec<_>>();
+        labels.sort();
+        for labels in labels {
+            f(labels, &values[labels]);
+        }
+    }
+}
+
+#[derive(Default)]
+struct Histogram {
+    /// How many took at most each of `BUCKETS`, so already cumulative.
+    buckets: [AtomicU64; BUCKETS.len()],
+    count: AtomicU64,
+    sum_nanos: AtomicU64,
+}
+
+impl Histogram {
+    fn observe(&self, latency: Duration) {
+        for (bucket, &le) in self.buckets.iter().zip(&BUCKETS) {
+            if latency <= Duration::from_millis(le) {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_nanos
+            .fetch_add(latency.as_nanos() as u64, Ordering::Relaxed);
+    }
+}
+
+/// Takes the request out of `in_flight` again however it ends, including the
+/// client going away before the response.
+struct InFlight<'a> {
+    metrics: &'a Metrics,
+    route: &'a Route,
+}
+
+impl<'a> InFlight<'a> {
+    fn start(metrics: &'a Metrics, route: &'a Route) -> Self {
+        metrics
+            .0
+            .in_flight
+            .with(route, |gauge| _ = gauge.fetch_add(1, Ordering::Relaxed));
+        Self { metrics, route }
+    }
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.metrics.0.in_flight.with(self.route, |gauge| {
+            _ = gauge.fetch_sub(1, Ordering::Relaxed)
+        });
+    }
+}
+
+/// Count the request and time it, by its route and method.
+pub async fn track(State(metrics): State<Metrics>, request: Request, next: Next) -> Response {
+    let path = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or(UNMATCHED, MatchedPath::as_str);
+    let route = (path.to_owned(), request.method().to_string());
+
+    let in_flight = InFlight::start(&metrics, &route);
+    let start = Instant::now();
+    let response = next.run(request).await;
+    let latency = start.elapsed();
+    drop(in_flight);
+
+    let registry = &metrics.0;
+    let status = response.status().as_u16();
+    registry.requests.with(&(route.clone(), status), |counter| {
+        counter.fetch_add(1, Ordering::Relaxed);
+    });
+    registry
+        .durations
+        .with(&route, |histogram| histogram.observe(latency));
+    response
+}
+
+/// Everything so far, in Prometheus' text format.
+pub async fn render(State(state): State<AppState>) -> impl IntoResponse {
+    let registry = &state.metrics.0;
+    let mut out = String::new();
+
+    out.push_str("# HELP http_requests_total Requests answered, by route, method and status.\n");
+    out.push_str("# TYPE http_requests_total counter\n");
+    registry.requests.each(|((path, method), status), counter| {
+        let count = counter.load(Ordering::Relaxed);
+        let labels = format!("{},status=\"{status}\"", route_labels(path, method));
+        writeln!(out, "http_requests_total{{{labels}}} {count}").unwrap();
+    });
+
+    out.push_str("# HELP http_requests_in_flight Requests being answered right now.\n");
+    out.push_str("# TYPE http_requests_in_flight gauge\n");
+    registry.in_flight.each(|(path, method), gauge| {
+        let labels = route_labels(path, method);
+        let value = gauge.load(Ordering::Relaxed);
+        writeln!(out, "http_requests_in_flight{{{labels}}} {value}").unwrap();
+    });
+
+    out.push_str("# HELP http_request_duration_seconds How long requests took to answer.\n");
+    out.push_str("# TYPE http_request_duration_seconds histogram\n");
+    registry.durations.each(|(path, method), histogram| {
+        let labels = route_labels(path, method);
+        let name = "http_request_duration_seconds";
+        for (bucket, le) in histogram.buckets.iter().zip(BUCKETS) {
+            let le = le as f64 / 1000.0;
+            let count = bucket.load(Ordering::Relaxed);
+            writeln!(out, "{name}_bucket{{{labels},le=\"{le}\"}} {count}").unwrap();
+        }
+        let count = histogram.count.load(Ordering::Relaxed);
+        writeln!(out, "{name}_bucket{{{labels},le=\"+Inf\"}} {count}").unwrap();
+        let sum = histogram.sum_nanos.load(Ordering::Relaxed) as f64 / 1e9;
+        writeln!(out, "{name}_sum{{{labels}}} {sum}").unwrap();
+        writeln!(out, "{name}_count{{{labels}}} {count}").unwrap();
+    });
+
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], out)
+}
+
+fn route_labels(path: &str, method: &str) -> String {
+    format!(
+        "path=\"{}\",method=\"{}\"",
+        escape_label(path),
+        escape_label(method)
+    )
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', r"\\")
+        .replace('"', r#"\""#)
+        .replace('\n', r"\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+    use axum::Router;
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{app, tests::state};
+
+    async fn get(app: &Router, uri: &str) -> String {
+        let request = Request::get(uri).body(Body::empty()).unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    /// Every sample in `metrics`, by its name and labels as they're written.
+    fn samples(metrics: &str) -> HashMap<&str, f64> {
+        metrics
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .map(|line| {
+                let (series, value) = line.rsplit_once(' ').unwrap();
+                (series, value.parse().unwrap())
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn counts_and_times_requests_by_route() {
+        let app = app(state("http://127.0.0.1:3000/"));
+        for _ in 0..3 {
+            get(&app, "/").await;
+        }
+        get(&app, "/slow?ms=0").await;
+        get(&app, "/no/such/page").await;
+        get(&app, "/nor/this/one").await;
+
+        let metrics = get(&app, "/metrics").await;
+        let samples = samples(&metrics);
+        let sample = |series: &str| {
+            *samples
+                .get(series)
+                .unwrap_or_else(|| panic!("no {series} in {metrics}"))
+        };
+
+        assert_eq!(
+            sample(r#"http_requests_total{path="/",method="GET",status="200"}"#),
+            3.0
+        );
+        assert_eq!(
+            sample(r#"http_requests_total{path="/slow",method="GET",status="200"}"#),
+            1.0
+        );
+        assert_eq!(
+            sample(r#"http_requests_total{path="unmatched",method="GET",status="404"}"#),
+            2.0
+        );
+        assert!(!metrics.contains("/no/such/page"), "{metrics}");
+        assert_eq!(
+            sample(r#"http_requests_in_flight{path="/",method="GET"}"#),
+            0.0
+        );
+        // The request for these very metrics.
+        assert_eq!(
+            sample(r#"http_requests_in_flight{path="/metrics",method="GET"}"#),
+            1.0
+        );
+
+        let labels = r#"path="/",method="GET""#;
+        let mut previous = 0.0;
+        for le in BUCKETS
+            .iter()
+            .map(|&le| (le as f64 / 1000.0).to_string())
+            .chain(["+Inf".to_owned()])
+        {
+            let count = sample(&format!(
+                r#"http_request_duration_seconds_bucket{{{labels},le="{le}"}}"#
+            ));
+            assert!(count >= previous, "{le}: {metrics}");
+            previous = count;
+        }
+        assert_eq!(previous, 3.0);
+        assert_eq!(
+            sample(&format!("http_request_duration_seconds_count{{{labels}}}")),
+            3.0
+        );
+    }
+
+    #[test]
+    fn histograms_are_cumulative() {
+        let histogram = Histogram::default();
+        histogram.observe(Duration::from_millis(3));
+        histogram.observe(Duration::from_millis(30));
+        histogram.observe(Duration::from_secs(60));
+        let buckets = histogram
+            .buckets
+            .iter()
+            .map(|bucket| bucket.load(Ordering::Relaxed))
+            .collect::<Vec<_>>();
+        assert_eq!(buckets, [1, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2]);
+        assert_eq!(histogram.count.load(Ordering::Relaxed), 3);
+    }
+
+    #[test]
+    fn label_values_are_escaped() {
+        assert_eq!(
+            route_labels("/a\"b\\c", "GET"),
+            r#"path="/a\"b\\c",method="GET""#
+        );
+    }
+}
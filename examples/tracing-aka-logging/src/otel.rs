@@ -0,0 +1,141 @@
+use axum::http::{HeaderMap, HeaderName, HeaderValue};
+use opentelemetry::propagation::{Extractor, Injector, TextMapPropagator};
+use opentelemetry::trace::TraceError;
+use opentelemetry::{Context, KeyValue};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{self, Tracer};
+use opentelemetry_sdk::{runtime, Resource};
+
+/// What the spans are exported as.
+pub const SERVICE_NAME: &str = "example-tracing";
+
+/// A tracer exporting over OTLP/gRPC to `endpoint`, in batches that
+/// `opentelemetry::global::shutdown_tracer_provider` sends off before exiting.
+pub fn tracer(endpoint: &str) -> Result<Tracer, TraceError> {
+    let exporter = opentelemetry_otlp::new_exporter()
+        .tonic()
+        .with_endpoint(endpoint);
+    let resource = Resource::new([KeyValue::new("service.name", SERVICE_NAME)]);
+    opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(exporter)
+        .with_trace_config(trace::config().with_resource(resource))
+        .install_batch(runtime::Tokio)
+}
+
+/// The trace context of a W3C `traceparent` (and `tracestate`) header, which
+/// is empty if there's none, or it's malformed.
+pub fn extract_context(headers: &HeaderMap) -> Context {
+    TraceContextPropagator::new().extract(&HeaderExtractor(headers))
+}
+
+/// Add `cx` as W3C `traceparent` and `tracestate` headers, for the service
+/// called to continue the trace.
+pub fn inject_context(cx: &Context, headers: &mut HeaderMap) {
+    TraceContextPropagator::new().inject_context(cx, &mut HeaderInjector(headers));
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key)?.to_str().ok()
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(HeaderName::as_str).collect()
+    }
+}
+
+struct HeaderInjector<'a>(&'a mut HeaderMap);
+
+impl Injector for HeaderInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(key.as_bytes()),
+            HeaderValue::from_str(&value),
+        ) else {
+            return;
+        };
+        self.0.insert(name, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use opentelemetry::trace::{
+        SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState,
+    };
+
+    use super::*;
+
+    const TRACEPARENT: &str = "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01";
+
+    fn headers(traceparent: &'static str) -> HeaderMap {
+        HeaderMap::from_iter([(
+            HeaderName::from_static("traceparent"),
+            HeaderValue::from_static(traceparent),
+        )])
+    }
+
+    #[test]
+    fn extracts_the_traceparent() {
+        let cx = extract_context(&headers(TRACEPARENT));
+        let span = cx.span();
+        let span_context = span.span_context();
+        assert!(span_context.is_valid());
+        assert!(span_context.is_remote());
+        assert!(span_context.is_sampled());
+        assert_eq!(
+            span_context.trace_id(),
+            TraceId::from_hex("0af7651916cd43dd8448eb211c80319c").unwrap()
+        );
+        assert_eq!(
+            span_context.span_id(),
+            SpanId::from_hex("b7ad6b7169203331").unwrap()
+        );
+    }
+
+    #[test]
+    fn ignores_missing_and_malformed_traceparents() {
+        assert!(!extract_context(&HeaderMap::new()).has_active_span());
+        for traceparent in [
+            "nope",
+            "00-00000000000000000000000000000000-b7ad6b7169203331-01",
+            "00-0AF7651916CD43DD8448EB211C80319C-b7ad6b7169203331-01",
+        ] {
+            let cx = extract_context(&headers(traceparent));
+            assert!(!cx.span().span_context().is_valid(), "{traceparent}");
+        }
+    }
+
+    #[test]
+    fn injects_the_traceparent() {
+        let span_context = SpanContext::new(
+            TraceId::from_hex("0af7651916cd43dd8448eb211c80319c").unwrap(),
+            SpanId::from_hex("b7ad6b7169203331").unwrap(),
+            TraceFlags::SAMPLED,
+            false,
+            TraceState::default(),
+        );
+        let cx = Context::new().with_remote_span_context(span_context);
+        let mut headers = HeaderMap::new();
+        inject_context(&cx, &mut headers);
+        assert_eq!(headers["traceparent"], TRACEPARENT);
+    }
+
+    #[test]
+    fn injects_nothing_without_a_trace() {
+        let mut headers = HeaderMap::new();
+        inject_context(&Context::new(), &mut headers);
+        assert!(headers.is_empty());
+    }
+
+    #[test]
+    fn round_trips() {
+        let mut headers = HeaderMap::new();
+        inject_context(&extract_context(&self::headers(TRACEPARENT)), &mut headers);
+        assert_eq!(headers["traceparent"], TRACEPARENT);
+    }
+}
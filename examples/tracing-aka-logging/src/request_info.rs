@@ -0,0 +1,37 @@
+use axum::extract::{MatchedPath, Request};
+use axum::http::Method;
+use axum::middleware::Next;
+use axum::response::Response;
+
+/// The request's method and route, kept with its response for what's logged
+/// in `TraceLayer::on_response`, which only gets the response.
+#[derive(Clone)]
+pub struct RequestInfo {
+    method: Method,
+    matched_path: Option<MatchedPath>,
+}
+
+impl RequestInfo {
+    pub fn of(response: &Response) -> Option<&Self> {
+        response.extensions().get()
+    }
+
+    pub fn method(&self) -> &str {
+        self.method.as_str()
+    }
+
+    pub fn matched_path(&self) -> Option<&str> {
+        self.matched_path.as_ref().map(MatchedPath::as_str)
+    }
+}
+
+/// Keep the request's `RequestInfo` with its response.
+pub async fn remember(request: Request, next: Next) -> Response {
+    let info = RequestInfo {
+        method: request.method().clone(),
+        matched_path: request.extensions().get::<MatchedPath>().cloned(),
+    };
+    let mut response = next.run(request).await;
+    response.extensions_mut().insert(info);
+    response
+}
@@ -0,0 +1,79 @@
+use std::collections::HashMap;
+use std::env;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use axum::extract::{Query, State};
+use axum::response::Response;
+use axum::Json;
+use serde::Deserialize;
+
+use crate::request_info::RequestInfo;
+use crate::AppState;
+
+/// When `SLOW_REQUEST_MS` isn't set.
+const DEFAULT_THRESHOLD: Duration = Duration::from_secs(1);
+
+/// `/slow` doesn't sleep for longer than this, whatever it's asked to.
+const MAX_SLEEP: Duration = Duration::from_secs(10);
+
+/// Requests taking longer than `threshold` to answer, counted by route.
+#[derive(Clone)]
+pub struct SlowRequests {
+    threshold: Duration,
+    counts: Arc<Mutex<HashMap<String, u64>>>,
+}
+
+impl SlowRequests {
+    pub fn new(threshold: Duration) -> Self {
+        Self {
+            threshold,
+            counts: Arc::default(),
+        }
+    }
+
+    /// The threshold from `SLOW_REQUEST_MS`, in milliseconds, or a second.
+    pub fn from_env() -> Self {
+        let threshold = env::var("SLOW_REQUEST_MS")
+            .ok()
+            .and_then(|ms| ms.parse().ok())
+            .map_or(DEFAULT_THRESHOLD, Duration::from_millis);
+        Self::new(threshold)
+    }
+
+    /// For `TraceLayer::on_response`: warn about the request `response` is
+    /// the answer to and count it, if it took too long.
+    pub fn record(&self, response: &Response, latency: Duration) {
+        if latency <= self.threshold {
+            return;
+        }
+        let info = RequestInfo::of(response);
+        let matched_path = info.and_then(RequestInfo::matched_path);
+        let method = info.map(RequestInfo::method);
+        tracing::warn!(
+            target: "slow_request",
+            latency_ms = latency.as_millis() as u64,
+            matched_path,
+            method,
+            "slow request"
+        );
+        let route = matched_path.unwrap_or("<unmatched>").to_owned();
+        *self.counts.lock().unwrap().entry(route).or_default() += 1;
+    }
+}
+
+#[derive(Deserialize)]
+pub struct Sleep {
+    ms: u64,
+}
+
+/// Answer after `ms` milliseconds, to see slow requests logged.
+pub async fn slow(Query(Sleep { ms }): Query<Sleep>) -> &'static str {
+    tokio::time::sleep(Duration::from_millis(ms).min(MAX_SLEEP)).await;
+    "done"
+}
+
+/// How many slow requests each route has had so far.
+pub async fn stats(State(state): State<AppState>) -> Json<HashMap<String, u64>> {
+    Json(state.slow_requests.counts.lock().unwrap().clone())
+}
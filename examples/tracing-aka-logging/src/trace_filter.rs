@@ -0,0 +1,95 @@
+use std::env;
+
+/// Which requests get a span, and with it are logged: not the ones to paths
+/// under `TRACE_IGNORE_PATHS`, like a health check every second, and of the
+/// others a `TRACE_SAMPLE_RATE` share picked at random.
+#[derive(Debug, Clone)]
+pub struct TraceFilter {
+    ignore_paths: Vec<String>,
+    /// From 0, for none, to 1, for all of them.
+    sample_rate: f64,
+}
+
+impl Default for TraceFilter {
+    fn default() -> Self {
+        Self::new(Vec::new(), 1.0)
+    }
+}
+
+impl TraceFilter {
+    pub fn new(ignore_paths: Vec<String>, sample_rate: f64) -> Self {
+        Self {
+            ignore_paths,
+            sample_rate: sample_rate.clamp(0.0, 1.0),
+        }
+    }
+
+    /// `TRACE_IGNORE_PATHS` separated by commas, like `/healthz,/metrics`,
+    /// and `TRACE_SAMPLE_RATE`, which is 1 if it's unset or not a number.
+    pub fn from_env() -> Self {
+        let ignore_paths = env::var("TRACE_IGNORE_PATHS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|path| !path.is_empty())
+            .map(str::to_owned)
+            .collect();
+        let sample_rate = env::var("TRACE_SAMPLE_RATE")
+            .ok()
+            .and_then(|rate| rate.parse().ok())
+            .unwrap_or(1.0);
+        Self::new(ignore_paths, sample_rate)
+    }
+
+    /// Drawn anew for every request, so call it once per request.
+    pub fn should_trace(&self, path: &str) -> bool {
+        if self
+            .ignore_paths
+            .iter()
+            .any(|ignored| is_under(path, ignored))
+        {
+            return false;
+        }
+        self.sample_rate >= 1.0 || fastrand::f64() < self.sample_rate
+    }
+}
+
+/// `/metrics` and `/metrics/anything`, but not `/metrics-v2`, are under
+/// `/metrics`.
+fn is_under(path: &str, prefix: &str) -> bool {
+    path.strip_prefix(prefix)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/') || prefix.ends_with('/'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ignores_paths_under_the_prefixes() {
+        let filter = TraceFilter::new(vec!["/healthz".to_owned(), "/debug/".to_owned()], 1.0);
+        for path in ["/healthz", "/healthz/ready", "/debug/slow-stats"] {
+            assert!(!filter.should_trace(path), "{path}");
+        }
+        for path in ["/", "/healthzz", "/debug", "/metrics"] {
+            assert!(filter.should_trace(path), "{path}");
+        }
+    }
+
+    #[test]
+    fn samples_the_rest() {
+        let all = TraceFilter::new(Vec::new(), 1.0);
+        let none = TraceFilter::new(Vec::new(), 0.0);
+        let half = TraceFilter::new(Vec::new(), 0.5);
+        assert!((0..1000).all(|_| all.should_trace("/")));
+        assert!((0..1000).all(|_| !none.should_trace("/")));
+        let sampled = (0..1000).filter(|_| half.should_trace("/")).count();
+        assert!((300..700).contains(&sampled), "{sampled}");
+    }
+
+    #[test]
+    fn rates_are_clamped() {
+        assert!(TraceFilter::new(Vec::new(), 7.0).should_trace("/"));
+        assert!(!TraceFilter::new(Vec::new(), -1.0).should_trace("/"));
+    }
+}
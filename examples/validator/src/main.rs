@@ -1,17 +1,25 @@
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::convert::Infallible;
+use std::sync::{Arc, OnceLock, RwLock};
+
 use async_trait::async_trait;
-use axum::extract::rejection::FormRejection;
-use axum::extract::{FromRequest, Request};
-use axum::http::StatusCode;
+use axum::extract::rejection::{FormRejection, JsonRejection, QueryRejection};
+use axum::extract::{FromRef, FromRequest, FromRequestParts, Query, Request, State};
+use axum::http::request::Parts;
+use axum::http::{header, HeaderMap, StatusCode};
 use axum::response::{Html, IntoResponse, Response};
-use axum::routing::get;
-use axum::{Form, Router};
+use axum::routing::{get, post};
+use axum::{Form, Json, Router};
+use minijinja::{context, AutoEscape, Environment};
 use serde::de::DeserializeOwned;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
 use thiserror::Error;
 use tokio::net::TcpListener;
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::util::SubscriberInitExt;
-use validator::{Validate, ValidationErrors};
+use validator::{Validate, ValidationError, ValidationErrors, ValidationErrorsKind};
 
 #[tokio::main]
 async fn main() {
@@ -23,60 +31,1396 @@ async fn main() {
         .with(tracing_subscriber::fmt::layer())
         .init();
 
-    let app = Router::new().route("/", get(handler));
-
     let listener = TcpListener::bind("127.0.0.1:3000").await.unwrap();
     tracing::debug!("listening on {}", listener.local_addr().unwrap());
-    axum::serve(listener, app).await.unwrap();
+    axum::serve(listener, app(AppState::default()))
+        .await
+        .unwrap();
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct AppState {
+    /// Every username signed up so far, lowercase, standing in for a
+    /// database table.
+    pub usernames: Arc<RwLock<HashSet<String>>>,
+    pub validation: ValidationConfig,
+}
+
+/// How the `Validated*` extractors report input that doesn't validate.
+#[derive(Debug, Clone, Copy)]
+pub struct ValidationConfig {
+    /// 422 Unprocessable Entity unless set otherwise, like to 400 for clients
+    /// that expect it. Input that can't be parsed keeps the rejection's status.
+    pub status: StatusCode,
+}
+
+impl Default for ValidationConfig {
+    fn default() -> Self {
+        Self {
+            status: StatusCode::UNPROCESSABLE_ENTITY,
+        }
+    }
 }
 
-#[derive(Debug, Deserialize, Validate)]
+impl FromRef<AppState> for ValidationConfig {
+    fn from_ref(state: &AppState) -> Self {
+        state.validation
+    }
+}
+
+fn app(state: AppState) -> Router {
+    Router::new()
+        .route("/", get(handler).post(handler))
+        .route("/json", post(json_handler))
+        .route("/query", get(query_handler))
+        .route("/register", post(register))
+        .route("/signup", post(signup))
+        .with_state(state)
+}
+
+#[derive(Debug, Serialize, Deserialize, Validate)]
 pub struct NameInput {
     #[validate(length(min = 1, message = "Can not be empty"))]
     pub name: String,
 }
 
-async fn handler(ValidatedForm(input): ValidatedForm<NameInput>) -> Html<String> {
-    Html(format!("<h1>Hello, {}</h1>", input.name))
+async fn handler(
+    PreferredLanguage(language): PreferredLanguage,
+    Validated(input): Validated<Form<NameInput>>,
+) -> Html<String> {
+    let greeting = match language {
+        Language::En => "Hello",
+        Language::De => "Hallo",
+    };
+    Html(format!("<h1>{greeting}, {}</h1>", input.name))
+}
+
+#[derive(Debug, Serialize)]
+pub struct Greeting {
+    pub message: String,
+}
+
+async fn json_handler(Validated(input): Validated<Json<NameInput>>) -> Json<Greeting> {
+    Json(Greeting {
+        message: format!("Hello, {}", input.name),
+    })
+}
+
+async fn query_handler(Validated(input): Validated<Query<NameInput>>) -> Json<Greeting> {
+    Json(Greeting {
+        message: format!("Hello, {}", input.name),
+    })
+}
+
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct RegistrationInput {
+    #[validate(nested)]
+    pub profile: Profile,
+    #[validate(length(min = 1, max = 5), nested)]
+    pub tags: Vec<Tag>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct Profile {
+    #[validate(email)]
+    pub email: String,
+    #[validate(length(min = 1, max = 64))]
+    pub display_name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct Tag {
+    #[validate(length(min = 1, max = 32))]
+    pub name: String,
+}
+
+async fn register(Validated(input): Validated<Json<RegistrationInput>>) -> Json<Greeting> {
+    Json(Greeting {
+        message: format!(
+            "Welcome, {}, with {} tags",
+            input.profile.display_name,
+            input.tags.len()
+        ),
+    })
+}
+
+/// Usernames nobody can sign up with, compared case-insensitively.
+const RESERVED_USERNAMES: [&str; 5] = ["admin", "root", "support", "system", "null"];
+
+/// Passwords shorter than this are weak, whatever they're made of.
+const MIN_PASSWORD_LENGTH: usize = 10;
+
+#[derive(Debug, Serialize, Deserialize, Validate)]
+#[validate(schema(function = "passwords_match", skip_on_field_errors = false))]
+pub struct PasswordInput {
+    #[validate(length(min = 3, max = 32), custom(function = "not_reserved"))]
+    pub username: String,
+    #[validate(custom(function = "strong_password"))]
+    pub password: String,
+    pub password_confirm: String,
+}
+
+/// At least `MIN_PASSWORD_LENGTH` characters, from at least three of
+/// lowercase letters, uppercase letters, digits and everything else.
+fn strong_password(password: &str) -> Result<(), ValidationError> {
+    let classes: [fn(char) -> bool; 4] = [
+        char::is_lowercase,
+        char::is_uppercase,
+        |c| c.is_ascii_digit(),
+        |c| !c.is_alphanumeric(),
+    ];
+    let used = classes
+        .iter()
+        .filter(|class| password.chars().any(class))
+        .count();
+    if password.chars().count() >= MIN_PASSWORD_LENGTH && used >= 3 {
+        return Ok(());
+    }
+    let mut error = ValidationError::new("weak_password").with_message(Cow::Owned(format!(
+        "Use at least {MIN_PASSWORD_LENGTH} characters mixing letters, digits and symbols"
+    )));
+    error.add_param(Cow::Borrowed("min_length"), &MIN_PASSWORD_LENGTH);
+    Err(error)
+}
+
+fn not_reserved(username: &str) -> Result<(), ValidationError> {
+    if RESERVED_USERNAMES
+        .iter()
+        .any(|reserved| username.eq_ignore_ascii_case(reserved))
+    {
+        return Err(ValidationError::new("reserved")
+            .with_message(Cow::Borrowed("This username is reserved")));
+    }
+    Ok(())
+}
+
+/// Reported under `__all__`, like every struct-level error.
+fn passwords_match(input: &PasswordInput) -> Result<(), ValidationError> {
+    if input.password != input.password_confirm {
+        return Err(ValidationError::new("mismatch")
+            .with_message(Cow::Borrowed("The passwords don't match")));
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl AsyncValidate<AppState> for PasswordInput {
+    async fn validate_async(&self, state: &AppState) -> Result<(), ValidationErrors> {
+        let taken = state
+            .usernames
+            .read()
+            .unwrap()
+            .contains(&self.username.to_lowercase());
+        if taken {
+            return Err(username_taken());
+        }
+        Ok(())
+    }
+}
+
+fn username_taken() -> ValidationErrors {
+    let mut errors = ValidationErrors::new();
+    let error =
+        ValidationError::new("taken").with_message(Cow::Borrowed("This username is already taken"));
+    errors.add("username", error);
+    errors
+}
+
+async fn signup(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    ValidatedWithState(input): ValidatedWithState<PasswordInput>,
+) -> Result<Json<Greeting>, ServerError> {
+    let inserted = state
+        .usernames
+        .write()
+        .unwrap()
+        .insert(input.username.to_lowercase());
+    // Someone else can sign up with the same name between `validate_async`
+    // and here, so the insert has the final say.
+    if !inserted {
+        let PreferredLanguage(language) = PreferredLanguage::from_headers(&headers);
+        return Err(ServerError::validation(
+            username_taken(),
+            ErrorFormat::from_headers(&headers),
+            language,
+            state.validation.status,
+            &input,
+        ));
+    }
+    Ok(Json(Greeting {
+        message: format!("Welcome, {}", input.username),
+    }))
+}
+
+/// An extractor whose value can be validated once it's taken out.
+pub trait HasInner {
+    type Inner;
+
+    fn into_inner(self) -> Self::Inner;
+}
+
+impl<T> HasInner for Form<T> {
+    type Inner = T;
+
+    fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> HasInner for Json<T> {
+    type Inner = T;
+
+    fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> HasInner for Query<T> {
+    type Inner = T;
+
+    fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+/// The value of the extractor `E`, once it's passed validation, like
+/// `Validated<Json<T>>` for a valid `T` from a JSON body.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Validated<E: HasInner>(pub E::Inner);
+
+pub type ValidatedForm<T> = Validated<Form<T>>;
+pub type ValidatedJson<T> = Validated<Json<T>>;
+
+#[async_trait]
+impl<E, S> FromRequest<S> for Validated<E>
+where
+    E: FromRequest<S> + HasInner,
+    E::Inner: Validate + Serialize,
+    ServerError: From<E::Rejection>,
+    S: Send + Sync,
+    ValidationConfig: FromRef<S>,
+{
+    type Rejection = ServerError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let format = ErrorFormat::from_headers(req.headers());
+        let PreferredLanguage(language) = PreferredLanguage::from_headers(req.headers());
+        let ValidationConfig { status } = ValidationConfig::from_ref(state);
+        let value = E::from_request(req, state).await?.into_inner();
+        value
+            .validate()
+            .map_err(|errors| ServerError::validation(errors, format, language, status, &value))?;
+        Ok(Validated(value))
+    }
 }
 
+#[async_trait]
+impl<E, S> FromRequestParts<S> for Validated<E>
+where
+    E: FromRequestParts<S> + HasInner,
+    E::Inner: Validate + Serialize,
+    ServerError: From<E::Rejection>,
+    S: Send + Sync,
+    ValidationConfig: FromRef<S>,
+{
+    type Rejection = ServerError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let format = ErrorFormat::from_headers(&parts.headers);
+        let PreferredLanguage(language) = PreferredLanguage::from_headers(&parts.headers);
+        let ValidationConfig { status } = ValidationConfig::from_ref(state);
+        let value = E::from_request_parts(parts, state).await?.into_inner();
+        value
+            .validate()
+            .map_err(|errors| ServerError::validation(errors, format, language, status, &value))?;
+        Ok(Validated(value))
+    }
+}
+
+/// Validation that needs I/O, like looking things up in a database, which
+/// `Validate` can't do.
+#[async_trait]
+pub trait AsyncValidate<S> {
+    async fn validate_async(&self, state: &S) -> Result<(), ValidationErrors>;
+}
+
+/// Like `Validated<Json<T>>`, then checked against the state by `AsyncValidate`.
+/// The errors of both are reported together.
 #[derive(Debug, Clone, Copy, Default)]
-pub struct ValidatedForm<T>(pub T);
+pub struct ValidatedWithState<T>(pub T);
 
 #[async_trait]
-impl<T, S> FromRequest<S> for ValidatedForm<T>
+impl<T, S> FromRequest<S> for ValidatedWithState<T>
 where
-    T: DeserializeOwned + Validate,
+    T: DeserializeOwned + Validate + Serialize + AsyncValidate<S> + Send + Sync,
     S: Send + Sync,
-    Form<T>: FromRequest<S, Rejection = FormRejection>,
+    ValidationConfig: FromRef<S>,
+    Json<T>: FromRequest<S, Rejection = JsonRejection>,
 {
     type Rejection = ServerError;
 
     async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
-        let Form(value) = Form::<T>::from_request(req, state).await?;
-        value.validate()?;
-        Ok(ValidatedForm(value))
+        let format = ErrorFormat::from_headers(req.headers());
+        let PreferredLanguage(language) = PreferredLanguage::from_headers(req.headers());
+        let ValidationConfig { status } = ValidationConfig::from_ref(state);
+        let Json(value) = Json::<T>::from_request(req, state).await?;
+        let mut errors = value.validate().err().unwrap_or_default();
+        if let Err(async_errors) = value.validate_async(state).await {
+            merge_errors(&mut errors, async_errors);
+        }
+        if !errors.is_empty() {
+            return Err(ServerError::validation(
+                errors, format, language, status, &value,
+            ));
+        }
+        Ok(ValidatedWithState(value))
+    }
+}
+
+/// Add `other`'s errors to `errors`, after theirs for the same field.
+fn merge_errors(errors: &mut ValidationErrors, other: ValidationErrors) {
+    for (field, kind) in other.into_errors() {
+        match (errors.errors_mut().get_mut(field), kind) {
+            (Some(ValidationErrorsKind::Field(existing)), ValidationErrorsKind::Field(new)) => {
+                existing.extend(new);
+            }
+            (_, kind) => {
+                errors.errors_mut().insert(field, kind);
+            }
+        }
+    }
+}
+
+/// How validation errors are reported, depending on what the client accepts.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum ErrorFormat {
+    /// `{ "error": "validation", "fields": { "name": [...] } }`.
+    #[default]
+    Json,
+    /// The form again, with the errors next to the fields, for browsers.
+    Html,
+    /// Every error on one line.
+    PlainText,
+}
+
+impl ErrorFormat {
+    /// Whichever `Accept` gives the highest q-value. Ties, like for `*/*`, and
+    /// a missing `Accept` go to JSON, so API clients that don't say what they
+    /// want get something they can parse.
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let (mut json, mut html, mut text) = (0.0_f32, 0.0_f32, 0.0_f32);
+        let ranges = headers
+            .get_all(header::ACCEPT)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','));
+        for range in ranges {
+            let mut params = range.split(';');
+            let media_type = params.next().unwrap_or_default().trim();
+            let q = params
+                .find_map(|param| param.trim().strip_prefix("q="))
+                .map_or(1.0, |q| q.parse().unwrap_or(0.0));
+            match media_type.to_ascii_lowercase().as_str() {
+                "application/json" | "application/*" => json = json.max(q),
+                "text/html" => html = html.max(q),
+                "text/plain" => text = text.max(q),
+                "text/*" => {
+                    html = html.max(q);
+                    text = text.max(q);
+                }
+                "*/*" => {
+                    json = json.max(q);
+                    html = html.max(q);
+                    text = text.max(q);
+                }
+                _ => {}
+            }
+        }
+        if json >= html && json >= text {
+            ErrorFormat::Json
+        } else if html > text {
+            ErrorFormat::Html
+        } else {
+            ErrorFormat::PlainText
+        }
+    }
+}
+
+/// Every field's errors by path, with nested structs' fields as
+/// `profile.email` and list items' as `tags[2].name`.
+pub fn field_errors(errors: &ValidationErrors) -> BTreeMap<String, &[ValidationError]> {
+    fn collect<'a>(
+        errors: &'a ValidationErrors,
+        prefix: &str,
+        fields: &mut BTreeMap<String, &'a [ValidationError]>,
+    ) {
+        for (field, kind) in errors.errors() {
+            let path = if prefix.is_empty() {
+                field.to_string()
+            } else {
+                format!("{prefix}.{field}")
+            };
+            match kind {
+                ValidationErrorsKind::Field(errors) => {
+                    fields.insert(path, errors);
+                }
+                ValidationErrorsKind::Struct(errors) => collect(errors, &path, fields),
+                ValidationErrorsKind::List(items) => {
+                    for (index, errors) in items {
+                        collect(errors, &format!("{path}[{index}]"), fields);
+                    }
+                }
+            }
+        }
+    }
+
+    let mut fields = BTreeMap::new();
+    collect(errors, "", &mut fields);
+    fields
+}
+
+/// The languages validation errors can be reported in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Language {
+    #[default]
+    En,
+    De,
+}
+
+impl Language {
+    pub fn code(self) -> &'static str {
+        match self {
+            Language::En => "en",
+            Language::De => "de",
+        }
+    }
+
+    fn from_tag(tag: &str) -> Option<Self> {
+        let primary = tag.split('-').next().unwrap_or_default();
+        [Language::En, Language::De]
+            .into_iter()
+            .find(|language| primary.eq_ignore_ascii_case(language.code()))
+    }
+}
+
+/// The language from `Accept-Language` with the highest q-value among those
+/// there are messages in, English if there's none.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PreferredLanguage(pub Language);
+
+impl PreferredLanguage {
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let mut ranges: Vec<(&str, f32)> = headers
+            .get_all(header::ACCEPT_LANGUAGE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(|range| {
+                let mut params = range.split(';');
+                let tag = params.next().unwrap_or_default().trim();
+                let q = params
+                    .find_map(|param| param.trim().strip_prefix("q="))
+                    .map_or(1.0, |q| q.parse().unwrap_or(0.0));
+                (tag, q)
+            })
+            .filter(|&(_, q)| q > 0.0)
+            .collect();
+        // Stable, so equal q-values keep the client's order.
+        ranges.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+        let language = ranges
+            .into_iter()
+            .find_map(|(tag, _)| Language::from_tag(tag))
+            .unwrap_or_default();
+        PreferredLanguage(language)
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for PreferredLanguage
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(PreferredLanguage::from_headers(&parts.headers))
+    }
+}
+
+/// Error messages by code and language.
+type Messages = HashMap<(&'static str, Language), &'static str>;
+
+fn messages() -> &'static Messages {
+    static MESSAGES: OnceLock<Messages> = OnceLock::new();
+    MESSAGES.get_or_init(|| {
+        HashMap::from([
+            (("email", Language::En), "Not a valid email address"),
+            (("length", Language::En), "Too short or too long"),
+            (("range", Language::En), "Out of range"),
+            (("email", Language::De), "Keine gültige E-Mail-Adresse"),
+            (("length", Language::De), "Zu kurz oder zu lang"),
+            (("range", Language::De), "Außerhalb des erlaubten Bereichs"),
+            (
+                ("weak_password", Language::De),
+                "Mindestens 10 Zeichen aus Buchstaben, Ziffern und Sonderzeichen verwenden",
+            ),
+            (
+                ("mismatch", Language::De),
+                "Die Passwörter stimmen nicht überein",
+            ),
+            (
+                ("reserved", Language::De),
+                "Dieser Benutzername ist reserviert",
+            ),
+            (
+                ("taken", Language::De),
+                "Dieser Benutzername ist bereits vergeben",
+            ),
+        ])
+    })
+}
+
+/// `error` with its message in `language`, or the one it came with when
+/// there's no translation. The messages in the code are English, so they're
+/// only filled in from `messages` when missing.
+pub fn localize(error: &ValidationError, language: Language) -> ValidationError {
+    let mut error = error.clone();
+    if language == Language::En && error.message.is_some() {
+        return error;
+    }
+    if let Some(message) = messages().get(&(&*error.code, language)) {
+        error.message = Some(Cow::Borrowed(message));
+    }
+    error
+}
+
+/// `field_errors`, with the messages in `language`.
+pub fn localized_field_errors(
+    errors: &ValidationErrors,
+    language: Language,
+) -> BTreeMap<String, Vec<ValidationError>> {
+    field_errors(errors)
+        .into_iter()
+        .map(|(path, errors)| {
+            let errors = errors.iter().map(|error| localize(error, language));
+            (path, errors.collect())
+        })
+        .collect()
+}
+
+/// Whether a field's value shouldn't be shown again, like passwords.
+fn is_secret(field: &str) -> bool {
+    field.to_ascii_lowercase().contains("password")
+}
+
+/// What was submitted, by the same paths as `field_errors`, leaving out
+/// secret fields.
+pub fn submitted_values<T: Serialize>(input: &T) -> BTreeMap<String, String> {
+    fn collect(value: &Value, path: String, values: &mut BTreeMap<String, String>) {
+        match value {
+            Value::Object(fields) => {
+                for (field, value) in fields.iter().filter(|(field, _)| !is_secret(field)) {
+                    let path = if path.is_empty() {
+                        field.clone()
+                    } else {
+                        format!("{path}.{field}")
+                    };
+                    collect(value, path, values);
+                }
+            }
+            Value::Array(items) => {
+                for (index, item) in items.iter().enumerate() {
+                    collect(item, format!("{path}[{index}]"), values);
+                }
+            }
+            Value::String(value) => {
+                values.insert(path, value.clone());
+            }
+            Value::Null => {
+                values.insert(path, String::new());
+            }
+            value => {
+                values.insert(path, value.to_string());
+            }
+        }
+    }
+
+    let mut values = BTreeMap::new();
+    if let Ok(value) = serde_json::to_value(input) {
+        collect(&value, String::new(), &mut values);
     }
+    values
+}
+
+fn templates() -> &'static Environment<'static> {
+    static TEMPLATES: OnceLock<Environment<'static>> = OnceLock::new();
+    TEMPLATES.get_or_init(|| {
+        let mut env = Environment::new();
+        env.set_auto_escape_callback(|_| AutoEscape::Html);
+        env.add_template("errors", include_str!("../templates/errors.jinja"))
+            .unwrap();
+        env
+    })
+}
+
+/// The form with the submitted values filled in, secret fields left empty,
+/// and the errors next to their fields.
+fn render_errors(
+    mut errors: BTreeMap<String, Vec<ValidationError>>,
+    values: &BTreeMap<String, String>,
+) -> String {
+    let message = |error: &ValidationError| match &error.message {
+        Some(message) => message.to_string(),
+        None => error.code.to_string(),
+    };
+    let form_errors: Vec<_> = errors
+        .remove("__all__")
+        .unwrap_or_default()
+        .iter()
+        .map(message)
+        .collect();
+
+    let mut names: Vec<&String> = values.keys().chain(errors.keys()).collect();
+    names.sort();
+    names.dedup();
+    let fields: Vec<_> = names
+        .into_iter()
+        .map(|name| {
+            let messages: Vec<_> = errors
+                .get(name)
+                .into_iter()
+                .flat_map(|errors| errors.iter())
+                .map(message)
+                .collect();
+            context! {
+                name,
+                value => values.get(name).map_or("", String::as_str),
+                secret => is_secret(name),
+                errors => messages,
+            }
+        })
+        .collect();
+
+    templates()
+        .get_template("errors")
+        .and_then(|template| template.render(context! { form_errors, fields }))
+        .unwrap()
 }
 
 #[derive(Debug, Error)]
 pub enum ServerError {
-    #[error(transparent)]
-    ValidationError(#[from] ValidationErrors),
+    #[error("{errors}")]
+    ValidationError {
+        errors: ValidationErrors,
+        format: ErrorFormat,
+        language: Language,
+        /// From `ValidationConfig`.
+        status: StatusCode,
+        /// From `submitted_values`, to fill the form in again.
+        values: BTreeMap<String, String>,
+    },
 
     #[error(transparent)]
     AxumFormRejection(#[from] FormRejection),
+
+    #[error(transparent)]
+    AxumJsonRejection(#[from] JsonRejection),
+
+    #[error(transparent)]
+    AxumQueryRejection(#[from] QueryRejection),
+}
+
+impl ServerError {
+    pub fn validation<T: Serialize>(
+        errors: ValidationErrors,
+        format: ErrorFormat,
+        language: Language,
+        status: StatusCode,
+        input: &T,
+    ) -> Self {
+        ServerError::ValidationError {
+            errors,
+            format,
+            language,
+            status,
+            values: submitted_values(input),
+        }
+    }
 }
 
 impl IntoResponse for ServerError {
     fn into_response(self) -> Response {
         match self {
-            ServerError::ValidationError(_) => {
+            ServerError::ValidationError {
+                ref errors,
+                format: ErrorFormat::Json,
+                language,
+                status,
+                ..
+            } => {
+                let body = json!({
+                    "error": "validation",
+                    "fields": localized_field_errors(errors, language),
+                });
+                (status, Json(body)).into_response()
+            }
+            ServerError::ValidationError {
+                ref errors,
+                format: ErrorFormat::Html,
+                language,
+                status,
+                ref values,
+            } => {
+                let page = render_errors(localized_field_errors(errors, language), values);
+                (status, Html(page)).into_response()
+            }
+            ServerError::ValidationError {
+                format: ErrorFormat::PlainText,
+                status,
+                ..
+            } => {
                 let message = format!("Input validation error: [{self}]").replace("\n", ", ");
-                (StatusCode::BAD_REQUEST, message)
+                (status, message).into_response()
+            }
+            ServerError::AxumFormRejection(ref rejection) => {
+                (rejection.status(), self.to_string()).into_response()
+            }
+            ServerError::AxumJsonRejection(ref rejection) => {
+                (rejection.status(), self.to_string()).into_response()
+            }
+            ServerError::AxumQueryRejection(ref rejection) => {
+                (rejection.status(), self.to_string()).into_response()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+    use axum::http::{HeaderValue, Request};
+    use http_body_util::BodyExt;
+    use serde_json::Value;
+    use tower::ServiceExt;
+
+    use super::*;
+
+    async fn post_json(body: &'static str) -> (StatusCode, String) {
+        let request = Request::post("/json")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body))
+            .unwrap();
+        let response = app(AppState::default()).oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn json_is_validated() {
+        let (status, body) = post_json(r#"{"name": "ferris"}"#).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, r#"{"message":"Hello, ferris"}"#);
+    }
+
+    #[tokio::test]
+    async fn invalid_json_is_rejected() {
+        let (status, body) = post_json(r#"{"name": "#).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(
+            body.starts_with("Failed to parse the request body as JSON"),
+            "{body}"
+        );
+
+        let (status, body) = post_json(r#"{"name": 1}"#).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(
+            body.starts_with("Failed to deserialize the JSON body"),
+            "{body}"
+        );
+    }
+
+    #[tokio::test]
+    async fn invalid_input_is_rejected() {
+        let (status, body) = post_json(r#"{"name": ""}"#).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["error"], "validation");
+        assert_eq!(
+            body["fields"],
+            json!({
+                "name": [{
+                    "code": "length",
+                    "message": "Can not be empty",
+                    "params": { "min": 1, "value": "" },
+                }],
+            })
+        );
+    }
+
+    async fn send(request: Request<Body>) -> (StatusCode, Option<HeaderValue>, String) {
+        send_to(app(AppState::default()), request).await
+    }
+
+    async fn send_to(
+        app: Router,
+        request: Request<Body>,
+    ) -> (StatusCode, Option<HeaderValue>, String) {
+        let response = app.oneshot(request).await.unwrap();
+        let status = response.status();
+        let content_type = response.headers().get(header::CONTENT_TYPE).cloned();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (
+            status,
+            content_type,
+            String::from_utf8(body.to_vec()).unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn accept_chooses_the_error_format() {
+        let json = Request::post("/json")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(header::ACCEPT, "application/json")
+            .body(Body::from(r#"{"name": ""}"#))
+            .unwrap();
+        let (status, content_type, body) = send(json).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(content_type.unwrap(), "application/json");
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["fields"]["name"][0]["code"], "length");
+
+        let html = Request::post("/")
+            .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .header(header::ACCEPT, "text/html,application/xhtml+xml,*/*;q=0.8")
+            .body(Body::from("name="))
+            .unwrap();
+        let (status, content_type, body) = send(html).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(content_type.unwrap(), "text/html; charset=utf-8");
+        assert!(
+            body.contains(r#"<input id="name" name="name" type="text" value="">"#),
+            "{body}"
+        );
+        assert!(
+            body.contains(r#"<span class="error">Can not be empty</span>"#),
+            "{body}"
+        );
+
+        let any = Request::get("/?name=")
+            .header(header::ACCEPT, "*/*")
+            .body(Body::empty())
+            .unwrap();
+        let (status, content_type, body) = send(any).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(content_type.unwrap(), "application/json");
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["fields"]["name"][0]["code"], "length");
+
+        let text = Request::get("/?name=")
+            .header(header::ACCEPT, "text/plain")
+            .body(Body::empty())
+            .unwrap();
+        let (status, content_type, body) = send(text).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(content_type.unwrap(), "text/plain; charset=utf-8");
+        assert_eq!(body, "Input validation error: [name: Can not be empty]");
+    }
+
+    #[tokio::test]
+    async fn error_pages_keep_the_input_but_not_passwords() {
+        let body = json!({
+            "username": "<admin>",
+            "password": "hunter2",
+            "password_confirm": "hunter2",
+        });
+        let request = Request::post("/signup")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(header::ACCEPT, "text/html")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let (status, _, body) = send(request).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(body.contains(r#"value="&lt;admin&gt;""#), "{body}");
+        assert!(!body.contains("hunter2"), "{body}");
+        assert!(
+            body.contains(r#"<input id="password" name="password" type="password" value="">"#),
+            "{body}"
+        );
+        assert!(body.contains("Use at least 10 characters"), "{body}");
+    }
+
+    #[test]
+    fn collects_submitted_values() {
+        let input = json!({
+            "profile": { "email": "ferris@example.com", "age": 7, "nickname": null },
+            "tags": [{ "name": "rust" }],
+            "new_password": "secret",
+        });
+        let values = submitted_values(&input);
+        let values: Vec<_> = values
+            .iter()
+            .map(|(path, value)| (path.as_str(), value.as_str()))
+            .collect();
+        assert_eq!(
+            values,
+            [
+                ("profile.age", "7"),
+                ("profile.email", "ferris@example.com"),
+                ("profile.nickname", ""),
+                ("tags[0].name", "rust"),
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_accept() {
+        let cases = [
+            (None, ErrorFormat::Json),
+            (Some("*/*"), ErrorFormat::Json),
+            (Some("text/*, application/*"), ErrorFormat::Json),
+            (Some("text/*"), ErrorFormat::PlainText),
+            (Some("application/json"), ErrorFormat::Json),
+            (Some("application/json, */*;q=0.1"), ErrorFormat::Json),
+            (Some("text/html"), ErrorFormat::Html),
+            (
+                Some("text/html,application/xhtml+xml,*/*;q=0.8"),
+                ErrorFormat::Html,
+            ),
+            (Some("Text/Plain"), ErrorFormat::PlainText),
+            (Some("text/html;q=0.5, application/json"), ErrorFormat::Json),
+            (
+                Some("application/json;q=0.5, text/*"),
+                ErrorFormat::PlainText,
+            ),
+            (
+                Some("application/json;q=0.5, text/plain;q=0.5"),
+                ErrorFormat::Json,
+            ),
+            (Some("image/png"), ErrorFormat::Json),
+        ];
+        for (accept, expected) in cases {
+            let mut headers = HeaderMap::new();
+            if let Some(accept) = accept {
+                headers.insert(header::ACCEPT, HeaderValue::from_static(accept));
+            }
+            assert_eq!(ErrorFormat::from_headers(&headers), expected, "{accept:?}");
+        }
+    }
+
+    async fn register(body: Value) -> (StatusCode, Value) {
+        let request = Request::post("/register")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let (status, _, body) = send(request).await;
+        (status, serde_json::from_str(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn nested_errors_have_paths() {
+        let (status, body) = register(json!({
+            "profile": { "email": "not an email", "display_name": "Ferris" },
+            "tags": [{ "name": "rust" }, { "name": "axum" }, { "name": "" }],
+        }))
+        .await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        let fields = body["fields"].as_object().unwrap();
+        let paths: Vec<_> = fields.keys().map(String::as_str).collect();
+        assert_eq!(paths, ["profile.email", "tags[2].name"]);
+        assert_eq!(fields["profile.email"][0]["code"], "email");
+        assert_eq!(fields["tags[2].name"][0]["code"], "length");
+    }
+
+    #[tokio::test]
+    async fn lists_are_validated_as_a_whole() {
+        let tags: Vec<_> = (0..6)
+            .map(|i| json!({ "name": format!("tag{i}") }))
+            .collect();
+        let (status, body) = register(json!({
+            "profile": { "email": "ferris@example.com", "display_name": "" },
+            "tags": tags,
+        }))
+        .await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        let fields = body["fields"].as_object().unwrap();
+        let paths: Vec<_> = fields.keys().map(String::as_str).collect();
+        assert_eq!(paths, ["profile.display_name", "tags"]);
+        assert_eq!(fields["tags"][0]["params"]["max"], 5);
+    }
+
+    #[tokio::test]
+    async fn valid_registrations_pass() {
+        let (status, body) = register(json!({
+            "profile": { "email": "ferris@example.com", "display_name": "Ferris" },
+            "tags": [{ "name": "rust" }],
+        }))
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["message"], "Welcome, Ferris, with 1 tags");
+    }
+
+    #[test]
+    fn flattens_nested_errors() {
+        let input = RegistrationInput {
+            profile: Profile {
+                email: "ferris@example.com".to_owned(),
+                display_name: "x".repeat(65),
+            },
+            tags: vec![
+                Tag {
+                    name: String::new(),
+                },
+                Tag {
+                    name: "ok".to_owned(),
+                },
+                Tag {
+                    name: "y".repeat(33),
+                },
+            ],
+        };
+        let errors = input.validate().unwrap_err();
+        let paths: Vec<_> = field_errors(&errors).into_keys().collect();
+        assert_eq!(
+            paths,
+            ["profile.display_name", "tags[0].name", "tags[2].name"]
+        );
+    }
+
+    async fn signup(username: &str, password: &str, password_confirm: &str) -> (StatusCode, Value) {
+        signup_to(&AppState::default(), username, password, password_confirm).await
+    }
+
+    async fn signup_to(
+        state: &AppState,
+        username: &str,
+        password: &str,
+        password_confirm: &str,
+    ) -> (StatusCode, Value) {
+        let body = json!({
+            "username": username,
+            "password": password,
+            "password_confirm": password_confirm,
+        });
+        let request = Request::post("/signup")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let (status, _, body) = send_to(app(state.clone()), request).await;
+        (status, serde_json::from_str(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn signups_are_validated() {
+        let (status, body) = signup("ferris", "Correct-Horse-1", "Correct-Horse-1").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["message"], "Welcome, ferris");
+
+        let (status, body) = signup("ferris", "password", "password").await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["fields"]["password"][0]["code"], "weak_password");
+        assert_eq!(body["fields"]["password"][0]["params"]["min_length"], 10);
+
+        let (status, body) = signup("ferris", "Correct-Horse-1", "Correct-Horse-2").await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["fields"]["__all__"][0]["code"], "mismatch");
+
+        let (status, body) = signup("Admin", "Correct-Horse-1", "Correct-Horse-1").await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["fields"]["username"][0]["code"], "reserved");
+    }
+
+    #[tokio::test]
+    async fn every_signup_error_is_reported() {
+        let (status, body) = signup("root", "short", "shorter").await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        let codes = |field: &str| -> Vec<Value> {
+            let errors = body["fields"][field].as_array().unwrap();
+            errors.iter().map(|error| error["code"].clone()).collect()
+        };
+        assert_eq!(codes("username"), ["reserved"]);
+        assert_eq!(codes("password"), ["weak_password"]);
+        assert_eq!(codes("__all__"), ["mismatch"]);
+    }
+
+    #[test]
+    fn checks_password_strength() {
+        for strong in ["Correct-Horse-1", "correcthorse1!", "CORRECT HORSE 1"] {
+            assert_eq!(strong_password(strong), Ok(()), "{strong}");
+        }
+        for weak in [
+            "",
+            "Sh0rt!",
+            "correcthorsebattery",
+            "CorrectHorseBattery",
+            "1234567890!",
+        ] {
+            let error = strong_password(weak).unwrap_err();
+            assert_eq!(error.code, "weak_password", "{weak}");
+        }
+    }
+
+    #[test]
+    fn rejects_reserved_usernames() {
+        assert_eq!(not_reserved("ferris"), Ok(()));
+        assert_eq!(not_reserved("administrator"), Ok(()));
+        for reserved in ["admin", "ROOT", "Support"] {
+            assert_eq!(not_reserved(reserved).unwrap_err().code, "reserved");
+        }
+    }
+
+    #[test]
+    fn passwords_must_match() {
+        let input = |confirm: &str| PasswordInput {
+            username: "ferris".to_owned(),
+            password: "Correct-Horse-1".to_owned(),
+            password_confirm: confirm.to_owned(),
+        };
+        assert_eq!(passwords_match(&input("Correct-Horse-1")), Ok(()));
+        let error = passwords_match(&input("correct-horse-1")).unwrap_err();
+        assert_eq!(error.code, "mismatch");
+    }
+
+    fn taken(usernames: &[&str]) -> AppState {
+        let state = AppState::default();
+        let names = usernames.iter().map(|name| name.to_string());
+        state.usernames.write().unwrap().extend(names);
+        state
+    }
+
+    #[tokio::test]
+    async fn taken_usernames_are_rejected() {
+        let state = taken(&["ferris"]);
+        let (status, body) =
+            signup_to(&state, "Ferris", "Correct-Horse-1", "Correct-Horse-1").await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["fields"]["username"][0]["code"], "taken");
+
+        let (status, _) = signup_to(&state, "corro", "Correct-Horse-1", "Correct-Horse-1").await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, body) = signup_to(&state, "corro", "Correct-Horse-1", "Correct-Horse-1").await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["fields"]["username"][0]["code"], "taken");
+    }
+
+    #[tokio::test]
+    async fn usernames_taken_after_validation_are_rejected() {
+        // As if another signup for the name finished after this one was
+        // validated.
+        let state = taken(&["ferris"]);
+        let input = PasswordInput {
+            username: "Ferris".to_owned(),
+            password: "Correct-Horse-1".to_owned(),
+            password_confirm: "Correct-Horse-1".to_owned(),
+        };
+        let response = super::signup(State(state), HeaderMap::new(), ValidatedWithState(input))
+            .await
+            .into_response();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["fields"]["username"][0]["code"], "taken");
+    }
+
+    #[tokio::test]
+    async fn sync_and_async_errors_are_merged() {
+        let state = taken(&["ab"]);
+        let (status, body) = signup_to(&state, "ab", "weak", "weak").await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        let codes = |field: &str| -> Vec<Value> {
+            let errors = body["fields"][field].as_array().unwrap();
+            errors.iter().map(|error| error["code"].clone()).collect()
+        };
+        assert_eq!(codes("username"), ["length", "taken"]);
+        assert_eq!(codes("password"), ["weak_password"]);
+    }
+
+    async fn get(uri: &str) -> (StatusCode, Option<HeaderValue>, String) {
+        send(Request::get(uri).body(Body::empty()).unwrap()).await
+    }
+
+    #[tokio::test]
+    async fn forms_are_validated() {
+        let (status, _, body) = get("/?name=ferris").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "<h1>Hello, ferris</h1>");
+
+        let (status, _, body) = get("/?name=").await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["fields"]["name"][0]["code"], "length");
+
+        let (status, _, body) = get("/").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body.starts_with("Failed to deserialize form"), "{body}");
+    }
+
+    #[tokio::test]
+    async fn queries_are_validated() {
+        let (status, _, body) = get("/query?name=ferris").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, r#"{"message":"Hello, ferris"}"#);
+
+        let (status, _, body) = get("/query?name=").await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["fields"]["name"][0]["code"], "length");
+
+        let (status, _, body) = get("/query").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(
+            body.starts_with("Failed to deserialize query string"),
+            "{body}"
+        );
+    }
+
+    #[test]
+    fn prefers_languages_by_q_value() {
+        let cases = [
+            (None, Language::En),
+            (Some("de"), Language::De),
+            (Some("de;q=0.9, en;q=0.8"), Language::De),
+            (Some("en;q=0.8, de;q=0.9"), Language::De),
+            (Some("de-AT, en;q=0.5"), Language::De),
+            (Some("fr, de;q=0.5, en;q=0.1"), Language::De),
+            (Some("en, de"), Language::En),
+            (Some("fr"), Language::En),
+            (Some("de;q=0"), Language::En),
+            (Some("*"), Language::En),
+        ];
+        for (accept, expected) in cases {
+            let mut headers = HeaderMap::new();
+            if let Some(accept) = accept {
+                headers.insert(header::ACCEPT_LANGUAGE, HeaderValue::from_static(accept));
             }
-            ServerError::AxumFormRejection(_) => (StatusCode::BAD_REQUEST, self.to_string()),
+            let PreferredLanguage(language) = PreferredLanguage::from_headers(&headers);
+            assert_eq!(language, expected, "{accept:?}");
+        }
+    }
+
+    async fn post_json_in(language: &'static str, uri: &str, body: Value) -> Value {
+        let request = Request::post(uri)
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(header::ACCEPT_LANGUAGE, language)
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let (status, _, body) = send(request).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        serde_json::from_str(&body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn messages_follow_accept_language() {
+        let body = post_json_in("de;q=0.9, en;q=0.8", "/json", json!({ "name": "" })).await;
+        assert_eq!(body["fields"]["name"][0]["code"], "length");
+        assert_eq!(body["fields"]["name"][0]["message"], "Zu kurz oder zu lang");
+
+        let body = post_json_in("fr-CH, fr;q=0.9", "/json", json!({ "name": "" })).await;
+        assert_eq!(body["fields"]["name"][0]["message"], "Can not be empty");
+
+        let signup = json!({
+            "username": "admin",
+            "password": "weak",
+            "password_confirm": "weak",
+        });
+        let body = post_json_in("de", "/signup", signup.clone()).await;
+        let password = &body["fields"]["password"][0];
+        assert_eq!(password["code"], "weak_password");
+        assert!(password["message"]
+            .as_str()
+            .unwrap()
+            .starts_with("Mindestens"));
+        assert_eq!(
+            body["fields"]["username"][0]["message"],
+            "Dieser Benutzername ist reserviert"
+        );
+        let body = post_json_in("en", "/signup", signup).await;
+        assert_eq!(
+            body["fields"]["username"][0]["message"],
+            "This username is reserved"
+        );
+    }
+
+    #[test]
+    fn untranslated_codes_keep_their_message() {
+        let error =
+            ValidationError::new("no_such_code").with_message(Cow::Borrowed("The default message"));
+        assert_eq!(localize(&error, Language::De), error);
+        let bare = ValidationError::new("no_such_code");
+        assert_eq!(localize(&bare, Language::De).message, None);
+
+        // English fills in missing messages only.
+        let length = ValidationError::new("length");
+        assert_eq!(
+            localize(&length, Language::En).message.as_deref(),
+            Some("Too short or too long")
+        );
+    }
+
+    #[tokio::test]
+    async fn handlers_can_extract_the_language() {
+        let request = Request::get("/?name=ferris")
+            .header(header::ACCEPT_LANGUAGE, "de-DE")
+            .body(Body::empty())
+            .unwrap();
+        let (status, _, body) = send(request).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "<h1>Hallo, ferris</h1>");
+    }
+
+    #[tokio::test]
+    async fn the_validation_status_is_configurable() {
+        let strict = app(AppState {
+            validation: ValidationConfig {
+                status: StatusCode::BAD_REQUEST,
+            },
+            ..AppState::default()
+        });
+        let default = app(AppState::default());
+
+        let invalid = || {
+            [
+                Request::get("/?name=").body(Body::empty()).unwrap(),
+                Request::get("/query?name=").body(Body::empty()).unwrap(),
+                Request::post("/json")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(r#"{"name": ""}"#))
+                    .unwrap(),
+                Request::post("/signup")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .header(header::ACCEPT, "text/plain")
+                    .body(Body::from(
+                        r#"{"username": "root", "password": "", "password_confirm": ""}"#,
+                    ))
+                    .unwrap(),
+            ]
+        };
+        for request in invalid() {
+            let uri = request.uri().clone();
+            let (status, _, _) = send_to(default.clone(), request).await;
+            assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{uri}");
+        }
+        for request in invalid() {
+            let uri = request.uri().clone();
+            let (status, _, _) = send_to(strict.clone(), request).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{uri}");
+        }
+
+        // Parse failures keep the rejection's own status either way.
+        for app in [default, strict] {
+            let (status, _, _) = send_to(
+                app.clone(),
+                Request::get("/query").body(Body::empty()).unwrap(),
+            )
+            .await;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+            let request = Request::post("/json")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(r#"{"name": 1}"#))
+                .unwrap();
+            let (status, _, _) = send_to(app, request).await;
+            assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
         }
-        .into_response()
     }
 }